@@ -1,6 +1,8 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    /* The last 272K is reserved for the persistent config and the tracklog */
+    /* (CONFIG_SIZE and TRACK_SIZE in main.rs) */
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 272K
 
     /* Pick one of the two options for RAM layout     */
 
@@ -1,32 +1,44 @@
 #![no_std]
 #![no_main]
 
-use alloc::string::ToString;
 use atat::asynch::Client;
 use atat::heapless::String;
 use atat::{AtatIngress, DefaultDigester, Ingress, ResponseSlot, UrcChannel};
+use core::cell::RefCell;
 use core::ptr::addr_of_mut;
 use defmt::*;
 use embassy_executor::Spawner;
 use embassy_futures::select::{Either3, select3};
 use embassy_rp::adc::{Adc, Channel, Config, InterruptHandler as AdcInterruptHandler};
 use embassy_rp::bind_interrupts;
+use embassy_rp::flash::{Blocking, ERASE_SIZE, Flash};
 use embassy_rp::gpio::{Level, Output, Pull};
-use embassy_rp::peripherals::UART0;
+use embassy_rp::peripherals::{FLASH, UART0};
 use embassy_rp::rtc::{DateTime, DateTimeFilter, DayOfWeek, Rtc};
 use embassy_rp::uart::{self, BufferedInterruptHandler, BufferedUart, BufferedUartRx};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::pubsub;
-use embassy_time::{Duration, Timer};
+use embassy_sync::watch::Watch;
+use embassy_time::{Duration, Instant, Timer};
 use embedded_alloc::LlffHeap as Heap;
 use static_cell::StaticCell;
 use {defmt_rtt as _, panic_probe as _};
 
 use embassy_rp::watchdog::Watchdog;
 use pico_lib::at::PicoHW;
+use pico_lib::config::ConfigStore;
+use pico_lib::error::Error;
+use pico_lib::nmea;
 use pico_lib::poro;
+use pico_lib::protector;
+use pico_lib::storage::Storage;
+use pico_lib::tracklog::Tracklog;
 use pico_lib::urc;
-use pico_lib::utils::{astring_to_string, send_command_logged};
-use pico_lib::{at, battery, call, gps, gsm, network, sms};
+use pico_lib::{
+    at, battery, call, concat, connectivity, dispatcher, epo, filter, gps, gsm, ip, location,
+    network, sms, telemetry,
+};
 
 extern crate alloc;
 
@@ -36,6 +48,35 @@ static HEAP: Heap = Heap::empty();
 const INGRESS_BUF_SIZE: usize = 1024;
 const URC_CAPACITY: usize = 128;
 const URC_SUBSCRIBERS: usize = 3;
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+const CONFIG_OFFSET: usize = FLASH_SIZE - CONFIG_SIZE; // keep in sync with memory.x
+const CONFIG_SIZE: usize = 4 * ERASE_SIZE;
+const TRACK_OFFSET: usize = CONFIG_OFFSET - TRACK_SIZE;
+const TRACK_SIZE: usize = 64 * ERASE_SIZE; // 8192 fixes, see tracklog::RECORD_SIZE
+
+// The data link, see connectivity::Supervisor.
+static LINK: Watch<CriticalSectionRawMutex, connectivity::Link, 2> = Watch::new();
+
+// The navigation state of the GNSS stream (gps::start_stream), updated by the ingress or by
+// the +UGNSINF URCs, read by location::StreamLocator during a trip.
+static NAVIGATION: Mutex<CriticalSectionRawMutex, RefCell<nmea::Navigation>> =
+    Mutex::new(RefCell::new(nmea::Navigation::new()));
+
+fn on_nmea_sentence(sentence: nmea::Sentence) {
+    let uptime_millis = Instant::now().as_millis();
+    NAVIGATION.lock(|navigation| navigation.borrow_mut().update(&sentence, uptime_millis));
+}
+
+fn navigation_location() -> Option<location::StreamFix> {
+    NAVIGATION.lock(|navigation| {
+        let navigation = navigation.borrow();
+        navigation.location().zip(navigation.updated_millis)
+    })
+}
+
+fn on_ip_event(event: ip::Event) {
+    info!("IP event {}", event);
+}
 
 bind_interrupts!(struct Irqs {
     UART0_IRQ => BufferedInterruptHandler<UART0>;
@@ -48,7 +89,7 @@ async fn main(spawner: Spawner) {
     info!("STARTING");
     {
         use core::mem::MaybeUninit;
-        const HEAP_SIZE: usize = 4096;
+        use pico_lib::HEAP_SIZE;
         static mut HEAP_MEM: [MaybeUninit<u8>; HEAP_SIZE] = [MaybeUninit::uninit(); HEAP_SIZE];
         unsafe { HEAP.init(addr_of_mut!(HEAP_MEM) as usize, HEAP_SIZE) }
     }
@@ -76,6 +117,21 @@ async fn main(spawner: Spawner) {
     let watchdog = Watchdog::new(p.WATCHDOG);
     spawner.spawn(watchdog_task(watchdog)).unwrap();
 
+    let flash = RefCell::new(Flash::new_blocking(p.FLASH));
+    let mut config_store = ConfigStore::new(FlashStorage {
+        flash: &flash,
+        offset: CONFIG_OFFSET,
+        size: CONFIG_SIZE,
+    });
+    let mut config = config_store.load();
+    info!("Config: {:?}", config);
+    let mut tracklog = Tracklog::new(FlashStorage {
+        flash: &flash,
+        offset: TRACK_OFFSET,
+        size: TRACK_SIZE,
+    });
+    tracklog.load();
+
     let mut pico = Pico {
         led: Output::new(p.PIN_25, Level::Low),
         power: Output::new(p.PIN_14, Level::Low),
@@ -92,6 +148,7 @@ async fn main(spawner: Spawner) {
             accuracy: 250.25f32,
             battery: 0.8912f32,
             timestamp: 1670077542109i64,
+            source: None,
         }),
         park_location: Some(poro::ParkLocation {
             position: poro::Position {
@@ -102,6 +159,7 @@ async fn main(spawner: Spawner) {
         }),
         status: Some(poro::Status::CarTheftDetected),
         service: Some(poro::Service { value: true }),
+        zone: None,
     });
     info!("PORO TEST: {}", dumped.as_str());
 
@@ -128,7 +186,7 @@ async fn main(spawner: Spawner) {
     static RES_SLOT: ResponseSlot<INGRESS_BUF_SIZE> = ResponseSlot::new();
     static URC_CHANNEL: UrcChannel<urc::Urc, URC_CAPACITY, URC_SUBSCRIBERS> = UrcChannel::new();
     let ingress = Ingress::new(
-        DefaultDigester::<urc::Urc>::default(),
+        ip::IpDigester::new(DefaultDigester::<urc::Urc>::default(), on_ip_event),
         INGRESS_BUF.init([0; INGRESS_BUF_SIZE]),
         &RES_SLOT,
         &URC_CHANNEL,
@@ -154,9 +212,26 @@ async fn main(spawner: Spawner) {
     info!("Network init");
     Timer::after(Duration::from_secs(2)).await;
 
-    network::init_network(&mut client, &mut pico).await;
-    sms::init(&mut client, &mut pico).await;
-    call::init(&mut client, &mut pico).await;
+    if let Err(e) = network::init_network(&mut client, &mut pico).await {
+        info!("Network init failed: {}", e);
+    }
+    if let Err(e) = sms::init(&mut client, &mut pico).await {
+        info!("SMS init failed: {}", e);
+    }
+    if let Err(e) = ip::configure(&mut client).await {
+        info!("IP init failed: {}", e);
+    }
+    if let Err(e) = call::init(&mut client, &mut pico).await {
+        info!("Call init failed: {}", e);
+    }
+    if let Err(e) = connectivity::init(&mut client).await {
+        info!("Connectivity init failed: {}", e);
+    }
+    // the client id and the topics of the MQTT telemetry
+    let device = network::imei(&mut client).await;
+    if let Err(e) = device.as_ref() {
+        info!("IMEI read failed: {}", e);
+    }
 
     for _ in 0..30 {
         pico.set_led_high();
@@ -165,38 +240,80 @@ async fn main(spawner: Spawner) {
         Timer::after(Duration::from_millis(100)).await;
     }
 
-    match gps::get_gps_location(&mut client, &mut pico, 5).await {
-        Some(v) => info!("GPS location: {:?}", v),
-        None => (),
+    match gps::get_gps_location(
+        &mut client,
+        &mut pico,
+        5,
+        gps::StartMode::Auto,
+        false,
+        &filter::Gate::default(),
+    )
+    .await
+    {
+        Ok(v) => info!("GPS location: {:?}", v),
+        Err(e) => info!("No GPS location: {}", e),
     }
 
-    match gsm::get_gsm_location(&mut client, &mut pico, 5, "online").await {
-        Some(v) => info!("GSM location: {:?}", v),
-        None => (),
+    match gsm::get_gsm_location(
+        &mut client,
+        &mut pico,
+        &mut gsm::Bearer::default(),
+        5,
+        &config.apn,
+        &config.clbs_server,
+        gsm::LonType::WGS84,
+    )
+    .await
+    {
+        Ok(v) => info!("GSM location: {:?}", v),
+        Err(e) => info!("No GSM location: {}", e),
     }
 
-    let phone_number: String<30> = String::try_from("+36301234567").unwrap();
-
+    let owner = config.owner().cloned().unwrap_or_default();
     call::call_number(
         &mut client,
         &mut pico,
-        &phone_number,
+        &owner,
         Duration::from_secs(10).as_millis(),
     )
-    .await;
+    .await
+    .ok();
 
     let mut tata_response: String<160> = String::try_from("$tATA/").unwrap();
     let _ = tata_response.push_str(dumped.as_str());
 
+    let mut reassembler = concat::Reassembler::default();
+    let reference = reassembler.next_reference();
     sms::send_sms(
         &mut client,
         &mut pico,
-        &phone_number,
-        &astring_to_string(tata_response.as_str()),
+        &owner,
+        tata_response.as_str(),
+        reference,
     )
-    .await;
-
-    sms::receive_sms(&mut client, &mut pico).await;
+    .await
+    .ok();
+
+    let mut guard = protector::Guard::new(config.protector_config());
+    guard.geofence.set_zones(&config.zones);
+    let mut battery_monitor = battery::BatteryMonitor::new(battery::BatteryConfig::default());
+    let mut strategy = location::Strategy::default();
+    strategy.stream.source = Some(navigation_location);
+    let mut supervisor = connectivity::Supervisor::new(LINK.dyn_sender());
+    let mut link = LINK.receiver().unwrap();
+
+    dispatcher::process_stored_messages(
+        &mut client,
+        &mut pico,
+        &mut guard,
+        &mut strategy,
+        &mut config_store,
+        &mut tracklog,
+        &mut config,
+        &mut reassembler,
+    )
+    .await
+    .ok();
 
     let mut counter = 0u64;
     rtc.schedule_alarm(DateTimeFilter::default().second(30));
@@ -230,6 +347,18 @@ async fn main(spawner: Spawner) {
 
                 pico.set_led_low();
                 Timer::after(Duration::from_millis(500)).await;
+
+                // reconnects with backoff, a no-op while the link is up
+                supervisor
+                    .poll(
+                        &mut client,
+                        &mut pico,
+                        &mut strategy.clbs.bearer,
+                        &config.apn,
+                    )
+                    .await;
+                // the parts that never arrive, add only expires when another part comes
+                reassembler.expire(pico.uptime_millis());
             }
             // Alarm triggered
             Either3::Second(_) => {
@@ -240,93 +369,250 @@ async fn main(spawner: Spawner) {
                 );
                 rtc.schedule_alarm(DateTimeFilter::default().second(30));
 
-                match gps::get_gps_location(&mut client, &mut pico, 5).await {
-                    Some(v) => info!("GPS location: {:?}", v),
-                    None => (),
+                match protector::check(&mut client, &mut pico, &mut guard, &mut strategy, &config)
+                    .await
+                {
+                    Ok(transitions) => {
+                        dispatcher::send_alerts(
+                            &mut client,
+                            &mut pico,
+                            &config,
+                            &mut reassembler,
+                            &transitions,
+                        )
+                        .await
+                    }
+                    Err(e) => info!("Protector check failed: {}", e),
                 }
-
-                match send_command_logged(
-                    &mut client,
-                    &battery::AtBatteryChargeExecute,
-                    "AtBatteryChargeExecute".to_string(),
-                )
-                .await
+                if let Some(location) = guard.last_location()
+                    && let Err(e) = tracklog.append(location)
                 {
-                    Ok(v) => info!("  {:?}", v),
-                    Err(_) => (),
+                    info!("Tracklog append failed: {}", e);
                 }
-            }
-            Either3::Third(m) => match &m {
-                pubsub::WaitResult::Message(u) => match u {
-                    urc::Urc::CallReady => {
-                        info!("URC CallReady");
-                    }
-                    urc::Urc::SMSReady => {
-                        info!("URC SMSReady");
+                // the fixes of a trip come from the NMEA stream
+                if tracklog.in_trip() != strategy.stream.streaming() {
+                    let ret = if tracklog.in_trip() {
+                        // the state of the previous trip is stale
+                        NAVIGATION
+                            .lock(|navigation| *navigation.borrow_mut() = nmea::Navigation::new());
+                        strategy.start_stream(&mut client, &mut pico).await
+                    } else {
+                        strategy.stop_stream(&mut client).await
+                    };
+                    if let Err(e) = ret {
+                        info!("NMEA stream: {}", e);
                     }
-                    urc::Urc::SetBearer(_v) => {
-                        info!("URC SetBearer");
-                    }
-                    urc::Urc::GprsDisconnected(_v) => {
-                        info!("URC GprsDisconnected");
-                    }
-                    urc::Urc::Ring => {
-                        info!("URC Ring");
-                    }
-                    urc::Urc::NormalPowerDown => {
-                        info!("URC NormalPowerDown");
-                    }
-                    urc::Urc::UnderVoltagePowerDown => {
-                        info!("URC UnderVoltagePowerDown");
-                    }
-                    urc::Urc::UnderVoltageWarning => {
-                        info!("URC UnderVoltageWarning");
-                    }
-                    urc::Urc::OverVoltagePowerDown => {
-                        info!("URC OverVoltagePowerDown");
-                    }
-                    urc::Urc::OverVoltageWarning => {
-                        info!("URC OverVoltageWarning");
-                    }
-                    urc::Urc::ChargeOnlyMode => {
-                        info!("URC ChargeOnlyMode");
-                        call::call_number(
+                }
+
+                if !config.mqtt_server.is_empty()
+                    && let Ok(device) = device.as_ref()
+                {
+                    if link.try_get() != Some(connectivity::Link::Up) {
+                        info!("MQTT report paused, the link is down");
+                    } else {
+                        match telemetry::report(
                             &mut client,
                             &mut pico,
-                            &phone_number,
-                            Duration::from_secs(10).as_millis(),
+                            &mut guard,
+                            &mut strategy,
+                            &mut config_store,
+                            &mut tracklog,
+                            &mut config,
+                            device,
                         )
-                        .await;
-                    }
-                    urc::Urc::Ready => {
-                        info!("URC Ready");
+                        .await
+                        {
+                            Ok(report) => {
+                                info!("MQTT report sent, {} commands", report.commands);
+                                dispatcher::send_alerts(
+                                    &mut client,
+                                    &mut pico,
+                                    &config,
+                                    &mut reassembler,
+                                    &report.transitions,
+                                )
+                                .await;
+                            }
+                            Err(e) => info!("MQTT report failed: {}", e),
+                        }
                     }
-                    urc::Urc::ConnectOK1 => {
-                        info!("URC ConnectOK1");
+                }
+
+                if strategy.gnss.epo.is_due(pico.uptime_millis()) {
+                    let mut urcs = URC_CHANNEL.subscribe().unwrap();
+                    if let Err(e) = epo::refresh(
+                        &mut client,
+                        &mut pico,
+                        &mut urcs,
+                        &mut strategy.gnss.epo,
+                        &mut strategy.clbs.bearer,
+                        &config.apn,
+                    )
+                    .await
+                    {
+                        info!("EPO refresh failed: {}", e);
                     }
-                    urc::Urc::ConnectOK => {
-                        info!("URC ConnectOK");
+                }
+
+                let alerts = battery::check(&mut client, &mut pico, &mut battery_monitor)
+                    .await
+                    .unwrap_or_default();
+                for alert in alerts {
+                    let message = battery_monitor.message(&alert, guard.last_location());
+                    for number in config.alert_numbers() {
+                        sms::send_sms(
+                            &mut client,
+                            &mut pico,
+                            number,
+                            &message,
+                            reassembler.next_reference(),
+                        )
+                        .await
+                        .ok();
                     }
-                    urc::Urc::ClipUrc(v) => {
-                        info!("URC ClipUrc number={}, type={}", v.number.as_str(), v.type_);
-                        if v.number == phone_number {
-                            Timer::after_millis(2000).await;
-                            call::answer_incoming_call(&mut client, &mut pico).await;
-                        } else {
-                            call::hangup_incoming_call(&mut client, &mut pico).await;
+                }
+                if let Some(level) = battery_monitor.level() {
+                    guard.set_battery(level);
+                }
+
+                dispatcher::check_storage(
+                    &mut client,
+                    &mut pico,
+                    &mut guard,
+                    &mut strategy,
+                    &mut config_store,
+                    &mut tracklog,
+                    &mut config,
+                    &mut reassembler,
+                )
+                .await
+                .ok();
+            }
+            Either3::Third(m) => match &m {
+                pubsub::WaitResult::Message(u) => {
+                    if let Some(alert) = battery_monitor.handle_urc(u) {
+                        let message = battery_monitor.message(&alert, guard.last_location());
+                        for number in config.alert_numbers() {
+                            sms::send_sms(
+                                &mut client,
+                                &mut pico,
+                                number,
+                                &message,
+                                reassembler.next_reference(),
+                            )
+                            .await
+                            .ok();
                         }
                     }
-                    urc::Urc::NewMessageIndicationUrc(v) => {
-                        info!(
-                            "URC NewMessageIndicationUrc index={} mem={}",
-                            v.index,
-                            v.mem.as_str()
-                        );
-                    }
-                    urc::Urc::EnterPinReadResponse(v) => {
-                        info!("URC EnterPinReadResponse code={}", v.code);
+                    supervisor.handle_urc(u, &mut strategy.clbs.bearer, pico.uptime_millis());
+                    match u {
+                        urc::Urc::CallReady => {
+                            info!("URC CallReady");
+                        }
+                        urc::Urc::SMSReady => {
+                            info!("URC SMSReady");
+                        }
+                        urc::Urc::SetBearer(_v) => {
+                            info!("URC SetBearer");
+                        }
+                        urc::Urc::GprsDisconnected(_v) => {
+                            info!("URC GprsDisconnected");
+                        }
+                        urc::Urc::Ring => {
+                            info!("URC Ring");
+                        }
+                        urc::Urc::NormalPowerDown => {
+                            info!("URC NormalPowerDown");
+                        }
+                        urc::Urc::UnderVoltagePowerDown => {
+                            info!("URC UnderVoltagePowerDown");
+                        }
+                        urc::Urc::UnderVoltageWarning => {
+                            info!("URC UnderVoltageWarning");
+                        }
+                        urc::Urc::OverVoltagePowerDown => {
+                            info!("URC OverVoltagePowerDown");
+                        }
+                        urc::Urc::OverVoltageWarning => {
+                            info!("URC OverVoltageWarning");
+                        }
+                        urc::Urc::ChargeOnlyMode => {
+                            info!("URC ChargeOnlyMode");
+                            for number in config.alert_numbers() {
+                                call::call_number(
+                                    &mut client,
+                                    &mut pico,
+                                    number,
+                                    Duration::from_secs(10).as_millis(),
+                                )
+                                .await
+                                .ok();
+                            }
+                        }
+                        urc::Urc::Ready => {
+                            info!("URC Ready");
+                        }
+                        urc::Urc::ConnectOK1 => {
+                            info!("URC ConnectOK1");
+                        }
+                        urc::Urc::ConnectOK => {
+                            info!("URC ConnectOK");
+                        }
+                        urc::Urc::ClipUrc(v) => {
+                            info!("URC ClipUrc number={}, type={}", v.number.as_str(), v.type_);
+                            if dispatcher::accept_call(&config, &v.number) {
+                                Timer::after_millis(2000).await;
+                                call::answer_incoming_call(&mut client, &mut pico)
+                                    .await
+                                    .ok();
+                            } else {
+                                call::hangup_incoming_call(&mut client, &mut pico)
+                                    .await
+                                    .ok();
+                            }
+                        }
+                        urc::Urc::NewMessageIndicationUrc(v) => {
+                            info!(
+                                "URC NewMessageIndicationUrc index={} mem={}",
+                                v.index,
+                                v.mem.as_str()
+                            );
+                            dispatcher::process_message(
+                                &mut client,
+                                &mut pico,
+                                &mut guard,
+                                &mut strategy,
+                                &mut config_store,
+                                &mut tracklog,
+                                &mut config,
+                                &mut reassembler,
+                                v.index as u32,
+                            )
+                            .await
+                            .ok();
+                        }
+                        urc::Urc::EnterPinReadResponse(v) => {
+                            info!("URC EnterPinReadResponse code={}", v.code);
+                        }
+                        urc::Urc::HttpAction(v) => {
+                            info!("URC HttpAction status={} size={}", v.status, v.size);
+                        }
+                        urc::Urc::NetworkRegistration(v) => {
+                            info!("URC NetworkRegistration {:?}", v.stat);
+                        }
+                        urc::Urc::GnssNavigationInformation(v) => match v.information() {
+                            Ok(information) => {
+                                let uptime_millis = pico.uptime_millis();
+                                NAVIGATION.lock(|navigation| {
+                                    navigation
+                                        .borrow_mut()
+                                        .update_information(&information, uptime_millis)
+                                });
+                            }
+                            Err(e) => info!("URC GnssNavigationInformation: {}", e),
+                        },
                     }
-                },
+                }
                 pubsub::WaitResult::Lagged(b) => {
                     info!("Urc Lagged messages: {}", b);
                 }
@@ -339,7 +625,7 @@ async fn main(spawner: Spawner) {
 async fn ingress_task(
     mut ingress: Ingress<
         'static,
-        DefaultDigester<urc::Urc>,
+        ip::IpDigester<DefaultDigester<urc::Urc>>,
         urc::Urc,
         INGRESS_BUF_SIZE,
         URC_CAPACITY,
@@ -348,7 +634,9 @@ async fn ingress_task(
     mut reader: BufferedUartRx,
 ) -> ! {
     info!("INGRESS TASK SPAWNED");
-    ingress.read_from(&mut reader).await
+    ingress
+        .read_from(nmea::NmeaReader::new(&mut reader, on_nmea_sentence))
+        .await
 }
 
 #[embassy_executor::task]
@@ -370,6 +658,46 @@ fn convert_to_celsius(raw_temp: u16) -> f32 {
     (rounded_temp_x10 as f32) / 10.0
 }
 
+// The config and the tracklog live in the last sectors of the flash, outside of the program
+// (see memory.x), each in its own partition.
+struct FlashStorage<'a> {
+    flash: &'a RefCell<Flash<'static, FLASH, Blocking, FLASH_SIZE>>,
+    offset: usize,
+    size: usize,
+}
+
+impl Storage for FlashStorage<'_> {
+    fn sector_size(&self) -> usize {
+        ERASE_SIZE
+    }
+
+    fn capacity(&self) -> usize {
+        self.size
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<(), Error> {
+        self.flash
+            .borrow_mut()
+            .blocking_read((self.offset + offset) as u32, bytes)
+            .map_err(|_| Error::Storage("flash read failed"))
+    }
+
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Error> {
+        self.flash
+            .borrow_mut()
+            .blocking_write((self.offset + offset) as u32, bytes)
+            .map_err(|_| Error::Storage("flash write failed"))
+    }
+
+    fn erase_sector(&mut self, offset: usize) -> Result<(), Error> {
+        let from = (self.offset + offset) as u32;
+        self.flash
+            .borrow_mut()
+            .blocking_erase(from, from + ERASE_SIZE as u32)
+            .map_err(|_| Error::Storage("flash erase failed"))
+    }
+}
+
 struct Pico<'a> {
     led: Output<'a>,
     power: Output<'a>,
@@ -388,6 +716,10 @@ impl at::PicoHW for Pico<'_> {
         self.led.set_low();
     }
 
+    fn uptime_millis(&self) -> u64 {
+        Instant::now().as_millis()
+    }
+
     async fn restart_module(&mut self) {
         // 5.3.2.3. Restart GSM by PWRKEY
         //   1. power off the GSM (between 1.5s and 2s)
@@ -1,8 +1,19 @@
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{Data, DeriveInput, Fields, parse_macro_input};
+use syn::{Data, DeriveInput, Field, Fields, parse_macro_input};
 
-#[proc_macro_derive(MachineDumper)]
+// #[machine(skip)] keeps a field out of the machine format, e.g. one the Android
+// application does not know about. It is left at its default when parsing.
+fn skipped(field: &Field) -> bool {
+    field.attrs.iter().any(|attr| {
+        attr.path().is_ident("machine")
+            && attr
+                .parse_args::<syn::Ident>()
+                .is_ok_and(|ident| ident == "skip")
+    })
+}
+
+#[proc_macro_derive(MachineDumper, attributes(machine))]
 pub fn derive_machine_dumper(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let struct_ident = input.ident;
@@ -23,7 +34,12 @@ pub fn derive_machine_dumper(input: TokenStream) -> TokenStream {
 
     match fields {
         Fields::Named(named) => {
-            for ident in named.named.iter().map(|f| f.ident.as_ref().unwrap()) {
+            for ident in named
+                .named
+                .iter()
+                .filter(|f| !skipped(f))
+                .map(|f| f.ident.as_ref().unwrap())
+            {
                 if !dump_calls.is_empty() {
                     dump_calls.push(quote! {
                         ret.push_str(" ");
@@ -57,7 +73,7 @@ pub fn derive_machine_dumper(input: TokenStream) -> TokenStream {
     expanded.into()
 }
 
-#[proc_macro_derive(MachineParser)]
+#[proc_macro_derive(MachineParser, attributes(machine))]
 pub fn derive_machine_parser(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let struct_ident = input.ident;
@@ -78,7 +94,12 @@ pub fn derive_machine_parser(input: TokenStream) -> TokenStream {
 
     match fields {
         Fields::Named(named) => {
-            for ident in named.named.iter().map(|f| f.ident.as_ref().unwrap()) {
+            for ident in named
+                .named
+                .iter()
+                .filter(|f| !skipped(f))
+                .map(|f| f.ident.as_ref().unwrap())
+            {
                 parse_calls.push(quote! {
                     self.#ident.x_parse(tokens)?;
                 });
@@ -96,7 +117,7 @@ pub fn derive_machine_parser(input: TokenStream) -> TokenStream {
 
     let expanded = quote! {
         impl MachineParser for #struct_ident {
-            fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), &'static str> {
+            fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), Error> {
                 #(#parse_calls)*
                 Ok(())
             }
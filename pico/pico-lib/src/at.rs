@@ -9,6 +9,8 @@ pub trait PicoHW {
     fn set_led_high(&mut self);
     fn set_led_low(&mut self);
     fn restart_module(&mut self) -> impl core::future::Future<Output = ()> + Send;
+    // Monotonic, e.g. for the age of a cached location.
+    fn uptime_millis(&self) -> u64;
 }
 
 #[cfg(test)]
@@ -18,7 +20,7 @@ extern crate std;
 pub mod tests {
     use alloc::string::String as AString;
     use alloc::{collections::vec_deque::VecDeque, vec::Vec as AVec};
-    use atat::{AtatCmd, heapless::Vec};
+    use atat::{AtatCmd, asynch::AtatClient, heapless::Vec};
 
     use crate::at::PicoHW;
 
@@ -58,12 +60,212 @@ pub mod tests {
 
     impl atat::asynch::AtatClient for ClientMock<'_> {
         async fn send<Cmd: AtatCmd>(&mut self, cmd: &Cmd) -> Result<Cmd::Response, atat::Error> {
-            let mut buffer = crate::at::tests::zeros();
-            cmd.write(&mut buffer);
-            let tmp = atat::heapless::String::from_utf8(buffer).unwrap();
-            let trimmed = tmp.trim_matches(char::from(0));
-            self.sent_commands.push_back(AString::from(trimmed));
-            cmd.parse(self.results.pop_front().expect("missing result"))
+            // same size as the client buffer in the app
+            let mut buffer = [0u8; 2048];
+            let len = cmd.write(&mut buffer);
+            let text = core::str::from_utf8(&buffer[..len]).unwrap();
+            self.sent_commands.push_back(AString::from(text));
+            cmd.parse(prompt(self.results.pop_front().expect("missing result")))
+        }
+    }
+
+    // The digester hands a data prompt, e.g. the ">" of AT+CMGS, to the command as an empty
+    // response, see atat::Response::Prompt.
+    fn prompt<'a>(
+        result: Result<&'a [u8], atat::InternalError<'a>>,
+    ) -> Result<&'a [u8], atat::InternalError<'a>> {
+        match result {
+            Ok(b">") => Ok(b""),
+            r => r,
+        }
+    }
+
+    const TRANSCRIPT_URC_CAPACITY: usize = 16;
+    const TRANSCRIPT_URC_SUBSCRIBERS: usize = 2;
+
+    pub type TranscriptUrcs = atat::UrcSubscription<
+        'static,
+        crate::urc::Urc,
+        TRANSCRIPT_URC_CAPACITY,
+        TRANSCRIPT_URC_SUBSCRIBERS,
+    >;
+
+    #[derive(Debug)]
+    pub enum Step {
+        Exchange(
+            &'static str,
+            Result<&'static [u8], atat::InternalError<'static>>,
+        ),
+        Timeout(&'static str),
+        Urc(&'static [u8]), // e.g. b"+CMTI: \"SM\",1", goes through the atat digester
+        Delay(u64),         // millis before the next response
+    }
+
+    // Client with the expected exchanges in strict order, e.g.
+    //
+    //     let mut client = TranscriptMock::default()
+    //         .expect("AT+CGATT=1\r", Ok(b""))
+    //         .urc(b"+PDP: DEACT")
+    //         .timeout("AT+CIICR\r");
+    //
+    // URCs are published as soon as the previous exchange is over, the ones in front
+    // of the first exchange with `flush_urcs`. A command out of order or anything left
+    // over at drop panics with the transcript.
+    pub struct TranscriptMock {
+        steps: VecDeque<Step>,
+        done: AVec<Step>,
+        urc_channel: &'static atat::UrcChannel<
+            crate::urc::Urc,
+            TRANSCRIPT_URC_CAPACITY,
+            TRANSCRIPT_URC_SUBSCRIBERS,
+        >,
+        ingress: atat::Ingress<
+            'static,
+            atat::DefaultDigester<crate::urc::Urc>,
+            crate::urc::Urc,
+            1024,
+            TRANSCRIPT_URC_CAPACITY,
+            TRANSCRIPT_URC_SUBSCRIBERS,
+        >,
+    }
+
+    impl Default for TranscriptMock {
+        fn default() -> Self {
+            // leaked, one set per test
+            let res_slot =
+                alloc::boxed::Box::leak(alloc::boxed::Box::new(atat::ResponseSlot::<1024>::new()));
+            let urc_channel =
+                alloc::boxed::Box::leak(alloc::boxed::Box::new(atat::UrcChannel::new()));
+            let buf = alloc::boxed::Box::leak(alloc::boxed::Box::new([0u8; 1024]));
+            TranscriptMock {
+                steps: VecDeque::new(),
+                done: AVec::new(),
+                urc_channel,
+                ingress: atat::Ingress::new(
+                    atat::DefaultDigester::<crate::urc::Urc>::default(),
+                    buf,
+                    res_slot,
+                    urc_channel,
+                ),
+            }
+        }
+    }
+
+    impl TranscriptMock {
+        pub fn expect(
+            mut self,
+            command: &'static str,
+            response: Result<&'static [u8], atat::InternalError<'static>>,
+        ) -> Self {
+            self.steps.push_back(Step::Exchange(command, response));
+            self
+        }
+
+        pub fn timeout(mut self, command: &'static str) -> Self {
+            self.steps.push_back(Step::Timeout(command));
+            self
+        }
+
+        pub fn urc(mut self, urc: &'static [u8]) -> Self {
+            self.steps.push_back(Step::Urc(urc));
+            self
+        }
+
+        pub fn delay(mut self, millis: u64) -> Self {
+            self.steps.push_back(Step::Delay(millis));
+            self
+        }
+
+        pub fn subscribe(&self) -> TranscriptUrcs {
+            self.urc_channel.subscribe().unwrap()
+        }
+
+        // Publishes the URCs up to the next exchange.
+        pub fn flush_urcs(&mut self) {
+            use atat::AtatIngress;
+            while let Some(Step::Urc(urc)) = self.steps.front() {
+                let mut bytes = AVec::new();
+                bytes.extend_from_slice(b"\r\n");
+                bytes.extend_from_slice(urc);
+                bytes.extend_from_slice(b"\r\n");
+                self.ingress.try_write(&bytes).unwrap();
+                self.done.push(self.steps.pop_front().unwrap());
+            }
+        }
+
+        fn render(step: &Step) -> AString {
+            match step {
+                Step::Exchange(command, Ok(response)) => {
+                    alloc::format!("{:?} -> {:?}", command, AString::from_utf8_lossy(response))
+                }
+                Step::Exchange(command, Err(e)) => alloc::format!("{:?} -> {:?}", command, e),
+                Step::Timeout(command) => alloc::format!("{:?} -> timeout", command),
+                Step::Urc(urc) => alloc::format!("urc {:?}", AString::from_utf8_lossy(urc)),
+                Step::Delay(millis) => alloc::format!("delay {}ms", millis),
+            }
+        }
+
+        // Transcript so far, '-' expected but missing, '+' sent but not expected.
+        fn diff(&self, sent: Option<&str>) -> AString {
+            let mut ret = AString::from("transcript mismatch:\n");
+            for step in self.done.iter() {
+                ret += &alloc::format!("  {}\n", Self::render(step));
+            }
+            match sent {
+                Some(sent) => {
+                    match self.steps.front() {
+                        Some(step) => ret += &alloc::format!("- {}\n", Self::render(step)),
+                        None => ret += "- <end of transcript>\n",
+                    }
+                    ret += &alloc::format!("+ {:?}\n", sent);
+                    for step in self.steps.iter().skip(1) {
+                        ret += &alloc::format!("  {}\n", Self::render(step));
+                    }
+                }
+                None => {
+                    for step in self.steps.iter() {
+                        ret += &alloc::format!("- {}\n", Self::render(step));
+                    }
+                }
+            }
+            ret
+        }
+    }
+
+    impl atat::asynch::AtatClient for TranscriptMock {
+        async fn send<Cmd: AtatCmd>(&mut self, cmd: &Cmd) -> Result<Cmd::Response, atat::Error> {
+            let mut buffer = [0u8; 2048];
+            let len = cmd.write(&mut buffer);
+            let text = core::str::from_utf8(&buffer[..len]).unwrap();
+
+            self.flush_urcs();
+            let mut delay = 0;
+            while let Some(Step::Delay(millis)) = self.steps.front() {
+                delay += millis;
+                self.done.push(self.steps.pop_front().unwrap());
+            }
+            if delay > 0 {
+                tokio::time::sleep(core::time::Duration::from_millis(delay)).await;
+            }
+
+            let ret = match self.steps.front() {
+                Some(Step::Exchange(command, response)) if *command == text => {
+                    cmd.parse(prompt(response.clone()))
+                }
+                Some(Step::Timeout(command)) if *command == text => Err(atat::Error::Timeout),
+                _ => panic!("{}", self.diff(Some(text))),
+            };
+            self.done.push(self.steps.pop_front().unwrap());
+            self.flush_urcs();
+            ret
+        }
+    }
+
+    impl Drop for TranscriptMock {
+        fn drop(&mut self) {
+            if !self.steps.is_empty() && !super::std::thread::panicking() {
+                panic!("{}", self.diff(None));
+            }
         }
     }
 
@@ -91,5 +293,124 @@ pub mod tests {
         async fn restart_module(&mut self) {
             self.restart_module_calls += 1;
         }
+
+        // Only the sleeps take time.
+        fn uptime_millis(&self) -> u64 {
+            self.sleep_calls.iter().sum()
+        }
+    }
+
+    // PicoHW for the end-to-end tests with sim868-emu, restarting the module power
+    // cycles the emulator. Sleeping only yields, the tests don't wait for real.
+    pub struct EmuPico {
+        pub modem: sim868_emu::Modem,
+        pub sleep_calls: AVec<u64>,
+        pub led_high: bool,
+        pub restart_module_calls: u32,
+    }
+
+    impl EmuPico {
+        pub fn new(modem: sim868_emu::Modem) -> Self {
+            EmuPico {
+                modem,
+                sleep_calls: AVec::new(),
+                led_high: false,
+                restart_module_calls: 0,
+            }
+        }
+    }
+
+    impl PicoHW for EmuPico {
+        async fn sleep(&mut self, millis: u64) {
+            self.sleep_calls.push(millis);
+            tokio::task::yield_now().await;
+        }
+
+        fn set_led_high(&mut self) {
+            self.led_high = true;
+        }
+
+        fn set_led_low(&mut self) {
+            self.led_high = false;
+        }
+
+        async fn restart_module(&mut self) {
+            self.restart_module_calls += 1;
+            self.modem.with(|sim| sim.restart());
+        }
+
+        fn uptime_millis(&self) -> u64 {
+            self.sleep_calls.iter().sum()
+        }
+    }
+
+    // With the digester chain of the app, see app/src/main.rs.
+    pub type Harness = sim868_emu::Harness<
+        crate::urc::Urc,
+        crate::ip::IpDigester<atat::DefaultDigester<crate::urc::Urc>>,
+    >;
+
+    #[tokio::test]
+    async fn test_transcript_mock() {
+        let mut client = TranscriptMock::default()
+            .urc(b"RING")
+            .expect("AT\r", Ok(b""))
+            .delay(100)
+            .timeout("AT+CSQ\r")
+            .urc(b"+CMTI: \"SM\",3")
+            .expect("AT+CPIN?\r", Err(atat::InternalError::Error));
+        let mut urcs = client.subscribe();
+        assert!(urcs.try_next_message_pure().is_none());
+        client.flush_urcs();
+        assert!(matches!(
+            urcs.try_next_message_pure(),
+            Some(crate::urc::Urc::Ring)
+        ));
+
+        assert!(client.send(&crate::network::AtInit).await.is_ok());
+        let start = tokio::time::Instant::now();
+        assert_eq!(
+            Err(atat::Error::Timeout),
+            client
+                .send(&crate::network::AtSignalQualityReportExecute)
+                .await
+                .map(|_| ())
+        );
+        assert!(start.elapsed() >= core::time::Duration::from_millis(100));
+        assert!(matches!(
+            urcs.try_next_message_pure(),
+            Some(crate::urc::Urc::NewMessageIndicationUrc(_))
+        ));
+        assert_eq!(
+            Err(atat::Error::Error),
+            client
+                .send(&crate::network::AtEnterPinRead)
+                .await
+                .map(|_| ())
+        );
+    }
+
+    #[tokio::test]
+    #[should_panic(
+        expected = "transcript mismatch:\n  \"AT\\r\" -> \"\"\n- \"AT+CSQ\\r\" -> timeout\n+ \"AT+CPIN?\\r\"\n  \"AT+COPS?\\r\" -> \"\"\n"
+    )]
+    async fn test_transcript_mock_out_of_order() {
+        let mut client = TranscriptMock::default()
+            .expect("AT\r", Ok(b""))
+            .timeout("AT+CSQ\r")
+            .expect("AT+COPS?\r", Ok(b""));
+        client.send(&crate::network::AtInit).await.ok();
+        client.send(&crate::network::AtEnterPinRead).await.ok();
+    }
+
+    #[tokio::test]
+    #[should_panic(
+        expected = "transcript mismatch:\n  \"AT\\r\" -> \"\"\n- \"AT+CSQ\\r\" -> timeout\n"
+    )]
+    async fn test_transcript_mock_unmatched() {
+        let mut client = TranscriptMock::default()
+            .expect("AT\r", Ok(b""))
+            .timeout("AT+CSQ\r");
+        client.send(&crate::network::AtInit).await.ok();
     }
 }
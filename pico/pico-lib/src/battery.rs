@@ -1,7 +1,17 @@
+use alloc::string::ToString;
 use atat::atat_derive::AtatCmd;
 use atat::atat_derive::AtatEnum;
 use atat::atat_derive::AtatResp;
+use atat::heapless::String;
+use atat::heapless::Vec;
+use core::fmt::Write;
 use defmt::Format;
+use defmt::info;
+
+use crate::error::Error;
+use crate::location::Location;
+use crate::urc::Urc;
+use crate::utils::send_command_logged;
 
 // 3.2.52 AT+CBC Battery Charge
 // AT+CBC
@@ -27,6 +37,173 @@ pub enum BatteryStatus {
     ChargingFinished = 2,
 }
 
+// Battery monitor
+//
+// Samples (AT+CBC) are evaluated with hysteresis: the battery becomes low at or below
+// `low_percent`/`low_millivolts` and recovers only at or above `recover_percent` and
+// `recover_millivolts`. Every event is alerted once, e.g. the under-voltage warning URC
+// is repeated by the module until the voltage recovers: an under-voltage URC is a new
+// event once a sample is at or above `recover_millivolts`, an over-voltage one once a
+// sample is at or below `recover_high_millivolts`.
+
+#[derive(Debug, Format, Clone, PartialEq)]
+pub struct BatteryConfig {
+    pub low_percent: u8,
+    pub recover_percent: u8,
+    pub low_millivolts: u32,
+    pub recover_millivolts: u32,
+    pub recover_high_millivolts: u32,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        BatteryConfig {
+            low_percent: 20,
+            recover_percent: 30,
+            low_millivolts: 3500,
+            recover_millivolts: 3700,
+            recover_high_millivolts: 4200,
+        }
+    }
+}
+
+#[derive(Debug, Format, Clone, PartialEq)]
+pub enum Alert {
+    LowBattery,
+    PowerLost, // stopped charging, e.g. the external power is disconnected
+    UnderVoltageWarning,
+    UnderVoltagePowerDown,
+    OverVoltageWarning,
+    OverVoltagePowerDown,
+}
+
+#[derive(Debug, Default)]
+pub struct BatteryMonitor {
+    pub config: BatteryConfig,
+    last: Option<BatteryChargeResponse>,
+    low: bool,
+    charging: Option<bool>,
+    alerted_urcs: Vec<Alert, 4>,
+}
+
+impl BatteryMonitor {
+    pub fn new(config: BatteryConfig) -> Self {
+        BatteryMonitor {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn last(&self) -> Option<&BatteryChargeResponse> {
+        self.last.as_ref()
+    }
+
+    pub fn low(&self) -> bool {
+        self.low
+    }
+
+    // Charge level in [0, 1].
+    pub fn level(&self) -> Option<f32> {
+        self.last.as_ref().map(|b| b.bcl as f32 / 100.0)
+    }
+
+    pub fn update(&mut self, sample: &BatteryChargeResponse) -> Vec<Alert, 2> {
+        let mut alerts = Vec::new();
+        let charging = sample.bcs != BatteryStatus::NotCharging;
+        if self.charging == Some(true) && !charging {
+            let _ = alerts.push(Alert::PowerLost);
+        }
+        self.charging = Some(charging);
+
+        if !self.low
+            && (sample.bcl <= self.config.low_percent
+                || sample.voltage <= self.config.low_millivolts)
+        {
+            self.low = true;
+            let _ = alerts.push(Alert::LowBattery);
+        } else if self.low
+            && sample.bcl >= self.config.recover_percent
+            && sample.voltage >= self.config.recover_millivolts
+        {
+            self.low = false;
+        }
+
+        // the module stops repeating the voltage URCs once the voltage is fine again
+        if sample.voltage >= self.config.recover_millivolts {
+            self.alerted_urcs.retain(|a| {
+                !matches!(a, Alert::UnderVoltageWarning | Alert::UnderVoltagePowerDown)
+            });
+        }
+        if sample.voltage <= self.config.recover_high_millivolts {
+            self.alerted_urcs
+                .retain(|a| !matches!(a, Alert::OverVoltageWarning | Alert::OverVoltagePowerDown));
+        }
+        self.last = Some(sample.clone());
+
+        for a in alerts.iter() {
+            info!("Battery alert: {:?} {:?}", a, sample);
+        }
+        alerts
+    }
+
+    pub fn handle_urc(&mut self, urc: &Urc) -> Option<Alert> {
+        let alert = match urc {
+            Urc::UnderVoltageWarning => Alert::UnderVoltageWarning,
+            Urc::UnderVoltagePowerDown => Alert::UnderVoltagePowerDown,
+            Urc::OverVoltageWarning => Alert::OverVoltageWarning,
+            Urc::OverVoltagePowerDown => Alert::OverVoltagePowerDown,
+            _ => return None,
+        };
+        if self.alerted_urcs.contains(&alert) {
+            return None;
+        }
+        info!("Battery alert: {:?}", alert);
+        let _ = self.alerted_urcs.push(alert.clone());
+        Some(alert)
+    }
+
+    // At most 79 characters of the GSM 7-bit alphabet (e.g. "Battery low 255% 4294967295mV"
+    // and "-90.000000,-180.000000"), a single SMS.
+    pub fn message(&self, alert: &Alert, location: Option<&Location>) -> String<160> {
+        let mut text: String<160> = String::new();
+        let _ = match alert {
+            Alert::LowBattery => text.push_str("Battery low"),
+            Alert::PowerLost => text.push_str("Power lost"),
+            Alert::UnderVoltageWarning => text.push_str("Low voltage"),
+            Alert::UnderVoltagePowerDown => text.push_str("Shutdown: low voltage"),
+            Alert::OverVoltageWarning => text.push_str("High voltage"),
+            Alert::OverVoltagePowerDown => text.push_str("Shutdown: high voltage"),
+        };
+        if let Some(b) = self.last.as_ref()
+            && matches!(alert, Alert::LowBattery | Alert::PowerLost)
+        {
+            let _ = write!(text, " {}% {}mV", b.bcl, b.voltage);
+        }
+        if let Some(l) = location {
+            let _ = write!(
+                text,
+                "\nhttps://maps.google.com/?q={:.6},{:.6}",
+                l.latitude, l.longitude
+            );
+        }
+        text
+    }
+}
+
+pub async fn check<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    _pico: &mut U,
+    monitor: &mut BatteryMonitor,
+) -> Result<Vec<Alert, 2>, Error> {
+    let v = send_command_logged(
+        client,
+        &AtBatteryChargeExecute,
+        "AtBatteryChargeExecute".to_string(),
+    )
+    .await?;
+    Ok(monitor.update(&v))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::cmd_serialization_tests;
@@ -72,4 +249,222 @@ mod tests {
             cmd.parse(Ok(b"+CBC: 2,100,600\r\n")).unwrap()
         );
     }
+
+    fn sample(bcs: BatteryStatus, bcl: u8, voltage: u32) -> BatteryChargeResponse {
+        BatteryChargeResponse { bcs, bcl, voltage }
+    }
+
+    #[test]
+    fn test_low_battery_hysteresis() {
+        let mut monitor = BatteryMonitor::new(BatteryConfig::default());
+        assert_eq!(None, monitor.level());
+        assert!(
+            monitor
+                .update(&sample(BatteryStatus::NotCharging, 50, 3900))
+                .is_empty()
+        );
+        assert_eq!(Some(0.5), monitor.level());
+
+        assert_eq!(
+            &[Alert::LowBattery],
+            monitor
+                .update(&sample(BatteryStatus::NotCharging, 20, 3690))
+                .as_slice()
+        );
+        assert!(monitor.low());
+        // one alert per event, not per sample
+        assert!(
+            monitor
+                .update(&sample(BatteryStatus::NotCharging, 19, 3680))
+                .is_empty()
+        );
+        assert!(
+            monitor
+                .update(&sample(BatteryStatus::NotCharging, 25, 3710))
+                .is_empty()
+        );
+        assert!(monitor.low());
+        assert!(
+            monitor
+                .update(&sample(BatteryStatus::Charging, 30, 3700))
+                .is_empty()
+        );
+        assert!(!monitor.low());
+
+        // voltage drop alone is enough
+        assert_eq!(
+            &[Alert::LowBattery],
+            monitor
+                .update(&sample(BatteryStatus::Charging, 60, 3450))
+                .as_slice()
+        );
+    }
+
+    #[test]
+    fn test_power_lost() {
+        let mut monitor = BatteryMonitor::new(BatteryConfig::default());
+        assert!(
+            monitor
+                .update(&sample(BatteryStatus::NotCharging, 80, 4000))
+                .is_empty()
+        );
+        assert!(
+            monitor
+                .update(&sample(BatteryStatus::ChargingFinished, 100, 4200))
+                .is_empty()
+        );
+        assert_eq!(
+            &[Alert::PowerLost],
+            monitor
+                .update(&sample(BatteryStatus::NotCharging, 100, 4100))
+                .as_slice()
+        );
+        assert!(
+            monitor
+                .update(&sample(BatteryStatus::NotCharging, 90, 4000))
+                .is_empty()
+        );
+        assert!(
+            monitor
+                .update(&sample(BatteryStatus::Charging, 50, 3900))
+                .is_empty()
+        );
+        assert_eq!(
+            &[Alert::PowerLost, Alert::LowBattery],
+            monitor
+                .update(&sample(BatteryStatus::NotCharging, 15, 3600))
+                .as_slice()
+        );
+    }
+
+    #[test]
+    fn test_voltage_urcs() {
+        let mut monitor = BatteryMonitor::new(BatteryConfig::default());
+        assert_eq!(None, monitor.handle_urc(&Urc::Ring));
+        assert_eq!(
+            Some(Alert::UnderVoltageWarning),
+            monitor.handle_urc(&Urc::UnderVoltageWarning)
+        );
+        assert_eq!(None, monitor.handle_urc(&Urc::UnderVoltageWarning));
+        assert_eq!(
+            Some(Alert::UnderVoltagePowerDown),
+            monitor.handle_urc(&Urc::UnderVoltagePowerDown)
+        );
+        assert_eq!(
+            Some(Alert::OverVoltageWarning),
+            monitor.handle_urc(&Urc::OverVoltageWarning)
+        );
+
+        // still low, the warnings are not repeated
+        monitor.update(&sample(BatteryStatus::NotCharging, 10, 3400));
+        assert_eq!(None, monitor.handle_urc(&Urc::UnderVoltageWarning));
+
+        // recovered, a new warning is a new event
+        monitor.update(&sample(BatteryStatus::Charging, 40, 3800));
+        assert_eq!(
+            Some(Alert::UnderVoltageWarning),
+            monitor.handle_urc(&Urc::UnderVoltageWarning)
+        );
+    }
+
+    #[test]
+    fn test_over_voltage_urcs() {
+        let mut monitor = BatteryMonitor::new(BatteryConfig::default());
+        assert_eq!(
+            Some(Alert::OverVoltageWarning),
+            monitor.handle_urc(&Urc::OverVoltageWarning)
+        );
+        // the battery is not low, but the voltage is still high
+        for voltage in [4400, 4350, 4300] {
+            assert!(
+                monitor
+                    .update(&sample(BatteryStatus::Charging, 100, voltage))
+                    .is_empty()
+            );
+            assert_eq!(None, monitor.handle_urc(&Urc::OverVoltageWarning));
+        }
+        // an under-voltage recovery does not clear it
+        assert_eq!(
+            Some(Alert::UnderVoltageWarning),
+            monitor.handle_urc(&Urc::UnderVoltageWarning)
+        );
+        monitor.update(&sample(BatteryStatus::Charging, 100, 4250));
+        assert_eq!(
+            Some(Alert::UnderVoltageWarning),
+            monitor.handle_urc(&Urc::UnderVoltageWarning)
+        );
+        assert_eq!(None, monitor.handle_urc(&Urc::OverVoltageWarning));
+
+        monitor.update(&sample(BatteryStatus::ChargingFinished, 100, 4200));
+        assert_eq!(
+            Some(Alert::OverVoltageWarning),
+            monitor.handle_urc(&Urc::OverVoltageWarning)
+        );
+    }
+
+    #[test]
+    fn test_message() {
+        use crate::hexstr::Charset;
+
+        let mut monitor = BatteryMonitor::new(BatteryConfig::default());
+        let location = Location {
+            latitude: 46.7624859,
+            longitude: 18.6304591,
+            accuracy: 5.0,
+            unix_timestamp_millis: 1670846541123,
+            source: crate::location::Source::Gnss,
+            speed: None,
+            course: None,
+        };
+        assert_eq!(
+            "Shutdown: high voltage",
+            monitor.message(&Alert::OverVoltagePowerDown, None).as_str()
+        );
+        monitor.update(&sample(BatteryStatus::NotCharging, 15, 3600));
+        let text = monitor.message(&Alert::LowBattery, Some(&location));
+        assert_eq!(
+            "Battery low 15% 3600mV\nhttps://maps.google.com/?q=46.762486,18.630459",
+            text.as_str()
+        );
+        assert!(text.chars().count() <= 79);
+
+        // the longest one
+        monitor.update(&sample(BatteryStatus::NotCharging, u8::MAX, u32::MAX));
+        let location = Location {
+            latitude: -90.0,
+            longitude: -180.0,
+            ..location
+        };
+        let text = monitor.message(&Alert::LowBattery, Some(&location));
+        assert_eq!(
+            "Battery low 255% 4294967295mV\nhttps://maps.google.com/?q=-90.000000,-180.000000",
+            text.as_str()
+        );
+        assert_eq!(79, text.chars().count());
+        assert_eq!(Charset::Gsm7, Charset::select(&text));
+        assert!(text.chars().map(|c| Charset::Gsm7.units(c)).sum::<usize>() <= 160);
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let mut client = crate::at::tests::ClientMock::default();
+        client.results.push_back(Ok(b"+CBC: 0,18,3650\r\n"));
+        client.results.push_back(Err(atat::InternalError::Timeout));
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut monitor = BatteryMonitor::new(BatteryConfig::default());
+        assert_eq!(
+            &[Alert::LowBattery],
+            check(&mut client, &mut pico, &mut monitor)
+                .await
+                .unwrap()
+                .as_slice()
+        );
+        assert_eq!(
+            Err(Error::Timeout),
+            check(&mut client, &mut pico, &mut monitor).await
+        );
+        assert_eq!("AT+CBC\r", client.sent_commands[0]);
+        assert_eq!(Some(0.18), monitor.level());
+    }
 }
@@ -9,6 +9,7 @@ use defmt::Format;
 use defmt::info;
 
 use crate::at::NoResponse;
+use crate::error::Error;
 use crate::utils::send_command_logged;
 
 // 6.2.19 AT+CHFA Swap the Audio Channels
@@ -158,7 +159,7 @@ pub enum MicrophoneBias {
 pub async fn init<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
     client: &mut T,
     _pico: &mut U,
-) {
+) -> Result<(), Error> {
     // Note, this is NO_SAVE, and by default is enabled on my device.
     // It takes a bit of time, but having an extra Read command messes
     // up the URC handling.
@@ -169,8 +170,7 @@ pub async fn init<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
         },
         "AtCallingLineIdentificationPresentationWrite".to_string(),
     )
-    .await
-    .ok();
+    .await?;
 
     send_command_logged(
         client,
@@ -179,8 +179,7 @@ pub async fn init<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
         },
         "AtSwapAudioChannelsWrite".to_string(),
     )
-    .await
-    .ok();
+    .await?;
 
     send_command_logged(
         client,
@@ -190,8 +189,7 @@ pub async fn init<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
         },
         "AtChangeMicrophoneGainLevelWrite".to_string(),
     )
-    .await
-    .ok();
+    .await?;
 
     send_command_logged(
         client,
@@ -200,8 +198,7 @@ pub async fn init<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
         },
         "AtCloseOrOpenMicrophoneWrite".to_string(),
     )
-    .await
-    .ok();
+    .await?;
 
     send_command_logged(
         client,
@@ -210,8 +207,8 @@ pub async fn init<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
         },
         "AtCloseOrOpenMicBiasWrite".to_string(),
     )
-    .await
-    .ok();
+    .await?;
+    Ok(())
 }
 
 pub async fn call_number<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
@@ -219,7 +216,7 @@ pub async fn call_number<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
     pico: &mut U,
     number: &String<30>,
     duration_millis: u64,
-) {
+) -> Result<(), Error> {
     send_command_logged(
         client,
         &AtDialNumber {
@@ -227,43 +224,41 @@ pub async fn call_number<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
         },
         "AtDialNumber".to_string(),
     )
-    .await
-    .ok();
+    .await?;
 
     {
         info!("Sleeping {} ms", duration_millis);
         pico.sleep(duration_millis).await;
     }
 
-    send_command_logged(client, &AtHangup, "AtHangup".to_string())
-        .await
-        .ok();
+    send_command_logged(client, &AtHangup, "AtHangup".to_string()).await?;
+    Ok(())
 }
 
 pub async fn answer_incoming_call<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
     client: &mut T,
     _pico: &mut U,
-) {
+) -> Result<(), Error> {
     send_command_logged(
         client,
         &AtAnswerIncomingCall,
         "AtAnswerIncomingCall".to_string(),
     )
-    .await
-    .ok();
+    .await?;
+    Ok(())
 }
 
 pub async fn hangup_incoming_call<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
     client: &mut T,
     _pico: &mut U,
-) {
+) -> Result<(), Error> {
     send_command_logged(
         client,
         &AtHangupIncomingCall,
         "AtHangupIncomingCall".to_string(),
     )
-    .await
-    .ok();
+    .await?;
+    Ok(())
 }
 
 #[cfg(test)]
@@ -325,25 +320,37 @@ mod tests {
         ),
     }
 
-    #[test]
-    fn test_clip_response() {
-        #[derive(Clone, Debug, Format, AtatCmd)]
-        #[at_cmd("+CLIP", ClipUrc, timeout_ms = 15000)]
-        struct AtUrcHack;
+    #[tokio::test]
+    async fn test_clip_urc() {
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .urc(b"RING")
+            .urc(b"+CLIP: \"+36301234567\",145,\"\",0,\"\",0")
+            .delay(200)
+            .expect("ATA\r", Ok(b""));
+        let mut urcs = client.subscribe();
+        client.flush_urcs();
+
+        assert!(matches!(
+            urcs.try_next_message_pure(),
+            Some(crate::urc::Urc::Ring)
+        ));
+        match urcs.try_next_message_pure() {
+            Some(crate::urc::Urc::ClipUrc(v)) => assert_eq!(
+                ClipUrc {
+                    number: String::try_from("+36301234567").unwrap(),
+                    type_: ClipType::International,
+                    sub_addr: Some(String::new()),
+                    sa_type: Some(0),
+                    alpha_id: Some(String::new()),
+                    cli_validity: Some(ClipValidity::Valid),
+                },
+                v
+            ),
+            _ => panic!("missing URC"),
+        }
 
-        let cmd = AtUrcHack;
-        assert_eq!(
-            ClipUrc {
-                number: String::try_from("+36301234567").unwrap(),
-                type_: ClipType::International,
-                sub_addr: Some(String::new()),
-                sa_type: Some(0),
-                alpha_id: Some(String::new()),
-                cli_validity: Some(ClipValidity::Valid),
-            },
-            cmd.parse(Ok(b"+CLIP: \"+36301234567\",145,\"\",0,\"\",0\r\n"))
-                .unwrap()
-        );
+        let mut pico = crate::at::tests::PicoMock::default();
+        answer_incoming_call(&mut client, &mut pico).await.unwrap();
 
         // TODO:
         // +CLIP: \"+36301234567\",1,0,\"\",0
@@ -359,7 +366,7 @@ mod tests {
         client.results.push_back(Ok("".as_bytes()));
 
         let mut pico = crate::at::tests::PicoMock::default();
-        init(&mut client, &mut pico).await;
+        init(&mut client, &mut pico).await.unwrap();
         assert_eq!(5, client.sent_commands.len());
         assert_eq!("AT+CLIP=1\r", client.sent_commands.get(0).unwrap());
         assert_eq!("AT+CHFA=1\r", client.sent_commands.get(1).unwrap());
@@ -381,7 +388,8 @@ mod tests {
             &String::try_from("+36301234567").unwrap(),
             100,
         )
-        .await;
+        .await
+        .unwrap();
         assert_eq!(2, client.sent_commands.len());
         assert_eq!("ATD+36301234567,i;\r", client.sent_commands.get(0).unwrap());
         assert_eq!("AT+CHUP;\r", client.sent_commands.get(1).unwrap());
@@ -396,7 +404,7 @@ mod tests {
         client.results.push_back(Ok("".as_bytes()));
 
         let mut pico = crate::at::tests::PicoMock::default();
-        answer_incoming_call(&mut client, &mut pico).await;
+        answer_incoming_call(&mut client, &mut pico).await.unwrap();
         assert_eq!(1, client.sent_commands.len());
         assert_eq!("ATA\r", client.sent_commands.get(0).unwrap());
     }
@@ -407,8 +415,65 @@ mod tests {
         client.results.push_back(Ok("".as_bytes()));
 
         let mut pico = crate::at::tests::PicoMock::default();
-        hangup_incoming_call(&mut client, &mut pico).await;
+        hangup_incoming_call(&mut client, &mut pico).await.unwrap();
         assert_eq!(1, client.sent_commands.len());
         assert_eq!("ATH\r", client.sent_commands.get(0).unwrap());
     }
+
+    #[tokio::test]
+    async fn test_calls_emulated() {
+        let mut harness = crate::at::tests::Harness::new(sim868_emu::Sim868::default());
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut sub = harness.urc_channel.subscribe().unwrap();
+
+        harness
+            .run(async |client| init(client, &mut pico).await)
+            .await
+            .unwrap();
+        harness
+            .run(async |client| {
+                call_number(
+                    client,
+                    &mut pico,
+                    &String::try_from("+36301234567").unwrap(),
+                    10000,
+                )
+                .await
+            })
+            .await
+            .unwrap();
+        assert_eq!(alloc::vec![10000], pico.sleep_calls);
+        assert_eq!(
+            alloc::vec!["+36301234567"],
+            harness.modem.with(|sim| sim.calls.clone())
+        );
+
+        harness.modem.with(|sim| sim.incoming_call("+36301234567"));
+        let number = harness
+            .run(async |_| {
+                loop {
+                    if let crate::urc::Urc::ClipUrc(v) = sub.next_message_pure().await {
+                        return v.number;
+                    }
+                }
+            })
+            .await;
+        assert_eq!("+36301234567", number);
+        harness
+            .run(async |client| answer_incoming_call(client, &mut pico).await)
+            .await
+            .unwrap();
+        assert_eq!(
+            sim868_emu::sim868::CallState::Active("+36301234567".into()),
+            harness.modem.with(|sim| sim.call.clone())
+        );
+        harness
+            .run(async |client| hangup_incoming_call(client, &mut pico).await)
+            .await
+            .unwrap();
+        assert_eq!(
+            sim868_emu::sim868::CallState::Idle,
+            harness.modem.with(|sim| sim.call.clone())
+        );
+    }
 }
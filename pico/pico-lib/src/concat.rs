@@ -0,0 +1,329 @@
+use atat::heapless::String;
+use atat::heapless::Vec;
+use defmt::info;
+
+use crate::error::Error;
+use crate::hexstr::Charset;
+use crate::pdu;
+
+// Concatenated SMS, long messages are split into parts carrying a pdu::Concat header.
+//
+// The parts are sent in 7-bit, 153 septets each (160 without the header), or as UCS2, 67
+// characters each (70 without the header) when the text has characters outside of the GSM
+// 03.38 alphabet. On receive the parts are collected until every one of them arrived, the
+// memory is bounded: a few messages of a few parts each, the oldest one is dropped when a
+// new one does not fit.
+
+pub const MAX_PARTS: usize = 4;
+pub const MAX_MESSAGE: usize = 1024;
+pub const SLOTS: usize = 2;
+pub const PART_TIMEOUT_MILLIS: u64 = 10 * 60 * 1000;
+
+const GSM7_SINGLE_UNITS: usize = pdu::MAX_SEPTETS;
+const GSM7_PART_UNITS: usize = pdu::MAX_SEPTETS - 7; // UDHL + 5 octets Concat IE, in septets
+const UCS2_SINGLE_UNITS: usize = pdu::MAX_USER_DATA / 2;
+const UCS2_PART_UNITS: usize = (pdu::MAX_USER_DATA - 6) / 2; // UDHL + 5 octets Concat IE
+pub const MAX_UNITS: usize = MAX_PARTS * UCS2_PART_UNITS; // UTF-16 code units
+
+// Splits the text into parts, escaped 7-bit characters and surrogate pairs are kept together.
+pub fn split(text: &str, charset: Charset) -> Result<Vec<&str, MAX_PARTS>, Error> {
+    let (single, part) = match charset {
+        Charset::Gsm7 => (GSM7_SINGLE_UNITS, GSM7_PART_UNITS),
+        Charset::Ucs2 => (UCS2_SINGLE_UNITS, UCS2_PART_UNITS),
+    };
+    let mut parts = Vec::new();
+    if text.chars().map(|c| charset.units(c)).sum::<usize>() <= single {
+        parts.push(text).unwrap();
+        return Ok(parts);
+    }
+    let mut start = 0;
+    let mut units = 0;
+    for (i, c) in text.char_indices() {
+        if units + charset.units(c) > part {
+            parts
+                .push(&text[start..i])
+                .map_err(|_| Error::Capacity("message too long"))?;
+            start = i;
+            units = 0;
+        }
+        units += charset.units(c);
+    }
+    parts
+        .push(&text[start..])
+        .map_err(|_| Error::Capacity("message too long"))?;
+    Ok(parts)
+}
+
+struct Pending {
+    sender: String<30>,
+    reference: u16,
+    count: u8,
+    uptime_millis: u64, // of the first part that arrived
+    parts: Vec<(u8, String<{ pdu::MAX_TEXT }>), MAX_PARTS>,
+}
+
+pub struct Reassembler {
+    pending: Vec<Pending, SLOTS>,
+    timeout_millis: u64,
+    reference: u8, // of the last sent message
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(PART_TIMEOUT_MILLIS)
+    }
+}
+
+impl Reassembler {
+    pub fn new(timeout_millis: u64) -> Self {
+        Reassembler {
+            pending: Vec::new(),
+            timeout_millis,
+            reference: 0,
+        }
+    }
+
+    // The reference of the next sent message, a counter: the same text sent twice to the
+    // same number gets a new one. It starts over after a restart, it is not stored, the
+    // receiver has reassembled (or timed out) the parts of the earlier messages by then.
+    pub fn next_reference(&mut self) -> u8 {
+        self.reference = self.reference.wrapping_add(1);
+        self.reference
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Returns the whole message when the last missing part arrives. The uptime is the clock,
+    // the SMSC timestamps are not comparable with it (the pico has no wall clock), the
+    // incomplete messages older than the timeout are dropped.
+    pub fn add(
+        &mut self,
+        sender: &str,
+        concat: &pdu::Concat,
+        text: &str,
+        uptime_millis: u64,
+    ) -> Result<Option<String<MAX_MESSAGE>>, Error> {
+        self.expire(uptime_millis);
+        if concat.count as usize > MAX_PARTS {
+            return Err(Error::Capacity("too many parts"));
+        }
+
+        let position = self
+            .pending
+            .iter()
+            .position(|p| p.sender == sender && p.reference == concat.reference);
+        let slot = match position {
+            Some(slot) => slot,
+            None => {
+                if self.pending.is_full() {
+                    info!("SMS parts dropped, no free slot");
+                    self.pending.remove(0);
+                }
+                let _ = self.pending.push(Pending {
+                    sender: String::try_from(sender)
+                        .map_err(|_| Error::Capacity("phone number too long"))?,
+                    reference: concat.reference,
+                    count: concat.count,
+                    uptime_millis,
+                    parts: Vec::new(),
+                });
+                self.pending.len() - 1
+            }
+        };
+
+        let pending = &mut self.pending[slot];
+        if pending.count != concat.count {
+            self.pending.remove(slot);
+            return Err(Error::Protocol("part count mismatch"));
+        }
+        if !pending.parts.iter().any(|(i, _)| *i == concat.index) {
+            let text = String::try_from(text).map_err(|_| Error::Capacity("part too long"))?;
+            let _ = pending.parts.push((concat.index, text));
+        }
+        if pending.parts.len() < pending.count as usize {
+            return Ok(None);
+        }
+
+        let mut pending = self.pending.remove(slot);
+        pending.parts.sort_unstable_by_key(|(i, _)| *i);
+        let mut message = String::new();
+        for (_, text) in pending.parts.iter() {
+            message
+                .push_str(text)
+                .map_err(|_| Error::Capacity("message too long"))?;
+        }
+        Ok(Some(message))
+    }
+
+    // Drops the incomplete messages older than the timeout, returns how many. Called by add,
+    // and by the main loop for the parts that never arrive.
+    pub fn expire(&mut self, uptime_millis: u64) -> usize {
+        let before = self.pending.len();
+        let timeout = self.timeout_millis;
+        self.pending
+            .retain(|p| uptime_millis.saturating_sub(p.uptime_millis) <= timeout);
+        let dropped = before - self.pending.len();
+        if dropped > 0 {
+            info!("SMS parts timed out, {} message(s) dropped", dropped);
+        }
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: u64 = 60 * 1000;
+
+    fn concat(reference: u16, count: u8, index: u8) -> pdu::Concat {
+        pdu::Concat {
+            reference,
+            count,
+            index,
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            &["hello"],
+            split("hello", Charset::Ucs2).unwrap().as_slice()
+        );
+        assert_eq!(&[""], split("", Charset::Ucs2).unwrap().as_slice());
+
+        let text: alloc::string::String = "a".repeat(70);
+        assert_eq!(
+            &[text.as_str()],
+            split(&text, Charset::Ucs2).unwrap().as_slice()
+        );
+
+        let text: alloc::string::String = "a".repeat(71);
+        assert_eq!(
+            &[&text[..67], &text[67..]],
+            split(&text, Charset::Ucs2).unwrap().as_slice()
+        );
+
+        // 😎 is 2 UTF-16 units, it is not split
+        let text = alloc::format!("{}😎bbbbb", "á".repeat(66));
+        let parts = split(&text, Charset::Ucs2).unwrap();
+        assert_eq!(2, parts.len());
+        assert_eq!("á".repeat(66), parts[0]);
+        assert_eq!("😎bbbbb", parts[1]);
+
+        assert_eq!(4, split(&"a".repeat(4 * 67), Charset::Ucs2).unwrap().len());
+        assert_eq!(
+            Err(Error::Capacity("message too long")),
+            split(&"a".repeat(4 * 67 + 1), Charset::Ucs2).map(|p| p.len())
+        );
+    }
+
+    #[test]
+    fn test_split_gsm7() {
+        let text: alloc::string::String = "a".repeat(160);
+        assert_eq!(
+            &[text.as_str()],
+            split(&text, Charset::Gsm7).unwrap().as_slice()
+        );
+
+        // € takes 2 septets
+        let text = alloc::format!("{}€", "a".repeat(159));
+        assert_eq!(2, split(&text, Charset::Gsm7).unwrap().len());
+
+        let text: alloc::string::String = "a".repeat(161);
+        assert_eq!(
+            &[&text[..153], &text[153..]],
+            split(&text, Charset::Gsm7).unwrap().as_slice()
+        );
+
+        // the escape and the extension character are not split
+        let text = alloc::format!("{}€bbbbbbbb", "a".repeat(152));
+        let parts = split(&text, Charset::Gsm7).unwrap();
+        assert_eq!("a".repeat(152), parts[0]);
+        assert_eq!("€bbbbbbbb", parts[1]);
+
+        assert_eq!(4, split(&"a".repeat(4 * 153), Charset::Gsm7).unwrap().len());
+        assert_eq!(
+            Err(Error::Capacity("message too long")),
+            split(&"a".repeat(4 * 153 + 1), Charset::Gsm7).map(|p| p.len())
+        );
+    }
+
+    #[test]
+    fn test_next_reference() {
+        let mut r = Reassembler::default();
+        assert_eq!(1, r.next_reference());
+        assert_eq!(2, r.next_reference());
+        r.reference = u8::MAX;
+        assert_eq!(0, r.next_reference());
+    }
+
+    #[test]
+    fn test_reassemble() {
+        let mut r = Reassembler::default();
+        assert_eq!(Ok(None), r.add("+36301234567", &concat(7, 3, 2), "lo ", 0));
+        // other sender, same reference
+        assert_eq!(Ok(None), r.add("+36701234567", &concat(7, 2, 1), "x", 0));
+        assert_eq!(2, r.pending());
+        // duplicate
+        assert_eq!(Ok(None), r.add("+36301234567", &concat(7, 3, 2), "lo ", 0));
+        assert_eq!(
+            Ok(None),
+            r.add("+36301234567", &concat(7, 3, 3), "world", MINUTE)
+        );
+        assert_eq!(
+            Ok(Some(String::try_from("hello world").unwrap())),
+            r.add("+36301234567", &concat(7, 3, 1), "hel", 2 * MINUTE)
+        );
+        assert_eq!(1, r.pending());
+        assert_eq!(
+            Ok(Some(String::try_from("xy").unwrap())),
+            r.add("+36701234567", &concat(7, 2, 2), "y", 2 * MINUTE)
+        );
+        assert_eq!(0, r.pending());
+    }
+
+    #[test]
+    fn test_reassemble_timeout() {
+        let mut r = Reassembler::new(5 * MINUTE);
+        assert_eq!(Ok(None), r.add("+36301234567", &concat(1, 2, 1), "a", 0));
+        assert_eq!(0, r.expire(5 * MINUTE));
+        assert_eq!(1, r.expire(5 * MINUTE + 1));
+
+        // the late part starts a new message
+        assert_eq!(Ok(None), r.add("+36301234567", &concat(1, 2, 1), "a", 0));
+        assert_eq!(
+            Ok(None),
+            r.add("+36301234567", &concat(1, 2, 2), "b", 6 * MINUTE)
+        );
+        assert_eq!(1, r.pending());
+    }
+
+    #[test]
+    fn test_reassemble_bounded() {
+        let mut r = Reassembler::default();
+        assert_eq!(Ok(None), r.add("+36301234567", &concat(1, 2, 1), "a", 0));
+        assert_eq!(Ok(None), r.add("+36301234567", &concat(2, 2, 1), "b", 0));
+        // the oldest one is dropped
+        assert_eq!(Ok(None), r.add("+36301234567", &concat(3, 2, 1), "c", 0));
+        assert_eq!(SLOTS, r.pending());
+        assert_eq!(
+            Ok(Some(String::try_from("bB").unwrap())),
+            r.add("+36301234567", &concat(2, 2, 2), "B", 0)
+        );
+        assert_eq!(Ok(None), r.add("+36301234567", &concat(1, 2, 2), "A", 0));
+        assert_eq!(SLOTS, r.pending());
+
+        assert_eq!(
+            Err(Error::Capacity("too many parts")),
+            r.add("+36301234567", &concat(4, 5, 1), "a", 0)
+        );
+        assert_eq!(Ok(None), r.add("+36301234567", &concat(5, 2, 1), "a", 0));
+        assert_eq!(
+            Err(Error::Protocol("part count mismatch")),
+            r.add("+36301234567", &concat(5, 3, 2), "b", 0)
+        );
+    }
+}
@@ -0,0 +1,997 @@
+use atat::heapless::String;
+use atat::heapless::Vec;
+use defmt::Format;
+use defmt::info;
+
+use crate::error::Error;
+use crate::geofence;
+use crate::geofence::MAX_ZONES;
+use crate::geofence::Zone;
+use crate::phone;
+use crate::poro::ConfigChange;
+use crate::protector::ProtectorConfig;
+use crate::storage::ERASED;
+use crate::storage::Storage;
+use crate::utils::crc32;
+
+// Device configuration, persisted in flash.
+//
+// Every save appends a record to the next slot of the storage (wear-levelling), the record
+// with the highest sequence number wins on load. The sector ahead is erased when the
+// writer reaches it, so at least two sectors are needed to always keep the latest record.
+//
+// Slot: | magic u32 | seq u32 | len u16 | payload (len bytes) | crc32 u32 (seq..payload) |
+// Payload: | version u16 | fields of that version |, integers are little endian,
+//          strings are | len u8 | utf-8 bytes |, lists are | count u8 | items |.
+//
+// Version 1: owner, password, apn, clbs_server, park_radius_meters
+// Version 2: contacts (number, role u8), password, apn, clbs_server, park_radius_meters
+// Version 3: version 2, zones (name, shape u8, 0: lat i32, lon i32, radius u32,
+//            1: points (lat i32, lon i32)), coordinates in 1e-6 degrees
+// Version 4: version 3, mqtt_server
+// Version 5: version 4, country_code, trunk_prefix
+
+pub const CONFIG_VERSION: u16 = 5;
+pub const MAX_CONTACTS: usize = 5;
+pub const SLOT_SIZE: usize = 256;
+const MAGIC: u32 = 0x74415441; // tATA
+const HEADER_SIZE: usize = 10;
+const CRC_SIZE: usize = 4;
+const MAX_PAYLOAD: usize = SLOT_SIZE - HEADER_SIZE - CRC_SIZE;
+const PARK_RADIUS_METERS: core::ops::RangeInclusive<u32> = 10..=10_000;
+// The home network, Hungary: +36 30 123 4567 is 06 30 123 4567 in the national format.
+const COUNTRY_CODE: &str = "36";
+const TRUNK_PREFIX: &str = "06";
+
+#[derive(Debug, Format, Clone, Copy, PartialEq)]
+pub enum Role {
+    Owner = 0,     // everything: commands, configuration, calls, alerts
+    Viewer = 1,    // location requests only
+    Emergency = 2, // receives the alerts only
+}
+
+impl Role {
+    fn from_u8(v: u8) -> Result<Role, Error> {
+        match v {
+            0 => Ok(Role::Owner),
+            1 => Ok(Role::Viewer),
+            2 => Ok(Role::Emergency),
+            _ => Err(Error::Storage("invalid role")),
+        }
+    }
+}
+
+#[derive(Debug, Format, Clone, PartialEq)]
+pub struct Contact {
+    pub number: String<30>,
+    pub role: Role,
+}
+
+#[derive(Debug, Format, Clone, PartialEq)]
+pub struct Config {
+    pub contacts: Vec<Contact, MAX_CONTACTS>,
+    pub password: String<20>, // 4..20 characters without '/', same as the Android app
+    pub apn: String<50>,
+    pub clbs_server: String<50>,
+    pub park_radius_meters: u32,
+    pub zones: Vec<Zone, MAX_ZONES>,
+    pub mqtt_server: String<21>, // ip:port of the broker, empty for none (see telemetry)
+    pub country_code: String<3>, // of the home network, see phone::Home
+    pub trunk_prefix: String<2>, // of the national numbers, empty for none
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            contacts: Vec::from_slice(&[Contact {
+                number: String::try_from("+36301234567").unwrap(),
+                role: Role::Owner,
+            }])
+            .unwrap(),
+            password: String::try_from("12345").unwrap(),
+            apn: String::try_from("online").unwrap(),
+            clbs_server: String::try_from("lbs-simcom.com:3002").unwrap(),
+            park_radius_meters: 100,
+            zones: Vec::new(),
+            mqtt_server: String::new(),
+            country_code: String::try_from(COUNTRY_CODE).unwrap(),
+            trunk_prefix: String::try_from(TRUNK_PREFIX).unwrap(),
+        }
+    }
+}
+
+impl Config {
+    pub fn protector_config(&self) -> ProtectorConfig {
+        ProtectorConfig {
+            park_radius_meters: self.park_radius_meters as f64,
+            ..Default::default()
+        }
+    }
+
+    pub fn role(&self, number: &str) -> Option<Role> {
+        self.contacts
+            .iter()
+            .find(|c| phone::compare(&c.number, number, self.home()))
+            .map(|c| c.role)
+    }
+
+    pub fn home(&self) -> phone::Home<'_> {
+        phone::Home {
+            country_code: &self.country_code,
+            trunk_prefix: &self.trunk_prefix,
+        }
+    }
+
+    // The first owner, e.g. for the calls initiated by the device.
+    pub fn owner(&self) -> Option<&String<30>> {
+        self.numbers(Role::Owner).next()
+    }
+
+    pub fn numbers(&self, role: Role) -> impl Iterator<Item = &String<30>> {
+        self.contacts
+            .iter()
+            .filter(move |c| c.role == role)
+            .map(|c| &c.number)
+    }
+
+    // Owners and emergency contacts.
+    pub fn alert_numbers(&self) -> impl Iterator<Item = &String<30>> {
+        self.contacts
+            .iter()
+            .filter(|c| c.role != Role::Viewer)
+            .map(|c| &c.number)
+    }
+
+    // Returns the config with the change applied, the current one is kept on error.
+    // A listed role replaces every contact of that role. The result has to fit in a slot of
+    // the ConfigStore, e.g. 5 contacts, 4 zones and the longest strings together do not.
+    pub fn apply(&self, change: &ConfigChange) -> Result<Config, Error> {
+        let mut config = self.clone();
+        for (role, numbers) in [
+            (Role::Owner, change.owners.as_ref()),
+            (Role::Viewer, change.viewers.as_ref()),
+            (Role::Emergency, change.emergency.as_ref()),
+        ] {
+            if let Some(numbers) = numbers {
+                config.set_contacts(role, numbers)?;
+            }
+        }
+        if config.owner().is_none() {
+            return Err(Error::Rejected("no owner"));
+        }
+        if let Some(password) = change.password.as_ref() {
+            config.password = validate_password(password)?;
+        }
+        if let Some(apn) = change.apn.as_ref() {
+            config.apn = validate_at_string(apn).ok_or(Error::Parse("invalid apn"))?;
+        }
+        if let Some(server) = change.clbs_server.as_ref() {
+            config.clbs_server = validate_server(server)?;
+        }
+        if let Some(server) = change.mqtt_server.as_ref() {
+            config.mqtt_server = validate_broker(server)?;
+        }
+        if let Some(code) = change.country_code.as_ref() {
+            config.country_code = validate_digits(code, 1..=3, "invalid country")?;
+            if config.country_code.starts_with('0') {
+                return Err(Error::Parse("invalid country"));
+            }
+        }
+        if let Some(prefix) = change.trunk_prefix.as_ref() {
+            config.trunk_prefix = validate_digits(prefix, 0..=2, "invalid trunk")?;
+        }
+        if let Some(radius) = change.park_radius_meters {
+            if !PARK_RADIUS_METERS.contains(&radius) {
+                return Err(Error::Parse("invalid park_radius"));
+            }
+            config.park_radius_meters = radius;
+        }
+        for (name, shape) in change.zones.iter() {
+            config.zones.retain(|z| z.name != name.as_str());
+            if !shape.is_empty() {
+                let zone = Zone::new(name, geofence::Shape::parse(shape)?)?;
+                config
+                    .zones
+                    .push(zone)
+                    .map_err(|_| Error::Capacity("too many zones"))?;
+            }
+        }
+        config
+            .serialize(&mut [0u8; MAX_PAYLOAD])
+            .map_err(|_| Error::Capacity("config too large"))?;
+        Ok(config)
+    }
+
+    fn set_contacts(&mut self, role: Role, numbers: &[alloc::string::String]) -> Result<(), Error> {
+        self.contacts.retain(|c| c.role != role);
+        for n in numbers {
+            let contact = Contact {
+                number: phone::normalize(n)?,
+                role,
+            };
+            self.contacts
+                .push(contact)
+                .map_err(|_| Error::Capacity("too many contacts"))?;
+        }
+        Ok(())
+    }
+
+    pub fn serialize(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut w = Writer { buf, pos: 0 };
+        w.put_u16(CONFIG_VERSION)?;
+        w.put_u8(self.contacts.len() as u8)?;
+        for c in self.contacts.iter() {
+            w.put_str(&c.number)?;
+            w.put_u8(c.role as u8)?;
+        }
+        w.put_str(&self.password)?;
+        w.put_str(&self.apn)?;
+        w.put_str(&self.clbs_server)?;
+        w.put_u32(self.park_radius_meters)?;
+        w.put_u8(self.zones.len() as u8)?;
+        for z in self.zones.iter() {
+            w.put_str(&z.name)?;
+            match &z.shape {
+                geofence::Shape::Circle {
+                    center,
+                    radius_meters,
+                } => {
+                    w.put_u8(0)?;
+                    w.put_point(center)?;
+                    w.put_u32(*radius_meters)?;
+                }
+                geofence::Shape::Polygon(points) => {
+                    w.put_u8(1)?;
+                    w.put_u8(points.len() as u8)?;
+                    for p in points.iter() {
+                        w.put_point(p)?;
+                    }
+                }
+            }
+        }
+        w.put_str(&self.mqtt_server)?;
+        w.put_str(&self.country_code)?;
+        w.put_str(&self.trunk_prefix)?;
+        Ok(w.pos)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Config, Error> {
+        let mut r = Reader { bytes, pos: 0 };
+        match r.get_u16()? {
+            1 => Ok(Config {
+                contacts: Vec::from_slice(&[Contact {
+                    number: r.get_str()?,
+                    role: Role::Owner,
+                }])
+                .unwrap(),
+                password: r.get_str()?,
+                apn: r.get_str()?,
+                clbs_server: r.get_str()?,
+                park_radius_meters: r.get_u32()?,
+                zones: Vec::new(),
+                mqtt_server: String::new(),
+                country_code: String::try_from(COUNTRY_CODE).unwrap(),
+                trunk_prefix: String::try_from(TRUNK_PREFIX).unwrap(),
+            }),
+            version @ (2..=5) => Ok(Config {
+                contacts: {
+                    let mut contacts = Vec::new();
+                    for _ in 0..r.get_u8()? {
+                        let contact = Contact {
+                            number: r.get_str()?,
+                            role: Role::from_u8(r.get_u8()?)?,
+                        };
+                        contacts
+                            .push(contact)
+                            .map_err(|_| Error::Capacity("too many contacts"))?;
+                    }
+                    contacts
+                },
+                password: r.get_str()?,
+                apn: r.get_str()?,
+                clbs_server: r.get_str()?,
+                park_radius_meters: r.get_u32()?,
+                zones: match version {
+                    2 => Vec::new(),
+                    _ => {
+                        let mut zones = Vec::new();
+                        for _ in 0..r.get_u8()? {
+                            zones
+                                .push(r.get_zone()?)
+                                .map_err(|_| Error::Capacity("too many zones"))?;
+                        }
+                        zones
+                    }
+                },
+                mqtt_server: match version {
+                    2 | 3 => String::new(),
+                    _ => r.get_str()?,
+                },
+                country_code: match version {
+                    2..=4 => String::try_from(COUNTRY_CODE).unwrap(),
+                    _ => r.get_str()?,
+                },
+                trunk_prefix: match version {
+                    2..=4 => String::try_from(TRUNK_PREFIX).unwrap(),
+                    _ => r.get_str()?,
+                },
+            }),
+            _ => Err(Error::Storage("unsupported config version")),
+        }
+    }
+}
+
+fn validate_password(v: &str) -> Result<String<20>, Error> {
+    if v.len() < 4 || v.contains('/') {
+        return Err(Error::Parse("invalid password"));
+    }
+    String::try_from(v).map_err(|_| Error::Parse("invalid password"))
+}
+
+fn validate_server(v: &str) -> Result<String<50>, Error> {
+    match v.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+            validate_at_string(v).ok_or(Error::Parse("invalid clbs"))
+        }
+        _ => Err(Error::Parse("invalid clbs")),
+    }
+}
+
+// An IPv4 address, the stack does not resolve names (see ip::Stack).
+fn validate_broker(v: &str) -> Result<String<21>, Error> {
+    if v.is_empty() {
+        return Ok(String::new());
+    }
+    v.parse::<core::net::SocketAddrV4>()
+        .map_err(|_| Error::Parse("invalid mqtt"))?;
+    String::try_from(v).map_err(|_| Error::Parse("invalid mqtt"))
+}
+
+fn validate_digits<const N: usize>(
+    v: &str,
+    len: core::ops::RangeInclusive<usize>,
+    error: &'static str,
+) -> Result<String<N>, Error> {
+    if !len.contains(&v.len()) || !v.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::Parse(error));
+    }
+    String::try_from(v).map_err(|_| Error::Parse(error))
+}
+
+// Sent in a quoted AT command parameter.
+fn validate_at_string<const N: usize>(v: &str) -> Option<String<N>> {
+    if v.is_empty() || !v.bytes().all(|b| b.is_ascii_graphic() && b != b'"') {
+        return None;
+    }
+    String::try_from(v).ok()
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if self.pos + bytes.len() > self.buf.len() {
+            return Err(Error::Capacity("config buffer too small"));
+        }
+        self.buf[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+        Ok(())
+    }
+
+    fn put_u8(&mut self, v: u8) -> Result<(), Error> {
+        self.put(&[v])
+    }
+
+    fn put_u16(&mut self, v: u16) -> Result<(), Error> {
+        self.put(&v.to_le_bytes())
+    }
+
+    fn put_u32(&mut self, v: u32) -> Result<(), Error> {
+        self.put(&v.to_le_bytes())
+    }
+
+    fn put_point(&mut self, v: &geofence::Point) -> Result<(), Error> {
+        self.put(&v.latitude_e6.to_le_bytes())?;
+        self.put(&v.longitude_e6.to_le_bytes())
+    }
+
+    fn put_str(&mut self, v: &str) -> Result<(), Error> {
+        let len = u8::try_from(v.len()).map_err(|_| Error::Capacity("config string too long"))?;
+        self.put_u8(len)?;
+        self.put(v.as_bytes())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn get(&mut self, len: usize) -> Result<&[u8], Error> {
+        if self.pos + len > self.bytes.len() {
+            return Err(Error::Storage("config truncated"));
+        }
+        let v = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(v)
+    }
+
+    fn get_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.get(1)?[0])
+    }
+
+    fn get_u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.get(2)?.try_into().unwrap()))
+    }
+
+    fn get_u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.get(4)?.try_into().unwrap()))
+    }
+
+    fn get_point(&mut self) -> Result<geofence::Point, Error> {
+        Ok(geofence::Point {
+            latitude_e6: self.get_u32()? as i32,
+            longitude_e6: self.get_u32()? as i32,
+        })
+    }
+
+    fn get_zone(&mut self) -> Result<Zone, Error> {
+        let name = self.get_str()?;
+        let shape = match self.get_u8()? {
+            0 => geofence::Shape::Circle {
+                center: self.get_point()?,
+                radius_meters: self.get_u32()?,
+            },
+            1 => {
+                let mut points = Vec::new();
+                for _ in 0..self.get_u8()? {
+                    points
+                        .push(self.get_point()?)
+                        .map_err(|_| Error::Capacity("too many zone vertices"))?;
+                }
+                geofence::Shape::Polygon(points)
+            }
+            _ => return Err(Error::Storage("invalid zone shape")),
+        };
+        Ok(Zone { name, shape })
+    }
+
+    fn get_str<const N: usize>(&mut self) -> Result<String<N>, Error> {
+        let len = self.get_u8()? as usize;
+        let s = core::str::from_utf8(self.get(len)?)
+            .map_err(|_| Error::Storage("config string not utf-8"))?;
+        String::try_from(s).map_err(|_| Error::Capacity("config string too long"))
+    }
+}
+
+pub struct ConfigStore<S: Storage> {
+    storage: S,
+    latest: Option<(usize, u32)>, // slot, sequence number
+}
+
+impl<S: Storage> ConfigStore<S> {
+    pub fn new(storage: S) -> Self {
+        assert!(storage.capacity() >= 2 * storage.sector_size());
+        assert!(storage.sector_size().is_multiple_of(SLOT_SIZE));
+        ConfigStore {
+            storage,
+            latest: None,
+        }
+    }
+
+    pub fn storage(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    fn slots(&self) -> usize {
+        self.storage.capacity() / SLOT_SIZE
+    }
+
+    fn slots_per_sector(&self) -> usize {
+        self.storage.sector_size() / SLOT_SIZE
+    }
+
+    // Falls back to the defaults when there is no valid record.
+    pub fn load(&mut self) -> Config {
+        self.latest = None;
+        let mut config = None;
+        for slot in 0..self.slots() {
+            if let Ok((seq, c)) = self.read_slot(slot)
+                && self.latest.is_none_or(|(_, s)| seq > s)
+            {
+                self.latest = Some((slot, seq));
+                config = Some(c);
+            }
+        }
+
+        match config {
+            Some(c) => c,
+            None => {
+                info!("No valid config found, using defaults");
+                Config::default()
+            }
+        }
+    }
+
+    pub fn save(&mut self, config: &Config) -> Result<(), Error> {
+        let mut record = [ERASED; SLOT_SIZE];
+        let len = config.serialize(&mut record[HEADER_SIZE..HEADER_SIZE + MAX_PAYLOAD])?;
+        let (mut slot, seq) = match self.latest {
+            Some((slot, seq)) => ((slot + 1) % self.slots(), seq.wrapping_add(1)),
+            None => (0, 1),
+        };
+
+        if slot % self.slots_per_sector() != 0 && !self.is_blank(slot)? {
+            // e.g. interrupted write, continue in the next sector
+            slot = (slot / self.slots_per_sector() + 1) * self.slots_per_sector() % self.slots();
+        }
+        if slot % self.slots_per_sector() == 0 {
+            self.storage.erase_sector(slot * SLOT_SIZE)?;
+        }
+
+        record[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        record[4..8].copy_from_slice(&seq.to_le_bytes());
+        record[8..10].copy_from_slice(&(len as u16).to_le_bytes());
+        let crc = crc32(&record[4..HEADER_SIZE + len]);
+        record[HEADER_SIZE + len..HEADER_SIZE + len + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+
+        self.storage
+            .write(slot * SLOT_SIZE, &record[..HEADER_SIZE + len + CRC_SIZE])?;
+
+        match self.read_slot(slot) {
+            Ok((s, ref c)) if s == seq && c == config => {
+                self.latest = Some((slot, seq));
+                Ok(())
+            }
+            _ => Err(Error::Storage("config verification failed")),
+        }
+    }
+
+    fn is_blank(&mut self, slot: usize) -> Result<bool, Error> {
+        let mut magic = [0u8; 4];
+        self.storage.read(slot * SLOT_SIZE, &mut magic)?;
+        Ok(magic == [ERASED; 4])
+    }
+
+    fn read_slot(&mut self, slot: usize) -> Result<(u32, Config), Error> {
+        let mut record = [0u8; SLOT_SIZE];
+        self.storage.read(slot * SLOT_SIZE, &mut record)?;
+        if u32::from_le_bytes(record[0..4].try_into().unwrap()) != MAGIC {
+            return Err(Error::Storage("no config record"));
+        }
+        let seq = u32::from_le_bytes(record[4..8].try_into().unwrap());
+        let len = u16::from_le_bytes(record[8..10].try_into().unwrap()) as usize;
+        if len > MAX_PAYLOAD {
+            return Err(Error::Storage("invalid config length"));
+        }
+        let crc = u32::from_le_bytes(
+            record[HEADER_SIZE + len..HEADER_SIZE + len + CRC_SIZE]
+                .try_into()
+                .unwrap(),
+        );
+        if crc != crc32(&record[4..HEADER_SIZE + len]) {
+            return Err(Error::Storage("config crc mismatch"));
+        }
+        Ok((
+            seq,
+            Config::deserialize(&record[HEADER_SIZE..HEADER_SIZE + len])?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::MemoryStorage;
+
+    fn config(apn: &str) -> Config {
+        Config {
+            apn: String::try_from(apn).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut buf = [0u8; 128];
+        let len = Config::default().serialize(&mut buf).unwrap();
+        assert_eq!(
+            b"\x05\x00\x01\x0c+36301234567\x00\x0512345\x06online\x13lbs-simcom.com:3002\x64\x00\x00\x00\x00\x00\x0236\x0206",
+            &buf[..len]
+        );
+        assert_eq!(Ok(Config::default()), Config::deserialize(&buf[..len]));
+
+        assert_eq!(
+            Err(Error::Capacity("config buffer too small")),
+            Config::default().serialize(&mut buf[..10])
+        );
+        assert_eq!(
+            Err(Error::Storage("config truncated")),
+            Config::deserialize(&buf[..len - 1])
+        );
+        assert_eq!(
+            Err(Error::Storage("unsupported config version")),
+            Config::deserialize(b"\x06\x00")
+        );
+        assert_eq!(
+            Err(Error::Storage("invalid role")),
+            Config::deserialize(b"\x02\x00\x01\x0c+36301234567\x03")
+        );
+    }
+
+    #[test]
+    fn test_deserialize_version_1() {
+        assert_eq!(
+            Ok(Config::default()),
+            Config::deserialize(
+                b"\x01\x00\x0c+36301234567\x0512345\x06online\x13lbs-simcom.com:3002\x64\x00\x00\x00"
+            )
+        );
+    }
+
+    #[test]
+    fn test_deserialize_version_2() {
+        assert_eq!(
+            Ok(Config::default()),
+            Config::deserialize(
+                b"\x02\x00\x01\x0c+36301234567\x00\x0512345\x06online\x13lbs-simcom.com:3002\x64\x00\x00\x00"
+            )
+        );
+    }
+
+    #[test]
+    fn test_deserialize_version_3() {
+        assert_eq!(
+            Ok(Config::default()),
+            Config::deserialize(
+                b"\x03\x00\x01\x0c+36301234567\x00\x0512345\x06online\x13lbs-simcom.com:3002\x64\x00\x00\x00\x00"
+            )
+        );
+    }
+
+    #[test]
+    fn test_deserialize_version_4() {
+        assert_eq!(
+            Ok(Config::default()),
+            Config::deserialize(
+                b"\x04\x00\x01\x0c+36301234567\x00\x0512345\x06online\x13lbs-simcom.com:3002\x64\x00\x00\x00\x00\x00"
+            )
+        );
+    }
+
+    #[test]
+    fn test_zones() {
+        let s = |v: &str| alloc::string::String::from(v);
+        let change = ConfigChange {
+            zones: alloc::vec![
+                (s("home"), s("46.762486,18.630459,200")),
+                (
+                    s("town"),
+                    s("46.757,18.62;46.767,18.62;46.767,18.64;46.757,18.64")
+                ),
+            ],
+            ..Default::default()
+        };
+        let config = Config::default().apply(&change).unwrap();
+        assert_eq!(2, config.zones.len());
+        assert_eq!("town", config.zones[1].name.as_str());
+
+        let mut buf = [0u8; 256];
+        let len = config.serialize(&mut buf).unwrap();
+        assert_eq!(
+            b"\x02\x04home\x00\xf6\x89\xc9\x02\x3b\x47\x1c\x01\xc8\x00\x00\x00\x04town\x01\x04",
+            &buf[54..80]
+        );
+        assert_eq!(Ok(config.clone()), Config::deserialize(&buf[..len]));
+
+        // replaced, removed
+        let change = ConfigChange {
+            zones: alloc::vec![
+                (s("home"), s("46.762486,18.630459,300")),
+                (s("town"), s(""))
+            ],
+            ..Default::default()
+        };
+        let config = config.apply(&change).unwrap();
+        assert_eq!(1, config.zones.len());
+        assert_eq!("46.762486,18.630459,300", config.zones[0].shape.dump());
+
+        let change = ConfigChange {
+            zones: (0..5)
+                .map(|i| (alloc::format!("z{}", i), s("46.7,18.6,100")))
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            Err(Error::Capacity("too many zones")),
+            Config::default().apply(&change)
+        );
+        let change = ConfigChange {
+            zones: alloc::vec![(s("home"), s("46.7,18.6"))],
+            ..Default::default()
+        };
+        assert_eq!(
+            Err(Error::Parse("invalid zone circle")),
+            Config::default().apply(&change)
+        );
+    }
+
+    #[test]
+    fn test_roles() {
+        let s = |v: &str| alloc::string::String::from(v);
+        let change = ConfigChange {
+            owners: Some(alloc::vec![s("+36301234567"), s("+36 20 111 2222")]),
+            viewers: Some(alloc::vec![s("06703334444")]),
+            emergency: Some(alloc::vec![s("+36705556666")]),
+            ..Default::default()
+        };
+        let config = Config::default().apply(&change).unwrap();
+        assert_eq!(Some(Role::Owner), config.role("+36301234567"));
+        assert_eq!(Some(Role::Owner), config.role("06201112222"));
+        assert_eq!(Some(Role::Viewer), config.role("+36703334444"));
+        assert_eq!(Some(Role::Emergency), config.role("36705556666"));
+        assert_eq!(None, config.role("+36301234568"));
+
+        assert_eq!("+36301234567", config.owner().unwrap().as_str());
+        assert_eq!(
+            alloc::vec!["+36301234567", "+36201112222", "+36705556666"],
+            config
+                .alert_numbers()
+                .map(|n| n.as_str())
+                .collect::<alloc::vec::Vec<_>>()
+        );
+
+        // other roles are kept
+        let change = ConfigChange {
+            viewers: Some(alloc::vec![]),
+            ..Default::default()
+        };
+        let config = config.apply(&change).unwrap();
+        assert_eq!(None, config.role("+36703334444"));
+        assert_eq!(3, config.contacts.len());
+
+        let mut buf = [0u8; 256];
+        let len = config.serialize(&mut buf).unwrap();
+        assert_eq!(Ok(config), Config::deserialize(&buf[..len]));
+    }
+
+    #[test]
+    fn test_apply() {
+        let change = ConfigChange {
+            owners: Some(alloc::vec![alloc::string::String::from("+36301112222")]),
+            apn: Some(alloc::string::String::from("internet")),
+            park_radius_meters: Some(150),
+            ..Default::default()
+        };
+        let config = Config::default().apply(&change).unwrap();
+        assert_eq!("+36301112222", config.owner().unwrap().as_str());
+        assert_eq!(1, config.contacts.len());
+        assert_eq!("internet", config.apn.as_str());
+        assert_eq!(150, config.park_radius_meters);
+        assert_eq!("12345", config.password.as_str());
+        assert_eq!(150.0, config.protector_config().park_radius_meters);
+    }
+
+    #[test]
+    fn test_mqtt_server() {
+        let change = |v: &str| ConfigChange {
+            mqtt_server: Some(alloc::string::String::from(v)),
+            ..Default::default()
+        };
+        let config = Config::default().apply(&change("10.0.0.2:1883")).unwrap();
+        assert_eq!("10.0.0.2:1883", config.mqtt_server.as_str());
+
+        let mut buf = [0u8; 128];
+        let len = config.serialize(&mut buf).unwrap();
+        assert_eq!(b"\x0d10.0.0.2:1883\x0236\x0206", &buf[len - 20..len]);
+        assert_eq!(Ok(config.clone()), Config::deserialize(&buf[..len]));
+
+        // names are not resolved, empty turns it off
+        assert_eq!(
+            Err(Error::Parse("invalid mqtt")),
+            config.apply(&change("broker.example.com:1883"))
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid mqtt")),
+            config.apply(&change("10.0.0.2"))
+        );
+        assert_eq!(Ok(Config::default()), config.apply(&change("")));
+    }
+
+    #[test]
+    fn test_home() {
+        let change = |country: &str, trunk: &str| ConfigChange {
+            country_code: Some(alloc::string::String::from(country)),
+            trunk_prefix: Some(alloc::string::String::from(trunk)),
+            ..Default::default()
+        };
+        assert_eq!(Some(Role::Owner), Config::default().role("06301234567"));
+        let config = Config::default().apply(&change("49", "0")).unwrap();
+        assert_eq!(None, config.role("06301234567"));
+        assert_eq!(Some(Role::Owner), config.role("+36301234567"));
+        assert_eq!(Some(Role::Owner), config.role("0036301234567"));
+
+        let mut buf = [0u8; 128];
+        let len = config.serialize(&mut buf).unwrap();
+        assert_eq!(b"\x0249\x010", &buf[len - 5..len]);
+        assert_eq!(Ok(config.clone()), Config::deserialize(&buf[..len]));
+
+        let config = config.apply(&change("1", "")).unwrap();
+        assert_eq!("", config.trunk_prefix.as_str());
+        assert_eq!(Ok(Config::default()), config.apply(&change("36", "06")));
+
+        for (country, trunk) in [
+            ("", "0"),
+            ("0", "0"),
+            ("3a", "0"),
+            ("1234", "0"),
+            ("36", "060"),
+        ] {
+            let error = match trunk {
+                "060" => "invalid trunk",
+                _ => "invalid country",
+            };
+            assert_eq!(
+                Err(Error::Parse(error)),
+                Config::default().apply(&change(country, trunk))
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_invalid() {
+        let apply = |change: ConfigChange| Config::default().apply(&change);
+        let s = |v: &str| Some(alloc::string::String::from(v));
+        let numbers =
+            |v: &[&str]| Some(v.iter().map(|n| alloc::string::String::from(*n)).collect());
+        assert_eq!(
+            Err(Error::Parse("invalid phone number")),
+            apply(ConfigChange {
+                owners: numbers(&["+3630abc4567"]),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            Err(Error::Rejected("no owner")),
+            apply(ConfigChange {
+                owners: numbers(&[]),
+                viewers: numbers(&["+36301234567"]),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            Err(Error::Capacity("too many contacts")),
+            apply(ConfigChange {
+                viewers: numbers(&[
+                    "06301111111",
+                    "06302222222",
+                    "06303333333",
+                    "06304444444",
+                    "06305555555"
+                ]),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid password")),
+            apply(ConfigChange {
+                password: s("123"),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid password")),
+            apply(ConfigChange {
+                password: s("123456789012345678901"),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid apn")),
+            apply(ConfigChange {
+                apn: s("inter\"net"),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid clbs")),
+            apply(ConfigChange {
+                clbs_server: s("lbs-simcom.com"),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid park_radius")),
+            apply(ConfigChange {
+                apn: s("internet"),
+                park_radius_meters: Some(5),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_load_defaults() {
+        let mut store = ConfigStore::new(MemoryStorage::new(1024, 2));
+        assert_eq!(Config::default(), store.load());
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut store = ConfigStore::new(MemoryStorage::new(1024, 2));
+        store.save(&config("internet")).unwrap();
+        assert_eq!(config("internet"), store.load());
+
+        store.save(&config("net")).unwrap();
+        let storage = store.storage;
+        let mut store = ConfigStore::new(storage);
+        assert_eq!(config("net"), store.load());
+    }
+
+    #[test]
+    fn test_save_load_largest() {
+        let s = alloc::string::String::from;
+        let mut change = ConfigChange {
+            password: Some("p".repeat(20)),
+            apn: Some("a".repeat(50)),
+            clbs_server: Some(alloc::format!("{}:3002", "c".repeat(45))),
+            mqtt_server: Some(s("192.168.100.200:65535")),
+            ..Default::default()
+        };
+        // longest numbers until it does not fit
+        let mut largest = Config::default().apply(&change).unwrap();
+        for n in 1..=MAX_CONTACTS {
+            change.owners = Some((0..n).map(|i| alloc::format!("+{:029}", i)).collect());
+            match Config::default().apply(&change) {
+                Ok(config) => largest = config,
+                Err(e) => {
+                    assert_eq!(Error::Capacity("config too large"), e);
+                    break;
+                }
+            }
+        }
+        assert!(largest.contacts.len() > 1);
+        assert!(largest.contacts.len() < MAX_CONTACTS);
+
+        let mut store = ConfigStore::new(MemoryStorage::new(1024, 2));
+        store.save(&largest).unwrap();
+        assert_eq!(largest, store.load());
+    }
+
+    #[test]
+    fn test_wear_levelling() {
+        let mut store = ConfigStore::new(MemoryStorage::new(1024, 2)); // 4 slots per sector
+        for i in 0..10 {
+            let apn = alloc::format!("apn{}", i);
+            store.save(&config(apn.as_str())).unwrap();
+            assert_eq!(config(apn.as_str()), store.load());
+        }
+        // sector 0 (slot 0), sector 1 (slot 4), sector 0 (slot 8)
+        assert_eq!(alloc::vec![0, 1024, 0], store.storage().erase_calls);
+        assert_eq!(10, store.storage().write_calls);
+    }
+
+    #[test]
+    fn test_corrupted_record() {
+        let mut store = ConfigStore::new(MemoryStorage::new(1024, 2));
+        store.save(&config("first")).unwrap();
+        store.save(&config("second")).unwrap();
+
+        // flip a bit in the latest record, the previous one is used
+        store.storage().data[SLOT_SIZE + 20] ^= 0x01;
+        assert_eq!(config("first"), store.load());
+
+        // the next write skips the damaged slot
+        store.save(&config("third")).unwrap();
+        assert_eq!(config("third"), store.load());
+
+        store.storage().data[0] = 0;
+        store.storage().data[SLOT_SIZE] = 0;
+        store.storage().data[4 * SLOT_SIZE + 12] ^= 0x80;
+        assert_eq!(Config::default(), store.load());
+    }
+}
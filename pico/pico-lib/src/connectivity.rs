@@ -0,0 +1,336 @@
+use alloc::string::ToString;
+use defmt::Format;
+use defmt::info;
+use embassy_sync::watch::DynSender;
+
+use crate::error::Error;
+use crate::gsm::Bearer;
+use crate::gsm::BearerStatus;
+use crate::network::AtNetworkRegistrationWrite;
+use crate::urc::Urc;
+use crate::utils::send_command_logged;
+
+// Keeps the data link up: the supervisor holds a reference of the shared gsm::Bearer, so
+// it stays open between the users (CLBS, EPO, MQTT), and opens it again when it is lost.
+//
+// The link goes down on +SAPBR 1: DEACT, +PDP: DEACT or a +CGREG: <stat> that is not
+// registered (see init), then poll reconnects once the network is back, with exponential
+// backoff from INITIAL_BACKOFF_MILLIS to MAX_BACKOFF_MILLIS between the failed attempts.
+//
+// The changes of the link are sent to a Watch, the long running work (HTTP, MQTT) can wait
+// for Link::Up with a receiver:
+//
+//     static LINK: Watch<CriticalSectionRawMutex, Link, 2> = Watch::new();
+//     let mut supervisor = Supervisor::new(LINK.dyn_sender());
+//     LINK.receiver().unwrap().changed_and(|l| *l == Link::Up).await;
+
+pub const INITIAL_BACKOFF_MILLIS: u64 = 5_000;
+pub const MAX_BACKOFF_MILLIS: u64 = 10 * 60 * 1000;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq)]
+pub enum Link {
+    Down,
+    Up,
+}
+
+pub struct Supervisor<'a> {
+    sender: DynSender<'a, Link>,
+    link: Link,
+    registered: bool,
+    held: bool, // the reference on the bearer
+    failures: u32,
+    retry_at: u64,
+}
+
+// Enables the +CGREG URC.
+pub async fn init<T: atat::asynch::AtatClient>(client: &mut T) -> Result<(), Error> {
+    send_command_logged(
+        client,
+        &AtNetworkRegistrationWrite { n: 1 },
+        "AtNetworkRegistrationWrite".to_string(),
+    )
+    .await?;
+    Ok(())
+}
+
+// The delay after the given number of failed attempts.
+pub fn backoff_millis(failures: u32) -> u64 {
+    if failures == 0 {
+        return 0;
+    }
+    INITIAL_BACKOFF_MILLIS
+        .saturating_mul(1 << (failures - 1).min(16))
+        .min(MAX_BACKOFF_MILLIS)
+}
+
+impl<'a> Supervisor<'a> {
+    // The network is assumed to be registered, see network::init_network.
+    pub fn new(sender: DynSender<'a, Link>) -> Self {
+        sender.send(Link::Down);
+        Supervisor {
+            sender,
+            link: Link::Down,
+            registered: true,
+            held: false,
+            failures: 0,
+            retry_at: 0,
+        }
+    }
+
+    pub fn link(&self) -> Link {
+        self.link
+    }
+
+    pub fn registered(&self) -> bool {
+        self.registered
+    }
+
+    // Of the current outage.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    // The uptime of the next attempt while the link is down.
+    pub fn retry_at(&self) -> Option<u64> {
+        (self.link == Link::Down).then_some(self.retry_at)
+    }
+
+    pub fn handle_urc(&mut self, urc: &Urc, bearer: &mut Bearer, uptime_millis: u64) {
+        bearer.handle_urc(urc);
+        match urc {
+            Urc::SetBearer(_) | Urc::GprsDisconnected(_) => self.lost(uptime_millis),
+            Urc::NetworkRegistration(v) => {
+                self.registered = v.stat.is_registered();
+                info!("Network registration: {:?}", v.stat);
+                if self.registered {
+                    // no need to wait for the backoff, the network is back
+                    self.retry_at = uptime_millis;
+                } else {
+                    self.lost(uptime_millis);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Reconnects when the link is down and the attempt is due. Returns the state of the
+    // link, a failed attempt is the backoff, see error.rs.
+    pub async fn poll<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+        &mut self,
+        client: &mut T,
+        pico: &mut U,
+        bearer: &mut Bearer,
+        apn: &str,
+    ) -> Link {
+        let now = pico.uptime_millis();
+        // closed by a URC another handler got
+        if self.link == Link::Up && bearer.status() != BearerStatus::Connected {
+            self.lost(now);
+        }
+        if self.link == Link::Up || !self.registered || now < self.retry_at {
+            return self.link;
+        }
+
+        let ret = if self.held {
+            bearer.reopen(client, pico, apn).await
+        } else {
+            bearer.acquire(client, pico, apn).await
+        };
+        match ret {
+            Ok(()) => {
+                self.held = true;
+                self.failures = 0;
+                self.set(Link::Up);
+            }
+            Err(e) => {
+                self.failures += 1;
+                self.retry_at = pico.uptime_millis() + backoff_millis(self.failures);
+                info!(
+                    "Link reconnect failed: {}, attempt {}, next at {}",
+                    e, self.failures, self.retry_at
+                );
+            }
+        }
+        self.link
+    }
+
+    // Gives up the reference on the bearer, it is closed once the other users are done.
+    // Does not fail, see error.rs.
+    pub async fn stop<T: atat::asynch::AtatClient>(&mut self, client: &mut T, bearer: &mut Bearer) {
+        if self.held {
+            self.held = false;
+            bearer.release(client).await;
+        }
+        self.set(Link::Down);
+    }
+
+    fn lost(&mut self, uptime_millis: u64) {
+        if self.link == Link::Up {
+            self.failures = 0;
+            self.retry_at = uptime_millis;
+            self.set(Link::Down);
+        }
+    }
+
+    fn set(&mut self, link: Link) {
+        if self.link != link {
+            info!("Link {:?}", link);
+            self.link = link;
+            self.sender.send(link);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::at::PicoHW;
+    use crate::network::NetworkRegistrationStatus;
+    use crate::network::NetworkRegistrationUrc;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_sync::watch::Watch;
+    use sim868_emu::Registration;
+
+    // The answer of AT+CGREG?, the URCs come before it.
+    async fn registration<T: atat::asynch::AtatClient>(
+        client: &mut T,
+    ) -> NetworkRegistrationStatus {
+        send_command_logged(
+            client,
+            &crate::network::AtNetworkRegistrationRead,
+            "AtNetworkRegistrationRead".to_string(),
+        )
+        .await
+        .unwrap()
+        .stat
+    }
+
+    #[test]
+    fn test_backoff_millis() {
+        assert_eq!(0, backoff_millis(0));
+        assert_eq!(5_000, backoff_millis(1));
+        assert_eq!(10_000, backoff_millis(2));
+        assert_eq!(40_000, backoff_millis(4));
+        assert_eq!(MAX_BACKOFF_MILLIS, backoff_millis(8));
+        assert_eq!(MAX_BACKOFF_MILLIS, backoff_millis(u32::MAX));
+    }
+
+    #[test]
+    fn test_handle_urc() {
+        let watch: Watch<NoopRawMutex, Link, 1> = Watch::new();
+        let mut receiver = watch.receiver().unwrap();
+        let mut supervisor = Supervisor::new(watch.dyn_sender());
+        let mut bearer = Bearer::default();
+        assert_eq!(Some(Link::Down), receiver.try_changed());
+
+        supervisor.link = Link::Up;
+        supervisor.handle_urc(&Urc::Ring, &mut bearer, 1000);
+        assert_eq!(Link::Up, supervisor.link());
+        assert_eq!(None, receiver.try_changed());
+
+        let registration = |stat| Urc::NetworkRegistration(NetworkRegistrationUrc { stat });
+        supervisor.handle_urc(
+            &registration(NetworkRegistrationStatus::Searching),
+            &mut bearer,
+            2000,
+        );
+        assert_eq!(Link::Down, supervisor.link());
+        assert!(!supervisor.registered());
+        assert_eq!(Some(2000), supervisor.retry_at());
+        assert_eq!(Some(Link::Down), receiver.try_changed());
+
+        supervisor.retry_at = 100_000;
+        supervisor.handle_urc(
+            &registration(NetworkRegistrationStatus::RegisteredRoaming),
+            &mut bearer,
+            3000,
+        );
+        assert!(supervisor.registered());
+        assert_eq!(Some(3000), supervisor.retry_at());
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_emulated() {
+        let mut harness = crate::at::tests::Harness::new(sim868_emu::Sim868::default());
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut urcs = harness.urc_channel.subscribe().unwrap();
+        let modem = harness.modem.clone();
+        let watch: Watch<NoopRawMutex, Link, 1> = Watch::new();
+        let mut receiver = watch.receiver().unwrap();
+        let mut supervisor = Supervisor::new(watch.dyn_sender());
+        let mut bearer = Bearer::default();
+
+        harness
+            .run(async |client| {
+                init(client).await.unwrap();
+                let apn = "online";
+                assert_eq!(
+                    Link::Up,
+                    supervisor.poll(client, &mut pico, &mut bearer, apn).await
+                );
+                assert_eq!(Some(Link::Up), receiver.try_changed());
+                assert_eq!(1, bearer.users());
+
+                // a user on the way keeps it open
+                bearer
+                    .scoped(client, &mut pico, apn, async |_, _| Ok(()))
+                    .await
+                    .unwrap();
+                assert!(modem.with(|sim| sim.bearer_open));
+
+                // the network is gone, the URCs come with the next response
+                modem.with(|sim| sim.set_registration(Registration::Searching));
+                assert_eq!(
+                    NetworkRegistrationStatus::Searching,
+                    registration(client).await
+                );
+                while let Some(urc) = urcs.try_next_message_pure() {
+                    supervisor.handle_urc(&urc, &mut bearer, pico.uptime_millis());
+                }
+                assert_eq!(Link::Down, supervisor.link());
+                assert_eq!(Some(Link::Down), receiver.try_changed());
+                assert_eq!(BearerStatus::Closed, bearer.status());
+
+                // no attempt until it is registered again
+                assert_eq!(
+                    Link::Down,
+                    supervisor.poll(client, &mut pico, &mut bearer, apn).await
+                );
+                assert_eq!(0, supervisor.failures());
+
+                // the attach fails, then the next attempt is delayed
+                supervisor.registered = true;
+                assert_eq!(
+                    Link::Down,
+                    supervisor.poll(client, &mut pico, &mut bearer, apn).await
+                );
+                assert_eq!(1, supervisor.failures());
+                let retry_at = supervisor.retry_at().unwrap();
+                assert_eq!(pico.uptime_millis() + INITIAL_BACKOFF_MILLIS, retry_at);
+                assert_eq!(
+                    Link::Down,
+                    supervisor.poll(client, &mut pico, &mut bearer, apn).await
+                );
+                assert_eq!(1, supervisor.failures());
+
+                modem.with(|sim| sim.set_registration(Registration::Registered));
+                registration(client).await;
+                while let Some(urc) = urcs.try_next_message_pure() {
+                    supervisor.handle_urc(&urc, &mut bearer, pico.uptime_millis());
+                }
+                assert_eq!(
+                    Link::Up,
+                    supervisor.poll(client, &mut pico, &mut bearer, apn).await
+                );
+                assert_eq!(Some(Link::Up), receiver.try_changed());
+                assert_eq!(0, supervisor.failures());
+                assert_eq!(1, bearer.users());
+
+                supervisor.stop(client, &mut bearer).await;
+                assert_eq!(Some(Link::Down), receiver.try_changed());
+            })
+            .await;
+        assert!(!harness.modem.with(|sim| sim.bearer_open));
+        assert_eq!(Bearer::default(), bearer);
+    }
+}
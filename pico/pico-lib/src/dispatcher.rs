@@ -0,0 +1,1483 @@
+use alloc::format;
+use alloc::string::String as AString;
+use alloc::vec::Vec;
+use atat::heapless::String;
+use defmt::info;
+
+use crate::call;
+use crate::concat;
+use crate::config::Config;
+use crate::config::ConfigStore;
+use crate::config::Role;
+use crate::error::Error;
+use crate::location;
+use crate::poro;
+use crate::protector::Guard;
+use crate::protector::Transition;
+use crate::sms;
+use crate::storage::Storage;
+use crate::tracklog::Tracklog;
+
+// $tATA/<command>/<password>
+// The Android Watcher SMS app sends "$TATA/<machine command>/<password>".
+const PREFIX: &str = "$tATA";
+const CALL_DURATION_MILLIS: u64 = 60 * 1000;
+
+#[derive(Debug, PartialEq)]
+pub struct Command<'a> {
+    pub command: &'a str,
+    pub password: &'a str,
+}
+
+pub fn split_command(message: &str) -> Result<Command<'_>, Error> {
+    let (prefix, rest) = message
+        .trim()
+        .split_once('/')
+        .ok_or(Error::Parse("missing $tATA prefix"))?;
+    if !prefix.eq_ignore_ascii_case(PREFIX) {
+        return Err(Error::Parse("missing $tATA prefix"));
+    }
+    let (command, password) = rest
+        .rsplit_once('/')
+        .ok_or(Error::Parse("missing password"))?;
+    Ok(Command { command, password })
+}
+
+pub fn parse(message: &str, sender: &str, password: &str) -> Result<poro::Watcher, Error> {
+    let command = split_command(message)?;
+    if command.password != password {
+        return Err(Error::Rejected("invalid password"));
+    }
+
+    let (mut watcher, source) = match (poro::WatcherHuman {}).parse(AString::from(command.command))
+    {
+        Ok(w) => (w, poro::Source::SmsHuman),
+        Err(_) => (
+            (poro::WatcherMachine {}).parse(AString::from(command.command))?,
+            poro::Source::SmsMachine,
+        ),
+    };
+
+    if watcher.receiver.is_none() {
+        watcher.receiver = Some(poro::ReceiverInfo {
+            source,
+            phone_number: AString::from(sender),
+        });
+    }
+
+    Ok(watcher)
+}
+
+pub fn parse_config(message: &str, password: &str) -> Result<poro::ConfigCommand, Error> {
+    let command = split_command(message)?;
+    if command.password != password {
+        return Err(Error::Rejected("invalid password"));
+    }
+    (poro::ConfigHuman {}).parse(AString::from(command.command))
+}
+
+pub fn parse_track(message: &str, password: &str) -> Result<poro::TrackCommand, Error> {
+    let command = split_command(message)?;
+    if command.password != password {
+        return Err(Error::Rejected("invalid password"));
+    }
+    (poro::TrackHuman {}).parse(AString::from(command.command))
+}
+
+// Reads the breadcrumbs or the latest trip from the flash.
+pub fn track<S: Storage>(
+    tracklog: &mut Tracklog<S>,
+    command: &poro::TrackCommand,
+) -> String<{ concat::MAX_MESSAGE }> {
+    let text = match command {
+        poro::TrackCommand::Last(n) => (poro::TrackHuman {}).dump_points(&tracklog.last(*n)),
+        poro::TrackCommand::Trip => (poro::TrackHuman {}).dump_trip(tracklog.trip().as_ref()),
+    };
+    truncate(text.as_str(), concat::MAX_UNITS)
+}
+
+// Validates, persists and applies the change, replies with the effective configuration.
+pub fn configure<S: Storage>(
+    guard: &mut Guard,
+    store: &mut ConfigStore<S>,
+    config: &mut Config,
+    command: &poro::ConfigCommand,
+) -> String<{ concat::MAX_MESSAGE }> {
+    if let poro::ConfigCommand::Change(change) = command {
+        let changed = match config.apply(change) {
+            Ok(c) => c,
+            Err(e) => return error_reply(e),
+        };
+        if let Err(e) = store.save(&changed) {
+            return error_reply(e);
+        }
+        guard.config = changed.protector_config();
+        guard.geofence.set_zones(&changed.zones);
+        *config = changed;
+        info!("Config changed: {:?}", config);
+    }
+    truncate(
+        (poro::ConfigHuman {}).dump(config).as_str(),
+        concat::MAX_UNITS,
+    )
+}
+
+fn error_reply(e: Error) -> String<{ concat::MAX_MESSAGE }> {
+    let mut text: String<{ concat::MAX_MESSAGE }> = String::try_from("Error: ").unwrap();
+    let _ = text.push_str(format!("{}", e).as_str());
+    text
+}
+
+pub fn reply(
+    protector: &poro::Protector,
+    source: &poro::Source,
+) -> String<{ concat::MAX_MESSAGE }> {
+    match source {
+        poro::Source::SmsHuman => {
+            let text = (poro::ProtectorHuman {}).dump(protector);
+            if text.is_empty() {
+                return String::try_from("OK").unwrap();
+            }
+            truncate(text.as_str(), concat::MAX_UNITS)
+        }
+        _ => {
+            let mut text: String<{ concat::MAX_MESSAGE }> = String::try_from(PREFIX).unwrap();
+            let _ = text.push('/');
+            let _ = text.push_str((poro::ProtectorMachine {}).dump(protector).as_str());
+            text
+        }
+    }
+}
+
+fn truncate(text: &str, max_units: usize) -> String<{ concat::MAX_MESSAGE }> {
+    let mut ret = String::new();
+    let mut units = 0;
+    for c in text.chars() {
+        units += c.len_utf16();
+        if units > max_units || ret.push(c).is_err() {
+            break;
+        }
+    }
+    ret
+}
+
+pub fn permitted(role: Role, watcher: &poro::Watcher) -> bool {
+    match role {
+        Role::Owner => true,
+        Role::Viewer => {
+            watcher.refresh.is_some()
+                && watcher.call.is_none()
+                && watcher.park.is_none()
+                && watcher.service.is_none()
+        }
+        Role::Emergency => false,
+    }
+}
+
+// Incoming calls are answered for the owners only.
+pub fn accept_call(config: &Config, number: &str) -> bool {
+    config.role(number) == Some(Role::Owner)
+}
+
+// Runs the requested actions, returns the reply when the command needs one, and the
+// transitions of the protector the new location raised (see send_alerts). Does not fail,
+// see error.rs.
+pub async fn execute<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    pico: &mut U,
+    guard: &mut Guard,
+    strategy: &mut location::Strategy,
+    watcher: &poro::Watcher,
+    config: &Config,
+) -> (Option<poro::Protector>, Vec<Transition>) {
+    let mut reply: Option<poro::Protector> = None;
+    let mut transitions = Vec::new();
+
+    if let Some(service) = watcher.service.as_ref() {
+        guard.set_service(service.value);
+    }
+
+    if let Some(park) = watcher.park.as_ref() {
+        guard.set_park(park.value);
+    }
+
+    if watcher.refresh.as_ref().is_some_and(|r| r.value) {
+        let loc = match strategy
+            .locate(client, pico, config, &location::Request::default())
+            .await
+        {
+            Ok(l) => Some(l),
+            Err(e) => {
+                info!("Locating failed, replying without a location: {}", e);
+                None
+            }
+        };
+        if let Some(l) = loc.as_ref() {
+            transitions.extend(guard.update(l));
+            transitions.extend(guard.update_zones(l));
+        }
+        reply = Some(guard.protector(loc.as_ref()));
+    } else if watcher.park.is_some() || watcher.service.is_some() {
+        reply = Some(guard.protector(None));
+    }
+
+    if let Some(r) = reply.as_mut() {
+        if watcher.service.is_some() {
+            r.service = Some(poro::Service {
+                value: guard.service(),
+            });
+        }
+        r.status = guard.status().cloned();
+    }
+
+    (reply, transitions)
+}
+
+#[allow(clippy::too_many_arguments)] // the state of the main loop, see the app
+pub async fn handle_new_message<T: atat::asynch::AtatClient, U: crate::at::PicoHW, S: Storage>(
+    client: &mut T,
+    pico: &mut U,
+    guard: &mut Guard,
+    strategy: &mut location::Strategy,
+    store: &mut ConfigStore<S>,
+    tracklog: &mut Tracklog<S>,
+    config: &mut Config,
+    reassembler: &mut concat::Reassembler,
+    index: u32,
+) -> Result<(), Error> {
+    let mut sms = sms::read_sms(client, pico, index).await?;
+    let sender: String<30> = String::try_from(sms.phone_number.as_str())
+        .map_err(|_| Error::Capacity("phone number too long"))?;
+
+    if let Some(c) = sms.concat.as_ref() {
+        match reassembler.add(&sender, c, &sms.message, pico.uptime_millis())? {
+            Some(message) => sms.message = message,
+            None => {
+                info!("SMS part {}/{} from {}", c.index, c.count, sender.as_str());
+                return Ok(());
+            }
+        }
+    }
+
+    let role = match config.role(sender.as_str()) {
+        Some(r) => r,
+        None => {
+            info!("Ignoring SMS from unknown number {}", sender.as_str());
+            return Err(Error::Rejected("unknown sender"));
+        }
+    };
+
+    let outcome = handle_command(
+        client,
+        pico,
+        guard,
+        strategy,
+        store,
+        tracklog,
+        config,
+        &sender,
+        role,
+        &sms.message,
+    )
+    .await?;
+    if let Some(text) = outcome.reply.as_ref() {
+        let reference = reassembler.next_reference();
+        send_reply(client, pico, &sender, text, reference).await;
+    }
+    send_alerts(client, pico, config, reassembler, &outcome.transitions).await;
+
+    if outcome.call
+        && let Err(e) = call::call_number(client, pico, &sender, CALL_DURATION_MILLIS).await
+    {
+        info!("Calling {} failed: {}", sender.as_str(), e);
+    }
+
+    Ok(())
+}
+
+// What is left to do after a command, the way back depends on where it came from.
+#[derive(Debug, Default, PartialEq)]
+pub struct Outcome {
+    pub reply: Option<String<{ concat::MAX_MESSAGE }>>,
+    pub call: bool,                   // a call to the sender was requested
+    pub transitions: Vec<Transition>, // for the alert numbers, see send_alerts
+}
+
+// Runs a configuration, track or watcher command of the sender with the given role, the
+// same for an SMS and an MQTT message (see telemetry).
+#[allow(clippy::too_many_arguments)] // the state of the main loop, see the app
+pub async fn handle_command<T: atat::asynch::AtatClient, U: crate::at::PicoHW, S: Storage>(
+    client: &mut T,
+    pico: &mut U,
+    guard: &mut Guard,
+    strategy: &mut location::Strategy,
+    store: &mut ConfigStore<S>,
+    tracklog: &mut Tracklog<S>,
+    config: &mut Config,
+    sender: &str,
+    role: Role,
+    message: &str,
+) -> Result<Outcome, Error> {
+    if let Ok(command) = parse_config(message, &config.password) {
+        if role != Role::Owner {
+            info!("Config command from {} not permitted", sender);
+            return Err(Error::Rejected("not permitted"));
+        }
+        info!("Config command from {}", sender);
+        return Ok(Outcome {
+            reply: Some(configure(guard, store, config, &command)),
+            ..Default::default()
+        });
+    }
+
+    if let Ok(command) = parse_track(message, &config.password) {
+        if role == Role::Emergency {
+            info!("Track command from {} not permitted", sender);
+            return Err(Error::Rejected("not permitted"));
+        }
+        info!("Track command from {}", sender);
+        return Ok(Outcome {
+            reply: Some(track(tracklog, &command)),
+            ..Default::default()
+        });
+    }
+
+    let watcher = match parse(message, sender, &config.password) {
+        Ok(w) => w,
+        Err(e) => {
+            info!("Ignoring command from {}: {}", sender, e);
+            return Err(e);
+        }
+    };
+    if !permitted(role, &watcher) {
+        info!("Command from {} ({:?}) not permitted", sender, role);
+        return Err(Error::Rejected("not permitted"));
+    }
+    let source = watcher.receiver.as_ref().map(|r| &r.source).unwrap();
+    info!("Command from {} source={:?}", sender, source);
+
+    let (protector, transitions) = execute(client, pico, guard, strategy, &watcher, config).await;
+    Ok(Outcome {
+        reply: protector.map(|p| reply(&p, source)),
+        call: watcher.call.as_ref().is_some_and(|c| c.value),
+        transitions,
+    })
+}
+
+// Sends the transitions of the protector to the owners and the emergency contacts, e.g.
+// the ones of protector::check. The Android application does not know about the zones,
+// these are sent in the human form. Does not fail, see error.rs.
+pub async fn send_alerts<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    pico: &mut U,
+    config: &Config,
+    reassembler: &mut concat::Reassembler,
+    transitions: &[Transition],
+) {
+    for t in transitions {
+        info!("Protector transition: {:?}", t.status);
+        let source = match t.status {
+            poro::Status::ZoneEntered | poro::Status::ZoneExited => poro::Source::SmsHuman,
+            _ => poro::Source::SmsMachine,
+        };
+        let message = reply(&t.message, &source);
+        for number in config.alert_numbers() {
+            let reference = reassembler.next_reference();
+            send_reply(client, pico, number, &message, reference).await;
+        }
+    }
+}
+
+// The command is handled already, a failed reply does not make the message unhandled.
+async fn send_reply<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    pico: &mut U,
+    number: &str,
+    text: &str,
+    reference: u8,
+) {
+    if let Err(e) = sms::send_sms(client, pico, number, text, reference).await {
+        info!("Reply to {} not sent: {}", number, e);
+    }
+}
+
+// Handles the message, then deletes it, the SIM storage would fill up otherwise. A message
+// that could not be read because the module did not respond is kept, it is picked up by
+// process_stored_messages.
+#[allow(clippy::too_many_arguments)] // the state of the main loop, see the app
+pub async fn process_message<T: atat::asynch::AtatClient, U: crate::at::PicoHW, S: Storage>(
+    client: &mut T,
+    pico: &mut U,
+    guard: &mut Guard,
+    strategy: &mut location::Strategy,
+    store: &mut ConfigStore<S>,
+    tracklog: &mut Tracklog<S>,
+    config: &mut Config,
+    reassembler: &mut concat::Reassembler,
+    index: u32,
+) -> Result<(), Error> {
+    let result = handle_new_message(
+        client,
+        pico,
+        guard,
+        strategy,
+        store,
+        tracklog,
+        config,
+        reassembler,
+        index,
+    )
+    .await;
+    if !matches!(
+        result,
+        Err(Error::Timeout | Error::Serial | Error::Cms(sms::INVALID_INDEX))
+    ) {
+        sms::delete_sms(client, pico, index).await?;
+    }
+    result
+}
+
+// Handles the unread messages left on the SIM, e.g. the ones that arrived before init or
+// could not be read. The read and the stored outgoing ones are deleted first, these were
+// processed already, so every message left is unread. They are listed (AT+CMGL), when the
+// listing fails or comes up short of the storage usage (e.g. a full SIM does not fit in the
+// ingress buffer) the slots are read one by one. Fails when a message could not be read, it
+// is kept. Returns the number of unread messages.
+#[allow(clippy::too_many_arguments)] // the state of the main loop, see the app
+pub async fn process_stored_messages<
+    T: atat::asynch::AtatClient,
+    U: crate::at::PicoHW,
+    S: Storage,
+>(
+    client: &mut T,
+    pico: &mut U,
+    guard: &mut Guard,
+    strategy: &mut location::Strategy,
+    store: &mut ConfigStore<S>,
+    tracklog: &mut Tracklog<S>,
+    config: &mut Config,
+    reassembler: &mut concat::Reassembler,
+) -> Result<usize, Error> {
+    sms::delete_all_sms(client, pico, sms::DeleteFlag::ReadSentAndUnsent).await?;
+    let storage = sms::select_storage(client, pico).await?;
+    let indexes = match sms::list_sms(client, pico, sms::SmsStat::ReceivedUnread).await {
+        Ok(indexes) if indexes.len() >= storage.used as usize => indexes,
+        listed => {
+            info!(
+                "SMS listing {} of {} messages, reading the slots",
+                listed.as_ref().map_or(0, |i| i.len()),
+                storage.used
+            );
+            (1..=storage.total).take(sms::MAX_LIST).collect()
+        }
+    };
+    let mut read = 0;
+    for index in indexes {
+        if read >= storage.used as usize {
+            break;
+        }
+        match process_message(
+            client,
+            pico,
+            guard,
+            strategy,
+            store,
+            tracklog,
+            config,
+            reassembler,
+            index,
+        )
+        .await
+        {
+            Err(Error::Cms(sms::INVALID_INDEX)) => {}
+            Err(e @ (Error::Timeout | Error::Serial)) => return Err(e),
+            _ => read += 1,
+        }
+    }
+    if read < storage.used as usize {
+        info!("SMS storage {} messages, found {}", storage.used, read);
+        return Err(Error::Protocol("stored message not found"));
+    }
+    Ok(read)
+}
+
+// New messages are not received while the SIM storage is full. The stored ones are processed
+// and deleted, if that does not help every message is deleted, unread ones included. That is
+// done only when every stored message was read, an unread command is not lost otherwise.
+#[allow(clippy::too_many_arguments)] // the state of the main loop, see the app
+pub async fn check_storage<T: atat::asynch::AtatClient, U: crate::at::PicoHW, S: Storage>(
+    client: &mut T,
+    pico: &mut U,
+    guard: &mut Guard,
+    strategy: &mut location::Strategy,
+    store: &mut ConfigStore<S>,
+    tracklog: &mut Tracklog<S>,
+    config: &mut Config,
+    reassembler: &mut concat::Reassembler,
+) -> Result<sms::SmsStorage, Error> {
+    let storage = sms::select_storage(client, pico).await?;
+    if !storage.is_full() {
+        return Ok(storage);
+    }
+    info!("SMS storage full {}/{}", storage.used, storage.total);
+    process_stored_messages(
+        client,
+        pico,
+        guard,
+        strategy,
+        store,
+        tracklog,
+        config,
+        reassembler,
+    )
+    .await?;
+    let storage = sms::select_storage(client, pico).await?;
+    if !storage.is_full() {
+        return Ok(storage);
+    }
+    info!("SMS storage still full, deleting every message");
+    sms::delete_all_sms(client, pico, sms::DeleteFlag::All).await?;
+    sms::select_storage(client, pico).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protector::ProtectorConfig;
+    use crate::sms::tests::cmgr;
+    use crate::sms::tests::cmgs;
+    use crate::storage::tests::MemoryStorage;
+
+    fn tracklog() -> Tracklog<MemoryStorage> {
+        Tracklog::new(MemoryStorage::new(4096, 2))
+    }
+
+    fn store() -> ConfigStore<MemoryStorage> {
+        ConfigStore::new(MemoryStorage::new(1024, 2))
+    }
+
+    const SMS_LOCATION: &str = "$tATA/location/12345";
+
+    fn receiver(source: poro::Source) -> Option<poro::ReceiverInfo> {
+        Some(poro::ReceiverInfo {
+            source,
+            phone_number: AString::from("+36301234567"),
+        })
+    }
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(
+            Ok(Command {
+                command: "park on",
+                password: "12345"
+            }),
+            split_command("$tATA/park on/12345")
+        );
+        assert_eq!(
+            Ok(Command {
+                command: "* * t * *",
+                password: "12345"
+            }),
+            split_command("$TATA/* * t * */12345\r\n")
+        );
+        assert_eq!(
+            Err(Error::Parse("missing $tATA prefix")),
+            split_command("location")
+        );
+        assert_eq!(
+            Err(Error::Parse("missing $tATA prefix")),
+            split_command("$xATA/location/1")
+        );
+        assert_eq!(
+            Err(Error::Parse("missing password")),
+            split_command("$tATA/location")
+        );
+    }
+
+    #[test]
+    fn test_parse_human() {
+        assert_eq!(
+            Ok(poro::Watcher {
+                call: None,
+                refresh: Some(poro::Refresh { value: true }),
+                park: None,
+                receiver: receiver(poro::Source::SmsHuman),
+                service: None,
+            }),
+            parse("$tATA/location/12345", "+36301234567", "12345")
+        );
+        assert_eq!(
+            Ok(poro::Watcher {
+                call: None,
+                refresh: None,
+                park: None,
+                receiver: receiver(poro::Source::SmsHuman),
+                service: Some(poro::Service { value: false }),
+            }),
+            parse("$tATA/service off/12345", "+36301234567", "12345")
+        );
+    }
+
+    #[test]
+    fn test_parse_machine() {
+        assert_eq!(
+            Ok(poro::Watcher {
+                call: None,
+                refresh: Some(poro::Refresh { value: true }),
+                park: Some(poro::Park { value: true }),
+                receiver: receiver(poro::Source::SmsMachine),
+                service: None,
+            }),
+            parse("$TATA/* t t * */12345", "+36301234567", "12345")
+        );
+        assert_eq!(
+            Ok(poro::Watcher {
+                call: Some(poro::Call { value: true }),
+                refresh: None,
+                park: None,
+                receiver: Some(poro::ReceiverInfo {
+                    source: poro::Source::Gcm,
+                    phone_number: AString::from("other"),
+                }),
+                service: None,
+            }),
+            parse("$TATA/t * * 0 other */12345", "+36301234567", "12345")
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Err(Error::Rejected("invalid password")),
+            parse("$tATA/location/1234", "+36301234567", "12345")
+        );
+        assert_eq!(
+            Err(Error::Parse("could not parse bool")),
+            parse("$tATA/unknown/12345", "+36301234567", "12345")
+        );
+    }
+
+    #[test]
+    fn test_reply() {
+        let protector = poro::Protector {
+            car_location: None,
+            park_location: None,
+            status: Some(poro::Status::CarTheftDetected),
+            service: None,
+            zone: None,
+        };
+        assert_eq!("OK", reply(&protector, &poro::Source::SmsHuman));
+        assert_eq!(
+            "$tATA/* * 2 *",
+            reply(&protector, &poro::Source::SmsMachine)
+        );
+
+        let protector = poro::Protector {
+            car_location: Some(poro::CarLocation {
+                position: poro::Position {
+                    latitude: 46.7624859f64,
+                    longitude: 18.6304591f64,
+                },
+                accuracy: 250.25f32,
+                battery: 0.8912f32,
+                timestamp: 1670077542109i64,
+                source: None,
+            }),
+            park_location: None,
+            status: None,
+            service: None,
+            zone: None,
+        };
+        // concatenated SMS, nothing is cut
+        assert_eq!(
+            "https://maps.google.com/?q=46.7624859,18.6304591\n\n250.25 meters, 89.12 %, 2022-12-03T14:25:42.109Z\n\n",
+            reply(&protector, &poro::Source::SmsHuman)
+        );
+
+        assert_eq!(
+            concat::MAX_UNITS,
+            truncate(&"😎".repeat(200), concat::MAX_UNITS)
+                .encode_utf16()
+                .count()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute() {
+        let mut client = crate::at::tests::ClientMock::default();
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut guard = Guard::new(ProtectorConfig::default());
+
+        let watcher = parse("$tATA/park on/12345", "+36301234567", "12345").unwrap();
+        let (protector, _) = execute(
+            &mut client,
+            &mut pico,
+            &mut guard,
+            &mut location::Strategy::default(),
+            &watcher,
+            &Config::default(),
+        )
+        .await;
+        assert_eq!(
+            Some(poro::Protector {
+                car_location: None,
+                park_location: None,
+                status: None,
+                service: None,
+                zone: None,
+            }),
+            protector
+        );
+        assert!(guard.park());
+
+        let watcher = parse("$tATA/service off/12345", "+36301234567", "12345").unwrap();
+        let (protector, _) = execute(
+            &mut client,
+            &mut pico,
+            &mut guard,
+            &mut location::Strategy::default(),
+            &watcher,
+            &Config::default(),
+        )
+        .await;
+        assert_eq!(
+            Some(poro::Service { value: false }),
+            protector.unwrap().service
+        );
+        assert!(!guard.service());
+
+        let watcher = parse("$tATA/call/12345", "+36301234567", "12345").unwrap();
+        let (protector, _) = execute(
+            &mut client,
+            &mut pico,
+            &mut guard,
+            &mut location::Strategy::default(),
+            &watcher,
+            &Config::default(),
+        )
+        .await;
+        assert_eq!(None, protector);
+        assert_eq!(0, client.sent_commands.len());
+    }
+
+    #[tokio::test]
+    async fn test_handle_new_message_location() {
+        let mut client = crate::at::tests::ClientMock::default();
+        let cmgr = cmgr(&[], SMS_LOCATION);
+        client.results.push_back(Ok(cmgr.as_bytes()));
+        client.results.push_back(Ok("".as_bytes())); // GPS Turn On
+        client.results.push_back(Ok("+CGNSINF: 1,1,20221212120221.123,46.7624859,18.6304591,329.218,2.20,285.8,1,,2.1,2.3,0.9,,7,6,,,51,,".as_bytes())); // location
+        client.results.push_back(Ok("".as_bytes())); // GPS Turn off
+        client.results.push_back(Ok(">".as_bytes()));
+        client.results.push_back(Ok("+CMGS: 1".as_bytes()));
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut guard = Guard::new(ProtectorConfig::default());
+        assert_eq!(
+            Ok(()),
+            handle_new_message(
+                &mut client,
+                &mut pico,
+                &mut guard,
+                &mut location::Strategy::default(),
+                &mut store(),
+                &mut tracklog(),
+                &mut Config::default(),
+                &mut concat::Reassembler::default(),
+                1
+            )
+            .await
+        );
+        assert_eq!(6, client.sent_commands.len());
+        assert_eq!("AT+CMGR=1\r", client.sent_commands[0]);
+        assert_eq!("AT+CGNSPWR=1\r", client.sent_commands[1]);
+        assert_eq!("AT+CGNSINF\r", client.sent_commands[2]);
+        assert_eq!("AT+CGNSPWR=0\r", client.sent_commands[3]);
+        // a single 7-bit SMS
+        let text = "https://maps.google.com/?q=46.7624859,18.6304591\n\n5.75 meters (GPS), 0.00 %, 2022-12-12T12:02:21.123Z\n\n";
+        let (cmgs, pdu) = cmgs("+36301234567", &[], text);
+        assert_eq!(cmgs, client.sent_commands[4]);
+        assert_eq!(pdu, client.sent_commands[5]);
+    }
+
+    #[tokio::test]
+    async fn test_handle_new_message_location_alert() {
+        let mut harness = crate::at::tests::Harness::new(sim868_emu::Sim868::default());
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut config = Config::default();
+        config
+            .contacts
+            .push(crate::config::Contact {
+                number: String::try_from("+36209876543").unwrap(),
+                role: Role::Emergency,
+            })
+            .unwrap();
+
+        // parked far from the fix of the module (2022-12-12 12:02:21)
+        let mut guard = Guard::new(ProtectorConfig::default());
+        guard.set_park(true);
+        for minute in [10, 5] {
+            guard.update(&location::Location {
+                latitude: 47.4979,
+                longitude: 19.0402,
+                accuracy: 5.0,
+                unix_timestamp_millis: 1670846541123 - minute * 60 * 1000,
+                source: location::Source::Gnss,
+                speed: None,
+                course: None,
+            });
+        }
+        assert_eq!(Some(&poro::Status::ParkingDetected), guard.status());
+
+        harness
+            .run(async |client| sms::init(client, &mut pico).await)
+            .await
+            .unwrap();
+        harness.modem.with(|sim| {
+            sim.receive_sms("+36301234567", SMS_LOCATION, "26/01/10,17:25:32+04");
+        });
+        let ret = harness
+            .run(async |client| {
+                handle_new_message(
+                    client,
+                    &mut pico,
+                    &mut guard,
+                    &mut location::Strategy::default(),
+                    &mut store(),
+                    &mut tracklog(),
+                    &mut config,
+                    &mut concat::Reassembler::default(),
+                    1,
+                )
+                .await
+            })
+            .await;
+        assert_eq!(Ok(()), ret);
+        assert_eq!(Some(&poro::Status::CarTheftDetected), guard.status());
+        harness.modem.with(|sim| {
+            // the reply, then the alert to the owner and the emergency contact
+            let numbers: Vec<_> = sim.sent_sms.iter().map(|s| s.number.as_str()).collect();
+            assert_eq!(
+                ["+36301234567", "+36301234567", "+36209876543"],
+                numbers.as_slice()
+            );
+            assert_eq!(sim.sent_sms[1].text, sim.sent_sms[2].text);
+        });
+    }
+
+    #[tokio::test]
+    async fn test_handle_new_message_concatenated() {
+        let header = |index| {
+            crate::pdu::Concat {
+                reference: 0x42,
+                count: 2,
+                index,
+            }
+            .header()
+        };
+        let mut client = crate::at::tests::ClientMock::default();
+        let part2 = cmgr(&header(2), "on/12345");
+        let part1 = cmgr(&header(1), "$tATA/park ");
+        client.results.push_back(Ok(part2.as_bytes()));
+        client.results.push_back(Ok(part1.as_bytes()));
+        client.results.push_back(Ok(">".as_bytes()));
+        client.results.push_back(Ok("+CMGS: 1".as_bytes()));
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut guard = Guard::new(ProtectorConfig::default());
+        let mut reassembler = concat::Reassembler::default();
+        for index in [4, 5] {
+            assert_eq!(
+                Ok(()),
+                handle_new_message(
+                    &mut client,
+                    &mut pico,
+                    &mut guard,
+                    &mut location::Strategy::default(),
+                    &mut store(),
+                    &mut tracklog(),
+                    &mut Config::default(),
+                    &mut reassembler,
+                    index
+                )
+                .await
+            );
+            // waiting for the first part
+            assert_eq!(index == 5, guard.park());
+        }
+        assert_eq!(4, client.sent_commands.len());
+        assert_eq!("AT+CMGR=4\r", client.sent_commands[0]);
+        assert_eq!("AT+CMGR=5\r", client.sent_commands[1]);
+        assert_eq!(0, reassembler.pending());
+    }
+
+    #[tokio::test]
+    async fn test_handle_new_message_call() {
+        let mut client = crate::at::tests::ClientMock::default();
+        let cmgr = cmgr(&[], "$tATA/call/12345");
+        client.results.push_back(Ok(cmgr.as_bytes()));
+        client.results.push_back(Ok("".as_bytes())); // ATD
+        client.results.push_back(Ok("".as_bytes())); // hang up
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut guard = Guard::new(ProtectorConfig::default());
+        assert_eq!(
+            Ok(()),
+            handle_new_message(
+                &mut client,
+                &mut pico,
+                &mut guard,
+                &mut location::Strategy::default(),
+                &mut store(),
+                &mut tracklog(),
+                &mut Config::default(),
+                &mut concat::Reassembler::default(),
+                2
+            )
+            .await
+        );
+        assert_eq!(3, client.sent_commands.len());
+        assert_eq!("AT+CMGR=2\r", client.sent_commands[0]);
+        assert_eq!("ATD+36301234567,i;\r", client.sent_commands[1]);
+        assert_eq!("AT+CHUP;\r", client.sent_commands[2]);
+        assert_eq!(alloc::vec![CALL_DURATION_MILLIS], pico.sleep_calls);
+    }
+
+    #[tokio::test]
+    async fn test_handle_new_message_wrong_password() {
+        let mut client = crate::at::tests::ClientMock::default();
+        let cmgr = cmgr(&[], SMS_LOCATION);
+        client.results.push_back(Ok(cmgr.as_bytes()));
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut guard = Guard::new(ProtectorConfig::default());
+        let mut config = Config {
+            password: String::try_from("54321").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            Err(Error::Rejected("invalid password")),
+            handle_new_message(
+                &mut client,
+                &mut pico,
+                &mut guard,
+                &mut location::Strategy::default(),
+                &mut store(),
+                &mut tracklog(),
+                &mut config,
+                &mut concat::Reassembler::default(),
+                1
+            )
+            .await
+        );
+        assert_eq!(1, client.sent_commands.len());
+    }
+
+    #[test]
+    fn test_parse_config() {
+        assert_eq!(
+            Ok(poro::ConfigCommand::Query),
+            parse_config("$tATA/config?/12345", "12345")
+        );
+        assert_eq!(
+            Err(Error::Rejected("invalid password")),
+            parse_config("$tATA/config?/1234", "12345")
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid config command")),
+            parse_config("$tATA/location/12345", "12345")
+        );
+    }
+
+    #[test]
+    fn test_configure() {
+        let mut guard = Guard::new(ProtectorConfig::default());
+        let mut store = store();
+        let mut config = Config::default();
+
+        let command =
+            parse_config("$tATA/config apn=internet park_radius=150/12345", "12345").unwrap();
+        assert_eq!(
+            "owner=+36301234567 apn=internet park_radius=150 clbs=lbs-simcom.com:3002 country=36 trunk=06",
+            configure(&mut guard, &mut store, &mut config, &command).as_str()
+        );
+        assert_eq!("internet", config.apn.as_str());
+        assert_eq!(150.0, guard.config.park_radius_meters);
+        assert_eq!(config, store.load());
+
+        let command = parse_config("$tATA/config park_radius=1 apn=x/12345", "12345").unwrap();
+        assert_eq!(
+            "Error: invalid park_radius",
+            configure(&mut guard, &mut store, &mut config, &command).as_str()
+        );
+        assert_eq!("internet", config.apn.as_str());
+        assert_eq!(1, store.storage().write_calls);
+
+        let command = parse_config("$tATA/config password=secret/12345", "12345").unwrap();
+        configure(&mut guard, &mut store, &mut config, &command);
+        assert_eq!(
+            Err(Error::Rejected("invalid password")),
+            parse_config("$tATA/config?/12345", config.password.as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_new_message_config() {
+        let mut client = crate::at::tests::ClientMock::default();
+        let cmgr = cmgr(&[], "$tATA/config?/12345");
+        client.results.push_back(Ok(cmgr.as_bytes()));
+        client.results.push_back(Ok(">".as_bytes()));
+        client.results.push_back(Ok("+CMGS: 1".as_bytes()));
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut guard = Guard::new(ProtectorConfig::default());
+        assert_eq!(
+            Ok(()),
+            handle_new_message(
+                &mut client,
+                &mut pico,
+                &mut guard,
+                &mut location::Strategy::default(),
+                &mut store(),
+                &mut tracklog(),
+                &mut Config::default(),
+                &mut concat::Reassembler::default(),
+                3
+            )
+            .await
+        );
+        assert_eq!(3, client.sent_commands.len());
+        let (_, pdu) = cmgs(
+            "+36301234567",
+            &[],
+            "owner=+36301234567 apn=online park_radius=100 clbs=lbs-simcom.com:3002 country=36 trunk=06",
+        );
+        assert_eq!(pdu, client.sent_commands[2]);
+    }
+
+    #[tokio::test]
+    async fn test_handle_new_message_track() {
+        let mut tracklog = tracklog();
+        tracklog
+            .append(&location::Location {
+                latitude: 46.7624859,
+                longitude: 18.6304591,
+                accuracy: 5.75,
+                unix_timestamp_millis: 1670077542109,
+                source: location::Source::Gnss,
+                speed: Some(35.4),
+                course: Some(285.8),
+            })
+            .unwrap();
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut guard = Guard::new(ProtectorConfig::default());
+        for (role, message, expected) in [
+            (
+                Role::Viewer,
+                "$tATA/track 3/12345",
+                Ok("12-03T14:25 46.76249,18.63046 35km/h\n"),
+            ),
+            (Role::Owner, "$tATA/trip/12345", Ok("No trip")),
+            (
+                Role::Emergency,
+                "$tATA/trip/12345",
+                Err(Error::Rejected("not permitted")),
+            ),
+        ] {
+            let mut client = crate::at::tests::ClientMock::default();
+            let cmgr = cmgr(&[], message);
+            client.results.push_back(Ok(cmgr.as_bytes()));
+            client.results.push_back(Ok(">".as_bytes()));
+            client.results.push_back(Ok("+CMGS: 1".as_bytes()));
+            let ret = handle_new_message(
+                &mut client,
+                &mut pico,
+                &mut guard,
+                &mut location::Strategy::default(),
+                &mut store(),
+                &mut tracklog,
+                &mut config_with(role),
+                &mut concat::Reassembler::default(),
+                3,
+            )
+            .await;
+            match expected {
+                Ok(text) => {
+                    assert_eq!(Ok(()), ret);
+                    assert_eq!(3, client.sent_commands.len());
+                    let (_, pdu) = cmgs("+36301234567", &[], text);
+                    assert_eq!(pdu, client.sent_commands[2]);
+                }
+                Err(e) => {
+                    assert_eq!(Err(e), ret);
+                    assert_eq!(1, client.sent_commands.len());
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_message() {
+        let mut client = crate::at::tests::ClientMock::default();
+        let cmgr = cmgr(&[], "hello");
+        client.results.push_back(Ok(cmgr.as_bytes()));
+        client.results.push_back(Ok("".as_bytes())); // CMGD
+        client.results.push_back(Err(atat::InternalError::Timeout));
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut guard = Guard::new(ProtectorConfig::default());
+        let mut config = Config::default();
+        config.contacts.clear();
+        let mut reassembler = concat::Reassembler::default();
+        assert_eq!(
+            Err(Error::Rejected("unknown sender")),
+            process_message(
+                &mut client,
+                &mut pico,
+                &mut guard,
+                &mut location::Strategy::default(),
+                &mut store(),
+                &mut tracklog(),
+                &mut config,
+                &mut reassembler,
+                4
+            )
+            .await
+        );
+        // kept, it is read later
+        assert_eq!(
+            Err(Error::Timeout),
+            process_message(
+                &mut client,
+                &mut pico,
+                &mut guard,
+                &mut location::Strategy::default(),
+                &mut store(),
+                &mut tracklog(),
+                &mut config,
+                &mut reassembler,
+                5
+            )
+            .await
+        );
+        assert_eq!(
+            alloc::vec!["AT+CMGR=4\r", "AT+CMGD=4\r", "AT+CMGR=5\r"],
+            client.sent_commands.iter().collect::<alloc::vec::Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_storage_emulated() {
+        let mut sim = sim868_emu::Sim868::default();
+        sim.sms_capacity = 3;
+        let mut harness = crate::at::tests::Harness::new(sim);
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut guard = Guard::new(ProtectorConfig::default());
+        let mut store = store();
+        let mut config = Config::default();
+        let mut reassembler = concat::Reassembler::default();
+
+        harness
+            .run(async |client| sms::init(client, &mut pico).await)
+            .await
+            .unwrap();
+        harness.modem.with(|sim| {
+            sim.receive_sms(
+                "+36301234567",
+                "$tATA/config?/12345",
+                "26/01/10,17:25:32+04",
+            );
+            sim.receive_sms("+36701234567", "hello", "26/01/10,17:25:32+04");
+            sim.receive_sms(
+                "+36301234567",
+                "$tATA/location/12345",
+                "26/01/10,17:25:32+04",
+            );
+            // processed before
+            sim.sms.get_mut(&3).unwrap().read = true;
+        });
+
+        let storage = harness
+            .run(async |client| {
+                check_storage(
+                    client,
+                    &mut pico,
+                    &mut guard,
+                    &mut location::Strategy::default(),
+                    &mut store,
+                    &mut tracklog(),
+                    &mut config,
+                    &mut reassembler,
+                )
+                .await
+            })
+            .await;
+        assert_eq!(Ok(sms::SmsStorage { used: 0, total: 3 }), storage);
+        harness.modem.with(|sim| {
+            assert!(sim.sms.is_empty());
+            // only the config command was executed
+            assert_eq!(1, sim.sent_sms.len());
+            assert!(sim.sent_sms[0].text.starts_with("owner=+36301234567"));
+        });
+
+        // not full, nothing to do
+        let storage = harness
+            .run(async |client| {
+                check_storage(
+                    client,
+                    &mut pico,
+                    &mut guard,
+                    &mut location::Strategy::default(),
+                    &mut store,
+                    &mut tracklog(),
+                    &mut config,
+                    &mut reassembler,
+                )
+                .await
+            })
+            .await;
+        assert_eq!(Ok(sms::SmsStorage { used: 0, total: 3 }), storage);
+    }
+
+    #[tokio::test]
+    async fn test_check_storage_last_resort() {
+        // a new message arrived while the stored ones were processed
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .expect("AT+CPMS=\"SM\",\"SM\",\"SM\"\r", Ok(b"+CPMS: 3,3,3,3,3,3"))
+            .expect("AT+CMGD=1,3\r", Ok(b""))
+            .expect("AT+CPMS=\"SM\",\"SM\",\"SM\"\r", Ok(b"+CPMS: 2,3,2,3,2,3"))
+            .expect(
+                "AT+CMGL=0,1\r",
+                Ok(b"+CMGL: 2,0,,21\r\n00040B916303214365F7000862100171522340020061\r\n+CMGL: 3,0,,21\r\n00040B916303214365F7000862100171522340020062"),
+            )
+            .expect(
+                "AT+CMGR=2\r",
+                Ok(b"+CMGR: 0,,21\r\n00040B916303214365F7000862100171522340020061"),
+            )
+            .expect("AT+CMGD=2\r", Ok(b""))
+            .expect(
+                "AT+CMGR=3\r",
+                Ok(b"+CMGR: 0,,21\r\n00040B916303214365F7000862100171522340020062"),
+            )
+            .expect("AT+CMGD=3\r", Ok(b""))
+            .expect("AT+CPMS=\"SM\",\"SM\",\"SM\"\r", Ok(b"+CPMS: 3,3,3,3,3,3"))
+            .expect("AT+CMGD=1,4\r", Ok(b""))
+            .expect("AT+CPMS=\"SM\",\"SM\",\"SM\"\r", Ok(b"+CPMS: 0,3,0,3,0,3"));
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut guard = Guard::new(ProtectorConfig::default());
+        assert_eq!(
+            Ok(sms::SmsStorage { used: 0, total: 3 }),
+            check_storage(
+                &mut client,
+                &mut pico,
+                &mut guard,
+                &mut location::Strategy::default(),
+                &mut store(),
+                &mut tracklog(),
+                &mut Config::default(),
+                &mut concat::Reassembler::default(),
+            )
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_storage_unread_kept() {
+        // every message is kept, the unread one could not be read, neither listed
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .expect("AT+CPMS=\"SM\",\"SM\",\"SM\"\r", Ok(b"+CPMS: 3,3,3,3,3,3"))
+            .expect("AT+CMGD=1,3\r", Ok(b""))
+            .expect("AT+CPMS=\"SM\",\"SM\",\"SM\"\r", Ok(b"+CPMS: 3,3,3,3,3,3"))
+            .timeout("AT+CMGL=0,1\r")
+            .timeout("AT+CMGR=1\r");
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut guard = Guard::new(ProtectorConfig::default());
+        assert_eq!(
+            Err(Error::Timeout),
+            check_storage(
+                &mut client,
+                &mut pico,
+                &mut guard,
+                &mut location::Strategy::default(),
+                &mut store(),
+                &mut tracklog(),
+                &mut Config::default(),
+                &mut concat::Reassembler::default(),
+            )
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_storage_full_sim_emulated() {
+        // the listing of the 50 stored messages does not fit in the ingress buffer, the slots
+        // are read one by one
+        let mut sim = sim868_emu::Sim868::default();
+        sim.sms_capacity = 50;
+        let mut harness = crate::at::tests::Harness::new(sim);
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut guard = Guard::new(ProtectorConfig::default());
+        let mut store = store();
+        let mut config = Config::default();
+        let mut reassembler = concat::Reassembler::default();
+
+        harness
+            .run(async |client| sms::init(client, &mut pico).await)
+            .await
+            .unwrap();
+        let long = "x".repeat(70);
+        harness.modem.with(|sim| {
+            for i in 0..50 {
+                let sender = if i == 42 {
+                    "+36301234567"
+                } else {
+                    "+36701234567"
+                };
+                let text = if i == 42 {
+                    "$tATA/config?/12345"
+                } else {
+                    &long
+                };
+                assert!(
+                    sim.receive_sms(sender, text, "26/01/10,17:25:32+04")
+                        .is_some()
+                );
+            }
+        });
+
+        let storage = harness
+            .run(async |client| {
+                check_storage(
+                    client,
+                    &mut pico,
+                    &mut guard,
+                    &mut location::Strategy::default(),
+                    &mut store,
+                    &mut tracklog(),
+                    &mut config,
+                    &mut reassembler,
+                )
+                .await
+            })
+            .await;
+        assert_eq!(Ok(sms::SmsStorage { used: 0, total: 50 }), storage);
+        harness.modem.with(|sim| {
+            assert!(sim.sms.is_empty());
+            // the owner command was executed, not deleted unread
+            assert_eq!(1, sim.sent_sms.len());
+            assert!(sim.sent_sms[0].text.starts_with("owner=+36301234567"));
+        });
+    }
+
+    #[tokio::test]
+    async fn test_process_stored_messages_emulated() {
+        // a short listing fits, a read one is deleted without processing
+        let mut harness = crate::at::tests::Harness::new(sim868_emu::Sim868::default());
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut guard = Guard::new(ProtectorConfig::default());
+        let mut store = store();
+        let mut config = Config::default();
+        let mut reassembler = concat::Reassembler::default();
+
+        harness
+            .run(async |client| sms::init(client, &mut pico).await)
+            .await
+            .unwrap();
+        harness.modem.with(|sim| {
+            for text in ["hello", "$tATA/config?/12345", "$tATA/location/12345"] {
+                sim.receive_sms("+36301234567", text, "26/01/10,17:25:32+04")
+                    .unwrap();
+            }
+            sim.sms.get_mut(&3).unwrap().read = true;
+        });
+
+        let processed = harness
+            .run(async |client| {
+                process_stored_messages(
+                    client,
+                    &mut pico,
+                    &mut guard,
+                    &mut location::Strategy::default(),
+                    &mut store,
+                    &mut tracklog(),
+                    &mut config,
+                    &mut reassembler,
+                )
+                .await
+            })
+            .await;
+        assert_eq!(Ok(2), processed);
+        harness.modem.with(|sim| {
+            assert!(sim.sms.is_empty());
+            assert_eq!(1, sim.sent_sms.len());
+            assert!(sim.sent_sms[0].text.starts_with("owner=+36301234567"));
+        });
+    }
+
+    fn config_with(role: Role) -> Config {
+        let mut config = Config::default();
+        config.contacts[0].role = role;
+        config
+    }
+
+    #[test]
+    fn test_permitted() {
+        let location = parse("$tATA/location/12345", "+36301234567", "12345").unwrap();
+        let park = parse("$tATA/park on/12345", "+36301234567", "12345").unwrap();
+        let call = parse("$tATA/call/12345", "+36301234567", "12345").unwrap();
+        assert!(permitted(Role::Owner, &location));
+        assert!(permitted(Role::Owner, &park));
+        assert!(permitted(Role::Viewer, &location));
+        assert!(!permitted(Role::Viewer, &park));
+        assert!(!permitted(Role::Viewer, &call));
+        assert!(!permitted(Role::Emergency, &location));
+    }
+
+    #[test]
+    fn test_accept_call() {
+        assert!(accept_call(&Config::default(), "06301234567"));
+        assert!(!accept_call(&Config::default(), "+36301234568"));
+        assert!(!accept_call(&config_with(Role::Viewer), "+36301234567"));
+        assert!(!accept_call(&config_with(Role::Emergency), "+36301234567"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_new_message_not_permitted() {
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut guard = Guard::new(ProtectorConfig::default());
+        for (role, expected) in [
+            (None, Error::Rejected("unknown sender")),
+            (Some(Role::Emergency), Error::Rejected("not permitted")),
+        ] {
+            let mut client = crate::at::tests::ClientMock::default();
+            let cmgr = cmgr(&[], SMS_LOCATION);
+            client.results.push_back(Ok(cmgr.as_bytes()));
+            let mut config = match role {
+                Some(r) => config_with(r),
+                None => Config {
+                    contacts: atat::heapless::Vec::new(),
+                    ..Default::default()
+                },
+            };
+            assert_eq!(
+                Err(expected),
+                handle_new_message(
+                    &mut client,
+                    &mut pico,
+                    &mut guard,
+                    &mut location::Strategy::default(),
+                    &mut store(),
+                    &mut tracklog(),
+                    &mut config,
+                    &mut concat::Reassembler::default(),
+                    1
+                )
+                .await
+            );
+            assert_eq!(1, client.sent_commands.len());
+        }
+    }
+}
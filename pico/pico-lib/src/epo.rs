@@ -0,0 +1,315 @@
+use defmt::Format;
+use defmt::debug;
+
+use alloc::string::ToString;
+use atat::UrcSubscription;
+use atat::heapless::String;
+
+use crate::error::Error;
+use crate::fs;
+use crate::gsm;
+use crate::http;
+use crate::urc::Urc;
+use crate::utils::send_command_logged;
+
+// Extended Prediction Orbit, the satellite orbits of the next days for the assisted GNSS
+// (see gps::turn_on). The file is downloaded over HTTP to the file system of the module,
+// AT+CGNSAID reads it from there.
+
+pub const EPO_URL: &str = "http://wepodownload.mediatek.com/EPO_GPS_3_1.DAT";
+pub const EPO_FILE: &str = "C:\\User\\MTK3.EPO";
+pub const VALIDITY_MILLIS: u64 = 3 * 24 * 60 * 60 * 1000; // EPO_GPS_3_1 covers 3 days
+pub const RETRY_MILLIS: u64 = 60 * 60 * 1000;
+
+// Tracks the age of the downloaded file, measured in uptime (see PicoHW::uptime_millis).
+// Lost on restart, the file is downloaded again then.
+#[derive(Clone, Debug, Format, PartialEq)]
+pub struct Epo {
+    pub url: String<100>,
+    downloaded_at: Option<u64>,
+    attempted_at: Option<u64>,
+}
+
+impl Default for Epo {
+    fn default() -> Self {
+        Epo {
+            url: String::try_from(EPO_URL).unwrap(),
+            downloaded_at: None,
+            attempted_at: None,
+        }
+    }
+}
+
+impl Epo {
+    pub fn is_valid(&self, uptime_millis: u64) -> bool {
+        self.downloaded_at
+            .is_some_and(|at| uptime_millis.saturating_sub(at) < VALIDITY_MILLIS)
+    }
+
+    // Not valid and not attempted in the last RETRY_MILLIS, the server or the network
+    // may be down for a while.
+    pub fn is_due(&self, uptime_millis: u64) -> bool {
+        !self.is_valid(uptime_millis)
+            && self
+                .attempted_at
+                .is_none_or(|at| uptime_millis.saturating_sub(at) >= RETRY_MILLIS)
+    }
+}
+
+// Downloads the file to EPO_FILE, the bearer has to be open (see gsm::Bearer).
+// Returns the size of the file.
+pub async fn download<
+    T: atat::asynch::AtatClient,
+    U: crate::at::PicoHW,
+    const CAP: usize,
+    const SUBS: usize,
+>(
+    client: &mut T,
+    pico: &mut U,
+    urcs: &mut UrcSubscription<'_, Urc, CAP, SUBS>,
+    url: &str,
+) -> Result<usize, Error> {
+    send_command_logged(
+        client,
+        &http::AtHttpInitExecute,
+        "AtHttpInitExecute".to_string(),
+    )
+    .await?;
+    let ret = download_file(client, pico, urcs, url).await;
+    send_command_logged(
+        client,
+        &http::AtHttpTermExecute,
+        "AtHttpTermExecute".to_string(),
+    )
+    .await
+    .ok();
+    ret
+}
+
+async fn download_file<
+    T: atat::asynch::AtatClient,
+    U: crate::at::PicoHW,
+    const CAP: usize,
+    const SUBS: usize,
+>(
+    client: &mut T,
+    pico: &mut U,
+    urcs: &mut UrcSubscription<'_, Urc, CAP, SUBS>,
+    url: &str,
+) -> Result<usize, Error> {
+    let action = http::action(client, pico, urcs, &http::Request::get(url)).await?;
+    if action.status != 200 {
+        debug!("EPO download failed: HTTP {}", action.status);
+        return Err(Error::Protocol("EPO download failed"));
+    }
+
+    let filename = String::<40>::try_from(EPO_FILE).unwrap();
+    send_command_logged(
+        client,
+        &fs::AtFsDeleteWrite {
+            filename: filename.clone(),
+        },
+        "AtFsDeleteWrite".to_string(),
+    )
+    .await
+    .ok(); // not there yet
+    send_command_logged(
+        client,
+        &fs::AtFsCreateWrite { filename },
+        "AtFsCreateWrite".to_string(),
+    )
+    .await?;
+
+    let size = action.size as usize;
+    let mut offset = 0;
+    while offset < size {
+        let chunk = send_command_logged(
+            client,
+            &http::AtHttpReadWrite {
+                start: offset as u32,
+                size: (size - offset).min(http::READ_CHUNK) as u32,
+            },
+            "AtHttpReadWrite".to_string(),
+        )
+        .await?;
+        // less than announced by +HTTPACTION
+        if chunk.data.is_empty() {
+            return Err(Error::Protocol("EPO file short"));
+        }
+        fs::append(client, EPO_FILE, &chunk.data).await?;
+        offset += chunk.data.len();
+    }
+    Ok(size)
+}
+
+// Opens the bearer for the download and closes it again.
+pub async fn refresh<
+    T: atat::asynch::AtatClient,
+    U: crate::at::PicoHW,
+    const CAP: usize,
+    const SUBS: usize,
+>(
+    client: &mut T,
+    pico: &mut U,
+    urcs: &mut UrcSubscription<'_, Urc, CAP, SUBS>,
+    epo: &mut Epo,
+    bearer: &mut gsm::Bearer,
+    apn: &str,
+) -> Result<(), Error> {
+    epo.attempted_at = Some(pico.uptime_millis());
+    let url = epo.url.clone();
+    let size = bearer
+        .scoped(client, pico, apn, async |client, pico| {
+            download(client, pico, urcs, &url).await
+        })
+        .await?;
+    debug!("EPO downloaded: {} bytes", size);
+    epo.downloaded_at = Some(pico.uptime_millis());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::at::PicoHW;
+
+    #[test]
+    fn test_validity() {
+        let mut epo = Epo::default();
+        assert!(!epo.is_valid(0));
+        assert!(epo.is_due(0));
+
+        epo.attempted_at = Some(1000);
+        assert!(!epo.is_due(1000 + RETRY_MILLIS - 1));
+        assert!(epo.is_due(1000 + RETRY_MILLIS));
+
+        epo.downloaded_at = Some(2000);
+        assert!(epo.is_valid(2000 + VALIDITY_MILLIS - 1));
+        assert!(!epo.is_due(2000 + VALIDITY_MILLIS - 1));
+        assert!(!epo.is_valid(2000 + VALIDITY_MILLIS));
+        assert!(epo.is_due(2000 + VALIDITY_MILLIS));
+    }
+
+    #[tokio::test]
+    async fn test_download() {
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .expect("AT+HTTPINIT\r", Ok(b""))
+            .expect("AT+HTTPPARA=\"CID\",\"1\"\r", Ok(b""))
+            .expect("AT+HTTPPARA=\"URL\",\"http://x/a\"\r", Ok(b""))
+            .expect("AT+HTTPACTION=0\r", Ok(b""))
+            .urc(b"+HTTPACTION: 0,200,5")
+            .expect(
+                "AT+FSDEL=\"C:\\User\\MTK3.EPO\"\r",
+                Err(atat::InternalError::Error),
+            )
+            .expect("AT+FSCREATE=\"C:\\User\\MTK3.EPO\"\r", Ok(b""))
+            .expect("AT+HTTPREAD=0,5\r", Ok(b"+HTTPREAD: 2\r\nab"))
+            .expect("AT+FSWRITE=\"C:\\User\\MTK3.EPO\",1,2,10\r", Ok(b">"))
+            .expect("ab", Ok(b""))
+            .expect("AT+HTTPREAD=2,3\r", Ok(b"+HTTPREAD: 3\r\n cd"))
+            .expect("AT+FSWRITE=\"C:\\User\\MTK3.EPO\",1,3,10\r", Ok(b">"))
+            .expect(" cd", Ok(b""))
+            .expect("AT+HTTPTERM\r", Ok(b""));
+        let mut urcs = client.subscribe();
+        let mut pico = crate::at::tests::PicoMock::default();
+        assert_eq!(
+            Ok(5),
+            download(&mut client, &mut pico, &mut urcs, "http://x/a").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_not_found() {
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .expect("AT+HTTPINIT\r", Ok(b""))
+            .expect("AT+HTTPPARA=\"CID\",\"1\"\r", Ok(b""))
+            .expect("AT+HTTPPARA=\"URL\",\"http://x/a\"\r", Ok(b""))
+            .expect("AT+HTTPACTION=0\r", Ok(b""))
+            .urc(b"+HTTPACTION: 0,404,0")
+            .expect("AT+HTTPTERM\r", Ok(b""));
+        let mut urcs = client.subscribe();
+        let mut pico = crate::at::tests::PicoMock::default();
+        assert_eq!(
+            Err(Error::Protocol("EPO download failed")),
+            download(&mut client, &mut pico, &mut urcs, "http://x/a").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refresh_emulated() {
+        // binary, taken by its length: whitespace at the end of a chunk and of the file, a final
+        // result and URCs in the data
+        let mut body: alloc::vec::Vec<u8> = (0..1200).map(|i| (i % 251) as u8).collect();
+        for (at, bytes) in [
+            (100, &b"\r\nOK\r\n"[..]),
+            (200, b"\r\nERROR\r\n"),
+            (300, b"\r\n+CMTI: \"SM\",1\r\n"),
+            (400, b"\r\nRING\r\n"),
+            (508, b"\t\r\n "),
+            (1196, b"\r\n\t "),
+        ] {
+            body[at..at + bytes.len()].copy_from_slice(bytes);
+        }
+        let mut sim = sim868_emu::Sim868::default();
+        sim.http_server
+            .insert(EPO_URL.to_string(), (200, body.clone()));
+        let mut harness = crate::at::tests::Harness::new(sim);
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut urcs = harness.urc_channel.subscribe().unwrap();
+        let mut epo = Epo::default();
+
+        let ret = harness
+            .run(async |client| {
+                refresh(
+                    client,
+                    &mut pico,
+                    &mut urcs,
+                    &mut epo,
+                    &mut gsm::Bearer::default(),
+                    "online",
+                )
+                .await
+            })
+            .await;
+        assert_eq!(Ok(()), ret);
+        assert!(epo.is_valid(pico.uptime_millis()));
+        assert_eq!(
+            Some(body),
+            harness.modem.with(|sim| sim.files.get(EPO_FILE).cloned())
+        );
+        assert!(!harness.modem.with(|sim| sim.bearer_open || sim.http_init));
+
+        // the GNSS takes it from there
+        let ret = harness
+            .run(async |client| crate::gps::turn_on(client, crate::gps::StartMode::Hot, true).await)
+            .await;
+        assert_eq!(Ok(()), ret);
+        assert!(harness.modem.with(|sim| sim.epo_injected));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_emulated_not_found() {
+        let mut harness = crate::at::tests::Harness::new(sim868_emu::Sim868::default());
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut urcs = harness.urc_channel.subscribe().unwrap();
+        let mut epo = Epo::default();
+
+        let ret = harness
+            .run(async |client| {
+                refresh(
+                    client,
+                    &mut pico,
+                    &mut urcs,
+                    &mut epo,
+                    &mut gsm::Bearer::default(),
+                    "online",
+                )
+                .await
+            })
+            .await;
+        assert_eq!(Err(Error::Protocol("EPO download failed")), ret);
+        assert!(!epo.is_valid(pico.uptime_millis()));
+        assert!(!epo.is_due(pico.uptime_millis()));
+        assert!(!harness.modem.with(|sim| sim.bearer_open || sim.http_init));
+    }
+}
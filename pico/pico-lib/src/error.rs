@@ -0,0 +1,147 @@
+use core::fmt;
+use core::num::ParseFloatError;
+use core::num::ParseIntError;
+use core::str::Utf8Error;
+
+use defmt::Format;
+
+use crate::gsm::LocationCode;
+
+// The error of every fallible function of the crate. The &'static str is the context, e.g.
+// Parse("PDU too short"), it is shown to the user as is, see dispatcher::error_reply.
+//
+// Every public async function returns a Result, except the ones whose caller could not do
+// anything with the error, these log it and go on:
+//
+//   dispatcher::execute        a failed locate is the "no location" reply of the command
+//   dispatcher::send_alerts    a failed SMS does not stop the ones to the other numbers
+//   gps::turn_off              the GNSS is left on at worst, it is turned off on every path
+//   gsm::Bearer::release       the next acquire opens the bearer again whatever state it is in
+//   connectivity::Supervisor   poll turns the failures into the backoff, stop gives up the
+//                              reference only, both return the Link instead
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub enum Error {
+    Modem,                  // ERROR without a code
+    Cme(u16),               // +CME ERROR: <err>, equipment, e.g. 10 SIM not inserted
+    Cms(u16),               // +CMS ERROR: <err>, network and SMS, e.g. 322 memory full
+    Timeout,                // no response from the module
+    Serial,                 // reading or writing the UART failed
+    Parse(&'static str),    // invalid response or input
+    Protocol(&'static str), // unexpected response or message, e.g. a status report
+    Capacity(&'static str), // does not fit in the (heapless) buffer
+    Rejected(&'static str), // not permitted, e.g. a command from an unknown number
+    Storage(&'static str),  // the persistent storage (flash) failed
+    Clbs(LocationCode),     // the location server answered an error, e.g. DNSError
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Modem => write!(f, "modem error"),
+            Error::Cme(code) => write!(f, "CME ERROR {}", code),
+            Error::Cms(code) => write!(f, "CMS ERROR {}", code),
+            Error::Timeout => write!(f, "timeout"),
+            Error::Serial => write!(f, "serial error"),
+            Error::Clbs(code) => write!(f, "{}", code),
+            Error::Parse(s)
+            | Error::Protocol(s)
+            | Error::Capacity(s)
+            | Error::Rejected(s)
+            | Error::Storage(s) => {
+                write!(f, "{}", s)
+            }
+        }
+    }
+}
+
+impl From<atat::Error> for Error {
+    fn from(value: atat::Error) -> Self {
+        match value {
+            atat::Error::Read | atat::Error::Write => Error::Serial,
+            atat::Error::Timeout => Error::Timeout,
+            atat::Error::InvalidResponse => Error::Protocol("invalid response"),
+            atat::Error::Aborted => Error::Protocol("aborted"),
+            atat::Error::Parse => Error::Parse("response"),
+            atat::Error::Error | atat::Error::Custom => Error::Modem,
+            atat::Error::CmeError(e) => Error::Cme(e as u16),
+            atat::Error::CmsError(e) => Error::Cms(e as u16),
+            atat::Error::ConnectionError(_) => Error::Protocol("connection error"),
+        }
+    }
+}
+
+// For the parse functions of the commands (#[at_cmd(..., parse = ...)]), atat turns every
+// error of those into atat::Error::Parse.
+impl From<ParseFloatError> for Error {
+    fn from(_: ParseFloatError) -> Self {
+        Error::Parse("invalid number")
+    }
+}
+
+impl From<ParseIntError> for Error {
+    fn from(_: ParseIntError) -> Self {
+        Error::Parse("invalid number")
+    }
+}
+
+impl From<Utf8Error> for Error {
+    fn from(_: Utf8Error) -> Self {
+        Error::Parse("invalid UTF-8")
+    }
+}
+
+impl From<atat::serde_at::de::Error> for Error {
+    fn from(_: atat::serde_at::de::Error) -> Self {
+        Error::Parse("response")
+    }
+}
+
+impl From<()> for Error {
+    fn from(_: ()) -> Self {
+        Error::Parse("unexpected response")
+    }
+}
+
+// For the sockets of ip::Stack (embedded-nal-async).
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        match self {
+            Error::Timeout => embedded_io_async::ErrorKind::TimedOut,
+            Error::Parse(_) | Error::Protocol(_) => embedded_io_async::ErrorKind::InvalidData,
+            Error::Capacity(_) => embedded_io_async::ErrorKind::OutOfMemory,
+            Error::Rejected(_) => embedded_io_async::ErrorKind::PermissionDenied,
+            _ => embedded_io_async::ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_atat_error() {
+        assert_eq!(Error::Timeout, Error::from(atat::Error::Timeout));
+        assert_eq!(
+            Error::Cms(322),
+            Error::from(atat::Error::CmsError(atat::CmsError::MemoryFull))
+        );
+        assert_eq!(
+            Error::Cme(20),
+            Error::from(atat::Error::CmeError(atat::CmeError::MemoryFull))
+        );
+        assert_eq!(Error::Modem, Error::from(atat::Error::Error));
+        assert_eq!(Error::Serial, Error::from(atat::Error::Write));
+        assert_eq!(Error::Parse("response"), Error::from(atat::Error::Parse));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!("CMS ERROR 322", alloc::format!("{}", Error::Cms(322)));
+        assert_eq!(
+            "invalid password",
+            alloc::format!("{}", Error::Rejected("invalid password"))
+        );
+        assert_eq!("timeout", alloc::format!("{}", Error::Timeout));
+    }
+}
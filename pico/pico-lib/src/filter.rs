@@ -0,0 +1,281 @@
+use defmt::Format;
+use defmt::info;
+
+use crate::error::Error;
+use crate::gps::GnssNavigationInformationResponse;
+use crate::location::Location;
+use crate::utils;
+
+// A single bad fix (a GNSS fix with a poor geometry, a CLBS fix from a distant tower) looks
+// like the car has moved, the theft detection must not see it.
+//
+// Gate: the GNSS fix is checked against the quality figures of +CGNSINF, a poor one is
+// treated as no fix (see gps::get_gps_location).
+// Filter: a fix implying an implausible speed from the previous one is rejected, the rest is
+// smoothed by a Kalman filter with a random walk model, the accuracy of the result is the
+// standard deviation of the estimate.
+
+#[derive(Clone, Debug, Format, PartialEq)]
+pub struct Gate {
+    pub max_hdop: f64,
+    pub max_pdop: f64,
+    pub min_satellites: u8,
+    pub max_hpa: f64, // meters
+}
+
+impl Default for Gate {
+    fn default() -> Self {
+        Gate {
+            max_hdop: 5.0,
+            max_pdop: 6.0,
+            min_satellites: 4,
+            max_hpa: 50.0,
+        }
+    }
+}
+
+impl Gate {
+    // A figure not reported by the module is not checked.
+    pub fn check(&self, resp: &GnssNavigationInformationResponse) -> Result<(), Error> {
+        let reason = if resp.hdop.is_some_and(|v| v > self.max_hdop) {
+            "HDOP"
+        } else if resp.pdop.is_some_and(|v| v > self.max_pdop) {
+            "PDOP"
+        } else if resp
+            .gnss_satellites_used
+            .is_some_and(|v| v < self.min_satellites)
+        {
+            "satellites"
+        } else if resp.hpa.is_some_and(|v| v > self.max_hpa) {
+            "HPA"
+        } else {
+            return Ok(());
+        };
+        info!("GPS fix rejected by {}", reason);
+        Err(Error::Rejected("poor GPS fix"))
+    }
+}
+
+const MIN_SPEED_MPS: f64 = 0.5; // the drift of a parked car, see Filter::apply
+const CONSISTENT_SIGMAS: f64 = 3.0;
+
+#[derive(Clone, Debug, Format, PartialEq)]
+struct Estimate {
+    latitude: f64,
+    longitude: f64,
+    variance: f64, // m^2
+    unix_timestamp_millis: i64,
+}
+
+#[derive(Clone, Debug, Format, PartialEq)]
+pub struct Filter {
+    pub max_speed_kmh: f64,
+    pub max_rejections: u8, // in a row, the car may really be there, e.g. towed
+    estimate: Option<Estimate>,
+    rejections: u8,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter {
+            max_speed_kmh: 200.0,
+            max_rejections: 3,
+            estimate: None,
+            rejections: 0,
+        }
+    }
+}
+
+impl Filter {
+    pub fn reset(&mut self) {
+        self.estimate = None;
+        self.rejections = 0;
+    }
+
+    // Returns the smoothed location, the time, the source, the speed and the course are the
+    // ones of the fix.
+    pub fn apply(&mut self, location: &Location) -> Result<Location, Error> {
+        let variance = location.accuracy * location.accuracy;
+        let Some(estimate) = self.estimate.as_mut() else {
+            return Ok(self.restart(location));
+        };
+
+        let distance = utils::get_distance_in_meters(
+            estimate.latitude,
+            estimate.longitude,
+            location.latitude,
+            location.longitude,
+        );
+        // at least a second, the fixes of the same time are compared as if it had passed
+        let seconds = (location.unix_timestamp_millis - estimate.unix_timestamp_millis).max(1000)
+            as f64
+            / 1000.0;
+        let unexplained = distance - libm::sqrt(estimate.variance) - location.accuracy;
+        if unexplained.max(0.0) / seconds * 3.6 > self.max_speed_kmh {
+            self.rejections += 1;
+            if self.rejections < self.max_rejections {
+                info!("Location rejected, {} m in {} s", distance, seconds);
+                return Err(Error::Rejected("implausible location jump"));
+            }
+            info!("Location accepted after {} rejections", self.rejections);
+            return Ok(self.restart(location));
+        }
+        self.rejections = 0;
+
+        // the car moves at the reported speed at most, a parked one drifts a little
+        let speed = location
+            .speed
+            .map_or(MIN_SPEED_MPS, |s| s / 3.6)
+            .max(MIN_SPEED_MPS);
+        let predicted = estimate.variance + (speed * seconds) * (speed * seconds);
+        if distance > CONSISTENT_SIGMAS * libm::sqrt(predicted + variance) {
+            // it has moved more than the model allows, e.g. a missed start of a trip
+            return Ok(self.restart(location));
+        }
+        let gain = predicted / (predicted + variance);
+        estimate.latitude += gain * (location.latitude - estimate.latitude);
+        estimate.longitude += gain * (location.longitude - estimate.longitude);
+        estimate.variance = (1.0 - gain) * predicted;
+        estimate.unix_timestamp_millis = location
+            .unix_timestamp_millis
+            .max(estimate.unix_timestamp_millis);
+        Ok(Location {
+            latitude: estimate.latitude,
+            longitude: estimate.longitude,
+            accuracy: libm::sqrt(estimate.variance),
+            ..location.clone()
+        })
+    }
+
+    fn restart(&mut self, location: &Location) -> Location {
+        self.rejections = 0;
+        self.estimate = Some(Estimate {
+            latitude: location.latitude,
+            longitude: location.longitude,
+            variance: location.accuracy * location.accuracy,
+            unix_timestamp_millis: location.unix_timestamp_millis,
+        });
+        location.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::location::Source;
+
+    const SECOND: i64 = 1000;
+
+    fn fix(latitude: f64, accuracy: f64, second: i64, speed: Option<f64>) -> Location {
+        Location {
+            latitude,
+            longitude: 18.6304591,
+            accuracy,
+            unix_timestamp_millis: 1670846541000 + second * SECOND,
+            source: Source::Gnss,
+            speed,
+            course: None,
+        }
+    }
+
+    fn response(
+        hdop: f64,
+        pdop: f64,
+        satellites: u8,
+        hpa: Option<f64>,
+    ) -> GnssNavigationInformationResponse {
+        GnssNavigationInformationResponse {
+            hdop: Some(hdop),
+            pdop: Some(pdop),
+            gnss_satellites_used: Some(satellites),
+            hpa,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_gate() {
+        let gate = Gate::default();
+        assert_eq!(Ok(()), gate.check(&response(2.1, 2.3, 6, None)));
+        assert_eq!(Ok(()), gate.check(&response(2.1, 2.3, 6, Some(12.5))));
+        assert_eq!(
+            Ok(()),
+            gate.check(&GnssNavigationInformationResponse::default())
+        );
+        for resp in [
+            response(5.1, 2.3, 6, None),
+            response(2.1, 6.1, 6, None),
+            response(2.1, 2.3, 3, None),
+            response(2.1, 2.3, 6, Some(50.1)),
+        ] {
+            assert_eq!(Err(Error::Rejected("poor GPS fix")), gate.check(&resp));
+        }
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let mut filter = Filter::default();
+        let first = fix(46.7624859, 10.0, 0, Some(0.0));
+        assert_eq!(Ok(first.clone()), filter.apply(&first));
+
+        // parked, the jitter is averaged, the estimate gets better
+        let second = filter.apply(&fix(46.7625859, 10.0, 10, Some(0.0))).unwrap();
+        assert!(second.latitude > 46.7624859 && second.latitude < 46.7625859);
+        assert!(second.accuracy < 10.0);
+        let third = filter.apply(&fix(46.7624859, 10.0, 20, Some(0.0))).unwrap();
+        assert!(third.accuracy < second.accuracy);
+
+        // on the move the fix is followed
+        let moving = fix(46.7634859, 10.0, 30, Some(50.0));
+        let smoothed = filter.apply(&moving).unwrap();
+        assert!((smoothed.latitude - moving.latitude).abs() < 0.00002);
+        assert_eq!(Some(50.0), smoothed.speed);
+        assert_eq!(moving.unix_timestamp_millis, smoothed.unix_timestamp_millis);
+
+        // a cell tower fix barely moves a good estimate, the accuracy stays honest
+        let cell = Location {
+            source: Source::Cell,
+            ..fix(46.7674859, 1000.0, 60, None)
+        };
+        let smoothed = filter.apply(&cell).unwrap();
+        assert!((smoothed.latitude - moving.latitude).abs() < 0.0001);
+        assert!(smoothed.accuracy > 10.0 && smoothed.accuracy < 100.0);
+        assert_eq!(Source::Cell, smoothed.source);
+    }
+
+    #[test]
+    fn test_jump_rejected() {
+        let mut filter = Filter::default();
+        filter.apply(&fix(46.7624859, 10.0, 0, Some(0.0))).unwrap();
+        // 11 km in a minute
+        let far = fix(46.8624859, 10.0, 60, Some(0.0));
+        assert_eq!(
+            Err(Error::Rejected("implausible location jump")),
+            filter.apply(&far)
+        );
+        // in reach, the rejections in a row start over
+        filter
+            .apply(&fix(46.7625859, 10.0, 120, Some(0.0)))
+            .unwrap();
+        assert!(filter.apply(&far).is_err());
+        assert!(filter.apply(&far).is_err());
+        // it is there indeed
+        assert_eq!(Ok(far.clone()), filter.apply(&far));
+        let next = fix(46.8624859, 10.0, 180, Some(0.0));
+        assert_eq!(46.8624859, filter.apply(&next).unwrap().latitude);
+
+        // an inaccurate fix is not a jump
+        filter.reset();
+        filter.apply(&fix(46.7624859, 10.0, 0, Some(0.0))).unwrap();
+        assert!(filter.apply(&fix(46.7674859, 1000.0, 10, None)).is_ok());
+    }
+
+    #[test]
+    fn test_inconsistent_fix_restarts() {
+        let mut filter = Filter::default();
+        filter.apply(&fix(46.7624859, 5.0, 0, Some(0.0))).unwrap();
+        // 1.1 km in 10 minutes while reported as standing
+        let moved = fix(46.7724859, 5.0, 600, Some(0.0));
+        assert_eq!(Ok(moved.clone()), filter.apply(&moved));
+    }
+}
@@ -0,0 +1,164 @@
+use defmt::Format;
+
+use alloc::string::ToString;
+use atat::AtatCmd;
+use atat::atat_derive::AtatCmd;
+use atat::atat_derive::AtatEnum;
+use atat::atat_derive::AtatResp;
+use atat::heapless::String;
+use atat::heapless::Vec;
+
+use crate::at::NoResponse;
+use crate::error::Error;
+use crate::utils::send_command_logged;
+
+// SIM800_Series_FS_Application_Note.pdf
+// The file system of the module, the user files are in C:\User\.
+
+pub const WRITE_CHUNK: usize = 512;
+
+// AT+FSCREATE Create a File
+// AT+FSCREATE=<filename>
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+FSCREATE", NoResponse)]
+pub struct AtFsCreateWrite {
+    pub filename: String<40>,
+}
+
+// AT+FSDEL Delete a File
+// AT+FSDEL=<filename>
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+FSDEL", NoResponse)]
+pub struct AtFsDeleteWrite {
+    pub filename: String<40>,
+}
+
+// AT+FSWRITE Write a File
+// AT+FSWRITE=<filename>,<mode>,<size>,<input time>
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+FSWRITE", NoResponse, timeout_ms = 5000)] // NoResponse == waiting for prompt ">"
+pub struct AtFsWriteWrite {
+    pub filename: String<40>,
+    pub mode: FsWriteMode,
+    pub size: u16,      // at most 10240
+    pub input_time: u8, // seconds to send the data, 1..100
+}
+
+#[derive(Debug, Format, Clone, PartialEq, AtatEnum)]
+pub enum FsWriteMode {
+    Overwrite = 0,
+    Append = 1,
+}
+
+// The <size> bytes after the prompt, as is, there is no ctrl-Z.
+#[derive(Clone, Debug)]
+pub struct AtFsData {
+    pub data: Vec<u8, WRITE_CHUNK>,
+}
+
+impl AtatCmd for AtFsData {
+    type Response = NoResponse;
+
+    const MAX_LEN: usize = WRITE_CHUNK;
+    const MAX_TIMEOUT_MS: u32 = 10000;
+
+    fn write(&self, buf: &mut [u8]) -> usize {
+        buf[..self.data.len()].copy_from_slice(&self.data);
+        self.data.len()
+    }
+
+    fn parse(
+        &self,
+        resp: Result<&[u8], atat::InternalError>,
+    ) -> Result<Self::Response, atat::Error> {
+        match resp {
+            Ok(_) => Ok(NoResponse),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// AT+FSFLSIZE Get File Size
+// AT+FSFLSIZE=<filename>
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+FSFLSIZE", FsFileSizeResponse)]
+pub struct AtFsFileSizeWrite {
+    pub filename: String<40>,
+}
+
+// +FSFLSIZE: <size>
+#[derive(Debug, Clone, AtatResp, PartialEq, Default)]
+pub struct FsFileSizeResponse {
+    pub size: u32,
+}
+
+// Appends a chunk to the file, it has to exist (see AtFsCreateWrite).
+pub async fn append<T: atat::asynch::AtatClient>(
+    client: &mut T,
+    filename: &str,
+    data: &[u8],
+) -> Result<(), Error> {
+    let filename = String::try_from(filename).map_err(|_| Error::Capacity("file name"))?;
+    let data = Vec::from_slice(data).map_err(|_| Error::Capacity("file chunk"))?;
+    send_command_logged(
+        client,
+        &AtFsWriteWrite {
+            filename,
+            mode: FsWriteMode::Append,
+            size: data.len() as u16,
+            input_time: 10,
+        },
+        "AtFsWriteWrite".to_string(),
+    )
+    .await?;
+    send_command_logged(client, &AtFsData { data }, "AtFsData".to_string()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cmd_serialization_tests;
+
+    use super::*;
+
+    cmd_serialization_tests! {
+        test_at_fs_create: (
+            AtFsCreateWrite {
+                filename: String::try_from("C:\\User\\MTK3.EPO").unwrap(),
+            },
+            "AT+FSCREATE=\"C:\\User\\MTK3.EPO\"\r",
+        ),
+        test_at_fs_delete: (
+            AtFsDeleteWrite {
+                filename: String::try_from("C:\\User\\MTK3.EPO").unwrap(),
+            },
+            "AT+FSDEL=\"C:\\User\\MTK3.EPO\"\r",
+        ),
+        test_at_fs_write: (
+            AtFsWriteWrite {
+                filename: String::try_from("C:\\User\\MTK3.EPO").unwrap(),
+                mode: FsWriteMode::Append,
+                size: 512,
+                input_time: 10,
+            },
+            "AT+FSWRITE=\"C:\\User\\MTK3.EPO\",1,512,10\r",
+        ),
+        test_at_fs_file_size: (
+            AtFsFileSizeWrite {
+                filename: String::try_from("C:\\User\\MTK3.EPO").unwrap(),
+            },
+            "AT+FSFLSIZE=\"C:\\User\\MTK3.EPO\"\r",
+        ),
+    }
+
+    #[tokio::test]
+    async fn test_append() {
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .expect("AT+FSWRITE=\"C:\\User\\A.BIN\",1,4,10\r", Ok(b">"))
+            .expect("\x00\r\n\x1a", Ok(b""));
+        assert_eq!(
+            Ok(()),
+            append(&mut client, "C:\\User\\A.BIN", b"\x00\r\n\x1a").await
+        );
+    }
+}
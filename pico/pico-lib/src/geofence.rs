@@ -0,0 +1,491 @@
+use alloc::format;
+use alloc::string::String as AString;
+use atat::heapless::String;
+use atat::heapless::Vec;
+use defmt::Format;
+use defmt::info;
+use libm::cos;
+
+use crate::error::Error;
+use crate::location::Location;
+use crate::utils;
+
+// Named zones (home, office, country border, ...) in WGS84, circles or polygons.
+//
+// A fix is inside a zone when it is deeper inside than its accuracy plus the hysteresis,
+// outside when it is that far outside, undecided (no change) in between. So an inaccurate
+// fix (e.g. CLBS) near the border does not flip the state back and forth. The first decided
+// fix of a zone (e.g. after boot) sets its state silently, only the crossings are events.
+
+pub const MAX_ZONES: usize = 4;
+pub const MAX_VERTICES: usize = 8;
+pub const MAX_NAME: usize = 16;
+// A circle is entered when a fix is its accuracy plus the hysteresis inside the border, a
+// radius at or below the hysteresis plus a typical GNSS accuracy (~10 m) is never entered.
+const HYSTERESIS_METERS: f64 = 20.0;
+const GNSS_ACCURACY_METERS: f64 = 10.0;
+const RADIUS_METERS: core::ops::RangeInclusive<u32> = 50..=1_000_000;
+const _: () = assert!(*RADIUS_METERS.start() as f64 > HYSTERESIS_METERS + GNSS_ACCURACY_METERS);
+const EARTH_RADIUS_METERS: f64 = 6371000.0;
+
+// Coordinates in 1e-6 degrees (~0.1 m), exact in the config record and in the replies.
+#[derive(Debug, Format, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub latitude_e6: i32,
+    pub longitude_e6: i32,
+}
+
+impl Point {
+    pub fn new(latitude: f64, longitude: f64) -> Result<Point, Error> {
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return Err(Error::Parse("invalid zone coordinate"));
+        }
+        Ok(Point {
+            latitude_e6: libm::round(latitude * 1e6) as i32,
+            longitude_e6: libm::round(longitude * 1e6) as i32,
+        })
+    }
+
+    pub fn latitude(&self) -> f64 {
+        self.latitude_e6 as f64 / 1e6
+    }
+
+    pub fn longitude(&self) -> f64 {
+        self.longitude_e6 as f64 / 1e6
+    }
+
+    // "lat,lon"
+    fn parse(v: &str) -> Result<Point, Error> {
+        let (latitude, longitude) = v
+            .split_once(',')
+            .ok_or(Error::Parse("invalid zone coordinate"))?;
+        Point::new(
+            latitude
+                .parse()
+                .map_err(|_| Error::Parse("invalid zone coordinate"))?,
+            longitude
+                .parse()
+                .map_err(|_| Error::Parse("invalid zone coordinate"))?,
+        )
+    }
+
+    fn dump(&self) -> AString {
+        format!("{},{}", e6(self.latitude_e6), e6(self.longitude_e6))
+    }
+}
+
+fn e6(v: i32) -> AString {
+    let sign = if v < 0 { "-" } else { "" };
+    let v = v.unsigned_abs();
+    format!("{}{}.{:06}", sign, v / 1_000_000, v % 1_000_000)
+}
+
+#[derive(Debug, Format, Clone, PartialEq)]
+pub enum Shape {
+    Circle { center: Point, radius_meters: u32 },
+    Polygon(Vec<Point, MAX_VERTICES>), // closed implicitly, not self-intersecting
+}
+
+impl Shape {
+    // "lat,lon,radius" or "lat,lon;lat,lon;lat,lon[;...]"
+    pub fn parse(v: &str) -> Result<Shape, Error> {
+        if v.contains(';') {
+            let mut points = Vec::new();
+            for p in v.split(';') {
+                points
+                    .push(Point::parse(p)?)
+                    .map_err(|_| Error::Capacity("too many zone vertices"))?;
+            }
+            if points.len() < 3 {
+                return Err(Error::Parse("invalid zone polygon"));
+            }
+            return Ok(Shape::Polygon(points));
+        }
+        let (center, radius) = v
+            .rsplit_once(',')
+            .ok_or(Error::Parse("invalid zone circle"))?;
+        let radius_meters = radius
+            .parse()
+            .map_err(|_| Error::Parse("invalid zone circle"))?;
+        if !RADIUS_METERS.contains(&radius_meters) {
+            return Err(Error::Parse("invalid zone radius"));
+        }
+        Ok(Shape::Circle {
+            center: Point::parse(center)?,
+            radius_meters,
+        })
+    }
+
+    pub fn dump(&self) -> AString {
+        match self {
+            Shape::Circle {
+                center,
+                radius_meters,
+            } => format!("{},{}", center.dump(), radius_meters),
+            Shape::Polygon(points) => points
+                .iter()
+                .map(|p| p.dump())
+                .collect::<alloc::vec::Vec<_>>()
+                .join(";"),
+        }
+    }
+
+    // Distance from the border in meters, negative inside.
+    pub fn distance(&self, latitude: f64, longitude: f64) -> f64 {
+        match self {
+            Shape::Circle {
+                center,
+                radius_meters,
+            } => {
+                utils::get_distance_in_meters(
+                    center.latitude(),
+                    center.longitude(),
+                    latitude,
+                    longitude,
+                ) - *radius_meters as f64
+            }
+            Shape::Polygon(points) => polygon_distance(points, latitude, longitude),
+        }
+    }
+}
+
+// Equirectangular projection around the fix, good enough for zones of a few hundred
+// kilometers away from the poles. Ray casting for inside, nearest edge for the distance.
+fn polygon_distance(points: &[Point], latitude: f64, longitude: f64) -> f64 {
+    let scale = EARTH_RADIUS_METERS * core::f64::consts::PI / 180.0;
+    let project = |p: &Point| {
+        (
+            (p.longitude() - longitude) * scale * cos(latitude.to_radians()),
+            (p.latitude() - latitude) * scale,
+        )
+    };
+
+    let mut inside = false;
+    let mut nearest = f64::MAX;
+    for (i, p) in points.iter().enumerate() {
+        let (ax, ay) = project(p);
+        let (bx, by) = project(&points[(i + 1) % points.len()]);
+        if (ay > 0.0) != (by > 0.0) && ax + (0.0 - ay) * (bx - ax) / (by - ay) > 0.0 {
+            inside = !inside;
+        }
+        // distance of the origin (the fix) from the a-b segment
+        let (dx, dy) = (bx - ax, by - ay);
+        let length = dx * dx + dy * dy;
+        let t = if length > 0.0 {
+            (-(ax * dx + ay * dy) / length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let (x, y) = (ax + t * dx, ay + t * dy);
+        nearest = nearest.min(libm::sqrt(x * x + y * y));
+    }
+    if inside { -nearest } else { nearest }
+}
+
+#[derive(Debug, Format, Clone, PartialEq)]
+pub struct Zone {
+    pub name: String<MAX_NAME>,
+    pub shape: Shape,
+}
+
+impl Zone {
+    pub fn new(name: &str, shape: Shape) -> Result<Zone, Error> {
+        if name.is_empty()
+            || !name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+        {
+            return Err(Error::Parse("invalid zone name"));
+        }
+        Ok(Zone {
+            name: String::try_from(name).map_err(|_| Error::Parse("invalid zone name"))?,
+            shape,
+        })
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq)]
+pub enum Presence {
+    Unknown,
+    Inside,
+    Outside,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq)]
+pub enum Crossing {
+    Enter,
+    Exit,
+}
+
+#[derive(Debug, Format, Clone, PartialEq)]
+pub struct Event {
+    pub zone: String<MAX_NAME>,
+    pub crossing: Crossing,
+}
+
+#[derive(Debug)]
+pub struct Geofence {
+    pub hysteresis_meters: f64,
+    zones: Vec<(Zone, Presence), MAX_ZONES>,
+}
+
+impl Default for Geofence {
+    fn default() -> Self {
+        Geofence {
+            hysteresis_meters: HYSTERESIS_METERS,
+            zones: Vec::new(),
+        }
+    }
+}
+
+impl Geofence {
+    // The state of the unchanged zones is kept, e.g. on a config change.
+    pub fn set_zones(&mut self, zones: &[Zone]) {
+        let mut updated = Vec::new();
+        for zone in zones.iter().take(MAX_ZONES) {
+            let presence = self
+                .zones
+                .iter()
+                .find(|(z, _)| z == zone)
+                .map_or(Presence::Unknown, |(_, p)| *p);
+            let _ = updated.push((zone.clone(), presence));
+        }
+        self.zones = updated;
+    }
+
+    pub fn presence(&self, name: &str) -> Option<Presence> {
+        self.zones
+            .iter()
+            .find(|(z, _)| z.name == name)
+            .map(|(_, p)| *p)
+    }
+
+    pub fn update(&mut self, location: &Location) -> Vec<Event, MAX_ZONES> {
+        let margin = location.accuracy + self.hysteresis_meters;
+        let mut events = Vec::new();
+        for (zone, presence) in self.zones.iter_mut() {
+            let distance = zone.shape.distance(location.latitude, location.longitude);
+            let decided = if distance < -margin {
+                Presence::Inside
+            } else if distance > margin {
+                Presence::Outside
+            } else {
+                continue;
+            };
+            let crossing = match (*presence, decided) {
+                (Presence::Outside, Presence::Inside) => Some(Crossing::Enter),
+                (Presence::Inside, Presence::Outside) => Some(Crossing::Exit),
+                _ => None,
+            };
+            *presence = decided;
+            if let Some(crossing) = crossing {
+                info!("Geofence: {} {:?}", zone.name, crossing);
+                let _ = events.push(Event {
+                    zone: zone.name.clone(),
+                    crossing,
+                });
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loc(latitude: f64, longitude: f64, accuracy: f64) -> Location {
+        Location {
+            latitude,
+            longitude,
+            accuracy,
+            unix_timestamp_millis: 1670846541000,
+            source: crate::location::Source::Gnss,
+            speed: None,
+            course: None,
+        }
+    }
+
+    fn home() -> Zone {
+        Zone::new("home", Shape::parse("46.762486,18.630459,200").unwrap()).unwrap()
+    }
+
+    // ~1.1 x 1.5 km around the home
+    fn town() -> Zone {
+        Zone::new(
+            "town",
+            Shape::parse("46.757,18.620;46.767,18.620;46.767,18.640;46.757,18.640").unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_dump() {
+        assert_eq!(
+            Shape::Circle {
+                center: Point {
+                    latitude_e6: 46762486,
+                    longitude_e6: 18630459
+                },
+                radius_meters: 200
+            },
+            home().shape
+        );
+        assert_eq!("46.762486,18.630459,200", home().shape.dump());
+        assert_eq!(
+            "46.757000,18.620000;46.767000,18.620000;46.767000,18.640000;46.757000,18.640000",
+            town().shape.dump()
+        );
+        assert_eq!(
+            "-33.868820,-151.209300,50",
+            Shape::parse("-33.86882,-151.2093,50").unwrap().dump()
+        );
+
+        assert_eq!(
+            Err(Error::Parse("invalid zone radius")),
+            Shape::parse("46.7,18.6,5")
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid zone coordinate")),
+            Shape::parse("96.7,18.6,50")
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid zone polygon")),
+            Shape::parse("46.7,18.6;46.8,18.6")
+        );
+        assert_eq!(
+            Err(Error::Capacity("too many zone vertices")),
+            Shape::parse("1,1;1,2;1,3;1,4;1,5;1,6;1,7;1,8;1,9")
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid zone name")),
+            Zone::new("my home", home().shape)
+        );
+    }
+
+    #[test]
+    fn test_distance() {
+        let home = home().shape;
+        assert!((home.distance(46.762486, 18.630459) + 200.0).abs() < 0.01);
+        // ~1112 meters north
+        assert!((home.distance(46.772486, 18.630459) - 912.0).abs() < 1.0);
+
+        let town = town().shape;
+        // ~556 meters from the north and the south edges
+        assert!((town.distance(46.762, 18.630) + 556.0).abs() < 1.0);
+        // ~762 meters east of the east edge
+        assert!((town.distance(46.762, 18.650) - 762.0).abs() < 1.0);
+        // nearest is the south-east corner
+        let d = utils::get_distance_in_meters(46.757, 18.640, 46.750, 18.650);
+        assert!((town.distance(46.750, 18.650) - d).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_enter_exit() {
+        let mut geofence = Geofence::default();
+        geofence.set_zones(&[home(), town()]);
+        assert_eq!(Some(Presence::Unknown), geofence.presence("home"));
+
+        // the first decision is silent
+        assert!(geofence.update(&loc(46.762486, 18.630459, 10.0)).is_empty());
+        assert_eq!(Some(Presence::Inside), geofence.presence("home"));
+        assert_eq!(Some(Presence::Inside), geofence.presence("town"));
+
+        // ~330 meters north, 130 meters out of home, but a CLBS fix can not tell
+        assert!(
+            geofence
+                .update(&loc(46.765486, 18.630459, 550.0))
+                .is_empty()
+        );
+        assert_eq!(Some(Presence::Inside), geofence.presence("home"));
+        assert_eq!(
+            alloc::vec![Event {
+                zone: String::try_from("home").unwrap(),
+                crossing: Crossing::Exit
+            }],
+            geofence.update(&loc(46.765486, 18.630459, 10.0)).to_vec()
+        );
+        // ~1.1 km north, out of town as well
+        assert_eq!(
+            alloc::vec![Event {
+                zone: String::try_from("town").unwrap(),
+                crossing: Crossing::Exit
+            }],
+            geofence.update(&loc(46.772486, 18.630459, 10.0)).to_vec()
+        );
+        assert_eq!(
+            alloc::vec![
+                Event {
+                    zone: String::try_from("home").unwrap(),
+                    crossing: Crossing::Enter
+                },
+                Event {
+                    zone: String::try_from("town").unwrap(),
+                    crossing: Crossing::Enter
+                }
+            ],
+            geofence.update(&loc(46.762586, 18.630459, 5.0)).to_vec()
+        );
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let mut geofence = Geofence::default();
+        geofence.set_zones(&[home()]);
+        geofence.update(&loc(46.762486, 18.630459, 5.0));
+        // ~200 meters north, on the border: 10 meters either way is undecided
+        for latitude in [46.764195, 46.764285, 46.764375] {
+            assert!(geofence.update(&loc(latitude, 18.630459, 5.0)).is_empty());
+        }
+        assert_eq!(Some(Presence::Inside), geofence.presence("home"));
+    }
+
+    #[test]
+    fn test_small_radius() {
+        assert_eq!(
+            Err(Error::Parse("invalid zone radius")),
+            Shape::parse("46.762486,18.630459,30")
+        );
+
+        // the smallest zone is entered and left with GNSS fixes
+        let car = Zone::new("car", Shape::parse("46.762486,18.630459,50").unwrap()).unwrap();
+        let mut geofence = Geofence::default();
+        geofence.set_zones(&[car]);
+        // ~100 meters north
+        geofence.update(&loc(46.763386, 18.630459, GNSS_ACCURACY_METERS));
+        assert_eq!(Some(Presence::Outside), geofence.presence("car"));
+        assert_eq!(
+            alloc::vec![Event {
+                zone: String::try_from("car").unwrap(),
+                crossing: Crossing::Enter
+            }],
+            geofence
+                .update(&loc(46.762486, 18.630459, GNSS_ACCURACY_METERS))
+                .to_vec()
+        );
+        assert_eq!(
+            alloc::vec![Event {
+                zone: String::try_from("car").unwrap(),
+                crossing: Crossing::Exit
+            }],
+            geofence
+                .update(&loc(46.763386, 18.630459, GNSS_ACCURACY_METERS))
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn test_set_zones() {
+        let mut geofence = Geofence::default();
+        geofence.set_zones(&[home(), town()]);
+        geofence.update(&loc(46.762486, 18.630459, 10.0));
+
+        let office = Zone::new("office", Shape::parse("47.497912,19.040235,100").unwrap()).unwrap();
+        let bigger = Zone::new("town", Shape::parse("46.762486,18.630459,5000").unwrap()).unwrap();
+        geofence.set_zones(&[home(), bigger, office]);
+        assert_eq!(Some(Presence::Inside), geofence.presence("home"));
+        assert_eq!(Some(Presence::Unknown), geofence.presence("town"));
+        assert_eq!(Some(Presence::Unknown), geofence.presence("office"));
+        assert_eq!(None, geofence.presence("border"));
+    }
+}
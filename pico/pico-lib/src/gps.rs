@@ -7,14 +7,16 @@ use alloc::string::ToString;
 use atat::atat_derive::AtatCmd;
 use atat::atat_derive::AtatEnum;
 use atat::atat_derive::AtatResp;
+use atat::heapless::String;
 use atat::heapless_bytes::Bytes;
 use fasttime::Date;
 use fasttime::DateTime;
 
 use crate::at::NoResponse;
+use crate::error::Error;
+use crate::filter;
 use crate::location;
 use crate::utils;
-use crate::utils::AtatError;
 use crate::utils::as_tokens;
 use crate::utils::bytes_to_string;
 use crate::utils::send_command_logged;
@@ -35,12 +37,82 @@ pub enum PowerMode {
     TurnOn = 1,
 }
 
+// AT+CGNSHOT, AT+CGNSWARM, AT+CGNSCOLD GNSS Hot/Warm/Cold Start
+// The GNSS has to be powered on, a hot start uses the time, the position and the ephemeris
+// it kept, a warm start drops the ephemeris, a cold start everything.
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CGNSHOT", NoResponse)]
+pub struct AtGnssHotStartExecute;
+
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CGNSWARM", NoResponse)]
+pub struct AtGnssWarmStartExecute;
+
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CGNSCOLD", NoResponse)]
+pub struct AtGnssColdStartExecute;
+
+// Auto is whatever the GNSS does on power on, it restarts with the data it kept.
+#[derive(Clone, Copy, Debug, Format, PartialEq, Default)]
+pub enum StartMode {
+    #[default]
+    Auto,
+    Hot,
+    Warm,
+    Cold,
+}
+
+// AT+CGNSAID Send AID Data to GNSS
+// AT+CGNSAID=<mode>,<time>,<position>
+// Mode 31 injects the EPO file of the file system (see epo::download).
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CGNSAID", NoResponse, timeout_ms = 10000)]
+pub struct AtGnssAidWrite {
+    pub mode: u8,
+    pub time: u8,
+    pub position: u8,
+}
+
 // 2.3 AT+CGNSINF GNSS navigation information parsed from NMEA sentences
 // AT+CGNSINF=[<mode>]
 #[derive(Clone, Debug, Format, AtatCmd)]
 #[at_cmd("+CGNSINF", GnssNavigationInformationResponse, parse = parse_gnss_navigation_information)] // TODO: this should not need custom parsing
 pub struct AtGnssNavigationInformationExecute;
 
+// AT+CGNSTST Send Data Received from GNSS Engine to AT UART
+// AT+CGNSTST=<mode>
+// The NMEA sentences are interleaved with the responses and the URCs, see nmea::NmeaReader.
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CGNSTST", NoResponse)]
+pub struct AtGnssNmeaOutputWrite {
+    pub mode: NmeaOutputMode,
+}
+
+#[derive(Debug, Format, Clone, PartialEq, AtatEnum)]
+pub enum NmeaOutputMode {
+    Stop = 0,
+    Start = 1,
+}
+
+// AT+CGNSURC GNSS Navigation URC Report
+// AT+CGNSURC=<n>
+// A +UGNSINF URC after every <n>th fix (one a second), 0 stops it.
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CGNSURC", NoResponse)]
+pub struct AtGnssUrcWrite {
+    pub period: u8,
+}
+
+// How the GNSS reports while streaming: the NMEA sentences (see nmea::NmeaReader), or a
+// +UGNSINF URC per fix, a single line instead of a burst of sentences but without the
+// satellites in view (see nmea::Navigation::update_information).
+#[derive(Clone, Copy, Debug, Format, PartialEq, Default)]
+pub enum StreamOutput {
+    #[default]
+    Nmea,
+    Urc,
+}
+
 // +CGNSINF: <GNSS run status>,<Fix status>,<UTC date & Time>,<Latitude>,<Longitude>,<MSL Altitude>,<Speed Over Ground>,<Course Over Ground>,<Fix Mode>,<Reserved1>,<HDOP>,<PDOP>,<VDOP>,<Reserved2>,<G NSS Satellites in View>,<GNSS Satellites Used>,<GLONASS Satellites Used>,<Reserved3>,<C/N0 max>,<HPA>,<VPA>
 #[derive(Debug, Clone, AtatResp, PartialEq, Default)]
 #[rustfmt::skip]
@@ -91,13 +163,30 @@ pub struct GnssNavigationInformationResponse {  // Length         Format
 
 fn parse_gnss_navigation_information(
     response: &[u8],
-) -> Result<GnssNavigationInformationResponse, AtatError> {
+) -> Result<GnssNavigationInformationResponse, Error> {
     debug!("   parse_gnss_navigation_information input: {:?}", response);
     const LEN: usize = "CGNSINF+: ".len();
     if response.len() < LEN {
         return Err(atat::Error::Parse.into());
     }
-    let text = core::str::from_utf8(&response[LEN..])?; // removes "AT+CGNSINF+", ends with \r\n
+    parse_navigation_fields(core::str::from_utf8(&response[LEN..])?) // removes "AT+CGNSINF+", ends with \r\n
+}
+
+// +UGNSINF: <the fields of +CGNSINF>
+// The derived parser can not take the fields (see parse_gnss_navigation_information), the
+// URC keeps them as text for `information`.
+#[derive(Debug, Clone, AtatResp, PartialEq, Default)]
+pub struct GnssNavigationInformationUrc {
+    pub fields: String<100>, // 94 at most, see GnssNavigationInformationResponse
+}
+
+impl GnssNavigationInformationUrc {
+    pub fn information(&self) -> Result<GnssNavigationInformationResponse, Error> {
+        parse_navigation_fields(&self.fields)
+    }
+}
+
+fn parse_navigation_fields(text: &str) -> Result<GnssNavigationInformationResponse, Error> {
     let mut tokens = as_tokens(text.trim_end().to_string(), ",");
     if tokens.len() != 21 {
         return Err(atat::Error::Parse.into());
@@ -118,7 +207,7 @@ fn parse_gnss_navigation_information(
     };
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: Bytes<18> = serde_at::from_str(text)?;
             resp.utc_date_time = Some(u);
@@ -127,7 +216,7 @@ fn parse_gnss_navigation_information(
     }?;
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: f64 = text.parse()?;
             resp.latitude = Some(u);
@@ -136,7 +225,7 @@ fn parse_gnss_navigation_information(
     }?;
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: f64 = text.parse()?;
             resp.longitude = Some(u);
@@ -145,7 +234,7 @@ fn parse_gnss_navigation_information(
     }?;
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: f64 = text.parse()?;
             resp.msl_altitude = Some(u);
@@ -154,7 +243,7 @@ fn parse_gnss_navigation_information(
     }?;
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: f64 = text.parse()?;
             resp.speed_over_ground = Some(u);
@@ -163,7 +252,7 @@ fn parse_gnss_navigation_information(
     }?;
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: f64 = text.parse()?;
             resp.course_over_ground = Some(u);
@@ -172,7 +261,7 @@ fn parse_gnss_navigation_information(
     }?;
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: u8 = text.parse()?;
             resp.fix_mode = Some(u);
@@ -183,7 +272,7 @@ fn parse_gnss_navigation_information(
     tokens.pop_front(); // reserved1
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: f64 = text.parse()?;
             resp.hdop = Some(u);
@@ -192,7 +281,7 @@ fn parse_gnss_navigation_information(
     }?;
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: f64 = text.parse()?;
             resp.pdop = Some(u);
@@ -201,7 +290,7 @@ fn parse_gnss_navigation_information(
     }?;
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: f64 = text.parse()?;
             resp.vdop = Some(u);
@@ -212,7 +301,7 @@ fn parse_gnss_navigation_information(
     tokens.pop_front(); // reserved2
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: u8 = text.parse()?;
             resp.gps_satellites_in_view = Some(u);
@@ -221,7 +310,7 @@ fn parse_gnss_navigation_information(
     }?;
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: u8 = text.parse()?;
             resp.gnss_satellites_used = Some(u);
@@ -230,7 +319,7 @@ fn parse_gnss_navigation_information(
     }?;
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: u8 = text.parse()?;
             resp.glonass_satellites_in_view = Some(u);
@@ -241,7 +330,7 @@ fn parse_gnss_navigation_information(
     tokens.pop_front(); // reserved3
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: u8 = text.parse()?;
             resp.c_n0_max = Some(u);
@@ -250,7 +339,7 @@ fn parse_gnss_navigation_information(
     }?;
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: f64 = text.parse()?;
             resp.hpa = Some(u);
@@ -259,7 +348,7 @@ fn parse_gnss_navigation_information(
     }?;
 
     match tokens.pop_front().unwrap().as_str() {
-        "" => Ok::<(), AtatError>(()),
+        "" => Ok::<(), Error>(()),
         text => {
             let u: f64 = text.parse()?;
             resp.vpa = Some(u);
@@ -267,7 +356,7 @@ fn parse_gnss_navigation_information(
         }
     }?;
 
-    return Ok(resp);
+    Ok(resp)
 }
 
 #[derive(Debug, Format, Clone, PartialEq, AtatEnum, Default)]
@@ -284,11 +373,13 @@ pub enum FixStatus {
     FixedPosition = 1,
 }
 
-pub async fn get_gps_location<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+// Powers the GNSS on and restarts it in the given mode. The EPO data is injected when
+// `assisted`, a failed injection is not fatal, the fix just takes longer.
+pub async fn turn_on<T: atat::asynch::AtatClient>(
     client: &mut T,
-    pico: &mut U,
-    max_retries: u8,
-) -> Option<location::Location> {
+    start: StartMode,
+    assisted: bool,
+) -> Result<(), Error> {
     send_command_logged(
         client,
         &AtGnssPowerControlWrite {
@@ -296,11 +387,68 @@ pub async fn get_gps_location<T: atat::asynch::AtatClient, U: crate::at::PicoHW>
         },
         "AtGnssPowerControlWrite ON".to_string(),
     )
-    .await
-    .ok();
+    .await?;
+
+    match start {
+        StartMode::Auto => (),
+        StartMode::Hot => {
+            send_command_logged(
+                client,
+                &AtGnssHotStartExecute,
+                "AtGnssHotStartExecute".to_string(),
+            )
+            .await?;
+        }
+        StartMode::Warm => {
+            send_command_logged(
+                client,
+                &AtGnssWarmStartExecute,
+                "AtGnssWarmStartExecute".to_string(),
+            )
+            .await?;
+        }
+        StartMode::Cold => {
+            send_command_logged(
+                client,
+                &AtGnssColdStartExecute,
+                "AtGnssColdStartExecute".to_string(),
+            )
+            .await?;
+        }
+    }
 
-    // TODO defer { AtGnssPowerControlWrite::TurnOff }; would be better
+    if assisted
+        && let Err(e) = send_command_logged(
+            client,
+            &AtGnssAidWrite {
+                mode: 31,
+                time: 1,
+                position: 1,
+            },
+            "AtGnssAidWrite".to_string(),
+        )
+        .await
+    {
+        debug!("EPO injection failed: {}", e);
+    }
+    Ok(())
+}
 
+pub async fn get_gps_location<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    pico: &mut U,
+    max_retries: u8,
+    start: StartMode,
+    assisted: bool,
+    gate: &filter::Gate,
+) -> Result<location::Location, Error> {
+    if let Err(e) = turn_on(client, start, assisted).await {
+        turn_off(client).await;
+        return Err(e);
+    }
+
+    // The error of the last attempt is returned, a timeout when there was none.
+    let mut result = Err(Error::Timeout);
     for i in 0..max_retries {
         pico.sleep(1000).await;
         match send_command_logged(
@@ -315,6 +463,11 @@ pub async fn get_gps_location<T: atat::asynch::AtatClient, U: crate::at::PicoHW>
                     || resp.latitude.is_none()
                     || resp.longitude.is_none()
                 {
+                    result = Err(Error::Protocol("no GPS fix"));
+                    continue;
+                }
+                if let Err(e) = gate.check(&resp) {
+                    result = Err(e);
                     continue;
                 }
 
@@ -341,28 +494,29 @@ pub async fn get_gps_location<T: atat::asynch::AtatClient, U: crate::at::PicoHW>
                     },
                 };
 
-                send_command_logged(
-                    client,
-                    &AtGnssPowerControlWrite {
-                        mode: PowerMode::TurnOff,
-                    },
-                    "AtGnssPowerControlWrite OFF".to_string(),
-                )
-                .await
-                .ok();
+                turn_off(client).await;
 
                 let pdop = resp.pdop.unwrap_or(10.0);
-                return Some(location::Location {
+                return Ok(location::Location {
                     latitude: resp.latitude.unwrap(),
                     longitude: resp.longitude.unwrap(),
                     accuracy: utils::estimate_gps_accuracy(pdop),
                     unix_timestamp_millis: (datetime.unix_timestamp_nanos() / 1_000_000) as i64,
+                    source: location::Source::Gnss,
+                    speed: resp.speed_over_ground,
+                    course: resp.course_over_ground,
                 });
             }
-            Err(_) => (),
+            Err(e) => result = Err(e),
         }
     }
 
+    turn_off(client).await;
+    result
+}
+
+// Errors are ignored, the GNSS is left on at worst, see error.rs.
+pub async fn turn_off<T: atat::asynch::AtatClient>(client: &mut T) {
     send_command_logged(
         client,
         &AtGnssPowerControlWrite {
@@ -372,8 +526,69 @@ pub async fn get_gps_location<T: atat::asynch::AtatClient, U: crate::at::PicoHW>
     )
     .await
     .ok();
+}
 
-    return None;
+// Turns the GNSS on and streams its fixes to the AT port, the navigation state is kept up
+// to date without polling +CGNSINF, e.g. while driving (see location::Strategy::start_stream).
+pub async fn start_stream<T: atat::asynch::AtatClient>(
+    client: &mut T,
+    start: StartMode,
+    assisted: bool,
+    output: StreamOutput,
+) -> Result<(), Error> {
+    turn_on(client, start, assisted).await?;
+    match output {
+        StreamOutput::Nmea => send_command_logged(
+            client,
+            &AtGnssNmeaOutputWrite {
+                mode: NmeaOutputMode::Start,
+            },
+            "AtGnssNmeaOutputWrite START".to_string(),
+        )
+        .await
+        .map(|_| ()),
+        StreamOutput::Urc => send_command_logged(
+            client,
+            &AtGnssUrcWrite { period: 1 },
+            "AtGnssUrcWrite 1".to_string(),
+        )
+        .await
+        .map(|_| ()),
+    }
+}
+
+// The GNSS is turned off even if the stream could not be stopped.
+pub async fn stop_stream<T: atat::asynch::AtatClient>(
+    client: &mut T,
+    output: StreamOutput,
+) -> Result<(), Error> {
+    let stopped = match output {
+        StreamOutput::Nmea => send_command_logged(
+            client,
+            &AtGnssNmeaOutputWrite {
+                mode: NmeaOutputMode::Stop,
+            },
+            "AtGnssNmeaOutputWrite STOP".to_string(),
+        )
+        .await
+        .map(|_| ()),
+        StreamOutput::Urc => send_command_logged(
+            client,
+            &AtGnssUrcWrite { period: 0 },
+            "AtGnssUrcWrite 0".to_string(),
+        )
+        .await
+        .map(|_| ()),
+    };
+    send_command_logged(
+        client,
+        &AtGnssPowerControlWrite {
+            mode: PowerMode::TurnOff,
+        },
+        "AtGnssPowerControlWrite OFF".to_string(),
+    )
+    .await?;
+    stopped
 }
 
 #[cfg(test)]
@@ -404,6 +619,22 @@ mod tests {
             AtGnssNavigationInformationExecute,
             "AT+CGNSINF\r",
         ),
+        test_at_gnss_nmea_output_start: (
+            AtGnssNmeaOutputWrite {
+                mode: NmeaOutputMode::Start,
+            },
+            "AT+CGNSTST=1\r",
+        ),
+        test_at_gnss_nmea_output_stop: (
+            AtGnssNmeaOutputWrite {
+                mode: NmeaOutputMode::Stop,
+            },
+            "AT+CGNSTST=0\r",
+        ),
+        test_at_gnss_urc_write: (
+            AtGnssUrcWrite { period: 1 },
+            "AT+CGNSURC=1\r",
+        ),
     }
 
     #[test]
@@ -483,7 +714,15 @@ mod tests {
         client.results.push_back(Ok("".as_bytes())); // Turn off
 
         let mut pico = crate::at::tests::PicoMock::default();
-        let loc1 = get_gps_location(&mut client, &mut pico, 5).await;
+        let loc1 = get_gps_location(
+            &mut client,
+            &mut pico,
+            5,
+            StartMode::Auto,
+            false,
+            &filter::Gate::default(),
+        )
+        .await;
         assert_eq!(5, client.sent_commands.len());
         assert_eq!("AT+CGNSPWR=1\r", client.sent_commands.get(0).unwrap());
         assert_eq!("AT+CGNSINF\r", client.sent_commands.get(1).unwrap());
@@ -496,6 +735,9 @@ mod tests {
                 longitude: 18.6304591,
                 accuracy: 5.75,
                 unix_timestamp_millis: 1670846541123,
+                source: location::Source::Gnss,
+                speed: Some(2.2),
+                course: Some(285.8),
             },
             loc1.unwrap()
         );
@@ -505,5 +747,181 @@ mod tests {
         assert_eq!(1000, *pico.sleep_calls.get(2).unwrap());
     }
 
+    #[tokio::test]
+    async fn test_get_gps_location_poor_fix() {
+        let mut client = crate::at::tests::ClientMock::default();
+        client.results.push_back(Ok("".as_bytes())); // Turn On
+        client.results.push_back(Ok("+CGNSINF: 1,1,20221212120221.123,46.7624859,18.6304591,329.218,2.20,285.8,1,,9.9,12.3,0.9,,7,3,,,51,,".as_bytes())); // poor
+        client.results.push_back(Ok("+CGNSINF: 1,1,20221212120222.123,46.7624860,18.6304591,329.218,2.20,285.8,1,,2.1,2.3,0.9,,7,6,,,51,,".as_bytes())); // location
+        client.results.push_back(Ok("".as_bytes())); // Turn off
+        client.results.push_back(Ok("".as_bytes())); // Turn On
+        client.results.push_back(Ok("+CGNSINF: 1,1,20221212120221.123,46.7624859,18.6304591,329.218,2.20,285.8,1,,9.9,12.3,0.9,,7,3,,,51,,".as_bytes())); // poor
+        client.results.push_back(Ok("".as_bytes())); // Turn off
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        let gate = filter::Gate::default();
+        let location = get_gps_location(&mut client, &mut pico, 5, StartMode::Auto, false, &gate)
+            .await
+            .unwrap();
+        assert_eq!(46.7624860, location.latitude);
+        assert_eq!(4, client.sent_commands.len());
+
+        assert_eq!(
+            Err(Error::Rejected("poor GPS fix")),
+            get_gps_location(&mut client, &mut pico, 1, StartMode::Auto, false, &gate).await
+        );
+        assert_eq!("AT+CGNSPWR=0\r", client.sent_commands[6]);
+    }
+
     // TODO test error handling
+
+    #[tokio::test]
+    async fn test_get_gps_location_emulated() {
+        let mut sim = sim868_emu::Sim868::default();
+        let fix = sim.gnss_fix.take();
+        // no fix for the first query
+        sim.on(
+            "AT+CGNSINF",
+            sim868_emu::Action::Hook(alloc::boxed::Box::new(|_| ())),
+        )
+        .on(
+            "AT+CGNSINF",
+            sim868_emu::Action::Hook(alloc::boxed::Box::new(move |s| s.gnss_fix = fix.clone())),
+        );
+        let mut harness = crate::at::tests::Harness::new(sim);
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+
+        let loc = harness
+            .run(async |client| {
+                get_gps_location(
+                    client,
+                    &mut pico,
+                    5,
+                    StartMode::Auto,
+                    false,
+                    &filter::Gate::default(),
+                )
+                .await
+            })
+            .await;
+        assert_eq!(
+            location::Location {
+                latitude: 46.7624859,
+                longitude: 18.6304591,
+                accuracy: utils::estimate_gps_accuracy(2.3),
+                unix_timestamp_millis: 1670846541123,
+                source: location::Source::Gnss,
+                speed: Some(0.0),
+                course: Some(0.0),
+            },
+            loc.unwrap()
+        );
+        assert_eq!(2, pico.sleep_calls.len());
+        assert!(!harness.modem.with(|sim| sim.gnss_power));
+
+        harness.modem.with(|sim| sim.gnss_fix = None);
+        let loc = harness
+            .run(async |client| {
+                get_gps_location(
+                    client,
+                    &mut pico,
+                    3,
+                    StartMode::Auto,
+                    false,
+                    &filter::Gate::default(),
+                )
+                .await
+            })
+            .await;
+        assert_eq!(Err(Error::Protocol("no GPS fix")), loc);
+        assert!(!harness.modem.with(|sim| sim.gnss_power));
+    }
+
+    #[tokio::test]
+    async fn test_nmea_stream() {
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .expect("AT+CGNSPWR=1\r", Ok(b""))
+            .expect("AT+CGNSTST=1\r", Ok(b""))
+            .timeout("AT+CGNSTST=0\r")
+            .expect("AT+CGNSPWR=0\r", Ok(b""));
+        assert_eq!(
+            Ok(()),
+            start_stream(&mut client, StartMode::Auto, false, StreamOutput::Nmea).await
+        );
+        // the GNSS is turned off anyway
+        assert_eq!(
+            Err(Error::Timeout),
+            stop_stream(&mut client, StreamOutput::Nmea).await
+        );
+    }
+
+    #[test]
+    fn test_gnss_navigation_information_urc() {
+        use atat::AtatUrc;
+
+        let urc = crate::urc::Urc::parse(b"+UGNSINF: 1,1,20221212120221.123,46.7624859,18.6304591,329.218,2.20,285.8,1,,2.1,2.3,0.9,,12,8,,,45,,");
+        let Some(crate::urc::Urc::GnssNavigationInformation(urc)) = urc else {
+            panic!("not +UGNSINF");
+        };
+        let information = urc.information().unwrap();
+        assert_eq!(Some(FixStatus::FixedPosition), information.fix_status);
+        assert_eq!(Some(46.7624859), information.latitude);
+        assert_eq!(Some(285.8), information.course_over_ground);
+        assert_eq!(Some(45), information.c_n0_max);
+
+        let urc = GnssNavigationInformationUrc {
+            fields: String::try_from("1,0,,,,,,,,,,,,,,,,,,,").unwrap(),
+        };
+        assert_eq!(
+            Some(FixStatus::NotFixedPosition),
+            urc.information().unwrap().fix_status
+        );
+        let urc = GnssNavigationInformationUrc {
+            fields: String::try_from("1,1,,").unwrap(),
+        };
+        assert_eq!(Err(atat::Error::Parse.into()), urc.information());
+    }
+
+    #[tokio::test]
+    async fn test_urc_stream_emulated() {
+        let mut harness = crate::at::tests::Harness::new(sim868_emu::Sim868::default());
+        let mut urcs = harness.urc_channel.subscribe().unwrap();
+        harness
+            .run(async |client| {
+                start_stream(client, StartMode::Auto, false, StreamOutput::Urc).await
+            })
+            .await
+            .unwrap();
+        assert_eq!(1, harness.modem.with(|sim| sim.gnss_urc));
+
+        harness.modem.with(|sim| sim.emit_gnss_urc());
+        let urc = harness.run(async |_| urcs.next_message_pure().await).await;
+        let crate::urc::Urc::GnssNavigationInformation(urc) = urc else {
+            panic!("not +UGNSINF");
+        };
+        let mut navigation = crate::nmea::Navigation::new();
+        navigation.update_information(&urc.information().unwrap(), 5);
+        assert_eq!(Some(5), navigation.updated_millis);
+        assert_eq!(
+            Some(location::Location {
+                latitude: 46.7624859,
+                longitude: 18.6304591,
+                accuracy: utils::estimate_gps_accuracy(2.3),
+                unix_timestamp_millis: 1670846541123,
+                source: location::Source::Gnss,
+                speed: Some(0.0),
+                course: Some(0.0),
+            }),
+            navigation.location()
+        );
+
+        harness
+            .run(async |client| stop_stream(client, StreamOutput::Urc).await)
+            .await
+            .unwrap();
+        harness.modem.with(|sim| {
+            assert_eq!(0, sim.gnss_urc);
+            assert!(!sim.gnss_power);
+        });
+    }
 }
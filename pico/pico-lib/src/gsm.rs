@@ -1,19 +1,24 @@
 use defmt::Format;
 
+use core::fmt;
+
 use alloc::format;
 use alloc::string::ToString;
+use atat::AtatLen;
 use atat::atat_derive::AtatCmd;
 use atat::atat_derive::AtatEnum;
 use atat::atat_derive::AtatResp;
 use atat::heapless::String;
 use atat::heapless_bytes::Bytes;
+use atat::serde_at::serde::{self, Serialize};
 use defmt::info;
 use fasttime::Date;
 use fasttime::DateTime;
 
 use crate::at::NoResponse;
+use crate::error::Error;
 use crate::location;
-use crate::utils::bytes_to_string;
+use crate::urc::Urc;
 use crate::utils::send_command_logged;
 
 // 7.2.1 AT+CGATT Attach or Detach from GPRS Service
@@ -48,11 +53,11 @@ pub struct AtBringUpWirelessConnectionExecute;
 
 // 8.2.11 AT+CIFSR Get Local IP Address
 // AT+CIFSR
+// <IP address>, without a final OK, the line is taken as the response by ip::IpDigester
 #[derive(Clone, Debug, Format, AtatCmd)]
 #[at_cmd("+CIFSR", GetLocalIPAddressResponse)]
 pub struct AtGetLocalIPAddressExecute;
 
-// TODO: parsing issue
 #[derive(Debug, Format, Clone, AtatResp, PartialEq, Default)]
 pub struct GetLocalIPAddressResponse {
     pub address: String<50>,
@@ -113,16 +118,55 @@ pub enum Para {
 
 // 2.1 AT+CLBS Base station Location
 // AT+CLBS=<type>,<cid>,[[<longitude>,<latitude>],[<lon_type>]]
+// The responses of type 1, 4 and 9, see AtBaseStationAccessTimesWrite for type 3. There is
+// no type 2.
 #[derive(Clone, Debug, Format, AtatCmd)]
-#[at_cmd("+CLBS", BaseStationLocationResponseType4)] // NOTE: only type 4 response is implemented
+#[at_cmd("+CLBS", BaseStationLocationResponse)]
 pub struct AtBaseStationLocationWrite {
     pub type_: LocationType,
     pub cid: u8,
-    pub longitude: Option<f64>,
-    pub latitude: Option<f64>,
+    pub longitude: Option<Coordinate>,
+    pub latitude: Option<Coordinate>,
     pub lon_type: Option<LonType>,
 }
 
+impl AtBaseStationLocationWrite {
+    // On the bearer (cid 1), the coordinates are left empty before a lon_type other than
+    // the default of the module: AT+CLBS=4,1,,,1
+    pub fn request(type_: LocationType, lon_type: LonType) -> Self {
+        let lon_type = (lon_type != LonType::WGS84).then_some(lon_type);
+        let blank = lon_type.map(|_| Coordinate(None));
+        AtBaseStationLocationWrite {
+            type_,
+            cid: 1,
+            longitude: blank,
+            latitude: blank,
+            lon_type,
+        }
+    }
+}
+
+// A parameter of AT+CLBS that keeps its place when empty, atat drops a None with its
+// comma.
+#[derive(Clone, Copy, Debug, Format, PartialEq, Default)]
+pub struct Coordinate(pub Option<f64>);
+
+impl Serialize for Coordinate {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self.0 {
+            Some(v) => serializer.serialize_f64(v),
+            None => serializer.serialize_bytes(&[]),
+        }
+    }
+}
+
+impl AtatLen for Coordinate {
+    const LEN: usize = <f64 as AtatLen>::LEN;
+}
+
 #[derive(Debug, Format, Clone, PartialEq, AtatEnum, Default)]
 pub enum LocationType {
     #[default]
@@ -133,9 +177,11 @@ pub enum LocationType {
 }
 
 #[derive(Debug, Clone, AtatResp, PartialEq, Default)]
-// +CLBS: <locationcode>[,<longitude>,<latitude>,<acc>,<date>,<time>]
+// type 1: +CLBS: <locationcode>[,<longitude>,<latitude>,<acc>]
+// type 4: +CLBS: <locationcode>[,<longitude>,<latitude>,<acc>,<date>,<time>]
+// type 9: +CLBS: <locationcode>
 #[rustfmt::skip]
-pub struct BaseStationLocationResponseType4 {
+pub struct BaseStationLocationResponse {
     #[at_arg(position = 0)]
     pub location_code: LocationCode,
     #[at_arg(position = 1)]
@@ -143,14 +189,78 @@ pub struct BaseStationLocationResponseType4 {
     #[at_arg(position = 2)]
     pub latitude: Option<f64>,       // [-90.000000,90.000000]
     #[at_arg(position = 3)]
-    pub accuracy: Option<f64>,
+    pub accuracy: Option<u32>,       // meters
     #[at_arg(position = 4)]
-    pub date: Bytes<8>,           // DD/MM/YY
+    pub date: Option<Bytes<8>>,      // DD/MM/YY
     #[at_arg(position = 5)]
-    pub time: Bytes<8>,           // HH:MM:SS
+    pub time: Option<Bytes<8>>,      // HH:MM:SS
 }
 
-#[derive(Debug, Format, Clone, PartialEq, AtatEnum, Default)]
+impl BaseStationLocationResponse {
+    // The timestamp is 0 for type 1, it has no date and time.
+    pub fn location(&self) -> Result<location::Location, Error> {
+        self.location_code.check()?;
+        let (Some(latitude), Some(longitude)) = (self.latitude, self.longitude) else {
+            return Err(Error::Parse("CLBS coordinates"));
+        };
+        let unix_timestamp_millis = match (self.date.as_ref(), self.time.as_ref()) {
+            (Some(date), Some(time)) => timestamp_millis(date, time)?,
+            _ => 0,
+        };
+        Ok(location::Location {
+            latitude,
+            longitude,
+            accuracy: self.accuracy.unwrap_or_default() as f64,
+            unix_timestamp_millis,
+            source: location::Source::Cell,
+            speed: None,
+            course: None,
+        })
+    }
+}
+
+// DD/MM/YY and HH:MM:SS (UTC)
+fn timestamp_millis(date: &[u8], time: &[u8]) -> Result<i64, Error> {
+    let field = |s: &[u8], i: usize| -> Result<u8, Error> {
+        let digits = s.get(i..i + 2).ok_or(Error::Parse("CLBS date"))?;
+        Ok(core::str::from_utf8(digits)?.parse()?)
+    };
+    let datetime = DateTime {
+        date: Date {
+            year: 2000 + field(date, 6)? as i32,
+            month: field(date, 3)?,
+            day: field(date, 0)?,
+        },
+        time: fasttime::Time {
+            hour: field(time, 0)?,
+            minute: field(time, 3)?,
+            second: field(time, 6)?,
+            nanosecond: 0,
+        },
+    };
+    Ok((datetime.unix_timestamp_nanos() / 1_000_000) as i64)
+}
+
+// 2.1 AT+CLBS=3,<cid> Get access times
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CLBS", BaseStationAccessTimesResponse)]
+pub struct AtBaseStationAccessTimesWrite {
+    #[at_arg(position = 0)]
+    pub type_: LocationType, // GetAccessTimes
+    #[at_arg(position = 1)]
+    pub cid: u8,
+}
+
+// +CLBS: <locationcode>[,<times>]
+#[derive(Debug, Format, Clone, AtatResp, PartialEq)]
+pub struct BaseStationAccessTimesResponse {
+    #[at_arg(position = 0)]
+    pub location_code: LocationCode,
+    #[at_arg(position = 1)]
+    pub times: Option<u32>,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, AtatEnum, Default)]
 pub enum LocationCode {
     #[default]
     Success = 0,
@@ -166,241 +276,369 @@ pub enum LocationCode {
     ReportLbsToServerFAiled = 82,
 }
 
-#[derive(Debug, Format, Clone, PartialEq, AtatEnum, Default)]
+impl LocationCode {
+    pub fn check(self) -> Result<(), Error> {
+        match self {
+            LocationCode::Success | LocationCode::ReportLbsToServerSuccess => Ok(()),
+            code => Err(Error::Clbs(code)),
+        }
+    }
+
+    // Worth another try, the rest depends on the server or the account.
+    pub fn is_transient(self) -> bool {
+        matches!(
+            self,
+            LocationCode::Failed
+                | LocationCode::Timeout
+                | LocationCode::NetError
+                | LocationCode::OtherError
+        )
+    }
+}
+
+impl fmt::Display for LocationCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            LocationCode::Success => "success",
+            LocationCode::Failed => "location failed",
+            LocationCode::Timeout => "location timeout",
+            LocationCode::NetError => "network error",
+            LocationCode::DNSError => "DNS error",
+            LocationCode::ServiceOverdue => "location service overdue",
+            LocationCode::AuthenticationFailed => "location authentication failed",
+            LocationCode::OtherError => "location error",
+            LocationCode::ReportLbsToServerSuccess => "report success",
+            LocationCode::ReportLbsToServerParameter => "report parameter error",
+            LocationCode::ReportLbsToServerFAiled => "report failed",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, AtatEnum, Default)]
 pub enum LonType {
     #[default]
     WGS84 = 0,
     GCJ02 = 1, // no plan to launch my car to Mars or China
 }
 
-pub async fn get_gsm_location<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+// AT+SAPBR=2,<cid> Query Bearer
+// +SAPBR: <cid>,<Status>,<IP_Addr>
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+SAPBR", BearerQueryResponse)]
+pub struct AtBearerQueryWrite {
+    #[at_arg(position = 0)]
+    pub cmd_type: CmdType, // QueryBearer
+    #[at_arg(position = 1)]
+    pub cid: u8,
+}
+
+#[derive(Debug, Format, Clone, AtatResp, PartialEq)]
+pub struct BearerQueryResponse {
+    #[at_arg(position = 0)]
+    pub cid: u8,
+    #[at_arg(position = 1)]
+    pub status: BearerStatus,
+    #[at_arg(position = 2)]
+    pub address: String<15>,
+}
+
+#[derive(Debug, Format, Clone, Copy, PartialEq, AtatEnum, Default)]
+pub enum BearerStatus {
+    Connecting = 0,
+    Connected = 1,
+    Closing = 2,
+    #[default]
+    Closed = 3,
+}
+
+// The GPRS attachment and the +SAPBR bearer (cid 1) of the IP applications, e.g. CLBS,
+// HTTP or the ip::Stack, shared by reference counting: the first user opens it, the last
+// one closes it. A bearer lost on the way (see handle_urc) is opened again by the next
+// user.
+//
+// Bearer::scoped releases on every path, acquire and release are for the users that span
+// more than one call and have to pair them.
+#[derive(Debug, Format, Clone, Default, PartialEq)]
+pub struct Bearer {
+    users: u8,
+    status: BearerStatus,
+    address: Option<String<15>>,
+}
+
+impl Bearer {
+    pub fn status(&self) -> BearerStatus {
+        self.status
+    }
+
+    // The local IP address while the bearer is up.
+    pub fn address(&self) -> Option<&str> {
+        self.address.as_ref().map(|a| a.as_str())
+    }
+
+    pub fn users(&self) -> u8 {
+        self.users
+    }
+
+    pub async fn acquire<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+        &mut self,
+        client: &mut T,
+        pico: &mut U,
+        apn: &str,
+    ) -> Result<(), Error> {
+        if self.status != BearerStatus::Connected {
+            self.reopen(client, pico, apn).await?;
+        }
+        self.users += 1;
+        Ok(())
+    }
+
+    // Opens the bearer for the current users, e.g. after it was lost.
+    pub async fn reopen<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+        &mut self,
+        client: &mut T,
+        pico: &mut U,
+        apn: &str,
+    ) -> Result<(), Error> {
+        if self.users > 0 {
+            info!("Bearer lost, opening it again");
+            close_bearer(client).await;
+        }
+        self.status = BearerStatus::Connecting;
+        match open_bearer(client, pico, apn).await {
+            Ok(address) => {
+                self.status = BearerStatus::Connected;
+                self.address = address;
+                Ok(())
+            }
+            Err(e) => {
+                self.status = BearerStatus::Closed;
+                self.address = None;
+                Err(e)
+            }
+        }
+    }
+
+    // Closes the bearer after the last user, errors are ignored, see error.rs.
+    pub async fn release<T: atat::asynch::AtatClient>(&mut self, client: &mut T) {
+        self.users = self.users.saturating_sub(1);
+        if self.users == 0 {
+            self.status = BearerStatus::Closing;
+            close_bearer(client).await;
+            self.status = BearerStatus::Closed;
+            self.address = None;
+        }
+    }
+
+    // Runs f on the bearer, released whatever f returns.
+    pub async fn scoped<T: atat::asynch::AtatClient, U: crate::at::PicoHW, R>(
+        &mut self,
+        client: &mut T,
+        pico: &mut U,
+        apn: &str,
+        f: impl AsyncFnOnce(&mut T, &mut U) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        self.acquire(client, pico, apn).await?;
+        let ret = f(client, pico).await;
+        self.release(client).await;
+        ret
+    }
+
+    // Asks the module, e.g. after a URC or to report the state.
+    pub async fn query<T: atat::asynch::AtatClient>(
+        &mut self,
+        client: &mut T,
+    ) -> Result<BearerStatus, Error> {
+        let resp = send_command_logged(
+            client,
+            &AtBearerQueryWrite {
+                cmd_type: CmdType::QueryBearer,
+                cid: 1,
+            },
+            "AtBearerQueryWrite".to_string(),
+        )
+        .await?;
+        self.status = resp.status;
+        self.address = (resp.status == BearerStatus::Connected).then_some(resp.address);
+        Ok(resp.status)
+    }
+
+    // +SAPBR 1: DEACT or +PDP: DEACT, the network dropped the bearer.
+    pub fn handle_urc(&mut self, urc: &Urc) {
+        if let Urc::SetBearer(_) | Urc::GprsDisconnected(_) = urc {
+            self.status = BearerStatus::Closed;
+            self.address = None;
+        }
+    }
+}
+
+// Attaches to GPRS and opens the bearer, detached again on failure. Returns the local IP
+// address.
+async fn open_bearer<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
     client: &mut T,
     pico: &mut U,
-    max_retries: u8,
     apn: &str,
-) -> Option<location::Location> {
-    let mut loc: Option<location::Location> = None;
-    if send_command_logged(
+) -> Result<Option<String<15>>, Error> {
+    send_command_logged(
         client,
         &AtAttachGPRS {
             state: AttachState::Attach,
         },
         "AtAttachGPRS ON".to_string(),
     )
-    .await
-    .is_err()
-    {
-        return loc;
+    .await?;
+    let ret = bring_up(client, pico, apn).await;
+    if ret.is_err() {
+        detach(client).await;
     }
+    ret
+}
 
-    // TODO defer { AttachState::Detach }; would be better
-    // TODO and_then(), or defer, all failures should stop
-    let detach = async |client: &mut T| -> () {
-        send_command_logged(
-            client,
-            &AtAttachGPRS {
-                state: AttachState::Detach,
-            },
-            "AtAttachGPRS OFF".to_string(),
-        )
-        .await
-        .ok();
-    };
-
-    if send_command_logged(
+async fn bring_up<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    pico: &mut U,
+    apn: &str,
+) -> Result<Option<String<15>>, Error> {
+    let apn_param = String::<50>::try_from(apn).map_err(|_| Error::Capacity("apn"))?;
+    send_command_logged(
         client,
         &AtSetApnWrite {
-            apn: String::<50>::try_from(apn).unwrap(),
+            apn: apn_param.clone(),
             user_name: None,
             password: None,
         },
         "AtSetApnWrite".to_string(),
     )
-    .await
-    .is_err()
-    {
-        detach(client).await;
-        return loc;
-    }
-
-    if send_command_logged(
+    .await?;
+    send_command_logged(
         client,
         &AtBringUpWirelessConnectionExecute,
         "AtBringUpWirelessConnectionExecute".to_string(),
     )
-    .await
-    .is_err()
-    {
-        detach(client).await;
-        return loc;
-    }
+    .await?;
 
     // Only after PDP context is activated, local IP address can be obtained by AT+CIFSR,
     // otherwise it will respond ERROR. To see the status use AT+CIPSTATUS command.
-    // TODO: debug. I get timeout error.
     pico.sleep(5000).await;
 
-    match send_command_logged(
+    let local = send_command_logged(
         client,
         &AtGetLocalIPAddressExecute,
         "AtGetLocalIPAddressExecute".to_string(),
     )
-    .await
-    {
-        Ok(v) => info!("   OK {:?}", v),
-        Err(_) => {
-            detach(client).await;
-            return loc;
-        }
-    }
+    .await?;
+    info!("   OK {:?}", local);
 
-    if send_command_logged(
+    for (tag, value) in [("Contype", "GPRS"), ("APN", apn_param.as_str())] {
+        send_command_logged(
+            client,
+            &AtSetBearerWrite {
+                cmd_type: CmdType::SetBearerParameters,
+                cid: 1,
+                con_param_tag: Some(String::try_from(tag).unwrap()),
+                con_param_value: Some(String::try_from(value).unwrap()),
+            },
+            format!("AtSetBearerWrite {}", tag),
+        )
+        .await?;
+    }
+    send_command_logged(
         client,
         &AtSetBearerWrite {
-            cmd_type: CmdType::SetBearerParameters,
+            cmd_type: CmdType::OpenBearer,
             cid: 1,
-            con_param_tag: Some(String::<50>::try_from("Contype").unwrap()),
-            con_param_value: Some(String::<64>::try_from("GPRS").unwrap()),
+            con_param_tag: None,
+            con_param_value: None,
         },
-        "AtSetBearerWrite GPRS".to_string(),
+        "AtSetBearerWrite ACTIVATE".to_string(),
     )
-    .await
-    .is_err()
-    {
-        detach(client).await;
-        return loc;
-    }
+    .await?;
+
+    Ok(String::try_from(local.address.as_str()).ok())
+}
 
-    if send_command_logged(
+// Errors are ignored, the bearer and the attachment are dropped anyway.
+async fn close_bearer<T: atat::asynch::AtatClient>(client: &mut T) {
+    send_command_logged(
         client,
         &AtSetBearerWrite {
-            cmd_type: CmdType::SetBearerParameters,
+            cmd_type: CmdType::CloseBearer,
             cid: 1,
-            con_param_tag: Some(String::<50>::try_from("APN").unwrap()),
-            con_param_value: Some(String::<64>::try_from(apn).unwrap()),
+            con_param_tag: None,
+            con_param_value: None,
         },
-        "AtSetBearerWrite APN".to_string(),
+        "AtSetBearerWrite DEACTIVATE".to_string(),
     )
     .await
-    .is_err()
-    {
-        detach(client).await;
-        return loc;
-    }
+    .ok();
+    detach(client).await;
+}
 
-    if send_command_logged(
+async fn detach<T: atat::asynch::AtatClient>(client: &mut T) {
+    send_command_logged(
         client,
-        &AtSetBearerWrite {
-            cmd_type: CmdType::OpenBearer,
-            cid: 1,
-            con_param_tag: None,
-            con_param_value: None,
+        &AtAttachGPRS {
+            state: AttachState::Detach,
         },
-        "AtSetBearerWrite ACTIVATE".to_string(),
+        "AtAttachGPRS OFF".to_string(),
     )
     .await
-    .is_err()
-    {
-        detach(client).await;
-        return loc;
-    }
+    .ok();
+}
 
-    let deactivate = async |client: &mut T| -> () {
-        send_command_logged(
-            client,
-            &AtSetBearerWrite {
-                cmd_type: CmdType::CloseBearer,
-                cid: 1,
-                con_param_tag: None,
-                con_param_value: None,
-            },
-            "AtSetBearerWrite DEACTIVATE".to_string(),
-        )
+// CLBS location on the shared bearer, the server is e.g. Config::clbs_server.
+pub async fn get_gsm_location<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    pico: &mut U,
+    bearer: &mut Bearer,
+    max_retries: u8,
+    apn: &str,
+    server: &str,
+    lon_type: LonType,
+) -> Result<location::Location, Error> {
+    bearer
+        .scoped(client, pico, apn, async |client, pico| {
+            locate(client, pico, max_retries, server, lon_type).await
+        })
         .await
-        .ok();
-    };
+}
 
-    if send_command_logged(
+async fn locate<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    pico: &mut U,
+    max_retries: u8,
+    server: &str,
+    lon_type: LonType,
+) -> Result<location::Location, Error> {
+    send_command_logged(
         client,
         &AtBaseStationLocationConfWrite {
             operate: Operate::Set,
             para: Para::ServerAddress,
-            value: Some(String::<50>::try_from("lbs-simcom.com:3002").unwrap()),
+            value: Some(String::<50>::try_from(server).map_err(|_| Error::Capacity("clbs"))?),
         },
         "AtBaseStationLocationConfWrite".to_string(),
     )
-    .await
-    .is_err()
-    {
-        deactivate(client).await;
-        detach(client).await;
-        return loc;
-    }
+    .await?;
 
+    // The error of the last attempt is returned, a timeout when there was none.
+    let mut result = Err(Error::Timeout);
     for i in 0..max_retries {
         pico.sleep(1000).await;
-        match send_command_logged(
+        result = send_command_logged(
             client,
-            &AtBaseStationLocationWrite {
-                type_: LocationType::GetLongLatDateTime,
-                cid: 1,
-                longitude: None,
-                latitude: None,
-                lon_type: None,
-            },
+            &AtBaseStationLocationWrite::request(LocationType::GetLongLatDateTime, lon_type),
             format!("AtBaseStationLocationWrite {}", i),
         )
         .await
-        {
-            Ok(resp) => {
-                if resp.location_code != LocationCode::Success {
-                    continue;
-                }
-
-                let date = bytes_to_string(&resp.date);
-                let (day, rest) = date.as_str().split_at(2);
-                let (_, rest) = rest.split_at(1);
-                let (month, rest) = rest.split_at(2);
-                let (_, rest) = rest.split_at(1);
-                let (yy, _) = rest.split_at(2);
-                let year = format!("20{}", yy);
-
-                let time = bytes_to_string(&resp.time);
-                let (hour, rest) = time.as_str().split_at(2);
-                let (_, rest) = rest.split_at(1);
-                let (minute, rest) = rest.split_at(2);
-                let (_, rest) = rest.split_at(1);
-                let (second, _) = rest.split_at(2);
-
-                let datetime = DateTime {
-                    date: Date {
-                        year: year.parse().unwrap_or_default(),
-                        month: month.parse().unwrap_or_default(),
-                        day: day.parse().unwrap_or_default(),
-                    },
-                    time: fasttime::Time {
-                        hour: hour.parse().unwrap_or_default(),
-                        minute: minute.parse().unwrap_or_default(),
-                        second: second.parse().unwrap_or_default(),
-                        nanosecond: 0,
-                    },
-                };
-
-                loc = Some(location::Location {
-                    latitude: resp.latitude.unwrap_or_default(),
-                    longitude: resp.longitude.unwrap_or_default(),
-                    accuracy: resp.accuracy.unwrap_or_default(),
-                    unix_timestamp_millis: (datetime.unix_timestamp_nanos() / 1_000_000) as i64,
-                });
-                break;
-            }
-            Err(_) => (),
+        .and_then(|resp| resp.location());
+        match result {
+            Ok(_) => break,
+            Err(Error::Clbs(code)) if !code.is_transient() => break,
+            Err(_) => {}
         }
     }
-
-    deactivate(client).await;
-    detach(client).await;
-    return loc;
+    result
 }
 
 #[cfg(test)]
@@ -415,6 +653,13 @@ mod tests {
     use atat::serde_at;
 
     cmd_serialization_tests! {
+        test_at_bearer_query_write: (
+            AtBearerQueryWrite {
+                cmd_type: CmdType::QueryBearer,
+                cid: 1,
+            },
+            "AT+SAPBR=2,1\r",
+        ),
         test_at_attach_gprs_on: (
             AtAttachGPRS {
                 state: AttachState::Attach,
@@ -497,6 +742,31 @@ mod tests {
             },
             "AT+CLBS=4,1\r",
         ),
+        test_at_base_station_write_lon_type: (
+            AtBaseStationLocationWrite::request(LocationType::Use3Cell, LonType::GCJ02),
+            "AT+CLBS=1,1,,,1\r",
+        ),
+        test_at_base_station_write_default_lon_type: (
+            AtBaseStationLocationWrite::request(LocationType::GetLongLatDateTime, LonType::WGS84),
+            "AT+CLBS=4,1\r",
+        ),
+        test_at_base_station_write_report: (
+            AtBaseStationLocationWrite {
+                type_: LocationType::ReportPositionError,
+                cid: 1,
+                longitude: Some(Coordinate(Some(18.630459))),
+                latitude: Some(Coordinate(Some(46.762486))),
+                lon_type: None,
+            },
+            "AT+CLBS=9,1,18.630459,46.762486\r",
+        ),
+        test_at_base_station_access_times_write: (
+            AtBaseStationAccessTimesWrite {
+                type_: LocationType::GetAccessTimes,
+                cid: 1,
+            },
+            "AT+CLBS=3,1\r",
+        ),
     }
 
     #[test]
@@ -524,13 +794,13 @@ mod tests {
         let date: Bytes<8> = serde_at::from_slice(b"12/12/22").unwrap();
         let time: Bytes<8> = serde_at::from_slice(b"12:02:21").unwrap();
         assert_eq!(
-            BaseStationLocationResponseType4 {
+            BaseStationLocationResponse {
                 location_code: LocationCode::Success,
                 longitude: Some(18.6304591),
                 latitude: Some(46.7624859),
-                accuracy: Some(550.0),
-                date: date,
-                time: time,
+                accuracy: Some(550),
+                date: Some(date),
+                time: Some(time),
             },
             cmd.parse(Ok(
                 b"+CLBS: 0,18.6304591,46.7624859,550,12/12/22,12:02:21\r\n"
@@ -539,6 +809,67 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_clbs_responses() {
+        let cmd = AtBaseStationLocationWrite::request(LocationType::Use3Cell, LonType::WGS84);
+        let resp = cmd
+            .parse(Ok(b"+CLBS: 0,18.630459,46.762486,550\r\n"))
+            .unwrap();
+        assert_eq!(None, resp.date);
+        assert_eq!(
+            Ok(location::Location {
+                latitude: 46.762486,
+                longitude: 18.630459,
+                accuracy: 550.0,
+                unix_timestamp_millis: 0,
+                source: location::Source::Cell,
+                speed: None,
+                course: None,
+            }),
+            resp.location()
+        );
+
+        let resp = cmd.parse(Ok(b"+CLBS: 4\r\n")).unwrap();
+        assert_eq!(Err(Error::Clbs(LocationCode::DNSError)), resp.location());
+        assert!(!LocationCode::DNSError.is_transient());
+        assert!(LocationCode::NetError.is_transient());
+        assert_eq!(
+            "location service overdue",
+            format!("{}", Error::Clbs(LocationCode::ServiceOverdue))
+        );
+
+        let resp = cmd
+            .parse(Ok(
+                b"+CLBS: 0,18.630459,46.762486,550,12/12/22,12:02:21\r\n",
+            ))
+            .unwrap();
+        assert_eq!(
+            1670846541000,
+            resp.location().unwrap().unix_timestamp_millis
+        );
+        let resp = cmd.parse(Ok(b"+CLBS: 0,18.630459,46.762486,550,12/12,12:02\r\n"));
+        assert_eq!(
+            Err(Error::Parse("CLBS date")),
+            resp.unwrap().location().map(|_| ())
+        );
+
+        // type 9
+        let resp = cmd.parse(Ok(b"+CLBS: 80\r\n")).unwrap();
+        assert_eq!(Ok(()), resp.location_code.check());
+
+        let cmd = AtBaseStationAccessTimesWrite {
+            type_: LocationType::GetAccessTimes,
+            cid: 1,
+        };
+        assert_eq!(
+            BaseStationAccessTimesResponse {
+                location_code: LocationCode::Success,
+                times: Some(12),
+            },
+            cmd.parse(Ok(b"+CLBS: 0,12\r\n")).unwrap()
+        );
+    }
+
     #[tokio::test]
     async fn test_get_gsm_location() {
         let mut client = crate::at::tests::ClientMock::default();
@@ -559,7 +890,16 @@ mod tests {
         client.results.push_back(Ok("".as_bytes())); // GPRS off
 
         let mut pico = crate::at::tests::PicoMock::default();
-        let loc1 = get_gsm_location(&mut client, &mut pico, 5, "online").await;
+        let loc1 = get_gsm_location(
+            &mut client,
+            &mut pico,
+            &mut Bearer::default(),
+            5,
+            "online",
+            "lbs-simcom.com:3002",
+            LonType::WGS84,
+        )
+        .await;
         assert_eq!(13, client.sent_commands.len());
         assert_eq!("AT+CGATT=1\r", client.sent_commands.get(0).unwrap());
         assert_eq!("AT+CSTT=\"online\"\r", client.sent_commands.get(1).unwrap());
@@ -589,6 +929,9 @@ mod tests {
                 longitude: 18.6304591,
                 accuracy: 550.0,
                 unix_timestamp_millis: 1670846541000,
+                source: location::Source::Cell,
+                speed: None,
+                course: None,
             },
             loc1.unwrap()
         );
@@ -600,5 +943,371 @@ mod tests {
         assert_eq!(1000, *pico.sleep_calls.get(3).unwrap());
     }
 
-    // TODO test error handling
+    #[tokio::test]
+    async fn test_get_gsm_location_timeout() {
+        // no answer to CIFSR, GPRS is detached
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .expect("AT+CGATT=1\r", Ok(b""))
+            .expect("AT+CSTT=\"online\"\r", Ok(b""))
+            .expect("AT+CIICR\r", Ok(b""))
+            .timeout("AT+CIFSR\r")
+            .expect("AT+CGATT=0\r", Ok(b""));
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        let loc = get_gsm_location(
+            &mut client,
+            &mut pico,
+            &mut Bearer::default(),
+            5,
+            "online",
+            "lbs-simcom.com:3002",
+            LonType::WGS84,
+        )
+        .await;
+        assert_eq!(Err(Error::Timeout), loc);
+        assert_eq!(alloc::vec![5000], pico.sleep_calls);
+    }
+
+    #[tokio::test]
+    async fn test_get_gsm_location_bearer_lost() {
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .expect("AT+CGATT=1\r", Ok(b""))
+            .expect("AT+CSTT=\"online\"\r", Ok(b""))
+            .expect("AT+CIICR\r", Ok(b""))
+            .expect("AT+CIFSR\r", Ok(b"100.95.173.97"))
+            .expect("AT+SAPBR=3,1,\"Contype\",\"GPRS\"\r", Ok(b""))
+            .expect("AT+SAPBR=3,1,\"APN\",\"online\"\r", Ok(b""))
+            .expect("AT+SAPBR=1,1\r", Ok(b""))
+            .expect("AT+CLBSCFG=1,3,\"lbs-simcom.com:3002\"\r", Ok(b""))
+            .urc(b"+SAPBR 1: DEACT")
+            .expect("AT+CLBS=4,1\r", Ok(b"+CLBS: 3"))
+            .timeout("AT+CLBS=4,1\r")
+            .expect("AT+SAPBR=0,1\r", Err(atat::InternalError::Error))
+            .expect("AT+CGATT=0\r", Ok(b""));
+        let mut urcs = client.subscribe();
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        let loc = get_gsm_location(
+            &mut client,
+            &mut pico,
+            &mut Bearer::default(),
+            2,
+            "online",
+            "lbs-simcom.com:3002",
+            LonType::WGS84,
+        )
+        .await;
+        assert_eq!(Err(Error::Timeout), loc);
+        assert!(matches!(
+            urcs.try_next_message_pure(),
+            Some(crate::urc::Urc::SetBearer(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_gsm_location_emulated() {
+        let mut harness = crate::at::tests::Harness::new(sim868_emu::Sim868::default());
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+
+        let loc = harness
+            .run(async |client| {
+                get_gsm_location(
+                    client,
+                    &mut pico,
+                    &mut Bearer::default(),
+                    5,
+                    "online",
+                    "lbs-simcom.com:3002",
+                    LonType::WGS84,
+                )
+                .await
+            })
+            .await;
+        assert_eq!(
+            location::Location {
+                latitude: 46.762486,
+                longitude: 18.630459,
+                accuracy: 550.0,
+                unix_timestamp_millis: 1670846541000,
+                source: location::Source::Cell,
+                speed: None,
+                course: None,
+            },
+            loc.unwrap()
+        );
+        harness.modem.with(|sim| {
+            assert_eq!(Some("online"), sim.apn.as_deref());
+            assert_eq!(
+                Some("online"),
+                sim.bearer_params.get("APN").map(|v| v.as_str())
+            );
+            assert_eq!("lbs-simcom.com:3002", sim.clbs_server);
+            assert!(!sim.bearer_open);
+            assert!(!sim.gprs_attached);
+        });
+    }
+
+    #[tokio::test]
+    async fn test_get_gsm_location_emulated_overdue() {
+        let mut sim = sim868_emu::Sim868::default();
+        sim.on_every(
+            "AT+CLBS=4,1,,,1",
+            sim868_emu::Action::Reply("\r\n+CLBS: 5\r\n\r\nOK\r\n".into()),
+        );
+        let mut harness = crate::at::tests::Harness::new(sim);
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+
+        let loc = harness
+            .run(async |client| {
+                get_gsm_location(
+                    client,
+                    &mut pico,
+                    &mut Bearer::default(),
+                    5,
+                    "online",
+                    "lbs-simcom.com:3002",
+                    LonType::GCJ02,
+                )
+                .await
+            })
+            .await;
+        // no retries, it depends on the account
+        assert_eq!(Err(Error::Clbs(LocationCode::ServiceOverdue)), loc);
+        harness.modem.with(|sim| {
+            assert_eq!(
+                1,
+                sim.commands
+                    .iter()
+                    .filter(|c| c.starts_with("AT+CLBS=4"))
+                    .count()
+            );
+            assert!(!sim.bearer_open);
+        });
+    }
+
+    #[tokio::test]
+    async fn test_get_gsm_location_emulated_cifsr_timeout() {
+        let mut sim = sim868_emu::Sim868::default();
+        sim.on("AT+CIFSR", sim868_emu::Action::Silent);
+        let mut harness = crate::at::tests::Harness::new(sim);
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+
+        let loc = harness
+            .run(async |client| {
+                get_gsm_location(
+                    client,
+                    &mut pico,
+                    &mut Bearer::default(),
+                    5,
+                    "online",
+                    "lbs-simcom.com:3002",
+                    LonType::WGS84,
+                )
+                .await
+            })
+            .await;
+        assert_eq!(Err(Error::Timeout), loc);
+        assert_eq!(
+            alloc::vec![
+                "AT+CGATT=1",
+                "AT+CSTT=\"online\"",
+                "AT+CIICR",
+                "AT+CIFSR",
+                "AT+CGATT=0"
+            ],
+            harness.modem.with(|sim| sim.commands.clone())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_gsm_location_emulated_network_drop_during_attach() {
+        let mut sim = sim868_emu::Sim868::default();
+        sim.on(
+            "AT+CGATT=1",
+            sim868_emu::Action::Hook(alloc::boxed::Box::new(|s| {
+                s.set_registration(sim868_emu::Registration::Searching)
+            })),
+        );
+        let mut harness = crate::at::tests::Harness::new(sim);
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+
+        let loc = harness
+            .run(async |client| {
+                get_gsm_location(
+                    client,
+                    &mut pico,
+                    &mut Bearer::default(),
+                    5,
+                    "online",
+                    "lbs-simcom.com:3002",
+                    LonType::WGS84,
+                )
+                .await
+            })
+            .await;
+        assert_eq!(Err(Error::Modem), loc);
+        assert_eq!(
+            alloc::vec!["AT+CGATT=1"],
+            harness.modem.with(|sim| sim.commands.clone())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_gsm_location_emulated_network_drop_with_bearer() {
+        let mut sim = sim868_emu::Sim868::default();
+        sim.on(
+            "AT+CLBS",
+            sim868_emu::Action::Hook(alloc::boxed::Box::new(|s| {
+                s.set_registration(sim868_emu::Registration::Searching)
+            })),
+        );
+        let mut harness = crate::at::tests::Harness::new(sim);
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut sub = harness.urc_channel.subscribe().unwrap();
+
+        let loc = harness
+            .run(async |client| {
+                get_gsm_location(
+                    client,
+                    &mut pico,
+                    &mut Bearer::default(),
+                    3,
+                    "online",
+                    "lbs-simcom.com:3002",
+                    LonType::WGS84,
+                )
+                .await
+            })
+            .await;
+        assert_eq!(Err(Error::Clbs(LocationCode::NetError)), loc);
+        assert!(matches!(
+            sub.try_next_message_pure(),
+            Some(crate::urc::Urc::SetBearer(_))
+        ));
+        assert!(matches!(
+            sub.try_next_message_pure(),
+            Some(crate::urc::Urc::GprsDisconnected(_))
+        ));
+        let commands = harness.modem.with(|sim| sim.commands.clone());
+        assert_eq!(
+            3,
+            commands
+                .iter()
+                .filter(|c| c.as_str() == "AT+CLBS=4,1")
+                .count()
+        );
+        assert_eq!(
+            &["AT+SAPBR=0,1", "AT+CGATT=0"],
+            &commands[commands.len() - 2..]
+        );
+    }
+
+    #[test]
+    fn test_bearer_query_response() {
+        assert_eq!(
+            BearerQueryResponse {
+                cid: 1,
+                status: BearerStatus::Connected,
+                address: String::try_from("100.95.173.97").unwrap(),
+            },
+            atat::serde_at::from_slice::<BearerQueryResponse>(b"+SAPBR: 1,1,\"100.95.173.97\"")
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bearer_shared_emulated() {
+        let mut harness = crate::at::tests::Harness::new(sim868_emu::Sim868::default());
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let modem = harness.modem.clone();
+        let mut bearer = Bearer::default();
+
+        harness
+            .run(async |client| {
+                bearer.acquire(client, &mut pico, "online").await.unwrap();
+                assert_eq!(BearerStatus::Connected, bearer.status());
+                assert_eq!(Some("100.95.173.97"), bearer.address());
+
+                // a second user, the bearer stays open after it
+                let loc = get_gsm_location(
+                    client,
+                    &mut pico,
+                    &mut bearer,
+                    5,
+                    "online",
+                    "lbs-simcom.com:3002",
+                    LonType::WGS84,
+                )
+                .await;
+                assert!(loc.is_ok());
+                assert_eq!(1, bearer.users());
+                assert!(modem.with(|sim| sim.bearer_open));
+                assert_eq!(Ok(BearerStatus::Connected), bearer.query(client).await);
+
+                // released on failure too
+                let ret: Result<(), Error> = bearer
+                    .scoped(client, &mut pico, "online", async |_, _| {
+                        Err(Error::Timeout)
+                    })
+                    .await;
+                assert_eq!(Err(Error::Timeout), ret);
+                assert_eq!(1, bearer.users());
+
+                bearer.release(client).await;
+                assert_eq!(0, bearer.users());
+                assert_eq!(BearerStatus::Closed, bearer.status());
+                assert_eq!(None, bearer.address());
+                assert_eq!(Ok(BearerStatus::Closed), bearer.query(client).await);
+            })
+            .await;
+
+        let commands = harness.modem.with(|sim| {
+            assert!(!sim.bearer_open);
+            assert!(!sim.gprs_attached);
+            sim.commands.clone()
+        });
+        for (command, count) in [("AT+CGATT=1", 1), ("AT+SAPBR=1,1", 1), ("AT+SAPBR=0,1", 1)] {
+            assert_eq!(
+                count,
+                commands.iter().filter(|c| c.as_str() == command).count(),
+                "{}",
+                command
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bearer_lost_emulated() {
+        let mut harness = crate::at::tests::Harness::new(sim868_emu::Sim868::default());
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut sub = harness.urc_channel.subscribe().unwrap();
+        let modem = harness.modem.clone();
+        let mut bearer = Bearer::default();
+
+        harness
+            .run(async |client| {
+                bearer.acquire(client, &mut pico, "online").await.unwrap();
+                modem.with(|sim| {
+                    sim.set_registration(sim868_emu::Registration::Searching);
+                    sim.set_registration(sim868_emu::Registration::Registered);
+                });
+                // the URCs arrive with the next response
+                bearer.query(client).await.ok();
+                while let Some(urc) = sub.try_next_message_pure() {
+                    bearer.handle_urc(&urc);
+                }
+                assert_eq!(BearerStatus::Closed, bearer.status());
+                assert_eq!(1, bearer.users());
+
+                // opened again by the next user
+                bearer.acquire(client, &mut pico, "online").await.unwrap();
+                assert_eq!(BearerStatus::Connected, bearer.status());
+                assert!(modem.with(|sim| sim.bearer_open));
+                bearer.release(client).await;
+                bearer.release(client).await;
+            })
+            .await;
+        assert!(!harness.modem.with(|sim| sim.bearer_open));
+        assert_eq!(Bearer::default(), bearer);
+    }
 }
@@ -1,68 +1,226 @@
-use alloc::{fmt, format, vec::Vec};
+use alloc::{fmt, format, vec::Vec as AVec};
 use atat::{
     AtatLen,
-    heapless::String,
+    heapless::{String, Vec},
     serde_at::serde::{self, Deserialize, Serialize, de::Visitor},
 };
-use core::{num::ParseIntError, str::FromStr};
+use core::str::FromStr;
 use defmt::Format;
 
-pub fn decode_hex_u8(s: &str) -> Result<Vec<u8>, ParseIntError> {
+use crate::error::Error;
+
+// The digits are sliced by bytes, anything but ASCII pairs is rejected before.
+pub fn decode_hex_u8(s: &str) -> Result<AVec<u8>, Error> {
+    if !s.is_ascii() || !s.len().is_multiple_of(2) {
+        return Err(Error::Parse("invalid hex string"));
+    }
     (0..s.len())
         .step_by(2)
-        .map(|i| u8::from_str_radix(&s[i..i + 2], 16))
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(Error::from))
         .collect()
 }
 
-pub fn decode_utf8_hex_string<const N: usize>(v: &[u8]) -> Result<String<N>, &'static str> {
-    let hex_str = core::str::from_utf8(&v).map_err(|_o| -> &'static str { "utf-8 error" })?;
-    let bytes = decode_hex_u8(&hex_str).map_err(|_o| -> &'static str { "decode_hex_u8 error" })?;
-    let utf8_str =
-        core::str::from_utf8(&bytes).map_err(|_o| -> &'static str { "from_utf8 error" })?;
-    String::from_str(utf8_str).map_err(|_o| -> &'static str { "from_str error" })
+pub fn decode_utf8_hex_string<const N: usize>(v: &[u8]) -> Result<String<N>, Error> {
+    let hex_str = core::str::from_utf8(v).map_err(|_o| Error::Parse("invalid UTF-8"))?;
+    let bytes = decode_hex_u8(hex_str).map_err(|_o| Error::Parse("invalid hex string"))?;
+    let utf8_str = core::str::from_utf8(&bytes).map_err(|_o| Error::Parse("invalid UTF-8"))?;
+    String::from_str(utf8_str).map_err(|_o| Error::Capacity("string"))
 }
 
-pub fn encode_utf8_hex_string<const N: usize>(v: &[u8]) -> Result<String<N>, &'static str> {
+pub fn encode_utf8_hex_string<const N: usize>(v: &[u8]) -> Result<String<N>, Error> {
     let mut hex_str = String::<N>::new();
     for c in v {
         let s = format!("{:02X}", c);
         hex_str
             .push_str(s.as_str())
-            .map_err(|_o| -> &'static str { "push_str error" })?;
+            .map_err(|_o| Error::Capacity("hex string"))?;
     }
     Ok(hex_str)
 }
 
-pub fn decode_hex_u16(s: &str) -> Result<Vec<u16>, ParseIntError> {
+pub fn decode_hex_u16(s: &str) -> Result<AVec<u16>, Error> {
+    if !s.is_ascii() || !s.len().is_multiple_of(4) {
+        return Err(Error::Parse("invalid hex string"));
+    }
     (0..s.len())
         .step_by(4)
-        .map(|i| u16::from_str_radix(&s[i..i + 4], 16))
+        .map(|i| u16::from_str_radix(&s[i..i + 4], 16).map_err(Error::from))
         .collect()
 }
 
-pub fn decode_utf16_hex_string<const N: usize>(v: &[u8]) -> Result<String<N>, &'static str> {
-    let hex_str = core::str::from_utf8(&v).map_err(|_o| -> &'static str { "utf-8 error" })?;
-    let bytes =
-        decode_hex_u16(&hex_str).map_err(|_o| -> &'static str { "decode_hex_u16 error" })?;
-    let utf16_str = alloc::string::String::from_utf16(&bytes)
-        .map_err(|_o| -> &'static str { "from_utf16 error" })?;
-    String::from_str(utf16_str.as_str()).map_err(|_o| -> &'static str { "from_str error" })
+pub fn decode_utf16_hex_string<const N: usize>(v: &[u8]) -> Result<String<N>, Error> {
+    let hex_str = core::str::from_utf8(v).map_err(|_o| Error::Parse("invalid UTF-8"))?;
+    let bytes = decode_hex_u16(hex_str).map_err(|_o| Error::Parse("invalid hex string"))?;
+    let utf16_str =
+        alloc::string::String::from_utf16(&bytes).map_err(|_o| Error::Parse("invalid UTF-16"))?;
+    String::from_str(utf16_str.as_str()).map_err(|_o| Error::Capacity("string"))
 }
 
-pub fn encode_utf16_hex_string<const N: usize>(v: &[u8]) -> Result<String<N>, &'static str> {
-    let utf8_str = core::str::from_utf8(v).map_err(|_o| -> &'static str { "from_utf8 error" })?;
-    let s = String::<N>::from_str(utf8_str).map_err(|_o| -> &'static str { "from_str error" })?;
-    let v: Vec<u16> = s.encode_utf16().collect();
+pub fn encode_utf16_hex_string<const N: usize>(v: &[u8]) -> Result<String<N>, Error> {
+    let utf8_str = core::str::from_utf8(v).map_err(|_o| Error::Parse("invalid UTF-8"))?;
+    let s = String::<N>::from_str(utf8_str).map_err(|_o| Error::Capacity("string"))?;
+    let v: AVec<u16> = s.encode_utf16().collect();
     let mut hex_str = String::<N>::new();
     for c in v {
         let s = format!("{:04X}", c);
         hex_str
             .push_str(s.as_str())
-            .map_err(|_o| -> &'static str { "push_str error" })?;
+            .map_err(|_o| Error::Capacity("hex string"))?;
     }
     Ok(hex_str)
 }
 
+// GSM 03.38 7-bit default alphabet, the extension table is reached with the escape septet.
+// https://en.wikipedia.org/wiki/GSM_03.38
+const GSM7_ESCAPE: u8 = 0x1B;
+
+#[rustfmt::skip]
+const GSM7_BASIC: [char; 128] = [
+    '@', '£', '$', '¥', 'è', 'é', 'ù', 'ì', 'ò', 'Ç', '\n', 'Ø', 'ø', '\r', 'Å', 'å',
+    'Δ', '_', 'Φ', 'Γ', 'Λ', 'Ω', 'Π', 'Ψ', 'Σ', 'Θ', 'Ξ', '\x1b', 'Æ', 'æ', 'ß', 'É',
+    ' ', '!', '"', '#', '¤', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',
+    '¡', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', 'Ä', 'Ö', 'Ñ', 'Ü', '§',
+    '¿', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', 'ä', 'ö', 'ñ', 'ü', 'à',
+];
+
+const GSM7_EXTENSION: [(u8, char); 10] = [
+    (0x0A, '\x0c'), // form feed
+    (0x14, '^'),
+    (0x28, '{'),
+    (0x29, '}'),
+    (0x2F, '\\'),
+    (0x3C, '['),
+    (0x3D, '~'),
+    (0x3E, ']'),
+    (0x40, '|'),
+    (0x65, '€'),
+];
+
+// The septets of the character, the extension table characters take two.
+pub fn gsm7_char(c: char) -> Option<([u8; 2], usize)> {
+    if c == '\x1b' {
+        return None;
+    }
+    if let Some(p) = GSM7_BASIC.iter().position(|g| *g == c) {
+        return Some(([p as u8, 0], 1));
+    }
+    GSM7_EXTENSION
+        .iter()
+        .find(|(_, e)| *e == c)
+        .map(|(s, _)| ([GSM7_ESCAPE, *s], 2))
+}
+
+pub fn encode_gsm7<const N: usize>(text: &str) -> Result<Vec<u8, N>, Error> {
+    let mut septets = Vec::new();
+    for c in text.chars() {
+        let (s, len) = gsm7_char(c).ok_or(Error::Parse("not a GSM 7-bit character"))?;
+        septets
+            .extend_from_slice(&s[..len])
+            .map_err(|_o| Error::Capacity("message too long"))?;
+    }
+    Ok(septets)
+}
+
+// Unknown extension characters are shown from the basic table, as the spec suggests.
+pub fn decode_gsm7<const N: usize>(septets: &[u8]) -> Result<String<N>, Error> {
+    let mut text = String::new();
+    let mut escaped = false;
+    for septet in septets.iter().map(|s| s & 0x7F) {
+        let c = if escaped {
+            escaped = false;
+            GSM7_EXTENSION
+                .iter()
+                .find(|(s, _)| *s == septet)
+                .map(|(_, c)| *c)
+                .unwrap_or(GSM7_BASIC[septet as usize])
+        } else if septet == GSM7_ESCAPE {
+            escaped = true;
+            continue;
+        } else {
+            GSM7_BASIC[septet as usize]
+        };
+        text.push(c)
+            .map_err(|_o| Error::Capacity("message too long"))?;
+    }
+    Ok(text)
+}
+
+// Septets are packed LSB first, fill_bits skips the bits used by the user data header.
+pub fn pack_septets<const N: usize>(septets: &[u8], fill_bits: usize) -> Result<Vec<u8, N>, Error> {
+    let mut packed = Vec::new();
+    let mut acc: u32 = 0;
+    let mut bits = fill_bits;
+    for septet in septets {
+        acc |= ((septet & 0x7F) as u32) << bits;
+        bits += 7;
+        while bits >= 8 {
+            packed
+                .push(acc as u8)
+                .map_err(|_o| Error::Capacity("message too long"))?;
+            acc >>= 8;
+            bits -= 8;
+        }
+    }
+    if bits > 0 {
+        packed
+            .push(acc as u8)
+            .map_err(|_o| Error::Capacity("message too long"))?;
+    }
+    Ok(packed)
+}
+
+pub fn unpack_septets<const N: usize>(
+    bytes: &[u8],
+    count: usize,
+    fill_bits: usize,
+) -> Result<Vec<u8, N>, Error> {
+    let mut septets = Vec::new();
+    for i in 0..count {
+        let bit = fill_bits + i * 7;
+        let (index, shift) = (bit / 8, bit % 8);
+        let mut septet =
+            (*bytes.get(index).ok_or(Error::Parse("septets too short"))? as u16) >> shift;
+        if shift > 1 {
+            septet |= (*bytes
+                .get(index + 1)
+                .ok_or(Error::Parse("septets too short"))? as u16)
+                << (8 - shift);
+        }
+        septets
+            .push((septet & 0x7F) as u8)
+            .map_err(|_o| Error::Capacity("message too long"))?;
+    }
+    Ok(septets)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub enum Charset {
+    Gsm7, // 160 characters per SMS
+    Ucs2, // 70 characters per SMS
+}
+
+impl Charset {
+    // 7-bit when every character is representable, UCS2 otherwise.
+    pub fn select(text: &str) -> Charset {
+        if text.chars().all(|c| gsm7_char(c).is_some()) {
+            Charset::Gsm7
+        } else {
+            Charset::Ucs2
+        }
+    }
+
+    // The size of the character in septets (Gsm7) or UTF-16 code units (Ucs2).
+    pub fn units(&self, c: char) -> usize {
+        match self {
+            Charset::Gsm7 => gsm7_char(c).map(|(_, len)| len).unwrap_or(2),
+            Charset::Ucs2 => c.len_utf16(),
+        }
+    }
+}
+
 struct HexStringVisitor<const N: usize>;
 
 impl<'de, const N: usize> Visitor<'de> for HexStringVisitor<N> {
@@ -161,6 +319,83 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_gsm7() {
+        let septets: Vec<u8, 32> = encode_gsm7("@£$ Hi!").unwrap();
+        assert_eq!(
+            &[0x00, 0x01, 0x02, 0x20, 0x48, 0x69, 0x21],
+            septets.as_slice()
+        );
+        assert_eq!("@£$ Hi!", decode_gsm7::<32>(&septets).unwrap().as_str());
+
+        let septets: Vec<u8, 32> = encode_gsm7("5€ [x]").unwrap();
+        assert_eq!(
+            &[0x35, 0x1B, 0x65, 0x20, 0x1B, 0x3C, 0x78, 0x1B, 0x3E],
+            septets.as_slice()
+        );
+        assert_eq!("5€ [x]", decode_gsm7::<32>(&septets).unwrap().as_str());
+
+        // unknown extension falls back to the basic table
+        assert_eq!("A", decode_gsm7::<32>(&[0x1B, 0x41]).unwrap().as_str());
+
+        assert_eq!(
+            Err(Error::Parse("not a GSM 7-bit character")),
+            encode_gsm7::<32>("ő")
+        );
+        assert_eq!(
+            Err(Error::Parse("not a GSM 7-bit character")),
+            encode_gsm7::<32>("\x1b")
+        );
+        assert_eq!(
+            Err(Error::Capacity("message too long")),
+            encode_gsm7::<2>("€€")
+        );
+        assert_eq!(
+            Err(Error::Capacity("message too long")),
+            decode_gsm7::<1>(b"ab")
+        );
+    }
+
+    #[test]
+    fn test_septets() {
+        let packed: Vec<u8, 16> = pack_septets(b"hellohello", 0).unwrap();
+        assert_eq!(
+            &[0xE8, 0x32, 0x9B, 0xFD, 0x46, 0x97, 0xD9, 0xEC, 0x37],
+            packed.as_slice()
+        );
+        let septets: Vec<u8, 16> = unpack_septets(&packed, 10, 0).unwrap();
+        assert_eq!(b"hellohello", septets.as_slice());
+
+        // after a 6 octet header the text starts with 1 fill bit
+        let packed: Vec<u8, 16> = pack_septets(b"hi", 1).unwrap();
+        assert_eq!(&[0xD0, 0x69], packed.as_slice());
+        let septets: Vec<u8, 16> = unpack_septets(&packed, 2, 1).unwrap();
+        assert_eq!(b"hi", septets.as_slice());
+
+        assert_eq!(
+            Err(Error::Parse("septets too short")),
+            unpack_septets::<16>(&[0xE8], 2, 0)
+        );
+        assert_eq!(
+            Err(Error::Capacity("message too long")),
+            pack_septets::<1>(b"hi", 0)
+        );
+    }
+
+    #[test]
+    fn test_charset() {
+        assert_eq!(Charset::Gsm7, Charset::select(""));
+        assert_eq!(Charset::Gsm7, Charset::select("$tATA/location/12345"));
+        assert_eq!(Charset::Gsm7, Charset::select("Ä {5€}\n"));
+        assert_eq!(Charset::Ucs2, Charset::select("Dömők"));
+        assert_eq!(Charset::Ucs2, Charset::select("😎"));
+
+        assert_eq!(1, Charset::Gsm7.units('a'));
+        assert_eq!(2, Charset::Gsm7.units('€'));
+        assert_eq!(1, Charset::Ucs2.units('€'));
+        assert_eq!(2, Charset::Ucs2.units('😎'));
+    }
+
     #[test]
     fn test_encode_utf8_hex_string() {
         assert_eq!(
@@ -278,6 +513,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_decode_hex() {
+        assert_eq!(Ok(alloc::vec![0x00, 0xAB, 0x7F]), decode_hex_u8("00ab7F"));
+        assert_eq!(Ok(alloc::vec![0x004B, 0xD83D]), decode_hex_u16("004BD83D"));
+        for s in ["0", "00A", "0é0", "xy"] {
+            assert!(decode_hex_u8(s).is_err(), "{}", s);
+        }
+        for s in ["004", "004B0", "00é0", "004X"] {
+            assert!(decode_hex_u16(s).is_err(), "{}", s);
+        }
+        assert_eq!(
+            Err(Error::Parse("invalid hex string")),
+            decode_utf8_hex_string::<30>(b"303")
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid hex string")),
+            decode_utf16_hex_string::<30>("00é0".as_bytes())
+        );
+    }
+
     #[test]
     fn test_decode_utf16_hex_string() {
         assert_eq!(
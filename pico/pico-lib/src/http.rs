@@ -0,0 +1,611 @@
+use defmt::Format;
+use defmt::debug;
+
+use alloc::string::ToString;
+use alloc::vec::Vec as AVec;
+use atat::AtatCmd;
+use atat::UrcSubscription;
+use atat::atat_derive::AtatCmd;
+use atat::atat_derive::AtatEnum;
+use atat::atat_derive::AtatResp;
+use atat::heapless::String;
+use atat::heapless::Vec;
+
+use crate::at::NoResponse;
+use crate::error::Error;
+use crate::urc::Urc;
+use crate::utils::send_command_logged;
+
+// 14.2 HTTP service of the module, it runs over the +SAPBR bearer (see gsm::Bearer).
+
+pub const READ_CHUNK: usize = 512; // fits in the ingress buffer with the +HTTPREAD header
+pub const MAX_BODY: usize = 1024; // of a request, sent at once after the DOWNLOAD prompt
+pub const MAX_RESPONSE: usize = 2048; // of a response body, whatever the caller takes
+pub const MAX_HEAP: usize = MAX_RESPONSE; // of fetch, the chunks are read on the stack
+pub const MAX_HEADERS: usize = 4;
+pub const ACTION_TIMEOUT_MILLIS: u64 = 60_000;
+const DATA_INPUT_MILLIS: u32 = 10_000;
+
+// 14.2.1 AT+HTTPINIT Initialize HTTP Service
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+HTTPINIT", NoResponse)]
+pub struct AtHttpInitExecute;
+
+// 14.2.2 AT+HTTPTERM Terminate HTTP Service
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+HTTPTERM", NoResponse)]
+pub struct AtHttpTermExecute;
+
+// 14.2.3 AT+HTTPPARA Set HTTP Parameters Value
+// AT+HTTPPARA=<HTTPParamTag>,<HTTPParamValue>
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+HTTPPARA", NoResponse)]
+pub struct AtHttpParaWrite {
+    pub tag: String<10>, // CID, URL, UA, PROIP, PROPORT, REDIR, BREAK, BREAKEND, TIMEOUT, CONTENT, USERDATA
+    pub value: String<200>, // the URL is at most 200 characters on the SIM800
+}
+
+// 14.2.4 AT+HTTPDATA Input HTTP Data
+// AT+HTTPDATA=<size>,<time>
+// DOWNLOAD (see ip::IpDigester), then the <size> bytes of the body, then OK.
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+HTTPDATA", NoResponse, timeout_ms = 5000)]
+pub struct AtHttpDataWrite {
+    pub size: u32,
+    pub time: u32, // millis to send the data
+}
+
+#[derive(Clone, Debug)]
+pub struct AtHttpData {
+    pub data: Vec<u8, MAX_BODY>,
+}
+
+impl AtatCmd for AtHttpData {
+    type Response = NoResponse;
+
+    const MAX_LEN: usize = MAX_BODY;
+    const MAX_TIMEOUT_MS: u32 = DATA_INPUT_MILLIS;
+
+    fn write(&self, buf: &mut [u8]) -> usize {
+        buf[..self.data.len()].copy_from_slice(&self.data);
+        self.data.len()
+    }
+
+    fn parse(
+        &self,
+        resp: Result<&[u8], atat::InternalError>,
+    ) -> Result<Self::Response, atat::Error> {
+        match resp {
+            Ok(_) => Ok(NoResponse),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// 14.2.5 AT+HTTPACTION HTTP Method Action
+// AT+HTTPACTION=<Method>
+// OK, then the +HTTPACTION URC when the request is over.
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+HTTPACTION", NoResponse)]
+pub struct AtHttpActionWrite {
+    pub method: HttpMethod,
+}
+
+#[derive(Debug, Format, Clone, PartialEq, AtatEnum)]
+pub enum HttpMethod {
+    Get = 0,
+    Post = 1,
+    Head = 2,
+}
+
+// +HTTPACTION: <Method>,<StatusCode>,<DataLen>
+// The status codes above 600 are the errors of the module, e.g. 601 network error.
+#[derive(Debug, Format, Clone, AtatResp, PartialEq)]
+pub struct HttpActionUrc {
+    pub method: u8,
+    pub status: u16,
+    pub size: u32,
+}
+
+// 14.2.6 AT+HTTPREAD Read the HTTP Server Response
+// AT+HTTPREAD=<start_address>,<byte_size>
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+HTTPREAD", HttpReadResponse, timeout_ms = 10000, parse = parse_http_read)]
+pub struct AtHttpReadWrite {
+    pub start: u32,
+    pub size: u32,
+}
+
+// +HTTPREAD: <data_len><CR><LF><data>
+#[derive(Debug, Clone, AtatResp, PartialEq, Default)]
+pub struct HttpReadResponse {
+    pub data: Vec<u8, READ_CHUNK>,
+}
+
+// The data is raw, taken by its length, see ip::IpDigester.
+fn parse_http_read(response: &[u8]) -> Result<HttpReadResponse, Error> {
+    let rest = response
+        .strip_prefix(b"+HTTPREAD: ")
+        .ok_or(Error::Parse("HTTPREAD"))?;
+    let i = rest
+        .iter()
+        .position(|b| *b == b'\r')
+        .ok_or(Error::Parse("HTTPREAD"))?;
+    let len: usize = core::str::from_utf8(&rest[..i])?.parse()?;
+    let data = rest[i..]
+        .strip_prefix(b"\r\n")
+        .ok_or(Error::Parse("HTTPREAD"))?;
+    if data.len() != len {
+        return Err(Error::Parse("HTTPREAD length"));
+    }
+    Ok(HttpReadResponse {
+        data: Vec::from_slice(data).map_err(|_| Error::Capacity("HTTP chunk"))?,
+    })
+}
+
+// A request of `action`, e.g.
+//
+//     Request::post(url, "application/json", body).header("Authorization", token)
+//
+// The headers go to USERDATA, the body to AT+HTTPDATA.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Request<'a> {
+    pub method: HttpMethod,
+    pub url: &'a str,
+    pub headers: Vec<(&'a str, &'a str), MAX_HEADERS>,
+    pub content_type: Option<&'a str>,
+    pub body: &'a [u8],
+}
+
+impl<'a> Request<'a> {
+    pub fn get(url: &'a str) -> Self {
+        Request {
+            method: HttpMethod::Get,
+            url,
+            headers: Vec::new(),
+            content_type: None,
+            body: &[],
+        }
+    }
+
+    pub fn post(url: &'a str, content_type: &'a str, body: &'a [u8]) -> Self {
+        Request {
+            method: HttpMethod::Post,
+            content_type: Some(content_type),
+            body,
+            ..Request::get(url)
+        }
+    }
+
+    // The headers over MAX_HEADERS are dropped, see `action`.
+    pub fn header(mut self, name: &'a str, value: &'a str) -> Self {
+        if self.headers.push((name, value)).is_err() {
+            debug!("HTTP header dropped: {}", name);
+        }
+        self
+    }
+}
+
+// The body of `fetch`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: AVec<u8>,
+}
+
+async fn set_parameter<T: atat::asynch::AtatClient>(
+    client: &mut T,
+    tag: &str,
+    value: &str,
+) -> Result<(), Error> {
+    send_command_logged(
+        client,
+        &AtHttpParaWrite {
+            tag: String::try_from(tag).map_err(|_| Error::Capacity("HTTP parameter"))?,
+            value: String::try_from(value).map_err(|_| Error::Capacity("HTTP parameter"))?,
+        },
+        "AtHttpParaWrite".to_string(),
+    )
+    .await?;
+    Ok(())
+}
+
+// Sends the request in the initialized HTTP service (see AtHttpInitExecute), returns the
+// status and the size of the body to be read with `read`.
+pub async fn action<
+    T: atat::asynch::AtatClient,
+    U: crate::at::PicoHW,
+    const CAP: usize,
+    const SUBS: usize,
+>(
+    client: &mut T,
+    pico: &mut U,
+    urcs: &mut UrcSubscription<'_, Urc, CAP, SUBS>,
+    request: &Request<'_>,
+) -> Result<HttpActionUrc, Error> {
+    set_parameter(client, "CID", "1").await?;
+    set_parameter(client, "URL", request.url).await?;
+    if !request.headers.is_empty() {
+        // a single parameter, the module turns the \r\n escapes into line breaks
+        let mut headers = alloc::string::String::new();
+        for (i, (name, value)) in request.headers.iter().enumerate() {
+            if i > 0 {
+                headers.push_str("\\r\\n");
+            }
+            headers.push_str(name);
+            headers.push_str(": ");
+            headers.push_str(value);
+        }
+        set_parameter(client, "USERDATA", &headers).await?;
+    }
+    if let Some(content_type) = request.content_type {
+        set_parameter(client, "CONTENT", content_type).await?;
+    }
+    if request.method == HttpMethod::Post {
+        let data = Vec::from_slice(request.body).map_err(|_| Error::Capacity("HTTP body"))?;
+        send_command_logged(
+            client,
+            &AtHttpDataWrite {
+                size: data.len() as u32,
+                time: DATA_INPUT_MILLIS,
+            },
+            "AtHttpDataWrite".to_string(),
+        )
+        .await?;
+        send_command_logged(client, &AtHttpData { data }, "AtHttpData".to_string()).await?;
+    }
+
+    while urcs.try_next_message_pure().is_some() {} // a stale +HTTPACTION
+    send_command_logged(
+        client,
+        &AtHttpActionWrite {
+            method: request.method.clone(),
+        },
+        "AtHttpActionWrite".to_string(),
+    )
+    .await?;
+    let action = wait_action(pico, urcs, ACTION_TIMEOUT_MILLIS).await?;
+    debug!("HTTP {} {}", action.status, action.size);
+    Ok(action)
+}
+
+// Reads at most `size` bytes (READ_CHUNK) of the body from `start`, empty at the end.
+pub async fn read<T: atat::asynch::AtatClient>(
+    client: &mut T,
+    start: usize,
+    size: usize,
+) -> Result<Vec<u8, READ_CHUNK>, Error> {
+    let chunk = send_command_logged(
+        client,
+        &AtHttpReadWrite {
+            start: start as u32,
+            size: size.min(READ_CHUNK) as u32,
+        },
+        "AtHttpReadWrite".to_string(),
+    )
+    .await?;
+    Ok(chunk.data)
+}
+
+// The whole exchange, the bearer has to be open (see gsm::Bearer). A body over `max_body`
+// or MAX_RESPONSE is not read (see crate::HEAP_SIZE).
+pub async fn fetch<
+    T: atat::asynch::AtatClient,
+    U: crate::at::PicoHW,
+    const CAP: usize,
+    const SUBS: usize,
+>(
+    client: &mut T,
+    pico: &mut U,
+    urcs: &mut UrcSubscription<'_, Urc, CAP, SUBS>,
+    request: &Request<'_>,
+    max_body: usize,
+) -> Result<Response, Error> {
+    send_command_logged(client, &AtHttpInitExecute, "AtHttpInitExecute".to_string()).await?;
+    let ret = fetch_body(client, pico, urcs, request, max_body).await;
+    send_command_logged(client, &AtHttpTermExecute, "AtHttpTermExecute".to_string())
+        .await
+        .ok();
+    ret
+}
+
+async fn fetch_body<
+    T: atat::asynch::AtatClient,
+    U: crate::at::PicoHW,
+    const CAP: usize,
+    const SUBS: usize,
+>(
+    client: &mut T,
+    pico: &mut U,
+    urcs: &mut UrcSubscription<'_, Urc, CAP, SUBS>,
+    request: &Request<'_>,
+    max_body: usize,
+) -> Result<Response, Error> {
+    let action = action(client, pico, urcs, request).await?;
+    let size = action.size as usize;
+    if size > max_body.min(MAX_RESPONSE) {
+        return Err(Error::Capacity("HTTP response"));
+    }
+    let mut body = AVec::with_capacity(size);
+    while body.len() < size {
+        let chunk = read(client, body.len(), size - body.len()).await?;
+        // less than announced by +HTTPACTION
+        if chunk.is_empty() {
+            return Err(Error::Protocol("HTTP body short"));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(Response {
+        status: action.status,
+        body,
+    })
+}
+
+// Waits for the +HTTPACTION URC of the request, polled as the client does not see the URCs.
+pub async fn wait_action<U: crate::at::PicoHW, const CAP: usize, const SUBS: usize>(
+    pico: &mut U,
+    urcs: &mut UrcSubscription<'_, Urc, CAP, SUBS>,
+    timeout_millis: u64,
+) -> Result<HttpActionUrc, Error> {
+    let mut waited = 0;
+    loop {
+        while let Some(urc) = urcs.try_next_message_pure() {
+            if let Urc::HttpAction(action) = urc {
+                return Ok(action);
+            }
+        }
+        if waited >= timeout_millis {
+            return Err(Error::Timeout);
+        }
+        pico.sleep(100).await;
+        waited += 100;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cmd_serialization_tests;
+
+    use super::*;
+    use atat::AtatCmd;
+
+    cmd_serialization_tests! {
+        test_at_http_init: (AtHttpInitExecute, "AT+HTTPINIT\r"),
+        test_at_http_term: (AtHttpTermExecute, "AT+HTTPTERM\r"),
+        test_at_http_para: (
+            AtHttpParaWrite {
+                tag: String::try_from("URL").unwrap(),
+                value: String::try_from("http://example.com/a.bin").unwrap(),
+            },
+            "AT+HTTPPARA=\"URL\",\"http://example.com/a.bin\"\r",
+        ),
+        test_at_http_action: (
+            AtHttpActionWrite {
+                method: HttpMethod::Get,
+            },
+            "AT+HTTPACTION=0\r",
+        ),
+        test_at_http_data: (
+            AtHttpDataWrite {
+                size: 42,
+                time: 10000,
+            },
+            "AT+HTTPDATA=42,10000\r",
+        ),
+        test_at_http_read: (
+            AtHttpReadWrite {
+                start: 512,
+                size: 512,
+            },
+            "AT+HTTPREAD=512,512\r",
+        ),
+    }
+
+    #[test]
+    fn test_at_http_read_responses() {
+        let cmd = AtHttpReadWrite {
+            start: 0,
+            size: 512,
+        };
+        assert_eq!(
+            b"\x00\r\nOK\x01",
+            cmd.parse(Ok(b"+HTTPREAD: 6\r\n\x00\r\nOK\x01"))
+                .unwrap()
+                .data
+                .as_slice()
+        );
+        assert_eq!(
+            b"ab \n",
+            cmd.parse(Ok(b"+HTTPREAD: 4\r\nab \n"))
+                .unwrap()
+                .data
+                .as_slice()
+        );
+        assert!(cmd.parse(Ok(b"+HTTPREAD: 0\r\n")).unwrap().data.is_empty());
+        assert!(cmd.parse(Ok(b"+HTTPREAD: 4\r\nab")).is_err());
+        assert!(cmd.parse(Ok(b"+HTTPREAD: 0")).is_err());
+        assert_eq!(
+            atat::Error::Parse,
+            cmd.parse(Ok(b"+CLBS: 0")).err().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_action() {
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .urc(b"RING")
+            .urc(b"+HTTPACTION: 0,200,1024");
+        let mut urcs = client.subscribe();
+        let mut pico = crate::at::tests::PicoMock::default();
+        assert_eq!(
+            Err(Error::Timeout),
+            wait_action(&mut pico, &mut urcs, 200).await
+        );
+        assert_eq!(2, pico.sleep_calls.len());
+
+        client.flush_urcs();
+        assert_eq!(
+            Ok(HttpActionUrc {
+                method: 0,
+                status: 200,
+                size: 1024
+            }),
+            wait_action(&mut pico, &mut urcs, 200).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_action_post() {
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .expect("AT+HTTPPARA=\"CID\",\"1\"\r", Ok(b""))
+            .expect("AT+HTTPPARA=\"URL\",\"http://x/p\"\r", Ok(b""))
+            .expect(
+                "AT+HTTPPARA=\"USERDATA\",\"Authorization: Bearer t\\r\\nX-Id: 7\"\r",
+                Ok(b""),
+            )
+            .expect("AT+HTTPPARA=\"CONTENT\",\"application/json\"\r", Ok(b""))
+            .expect("AT+HTTPDATA=2,10000\r", Ok(b""))
+            .expect("{}", Ok(b""))
+            .expect("AT+HTTPACTION=1\r", Ok(b""))
+            .urc(b"+HTTPACTION: 1,201,0");
+        let mut urcs = client.subscribe();
+        let mut pico = crate::at::tests::PicoMock::default();
+        let request = Request::post("http://x/p", "application/json", b"{}")
+            .header("Authorization", "Bearer t")
+            .header("X-Id", "7");
+        assert_eq!(
+            Ok(HttpActionUrc {
+                method: 1,
+                status: 201,
+                size: 0
+            }),
+            action(&mut client, &mut pico, &mut urcs, &request).await
+        );
+
+        let body = [0u8; MAX_BODY + 1];
+        let request = Request::post("http://x/p", "application/json", &body);
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .expect("AT+HTTPPARA=\"CID\",\"1\"\r", Ok(b""))
+            .expect("AT+HTTPPARA=\"URL\",\"http://x/p\"\r", Ok(b""))
+            .expect("AT+HTTPPARA=\"CONTENT\",\"application/json\"\r", Ok(b""));
+        assert_eq!(
+            Err(Error::Capacity("HTTP body")),
+            action(&mut client, &mut pico, &mut urcs, &request).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_emulated() {
+        let body: AVec<u8> = (0..1100).map(|i| b'a' + (i % 26) as u8).collect();
+        let mut sim = sim868_emu::Sim868::default();
+        sim.bearer_open = true;
+        sim.http_server
+            .insert("http://x/big".into(), (200, body.clone()));
+        sim.http_server
+            .insert("http://x/events".into(), (201, b"{\"id\":1}".to_vec()));
+        sim.http_server.insert(
+            "http://x/text".into(),
+            (200, b"ok\r\nOK\r\nRING\r\n \n".to_vec()),
+        );
+        let mut harness = crate::at::tests::Harness::new(sim);
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut urcs = harness.urc_channel.subscribe().unwrap();
+
+        let ret = harness
+            .run(async |client| {
+                fetch(
+                    client,
+                    &mut pico,
+                    &mut urcs,
+                    &Request::get("http://x/big"),
+                    2048,
+                )
+                .await
+            })
+            .await;
+        assert_eq!(Ok(Response { status: 200, body }), ret);
+
+        let request = Request::post("http://x/events", "application/json", b"{\"armed\":true}")
+            .header("Authorization", "Bearer t");
+        let ret = harness
+            .run(async |client| fetch(client, &mut pico, &mut urcs, &request, 64).await)
+            .await;
+        assert_eq!(
+            Ok(Response {
+                status: 201,
+                body: b"{\"id\":1}".to_vec()
+            }),
+            ret
+        );
+        let got = harness.modem.with(|sim| sim.http_requests[1].clone());
+        assert_eq!(b"{\"armed\":true}".to_vec(), got.body);
+        assert_eq!(Some("application/json".into()), got.content_type);
+        assert_eq!(
+            alloc::vec![("Authorization".into(), "Bearer t".into())],
+            got.headers
+        );
+
+        // whitespace at the end, a final result and a URC in the body
+        let ret = harness
+            .run(async |client| {
+                fetch(
+                    client,
+                    &mut pico,
+                    &mut urcs,
+                    &Request::get("http://x/text"),
+                    64,
+                )
+                .await
+            })
+            .await;
+        assert_eq!(
+            Ok(Response {
+                status: 200,
+                body: b"ok\r\nOK\r\nRING\r\n \n".to_vec()
+            }),
+            ret
+        );
+
+        let ret = harness
+            .run(async |client| {
+                fetch(
+                    client,
+                    &mut pico,
+                    &mut urcs,
+                    &Request::get("http://x/big"),
+                    1024,
+                )
+                .await
+            })
+            .await;
+        assert_eq!(Err(Error::Capacity("HTTP response")), ret);
+        assert!(!harness.modem.with(|sim| sim.http_init));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_emulated_rejected() {
+        let mut sim = sim868_emu::Sim868::default();
+        sim.bearer_open = true;
+        sim.http_server.insert(
+            "http://x/huge".into(),
+            (200, alloc::vec![b'a'; MAX_RESPONSE + 1]),
+        );
+        let mut harness = crate::at::tests::Harness::new(sim);
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut urcs = harness.urc_channel.subscribe().unwrap();
+
+        let ret = harness
+            .run(async |client| {
+                let request = Request::get("http://x/huge");
+                fetch(client, &mut pico, &mut urcs, &request, usize::MAX).await
+            })
+            .await;
+        assert_eq!(Err(Error::Capacity("HTTP response")), ret);
+        assert!(
+            !harness
+                .modem
+                .with(|sim| sim.commands.iter().any(|c| c.starts_with("AT+HTTPREAD")))
+        );
+        assert!(!harness.modem.with(|sim| sim.http_init));
+    }
+}
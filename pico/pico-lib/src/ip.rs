@@ -0,0 +1,984 @@
+use core::cell::Cell;
+use core::net::Ipv4Addr;
+use core::net::SocketAddr;
+use core::net::SocketAddrV4;
+
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec::Vec as AVec;
+use atat::AtatCmd;
+use atat::AtatResp;
+use atat::DigestResult;
+use atat::Digester;
+use atat::InternalError;
+use atat::atat_derive::AtatCmd;
+use atat::atat_derive::AtatEnum;
+use atat::atat_derive::AtatResp;
+use atat::heapless::String;
+use atat::heapless::Vec;
+use defmt::Format;
+use defmt::debug;
+use defmt::info;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+
+use crate::at::NoResponse;
+use crate::error::Error;
+use crate::hexstr;
+use crate::utils::send_command_logged;
+
+// SIM800 Series AT Command Manual V1.09, 8 Commands for TCPIP Application Toolkit.
+//
+// The sockets of embedded-nal-async over the multi IP connection mode of the module
+// (AT+CIPMUX=1), up to 6 connections at a time on the context of gsm::Bearer. The
+// received data is buffered by the module and read manually in hex (AT+CIPRXGET=3), the
+// raw data could hold an OK or a URC the atat digester would take.
+
+pub const MAX_CONNECTIONS: u8 = 6;
+pub const READ_CHUNK: usize = 256; // twice in hex, the response has to fit in the ingress buffer
+pub const SEND_CHUNK: usize = 1024; // at most 1460, the client buffer is 2048
+const POLL_MILLIS: u64 = 200;
+const CONNECT_POLLS: u32 = 400; // 80 s, the module gives up a TCP connect in 75 s
+pub const RECEIVE_TIMEOUT_MILLIS: u64 = 60_000; // see Connection::set_timeout
+
+// 8.2.20 AT+CIPMUX Start Up Multi-IP Connection
+// AT+CIPMUX=<n>
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CIPMUX", NoResponse)]
+pub struct AtMultiIpConnectionWrite {
+    pub n: u8, // 0 single, 1 multi IP connection, in the IP INITIAL state only
+}
+
+// 8.2.26 AT+CIPRXGET Get Data from Network Manually
+// AT+CIPRXGET=<mode>[,<id>,<reqlength>]
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CIPRXGET", NoResponse)]
+pub struct AtManualReceiveWrite {
+    pub mode: ReceiveMode,
+}
+
+#[derive(Debug, Format, Clone, PartialEq, AtatEnum)]
+pub enum ReceiveMode {
+    Disable = 0, // the data is sent as +RECEIVE,<id>,<length>:
+    Enable = 1,  // +CIPRXGET: 1,<id> when data arrives in the empty buffer
+    Normal = 2,  // at most 1460 bytes
+    Hex = 3,     // at most 730 bytes
+    Query = 4,
+}
+
+// +CIPRXGET: 3,<id>,<reqlength>,<cnflength><CR><LF><data>
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CIPRXGET", ReceivedDataResponse, parse = parse_received_data)]
+pub struct AtReceiveDataWrite {
+    pub mode: ReceiveMode, // Hex, see ReceivedDataResponse
+    pub id: u8,
+    pub length: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ReceivedDataResponse {
+    pub id: u8,
+    pub left: u16, // still in the buffer of the module
+    pub data: Vec<u8, READ_CHUNK>,
+}
+
+impl AtatResp for ReceivedDataResponse {}
+
+fn parse_received_data(response: &[u8]) -> Result<ReceivedDataResponse, Error> {
+    let text = core::str::from_utf8(response)?;
+    let text = text.strip_prefix("+CIPRXGET: 3,").ok_or(())?;
+    let (header, hex) = text.split_once("\r\n").unwrap_or((text, ""));
+    let fields: AVec<&str> = header.split(',').collect();
+    let [id, length, left] = fields[..] else {
+        return Err(Error::Parse("response"));
+    };
+    let data = hexstr::decode_hex_u8(hex.trim_end())?;
+    if data.len() != length.parse::<usize>()? {
+        return Err(Error::Parse("data length"));
+    }
+    Ok(ReceivedDataResponse {
+        id: id.parse()?,
+        left: left.parse()?,
+        data: Vec::from_slice(&data).map_err(|_| Error::Capacity("received data"))?,
+    })
+}
+
+// 8.2.2 AT+CIPSTART Start Up TCP or UDP Connection
+// AT+CIPSTART=<n>,<mode>,<IP address>,<port>
+// OK, then <n>, CONNECT OK, CONNECT FAIL or ALREADY CONNECT (see IpDigester)
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CIPSTART", NoResponse, timeout_ms = 5000)]
+pub struct AtStartConnectionWrite {
+    pub id: u8,
+    pub mode: String<3>, // TCP or UDP
+    pub address: String<15>,
+    pub port: u16,
+}
+
+// 8.2.7 AT+CIPSTATUS Query Current Connection Status
+// AT+CIPSTATUS=<n>
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CIPSTATUS", ConnectionStatusResponse)]
+pub struct AtConnectionStatusWrite {
+    pub id: u8,
+}
+
+// +CIPSTATUS: <n>,<bearer>,<TCP/UDP>,<IP address>,<port>,<client state>
+#[derive(Debug, Clone, AtatResp, PartialEq, Default)]
+pub struct ConnectionStatusResponse {
+    pub id: u8,
+    pub bearer: Option<u8>,
+    pub mode: String<3>,
+    pub address: String<15>,
+    pub port: String<5>,
+    pub state: String<16>, // INITIAL, CONNECTING, CONNECTED, REMOTE CLOSING, CLOSING, CLOSED
+}
+
+// 8.2.3 AT+CIPSEND Send Data Through TCP or UDP Connection
+// AT+CIPSEND=<n>,<length>
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CIPSEND", NoResponse, timeout_ms = 5000)] // NoResponse == waiting for prompt ">"
+pub struct AtSendWrite {
+    pub id: u8,
+    pub length: u16,
+}
+
+// The <length> bytes after the prompt, then <n>, SEND OK or SEND FAIL (see IpDigester).
+#[derive(Clone, Debug)]
+pub struct AtSendData {
+    pub data: Vec<u8, SEND_CHUNK>,
+}
+
+impl AtatCmd for AtSendData {
+    type Response = NoResponse;
+
+    const MAX_LEN: usize = SEND_CHUNK;
+    const MAX_TIMEOUT_MS: u32 = 20000; // SEND OK after the TCP ACK of the peer
+
+    fn write(&self, buf: &mut [u8]) -> usize {
+        buf[..self.data.len()].copy_from_slice(&self.data);
+        self.data.len()
+    }
+
+    fn parse(
+        &self,
+        resp: Result<&[u8], atat::InternalError>,
+    ) -> Result<Self::Response, atat::Error> {
+        match resp {
+            Ok(_) => Ok(NoResponse),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// 8.2.4 AT+CIPCLOSE Close TCP or UDP Connection
+// AT+CIPCLOSE=<id>,[<n>]
+// <id>, CLOSE OK (see IpDigester)
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CIPCLOSE", NoResponse, timeout_ms = 5000)]
+pub struct AtCloseConnectionWrite {
+    pub id: u8,
+    pub n: u8, // 1 quick close
+}
+
+// Sets the modes of the sockets, before gsm::Bearer opens it, they can't be changed in the
+// other states of the IP stack.
+pub async fn configure<T: atat::asynch::AtatClient>(client: &mut T) -> Result<(), Error> {
+    send_command_logged(
+        client,
+        &AtMultiIpConnectionWrite { n: 1 },
+        "AtMultiIpConnectionWrite".to_string(),
+    )
+    .await?;
+    send_command_logged(
+        client,
+        &AtManualReceiveWrite {
+            mode: ReceiveMode::Enable,
+        },
+        "AtManualReceiveWrite".to_string(),
+    )
+    .await?;
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, Format, PartialEq)]
+pub enum Event {
+    Connected(u8),     // <n>, CONNECT OK or ALREADY CONNECT
+    ConnectFailed(u8), // <n>, CONNECT FAIL
+    Closed(u8),        // <n>, CLOSED, by the peer or the network
+    DataAvailable(u8), // +CIPRXGET: 1,<n>
+}
+
+const DATA_AVAILABLE: &[u8] = b"+CIPRXGET: 1,";
+const DOWNLOAD: &[u8] = b"DOWNLOAD\r\n"; // the prompt of http::AtHttpDataWrite
+const HTTP_READ: &[u8] = b"+HTTPREAD: "; // <data_len> raw bytes follow, see http::AtHttpReadWrite
+const FINAL_OK: &[u8] = b"\r\nOK\r\n";
+
+// Digester in front of the one of the URCs and responses, the result codes of the
+// connections start with the id (e.g. "0, SEND OK") instead of a ':' separated code (see
+// atat_derive). The ones of a command (SEND OK, SEND FAIL, CLOSE OK) are its response, the
+// rest is passed to `on_event`, e.g. to log them, the Stack polls the state anyway. The
+// DOWNLOAD prompt of the HTTP service and the bare address of AT+CIFSR (no final OK, see
+// gsm::AtGetLocalIPAddressExecute) are taken as a response as well. The data of +HTTPREAD is
+// taken by its length, the default digester would trim the whitespace at its end or stop at
+// an OK or a URC in it.
+pub struct IpDigester<D: Digester> {
+    inner: D,
+    on_event: fn(Event),
+}
+
+impl<D: Digester> IpDigester<D> {
+    pub fn new(inner: D, on_event: fn(Event)) -> Self {
+        IpDigester { inner, on_event }
+    }
+}
+
+impl<D: Digester + Default> Default for IpDigester<D> {
+    fn default() -> Self {
+        IpDigester::new(D::default(), |_| {})
+    }
+}
+
+impl<D: Digester> Digester for IpDigester<D> {
+    fn digest<'a>(&mut self, buf: &'a [u8]) -> (DigestResult<'a>, usize) {
+        let start = buf
+            .iter()
+            .take_while(|b| **b == b'\r' || **b == b'\n')
+            .count();
+        let rest = &buf[start..];
+        if rest.starts_with(DOWNLOAD) {
+            return (DigestResult::Response(Ok(&[])), start + DOWNLOAD.len());
+        }
+        // after the echo of the command, if it is on
+        let echo = match rest.iter().position(|b| *b == b'\r') {
+            Some(end) if rest.starts_with(b"AT") => {
+                end + rest[end..]
+                    .iter()
+                    .take_while(|b| **b == b'\r' || **b == b'\n')
+                    .count()
+            }
+            _ => 0,
+        };
+        if let Some((result, len)) = http_read(&rest[echo..]) {
+            return match len {
+                0 => (result, 0),
+                _ => (result, start + echo + len),
+            };
+        }
+        if let Some(address) = local_address(rest) {
+            return (
+                DigestResult::Response(Ok(address)),
+                start + address.len() + 2,
+            );
+        }
+        let id = match rest {
+            [id @ b'0'..=b'5', b',', b' ', ..] => id - b'0',
+            _ => match rest.strip_prefix(DATA_AVAILABLE) {
+                Some([id @ b'0'..=b'5', ..]) => id - b'0',
+                _ => return self.inner.digest(buf),
+            },
+        };
+        let Some(end) = rest.iter().position(|b| *b == b'\n') else {
+            return (DigestResult::None, 0); // the rest of the line is coming
+        };
+        let len = start + end + 1;
+        let event = match rest[..end].trim_ascii_end() {
+            [b'+', ..] => Event::DataAvailable(id),
+            [_, _, _, text @ ..] => match text {
+                b"SEND OK" | b"CLOSE OK" => return (DigestResult::Response(Ok(&[])), len),
+                b"SEND FAIL" => return (DigestResult::Response(Err(InternalError::Error)), len),
+                b"CONNECT OK" | b"ALREADY CONNECT" => Event::Connected(id),
+                b"CONNECT FAIL" => Event::ConnectFailed(id),
+                b"CLOSED" => Event::Closed(id),
+                _ => return self.inner.digest(buf),
+            },
+            _ => return self.inner.digest(buf),
+        };
+        debug!("IP event {}", event);
+        (self.on_event)(event);
+        (DigestResult::None, len)
+    }
+}
+
+// +HTTPREAD: <data_len><CR><LF><data><CR><LF>OK<CR><LF>, None when it is not one.
+fn http_read(rest: &[u8]) -> Option<(DigestResult<'_>, usize)> {
+    let digits = rest
+        .strip_prefix(HTTP_READ)?
+        .iter()
+        .take_while(|b| b.is_ascii_digit())
+        .count();
+    let header = HTTP_READ.len() + digits + 2;
+    if rest.len() < header {
+        return Some((DigestResult::None, 0)); // the rest of the line is coming
+    }
+    if &rest[header - 2..header] != b"\r\n" {
+        return None;
+    }
+    let size: usize = core::str::from_utf8(&rest[HTTP_READ.len()..header - 2])
+        .ok()?
+        .parse()
+        .ok()?;
+    let len = header + size;
+    if rest.len() < len + FINAL_OK.len() {
+        return Some((DigestResult::None, 0)); // the rest of the data is coming
+    }
+    if !rest[len..].starts_with(FINAL_OK) {
+        return Some((DigestResult::Response(Err(InternalError::Error)), len));
+    }
+    Some((
+        DigestResult::Response(Ok(&rest[..len])),
+        len + FINAL_OK.len(),
+    ))
+}
+
+// An IPv4 address on a line of its own, e.g. "100.95.173.97\r\n".
+fn local_address(rest: &[u8]) -> Option<&[u8]> {
+    let len = rest
+        .iter()
+        .take_while(|b| b.is_ascii_digit() || **b == b'.')
+        .count();
+    let address = &rest[..len];
+    if !rest[len..].starts_with(b"\r\n") {
+        return None;
+    }
+    core::str::from_utf8(address)
+        .ok()?
+        .parse::<Ipv4Addr>()
+        .ok()
+        .map(|_| address)
+}
+
+// TCP (TcpConnect) and connected UDP (UdpStack, on a reference of the Stack) sockets, the
+// client and the pico are borrowed for the lifetime of the stack. The commands of the
+// sockets don't overlap, the client is locked for one exchange at a time.
+//
+// A connection is closed with Connection::close, a dropped one is closed before the next
+// connect, the id is taken until then.
+pub struct Stack<'a, T, U> {
+    client: Mutex<NoopRawMutex, &'a mut T>,
+    pico: Mutex<NoopRawMutex, &'a mut U>,
+    used: Cell<u8>,  // bitmask of the connection ids
+    stale: Cell<u8>, // dropped without close
+}
+
+impl<'a, T: atat::asynch::AtatClient, U: crate::at::PicoHW> Stack<'a, T, U> {
+    pub fn new(client: &'a mut T, pico: &'a mut U) -> Self {
+        Stack {
+            client: Mutex::new(client),
+            pico: Mutex::new(pico),
+            used: Cell::new(0),
+            stale: Cell::new(0),
+        }
+    }
+
+    // Returns the id of the connection once it is up.
+    async fn open(&self, mode: &str, remote: SocketAddr) -> Result<u8, Error> {
+        let SocketAddr::V4(remote) = remote else {
+            return Err(Error::Protocol("IPv6 not supported"));
+        };
+        self.close_stale().await;
+        let id = (0..MAX_CONNECTIONS)
+            .find(|id| self.used.get() & (1 << id) == 0)
+            .ok_or(Error::Capacity("connections"))?;
+        let cmd = AtStartConnectionWrite {
+            id,
+            mode: String::try_from(mode).map_err(|_| Error::Capacity("mode"))?,
+            address: String::try_from(format!("{}", remote.ip()).as_str())
+                .map_err(|_| Error::Capacity("address"))?,
+            port: remote.port(),
+        };
+        send_command_logged(
+            *self.client.lock().await,
+            &cmd,
+            "AtStartConnectionWrite".to_string(),
+        )
+        .await?;
+        self.used.set(self.used.get() | 1 << id);
+
+        for _ in 0..CONNECT_POLLS {
+            match self.status(id).await?.state.as_str() {
+                "CONNECTED" => {
+                    info!("Connection {} to {} is up", id, cmd.address.as_str());
+                    return Ok(id);
+                }
+                "INITIAL" | "CONNECTING" => self.pico.lock().await.sleep(POLL_MILLIS).await,
+                _ => {
+                    self.release(id);
+                    return Err(Error::Protocol("connect failed"));
+                }
+            }
+        }
+        let _ = self.close(id).await;
+        Err(Error::Timeout)
+    }
+
+    async fn status(&self, id: u8) -> Result<ConnectionStatusResponse, Error> {
+        send_command_logged(
+            *self.client.lock().await,
+            &AtConnectionStatusWrite { id },
+            "AtConnectionStatusWrite".to_string(),
+        )
+        .await
+    }
+
+    // A single datagram on UDP.
+    async fn send(&self, id: u8, data: &[u8]) -> Result<(), Error> {
+        let data = Vec::from_slice(data).map_err(|_| Error::Capacity("send chunk"))?;
+        let mut client = self.client.lock().await;
+        send_command_logged(
+            *client,
+            &AtSendWrite {
+                id,
+                length: data.len() as u16,
+            },
+            "AtSendWrite".to_string(),
+        )
+        .await?;
+        send_command_logged(*client, &AtSendData { data }, "AtSendData".to_string()).await?;
+        Ok(())
+    }
+
+    // Waits for the data, 0 when the connection is closed and everything is read. A peer
+    // that sends nothing for `timeout_millis` (e.g. a half-open connection) is a Timeout.
+    async fn receive(&self, id: u8, buf: &mut [u8], timeout_millis: u64) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let start = self.pico.lock().await.uptime_millis();
+        loop {
+            let resp = send_command_logged(
+                *self.client.lock().await,
+                &AtReceiveDataWrite {
+                    mode: ReceiveMode::Hex,
+                    id,
+                    length: buf.len().min(READ_CHUNK) as u16,
+                },
+                "AtReceiveDataWrite".to_string(),
+            )
+            .await?;
+            if !resp.data.is_empty() {
+                buf[..resp.data.len()].copy_from_slice(&resp.data);
+                return Ok(resp.data.len());
+            }
+            if self.status(id).await?.state != "CONNECTED" {
+                return Ok(0);
+            }
+            let mut pico = self.pico.lock().await;
+            if pico.uptime_millis() - start >= timeout_millis {
+                return Err(Error::Timeout);
+            }
+            pico.sleep(POLL_MILLIS).await;
+        }
+    }
+
+    async fn close(&self, id: u8) -> Result<(), Error> {
+        let ret = send_command_logged(
+            *self.client.lock().await,
+            &AtCloseConnectionWrite { id, n: 1 },
+            "AtCloseConnectionWrite".to_string(),
+        )
+        .await;
+        self.release(id);
+        match ret {
+            Err(Error::Modem) => Ok(()), // closed already, e.g. by the peer
+            ret => ret.map(|_| ()),
+        }
+    }
+
+    async fn close_stale(&self) {
+        for id in 0..MAX_CONNECTIONS {
+            if self.stale.get() & (1 << id) != 0 {
+                let _ = self.close(id).await;
+            }
+        }
+    }
+
+    fn release(&self, id: u8) {
+        self.used.set(self.used.get() & !(1 << id));
+        self.stale.set(self.stale.get() & !(1 << id));
+    }
+}
+
+// TCP stream or connected UDP socket.
+pub struct Connection<'s, 'a, T: atat::asynch::AtatClient, U: crate::at::PicoHW> {
+    stack: &'s Stack<'a, T, U>,
+    id: u8,
+    closed: bool,
+    timeout_millis: u64, // of a read
+}
+
+impl<T: atat::asynch::AtatClient, U: crate::at::PicoHW> Connection<'_, '_, T, U> {
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    // How long a read waits for the peer, RECEIVE_TIMEOUT_MILLIS by default.
+    pub fn set_timeout(&mut self, millis: u64) {
+        self.timeout_millis = millis;
+    }
+
+    pub async fn close(mut self) -> Result<(), Error> {
+        self.closed = true;
+        self.stack.close(self.id).await
+    }
+}
+
+impl<T: atat::asynch::AtatClient, U: crate::at::PicoHW> Drop for Connection<'_, '_, T, U> {
+    fn drop(&mut self) {
+        if !self.closed {
+            self.stack.stale.set(self.stack.stale.get() | 1 << self.id);
+        }
+    }
+}
+
+impl<T: atat::asynch::AtatClient, U: crate::at::PicoHW> embedded_io_async::ErrorType
+    for Connection<'_, '_, T, U>
+{
+    type Error = Error;
+}
+
+impl<T: atat::asynch::AtatClient, U: crate::at::PicoHW> embedded_io_async::Read
+    for Connection<'_, '_, T, U>
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.stack.receive(self.id, buf, self.timeout_millis).await
+    }
+}
+
+impl<T: atat::asynch::AtatClient, U: crate::at::PicoHW> embedded_io_async::Write
+    for Connection<'_, '_, T, U>
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let n = buf.len().min(SEND_CHUNK);
+        self.stack.send(self.id, &buf[..n]).await?;
+        Ok(n)
+    }
+
+    // SEND OK is the end of the write already.
+    async fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<T: atat::asynch::AtatClient, U: crate::at::PicoHW> embedded_nal_async::ConnectedUdp
+    for Connection<'_, '_, T, U>
+{
+    type Error = Error;
+
+    async fn send(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.stack.send(self.id, data).await
+    }
+
+    // The datagrams are not separated in the buffer of the module.
+    async fn receive_into(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.stack.receive(self.id, buf, self.timeout_millis).await
+    }
+}
+
+impl<'a, T: atat::asynch::AtatClient, U: crate::at::PicoHW> embedded_nal_async::TcpConnect
+    for Stack<'a, T, U>
+{
+    type Error = Error;
+    type Connection<'s>
+        = Connection<'s, 'a, T, U>
+    where
+        Self: 's;
+
+    async fn connect<'s>(&'s self, remote: SocketAddr) -> Result<Connection<'s, 'a, T, U>, Error> {
+        let id = self.open("TCP", remote).await?;
+        Ok(Connection {
+            stack: self,
+            id,
+            closed: false,
+            timeout_millis: RECEIVE_TIMEOUT_MILLIS,
+        })
+    }
+}
+
+// The module has no listening sockets.
+pub enum Unbound {}
+
+impl embedded_nal_async::UnconnectedUdp for Unbound {
+    type Error = Error;
+
+    async fn send(&mut self, _: SocketAddr, _: SocketAddr, _: &[u8]) -> Result<(), Error> {
+        match *self {}
+    }
+
+    async fn receive_into(
+        &mut self,
+        _: &mut [u8],
+    ) -> Result<(usize, SocketAddr, SocketAddr), Error> {
+        match *self {}
+    }
+}
+
+impl<'s, 'a, T: atat::asynch::AtatClient, U: crate::at::PicoHW> embedded_nal_async::UdpStack
+    for &'s Stack<'a, T, U>
+{
+    type Error = Error;
+    type Connected = Connection<'s, 'a, T, U>;
+    type UniquelyBound = Unbound;
+    type MultiplyBound = Unbound;
+
+    // The local address is not known, the module picks it.
+    async fn connect_from(
+        &self,
+        _local: SocketAddr,
+        remote: SocketAddr,
+    ) -> Result<(SocketAddr, Connection<'s, 'a, T, U>), Error> {
+        let id = self.open("UDP", remote).await?;
+        let local = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+        Ok((
+            local,
+            Connection {
+                stack: *self,
+                id,
+                closed: false,
+                timeout_millis: RECEIVE_TIMEOUT_MILLIS,
+            },
+        ))
+    }
+
+    async fn bind_single(&self, _: SocketAddr) -> Result<(SocketAddr, Unbound), Error> {
+        Err(Error::Protocol("UDP bind not supported"))
+    }
+
+    async fn bind_multiple(&self, _: SocketAddr) -> Result<Unbound, Error> {
+        Err(Error::Protocol("UDP bind not supported"))
+    }
+}
+
+#[cfg(test)]
+extern crate std;
+
+#[cfg(test)]
+mod tests {
+    use crate::at::PicoHW;
+    use crate::at::tests::EmuPico;
+    use crate::at::tests::Harness;
+    use crate::cmd_serialization_tests;
+    use crate::gsm;
+    use embedded_io_async::Read;
+    use embedded_io_async::Write;
+    use embedded_nal_async::ConnectedUdp;
+    use embedded_nal_async::TcpConnect;
+    use embedded_nal_async::UdpStack;
+    use sim868_emu::sim868::Echo;
+    use sim868_emu::sim868::Silent;
+
+    use super::*;
+
+    cmd_serialization_tests! {
+        test_at_multi_ip_connection: (
+            AtMultiIpConnectionWrite { n: 1 },
+            "AT+CIPMUX=1\r",
+        ),
+        test_at_manual_receive: (
+            AtManualReceiveWrite { mode: ReceiveMode::Enable },
+            "AT+CIPRXGET=1\r",
+        ),
+        test_at_receive_data: (
+            AtReceiveDataWrite { mode: ReceiveMode::Hex, id: 2, length: 256 },
+            "AT+CIPRXGET=3,2,256\r",
+        ),
+        test_at_start_connection: (
+            AtStartConnectionWrite {
+                id: 0,
+                mode: String::try_from("TCP").unwrap(),
+                address: String::try_from("10.0.0.1").unwrap(),
+                port: 1883,
+            },
+            "AT+CIPSTART=0,\"TCP\",\"10.0.0.1\",1883\r",
+        ),
+        test_at_connection_status: (
+            AtConnectionStatusWrite { id: 0 },
+            "AT+CIPSTATUS=0\r",
+        ),
+        test_at_send: (
+            AtSendWrite { id: 0, length: 5 },
+            "AT+CIPSEND=0,5\r",
+        ),
+        test_at_close_connection: (
+            AtCloseConnectionWrite { id: 0, n: 1 },
+            "AT+CIPCLOSE=0,1\r",
+        ),
+    }
+
+    fn read_cmd() -> AtReceiveDataWrite {
+        AtReceiveDataWrite {
+            mode: ReceiveMode::Hex,
+            id: 1,
+            length: 256,
+        }
+    }
+
+    #[test]
+    fn test_parse_received_data() {
+        assert_eq!(
+            ReceivedDataResponse {
+                id: 1,
+                left: 3,
+                data: Vec::from_slice(b"he\r\n").unwrap(),
+            },
+            read_cmd()
+                .parse(Ok(b"+CIPRXGET: 3,1,4,3\r\n68650D0A"))
+                .unwrap()
+        );
+        assert_eq!(
+            ReceivedDataResponse {
+                id: 1,
+                left: 0,
+                data: Vec::new(),
+            },
+            read_cmd().parse(Ok(b"+CIPRXGET: 3,1,0,0")).unwrap()
+        );
+        assert!(read_cmd().parse(Ok(b"+CIPRXGET: 3,1,4,3\r\n6865")).is_err());
+        assert!(read_cmd().parse(Ok(b"+CIPRXGET: 3,1,1,0\r\n686")).is_err());
+        assert!(read_cmd().parse(Ok(b"+CIPRXGET: 2,1,1,0\r\nh")).is_err());
+    }
+
+    static EVENTS: std::sync::Mutex<std::vec::Vec<Event>> =
+        std::sync::Mutex::new(std::vec::Vec::new());
+
+    #[test]
+    fn test_ip_digester() {
+        let mut digester = IpDigester::new(
+            atat::DefaultDigester::<crate::urc::Urc>::default(),
+            |event| EVENTS.lock().unwrap().push(event),
+        );
+        assert_eq!(
+            (DigestResult::Response(Ok(b"")), 14),
+            digester.digest(b"\r\n0, SEND OK\r\n\r\nRING\r\n")
+        );
+        assert_eq!(
+            (DigestResult::Response(Err(InternalError::Error)), 16),
+            digester.digest(b"\r\n1, SEND FAIL\r\n")
+        );
+        assert_eq!(
+            (DigestResult::Response(Ok(b"")), 15),
+            digester.digest(b"\r\n2, CLOSE OK\r\n")
+        );
+        assert_eq!((DigestResult::None, 0), digester.digest(b"\r\n0, CONN"));
+        assert_eq!(
+            (DigestResult::None, 17),
+            digester.digest(b"\r\n0, CONNECT OK\r\n")
+        );
+        assert_eq!(
+            (DigestResult::None, 18),
+            digester.digest(b"\r\n+CIPRXGET: 1,3\r\n")
+        );
+        assert_eq!(
+            (DigestResult::None, 13),
+            digester.digest(b"\r\n5, CLOSED\r\n")
+        );
+        assert_eq!(
+            [
+                Event::Connected(0),
+                Event::DataAvailable(3),
+                Event::Closed(5)
+            ],
+            EVENTS.lock().unwrap().as_slice()
+        );
+        assert_eq!(
+            (DigestResult::Response(Ok(b"")), 12),
+            digester.digest(b"\r\nDOWNLOAD\r\n")
+        );
+        assert_eq!(
+            (DigestResult::Response(Ok(b"100.95.173.97")), 17),
+            digester.digest(b"\r\n100.95.173.97\r\n")
+        );
+        assert_eq!((DigestResult::None, 0), digester.digest(b"\r\n100.95.1"));
+        // raw data by its length
+        assert_eq!(
+            (
+                DigestResult::Response(Ok(b"+HTTPREAD: 11\r\n\r\nOK\r\nRING ")),
+                34
+            ),
+            digester.digest(b"\r\n+HTTPREAD: 11\r\n\r\nOK\r\nRING \r\nOK\r\n")
+        );
+        assert_eq!(
+            (DigestResult::Response(Ok(b"+HTTPREAD: 2\r\n\n ")), 40),
+            digester.digest(b"AT+HTTPREAD=0,2\r\r\n+HTTPREAD: 2\r\n\n \r\nOK\r\n")
+        );
+        assert_eq!(
+            (DigestResult::Response(Ok(b"+HTTPREAD: 0\r\n")), 22),
+            digester.digest(b"\r\n+HTTPREAD: 0\r\n\r\nOK\r\n")
+        );
+        assert_eq!(
+            (DigestResult::None, 0),
+            digester.digest(b"\r\n+HTTPREAD: 1")
+        );
+        assert_eq!(
+            (DigestResult::None, 0),
+            digester.digest(b"\r\n+HTTPREAD: 4\r\nab\r\n\r\nO")
+        );
+        assert_eq!(
+            (DigestResult::Response(Err(InternalError::Error)), 18),
+            digester.digest(b"\r\n+HTTPREAD: 2\r\nab\r\nERROR\r\n")
+        );
+        // responses and URCs of the others
+        assert_eq!(
+            (DigestResult::Urc(b"RING"), 8),
+            digester.digest(b"\r\nRING\r\n")
+        );
+        assert_eq!(
+            (DigestResult::Response(Ok(b"+CIPRXGET: 3,0,0,0")), 28),
+            digester.digest(b"\r\n+CIPRXGET: 3,0,0,0\r\n\r\nOK\r\n")
+        );
+    }
+
+    fn harness() -> Harness {
+        let harness = Harness::new(sim868_emu::Sim868::default());
+        harness.modem.with(|sim| {
+            sim.servers
+                .insert("10.0.0.1:7".to_string(), alloc::boxed::Box::new(Echo));
+        });
+        harness
+    }
+
+    #[tokio::test]
+    async fn test_tcp_receive_timeout() {
+        let mut harness = harness();
+        harness.modem.with(|sim| {
+            sim.servers
+                .insert("10.0.0.1:9".to_string(), alloc::boxed::Box::new(Silent));
+        });
+        let mut pico = EmuPico::new(harness.modem.clone());
+        let elapsed = harness
+            .run(async |client| {
+                configure(client).await.unwrap();
+                gsm::Bearer::default()
+                    .acquire(client, &mut pico, "online")
+                    .await
+                    .unwrap();
+                let stack = Stack::new(client, &mut pico);
+                let mut connection = stack.connect("10.0.0.1:9".parse().unwrap()).await.unwrap();
+                connection.set_timeout(2000);
+                connection.write_all(b"hello").await.unwrap();
+                let start = stack.pico.lock().await.uptime_millis();
+                let mut buf = [0u8; 16];
+                assert_eq!(Err(Error::Timeout), connection.read(&mut buf).await);
+                let elapsed = stack.pico.lock().await.uptime_millis() - start;
+                connection.close().await.unwrap();
+                elapsed
+            })
+            .await;
+        assert!((2000..2000 + POLL_MILLIS).contains(&elapsed));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_echo() {
+        let mut harness = harness();
+        let modem = harness.modem.clone();
+        let mut pico = EmuPico::new(harness.modem.clone());
+        harness
+            .run(async |client| {
+                configure(client).await.unwrap();
+                gsm::Bearer::default()
+                    .acquire(client, &mut pico, "online")
+                    .await
+                    .unwrap();
+                let stack = Stack::new(client, &mut pico);
+
+                let mut connection = stack.connect("10.0.0.1:7".parse().unwrap()).await.unwrap();
+                assert_eq!(0, connection.id());
+                connection.write_all(b"hello\r\nOK\r\n").await.unwrap();
+                let mut buf = [0u8; 512];
+                let n = connection.read(&mut buf).await.unwrap();
+                assert_eq!(b"hello\r\nOK\r\n", &buf[..n]);
+                assert_eq!(
+                    b"hello\r\nOK\r\n".to_vec(),
+                    modem.with(|sim| sim.connections[&0].sent.clone())
+                );
+
+                // a second one, the data arrives later
+                let mut other = stack.connect("10.0.0.1:7".parse().unwrap()).await.unwrap();
+                assert_eq!(1, other.id());
+                let pusher = modem.clone();
+                let (n, _) = tokio::join!(other.read(&mut buf), async {
+                    tokio::task::yield_now().await;
+                    pusher.with(|sim| sim.push_data(1, &[0xAB; 300]));
+                });
+                assert_eq!(READ_CHUNK, n.unwrap());
+                assert_eq!(44, other.read(&mut buf).await.unwrap());
+
+                // closed by the peer, the rest is read first
+                modem.with(|sim| {
+                    sim.push_data(0, b"bye");
+                    sim.close_remote(0);
+                });
+                assert_eq!(3, connection.read(&mut buf).await.unwrap());
+                assert_eq!(0, connection.read(&mut buf).await.unwrap());
+                assert!(connection.write(b"x").await.is_err());
+                connection.close().await.unwrap();
+
+                // dropped, closed before the next connect, the id is reused
+                drop(other);
+                let connection = stack.connect("10.0.0.1:7".parse().unwrap()).await.unwrap();
+                assert_eq!(0, connection.id());
+                assert!(!modem.with(|sim| sim.connections.contains_key(&1)));
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_udp_and_failures() {
+        let mut harness = harness();
+        let mut pico = EmuPico::new(harness.modem.clone());
+        harness
+            .run(async |client| {
+                configure(client).await.unwrap();
+                gsm::Bearer::default()
+                    .acquire(client, &mut pico, "online")
+                    .await
+                    .unwrap();
+                let stack = Stack::new(client, &mut pico);
+
+                let (_, mut socket) = UdpStack::connect(&&stack, "10.0.0.1:7".parse().unwrap())
+                    .await
+                    .unwrap();
+                socket.send(b"ping").await.unwrap();
+                let mut buf = [0u8; 16];
+                assert_eq!(4, socket.receive_into(&mut buf).await.unwrap());
+                assert_eq!(
+                    Err(Error::Capacity("send chunk")),
+                    socket.send(&[0; SEND_CHUNK + 1]).await
+                );
+                socket.close().await.unwrap();
+                assert!(
+                    (&stack)
+                        .bind_single("0.0.0.0:7".parse().unwrap())
+                        .await
+                        .is_err()
+                );
+
+                assert_eq!(
+                    Err(Error::Protocol("connect failed")),
+                    stack
+                        .connect("10.0.0.2:7".parse().unwrap())
+                        .await
+                        .map(|c| c.id())
+                );
+                assert_eq!(
+                    Err(Error::Protocol("IPv6 not supported")),
+                    stack
+                        .connect("[::1]:7".parse().unwrap())
+                        .await
+                        .map(|c| c.id())
+                );
+                let mut connections = AVec::new();
+                for _ in 0..MAX_CONNECTIONS {
+                    connections.push(stack.connect("10.0.0.1:7".parse().unwrap()).await.unwrap());
+                }
+                assert_eq!(
+                    Err(Error::Capacity("connections")),
+                    stack
+                        .connect("10.0.0.1:7".parse().unwrap())
+                        .await
+                        .map(|c| c.id())
+                );
+            })
+            .await;
+    }
+}
@@ -2,15 +2,41 @@
 
 extern crate alloc;
 
+// The heap of the app. The paths that allocate more than a few bytes keep to half of it (see
+// the MAX_HEAP of the modules), the rest is left for the fragmentation.
+pub const HEAP_SIZE: usize = 16 * 1024;
+const _: () = assert!(http::MAX_HEAP <= HEAP_SIZE / 2);
+const _: () = assert!(mqtt::MAX_HEAP <= HEAP_SIZE / 2);
+const _: () = assert!(telemetry::MAX_HEAP <= HEAP_SIZE / 2);
+
 pub mod at;
 pub mod battery;
 pub mod call;
+pub mod concat;
+pub mod config;
+pub mod connectivity;
+pub mod dispatcher;
+pub mod epo;
+pub mod error;
+pub mod filter;
+pub mod fs;
+pub mod geofence;
 pub mod gps;
 pub mod gsm;
 pub mod hexstr;
+pub mod http;
+pub mod ip;
 pub mod location;
+pub mod mqtt;
 pub mod network;
+pub mod nmea;
+pub mod pdu;
+pub mod phone;
 pub mod poro;
+pub mod protector;
 pub mod sms;
+pub mod storage;
+pub mod telemetry;
+pub mod tracklog;
 pub mod urc;
 pub mod utils;
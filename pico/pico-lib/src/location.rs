@@ -1,22 +1,633 @@
-use crate::{gps::get_gps_location, gsm::get_gsm_location};
+use core::future::Future;
+
+use atat::heapless::Vec;
 use defmt::Format;
 
+use crate::config::Config;
+use crate::epo::Epo;
+use crate::error::Error;
+use crate::filter::Filter;
+use crate::filter::Gate;
+use crate::gps;
+use crate::gps::StartMode;
+use crate::gsm;
+use crate::{gps::get_gps_location, gsm::get_gsm_location};
+
 #[derive(Clone, Debug, Format, PartialEq)]
 pub struct Location {
     pub latitude: f64,
     pub longitude: f64,
     pub accuracy: f64,
     pub unix_timestamp_millis: i64,
+    pub source: Source,
+    pub speed: Option<f64>,  // km/h, GNSS only
+    pub course: Option<f64>, // degrees from the true north, GNSS only
+}
+
+// What produced the position, a cached one keeps the source of the original fix.
+#[derive(Clone, Copy, Debug, Format, PartialEq)]
+pub enum Source {
+    Gnss,
+    Cell, // CLBS, the position of the serving cell tower
 }
 
-// TODO how to abstract this in rust, a Locator trait Vec<dyn Locator>
-pub async fn get_location<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
-    client: &mut T,
-    pico: &mut U,
-    max_retries: u8,
-    apn: &str,
-) -> Option<Location> {
-    return get_gps_location(client, pico, max_retries)
+impl Source {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Source::Gnss => "GPS",
+            Source::Cell => "cell tower",
+        }
+    }
+}
+
+// Relative power cost of a fix, GNSS keeps the receiver on, CLBS brings up GPRS.
+#[derive(Clone, Copy, Debug, Format, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Cost {
+    Low,
+    Medium,
+    High,
+}
+
+// Typical figures of a fix, the accuracy is in meters.
+#[derive(Clone, Debug, Format, PartialEq)]
+pub struct Profile {
+    pub accuracy: f64,
+    pub millis: u64,
+    pub cost: Cost,
+}
+
+// What the caller can accept, the accuracy is in meters and the age of a cached fix is
+// measured in uptime (see PicoHW::uptime_millis).
+#[derive(Clone, Debug, Format, PartialEq)]
+pub struct Request {
+    pub accuracy: f64,
+    pub budget_millis: u64,
+    pub max_cost: Cost,
+    pub max_age_millis: u64,
+}
+
+impl Request {
+    // A live fix, GPS preferred, the cell tower position is the fallback.
+    pub fn fresh() -> Self {
+        Request {
+            accuracy: 100.0,
+            budget_millis: 60_000,
+            max_cost: Cost::High,
+            max_age_millis: 0,
+        }
+    }
+}
+
+impl Default for Request {
+    // A recent fix will do, e.g. for a location request of the owner.
+    fn default() -> Self {
+        Request {
+            max_age_millis: 60_000,
+            ..Request::fresh()
+        }
+    }
+}
+
+pub trait Locator {
+    // None when the locator can not provide a fix now, e.g. an empty cache.
+    fn profile(&self, uptime_millis: u64) -> Option<Profile>;
+
+    fn locate<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+        &mut self,
+        client: &mut T,
+        pico: &mut U,
+        config: &Config,
+    ) -> impl Future<Output = Result<Location, Error>>;
+}
+
+// Assisted when the EPO file is valid, see epo::refresh.
+pub struct GnssLocator {
+    pub max_retries: u8,
+    pub start: StartMode,
+    pub epo: Epo,
+    pub gate: Gate,
+}
+
+impl Locator for GnssLocator {
+    fn profile(&self, _uptime_millis: u64) -> Option<Profile> {
+        Some(Profile {
+            accuracy: 10.0,
+            millis: self.max_retries as u64 * 1000,
+            cost: Cost::High,
+        })
+    }
+
+    async fn locate<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+        &mut self,
+        client: &mut T,
+        pico: &mut U,
+        _config: &Config,
+    ) -> Result<Location, Error> {
+        let assisted = self.epo.is_valid(pico.uptime_millis());
+        get_gps_location(
+            client,
+            pico,
+            self.max_retries,
+            self.start,
+            assisted,
+            &self.gate,
+        )
+        .await
+    }
+}
+
+pub struct ClbsLocator {
+    pub max_retries: u8,
+    pub lon_type: gsm::LonType,
+    pub bearer: gsm::Bearer, // shared with the other IP applications, e.g. EPO
+}
+
+impl Locator for ClbsLocator {
+    // The PDP context takes 5 s unless the bearer is open already (see gsm::Bearer), then
+    // a try per second.
+    fn profile(&self, _uptime_millis: u64) -> Option<Profile> {
+        let attach = match self.bearer.status() {
+            gsm::BearerStatus::Connected => 0,
+            _ => 5000,
+        };
+        Some(Profile {
+            accuracy: 1000.0,
+            millis: attach + self.max_retries as u64 * 1000,
+            cost: Cost::Medium,
+        })
+    }
+
+    async fn locate<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+        &mut self,
+        client: &mut T,
+        pico: &mut U,
+        config: &Config,
+    ) -> Result<Location, Error> {
+        get_gsm_location(
+            client,
+            pico,
+            &mut self.bearer,
+            self.max_retries,
+            &config.apn,
+            &config.clbs_server,
+            self.lon_type,
+        )
         .await
-        .or(get_gsm_location(client, pico, max_retries, apn).await);
+    }
+}
+
+// The last live fix and the uptime it was taken at.
+#[derive(Default)]
+pub struct CachedLocator {
+    last: Option<(Location, u64)>,
+}
+
+impl CachedLocator {
+    pub fn update(&mut self, location: &Location, uptime_millis: u64) {
+        self.last = Some((location.clone(), uptime_millis));
+    }
+
+    pub fn age_millis(&self, uptime_millis: u64) -> Option<u64> {
+        self.last
+            .as_ref()
+            .map(|(_, at)| uptime_millis.saturating_sub(*at))
+    }
+}
+
+impl Locator for CachedLocator {
+    fn profile(&self, _uptime_millis: u64) -> Option<Profile> {
+        self.last.as_ref().map(|(location, _)| Profile {
+            accuracy: location.accuracy,
+            millis: 0,
+            cost: Cost::Low,
+        })
+    }
+
+    async fn locate<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+        &mut self,
+        _client: &mut T,
+        _pico: &mut U,
+        _config: &Config,
+    ) -> Result<Location, Error> {
+        self.last
+            .as_ref()
+            .map(|(location, _)| location.clone())
+            .ok_or(Error::Protocol("no cached location"))
+    }
+}
+
+// The fix of the GNSS stream (see gps::start_stream) while it runs, e.g. during a trip. The
+// navigation state is updated by the ingress or by the +UGNSINF URCs (see `output`), `source`
+// reads it with the uptime of its last update, e.g. nmea::Navigation of a static shared with
+// nmea::NmeaReader.
+// The stream reports every second, a fix of the last cycle is live, one older than a few
+// cycles is stale, e.g. the sentences stopped with the UART or the receiver.
+#[derive(Default)]
+pub struct StreamLocator {
+    pub source: Option<fn() -> Option<StreamFix>>,
+    pub output: gps::StreamOutput,
+    streaming: bool,
+}
+
+// The fix and the uptime of its last sentence.
+pub type StreamFix = (Location, u64);
+
+const STREAM_LIVE_MILLIS: u64 = 1_500;
+const STREAM_MAX_AGE_MILLIS: u64 = 3_000;
+
+impl StreamLocator {
+    pub fn streaming(&self) -> bool {
+        self.streaming
+    }
+
+    fn location(&self, uptime_millis: u64) -> Option<(Location, u64)> {
+        match self.source {
+            Some(source) if self.streaming => source()
+                .map(|(location, at)| (location, uptime_millis.saturating_sub(at)))
+                .filter(|(_, age)| *age <= STREAM_MAX_AGE_MILLIS),
+            _ => None,
+        }
+    }
+
+    pub fn age_millis(&self, uptime_millis: u64) -> Option<u64> {
+        self.location(uptime_millis).map(|(_, age)| age)
+    }
+}
+
+impl Locator for StreamLocator {
+    fn profile(&self, uptime_millis: u64) -> Option<Profile> {
+        self.location(uptime_millis).map(|(location, _)| Profile {
+            accuracy: location.accuracy,
+            millis: 0,
+            cost: Cost::Low,
+        })
+    }
+
+    async fn locate<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+        &mut self,
+        _client: &mut T,
+        pico: &mut U,
+        _config: &Config,
+    ) -> Result<Location, Error> {
+        self.location(pico.uptime_millis())
+            .map(|(location, _)| location)
+            .ok_or(Error::Protocol("no NMEA fix"))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Format, PartialEq)]
+pub enum Kind {
+    Cached,
+    Stream,
+    Gnss,
+    Clbs,
+}
+
+// Picks among the locators by the request, the ones within the time budget and the cost
+// limit are tried, the ones accurate enough first (cheapest first), then the rest (most
+// accurate first). The first fix accurate enough is returned, the most accurate otherwise.
+// The live fixes go through the filter, the cache keeps the filtered one. GnssLocator turns
+// the receiver off after the fix, it is not used while the NMEA stream runs.
+pub struct Strategy {
+    pub cached: CachedLocator,
+    pub stream: StreamLocator,
+    pub gnss: GnssLocator,
+    pub clbs: ClbsLocator,
+    pub filter: Filter,
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy {
+            cached: CachedLocator::default(),
+            stream: StreamLocator::default(),
+            gnss: GnssLocator {
+                max_retries: 5,
+                start: StartMode::Auto,
+                epo: Epo::default(),
+                gate: Gate::default(),
+            },
+            clbs: ClbsLocator {
+                max_retries: 5,
+                lon_type: gsm::LonType::WGS84,
+                bearer: gsm::Bearer::default(),
+            },
+            filter: Filter::default(),
+        }
+    }
+}
+
+impl Strategy {
+    pub fn plan(&self, request: &Request, uptime_millis: u64) -> Vec<Kind, 4> {
+        let fresh = self
+            .cached
+            .age_millis(uptime_millis)
+            .is_some_and(|age| age <= request.max_age_millis);
+        let live = self
+            .stream
+            .age_millis(uptime_millis)
+            .is_some_and(|age| age <= request.max_age_millis.max(STREAM_LIVE_MILLIS));
+        let mut candidates: Vec<(Kind, Profile), 4> = Vec::new();
+        for (kind, profile) in [
+            (
+                Kind::Cached,
+                self.cached.profile(uptime_millis).filter(|_| fresh),
+            ),
+            (
+                Kind::Stream,
+                self.stream.profile(uptime_millis).filter(|_| live),
+            ),
+            (
+                Kind::Gnss,
+                self.gnss
+                    .profile(uptime_millis)
+                    .filter(|_| !self.stream.streaming),
+            ),
+            (Kind::Clbs, self.clbs.profile(uptime_millis)),
+        ] {
+            if let Some(p) = profile
+                && p.millis <= request.budget_millis
+                && p.cost <= request.max_cost
+            {
+                let _ = candidates.push((kind, p));
+            }
+        }
+        let accurate = |p: &Profile| p.accuracy <= request.accuracy;
+        candidates.sort_by(|(_, a), (_, b)| {
+            accurate(b)
+                .cmp(&accurate(a))
+                .then_with(|| match accurate(a) {
+                    true => a.cost.cmp(&b.cost),
+                    false => a.accuracy.total_cmp(&b.accuracy),
+                })
+        });
+        candidates.iter().map(|(kind, _)| *kind).collect()
+    }
+
+    pub async fn locate<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+        &mut self,
+        client: &mut T,
+        pico: &mut U,
+        config: &Config,
+        request: &Request,
+    ) -> Result<Location, Error> {
+        let mut best: Option<Location> = None;
+        let mut result = Err(Error::Rejected("no locator for the request"));
+        for kind in self.plan(request, pico.uptime_millis()) {
+            let location = match kind {
+                Kind::Cached => self.cached.locate(client, pico, config).await,
+                Kind::Stream => self.stream.locate(client, pico, config).await,
+                Kind::Gnss => self.gnss.locate(client, pico, config).await,
+                Kind::Clbs => self.clbs.locate(client, pico, config).await,
+            };
+            let location = match kind {
+                Kind::Cached => location,
+                _ => location.and_then(|l| self.filter.apply(&l)),
+            };
+            match location {
+                Ok(l) => {
+                    if kind != Kind::Cached {
+                        self.cached.update(&l, pico.uptime_millis());
+                    }
+                    if l.accuracy <= request.accuracy {
+                        return Ok(l);
+                    }
+                    if best.as_ref().is_none_or(|b| l.accuracy < b.accuracy) {
+                        best = Some(l);
+                    }
+                }
+                Err(e) => result = Err(e),
+            }
+        }
+        match best {
+            Some(l) => Ok(l),
+            None => result,
+        }
+    }
+
+    // Keeps the GNSS on and streams the NMEA sentences, e.g. while driving, the fixes come
+    // without polling +CGNSINF.
+    pub async fn start_stream<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+        &mut self,
+        client: &mut T,
+        pico: &mut U,
+    ) -> Result<(), Error> {
+        let assisted = self.gnss.epo.is_valid(pico.uptime_millis());
+        gps::start_stream(client, self.gnss.start, assisted, self.stream.output).await?;
+        self.stream.streaming = true;
+        Ok(())
+    }
+
+    // GnssLocator is used again even if the stream could not be stopped, it turns the
+    // receiver off anyway.
+    pub async fn stop_stream<T: atat::asynch::AtatClient>(
+        &mut self,
+        client: &mut T,
+    ) -> Result<(), Error> {
+        self.stream.streaming = false;
+        gps::stop_stream(client, self.stream.output).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fix(accuracy: f64, source: Source) -> Location {
+        Location {
+            latitude: 46.7624859,
+            longitude: 18.6304591,
+            accuracy,
+            unix_timestamp_millis: 1670846541123,
+            source,
+            speed: None,
+            course: None,
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let mut strategy = Strategy::default();
+        assert_eq!(
+            [Kind::Gnss, Kind::Clbs],
+            strategy.plan(&Request::default(), 0).as_slice()
+        );
+
+        strategy.cached.update(&fix(5.0, Source::Gnss), 1000);
+        assert_eq!(
+            [Kind::Cached, Kind::Gnss, Kind::Clbs],
+            strategy.plan(&Request::default(), 2000).as_slice()
+        );
+        assert_eq!(
+            [Kind::Gnss, Kind::Clbs],
+            strategy.plan(&Request::fresh(), 2000).as_slice()
+        );
+        assert_eq!(
+            [Kind::Gnss, Kind::Clbs],
+            strategy.plan(&Request::default(), 62_000).as_slice()
+        );
+
+        // everything is accurate enough, the cheapest first
+        let coarse = Request {
+            accuracy: 2000.0,
+            ..Request::default()
+        };
+        assert_eq!(
+            [Kind::Cached, Kind::Clbs, Kind::Gnss],
+            strategy.plan(&coarse, 2000).as_slice()
+        );
+
+        // a cell tower fix is better than nothing
+        let cheap = Request {
+            budget_millis: 10_000,
+            max_cost: Cost::Medium,
+            ..Request::fresh()
+        };
+        assert_eq!([Kind::Clbs], strategy.plan(&cheap, 2000).as_slice());
+        let quick = Request {
+            budget_millis: 5000,
+            ..Request::fresh()
+        };
+        assert_eq!([Kind::Gnss], strategy.plan(&quick, 2000).as_slice());
+    }
+
+    #[tokio::test]
+    async fn test_locate_cached() {
+        let mut client = crate::at::tests::ClientMock::default();
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut strategy = Strategy::default();
+        strategy.cached.update(&fix(1000.0, Source::Cell), 0);
+
+        // not accurate enough, but nothing else fits
+        let request = Request {
+            budget_millis: 0,
+            ..Request::default()
+        };
+        assert_eq!(
+            Ok(fix(1000.0, Source::Cell)),
+            strategy
+                .locate(&mut client, &mut pico, &Config::default(), &request)
+                .await
+        );
+        assert!(client.sent_commands.is_empty());
+
+        let request = Request {
+            max_age_millis: 0,
+            ..request
+        };
+        pico.sleep_calls.push(1);
+        assert_eq!(
+            Err(Error::Rejected("no locator for the request")),
+            strategy
+                .locate(&mut client, &mut pico, &Config::default(), &request)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_locate_gnss() {
+        let mut client = crate::at::tests::ClientMock::default();
+        client.results.push_back(Ok(b"")); // Turn On
+        client.results.push_back(Ok(b"+CGNSINF: 1,1,20221212120221.123,46.7624859,18.6304591,329.218,2.20,285.8,1,,2.1,2.3,0.9,,7,6,,,51,,"));
+        client.results.push_back(Ok(b"")); // Turn off
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut strategy = Strategy::default();
+
+        let location = strategy
+            .locate(
+                &mut client,
+                &mut pico,
+                &Config::default(),
+                &Request::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(Source::Gnss, location.source);
+        assert_eq!(3, client.sent_commands.len());
+        // the next request within a minute is answered from the cache
+        assert_eq!(
+            Ok(location),
+            strategy
+                .locate(
+                    &mut client,
+                    &mut pico,
+                    &Config::default(),
+                    &Request::default()
+                )
+                .await
+        );
+        assert_eq!(3, client.sent_commands.len());
+    }
+
+    fn streamed() -> Option<StreamFix> {
+        Some((fix(5.0, Source::Gnss), 0))
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .expect("AT+CGNSPWR=1\r", Ok(b""))
+            .expect("AT+CGNSTST=1\r", Ok(b""))
+            .expect("AT+CGNSTST=0\r", Ok(b""))
+            .expect("AT+CGNSPWR=0\r", Ok(b""));
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut strategy = Strategy::default();
+        strategy.stream.source = Some(streamed);
+        assert_eq!(
+            [Kind::Gnss, Kind::Clbs],
+            strategy.plan(&Request::fresh(), 0).as_slice()
+        );
+
+        strategy.start_stream(&mut client, &mut pico).await.unwrap();
+        assert!(strategy.stream.streaming());
+        // no GnssLocator, it would turn the receiver off
+        assert_eq!(
+            [Kind::Stream, Kind::Clbs],
+            strategy.plan(&Request::fresh(), 0).as_slice()
+        );
+        assert_eq!(
+            Ok(fix(5.0, Source::Gnss)),
+            strategy
+                .locate(
+                    &mut client,
+                    &mut pico,
+                    &Config::default(),
+                    &Request::fresh()
+                )
+                .await
+        );
+
+        // a live fix is of the last cycle, a few cycles at most for an older request
+        assert_eq!(
+            [Kind::Stream, Kind::Clbs],
+            strategy.plan(&Request::fresh(), 1_500).as_slice()
+        );
+        assert_eq!(
+            [Kind::Clbs],
+            strategy.plan(&Request::fresh(), 2_000).as_slice()
+        );
+        assert_eq!(
+            [Kind::Cached, Kind::Stream, Kind::Clbs],
+            strategy.plan(&Request::default(), 3_000).as_slice()
+        );
+        assert_eq!(
+            [Kind::Cached, Kind::Clbs],
+            strategy.plan(&Request::default(), 3_001).as_slice()
+        );
+        pico.sleep_calls.push(3_001);
+        assert_eq!(
+            Err(Error::Protocol("no NMEA fix")),
+            strategy
+                .stream
+                .locate(&mut client, &mut pico, &Config::default())
+                .await
+        );
+
+        strategy.stop_stream(&mut client).await.unwrap();
+        assert!(!strategy.stream.streaming());
+        assert_eq!(
+            [Kind::Gnss, Kind::Clbs],
+            strategy.plan(&Request::fresh(), 1).as_slice()
+        );
+    }
 }
@@ -0,0 +1,683 @@
+use alloc::collections::VecDeque;
+use alloc::string::String as AString;
+use alloc::vec::Vec as AVec;
+use defmt::Format;
+use defmt::debug;
+use embedded_io_async::ErrorType;
+use embedded_io_async::Read;
+use embedded_io_async::Write;
+
+use crate::error::Error;
+use crate::ip;
+
+// MQTT Version 3.1.1 (OASIS Standard, 29 October 2014), the client side of QoS 0 and 1 over
+// any embedded-io-async stream, e.g. an ip::Connection.
+//
+// There is no timer in the client, the packets are read while an acknowledgement is
+// awaited. A read gives up after READ_TIMEOUT_MILLIS (see ReadTimeout) whatever the keep
+// alive is, that is the limit of the broker (3.1.2.10), not of the client. The client runs
+// in the main loop of the app, a broker that stops answering is a Timeout in a few seconds.
+// A PINGREQ is answered after everything the broker had for the client, so Client::ping
+// collects the messages of the subscriptions too.
+//
+// At most MAX_INCOMING messages are queued, the ones over it are not acknowledged, the broker
+// sends them again in the next session (4.4). The queue and the input buffer take MAX_HEAP of
+// the heap at most, the packets being sent come on top.
+
+pub const MAX_PACKET: usize = 1024; // incoming, the rest is refused
+pub const MAX_TOPIC: usize = 64;
+pub const MAX_INCOMING: usize = 2;
+pub const MAX_HEAP: usize = MAX_PACKET + READ_CHUNK + MAX_INCOMING * MAX_PACKET;
+const READ_CHUNK: usize = 256;
+const READ_TIMEOUT_MILLIS: u64 = 5_000;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const SUBSCRIBE: u8 = 0x82; // the reserved flags are 0010
+const SUBACK: u8 = 0x90;
+const PINGREQ: u8 = 0xC0;
+const PINGRESP: u8 = 0xD0;
+const DISCONNECT: u8 = 0xE0;
+
+#[derive(Debug, Format, Clone, Copy, PartialEq)]
+pub enum QoS {
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+}
+
+impl QoS {
+    // QoS 2 is not supported, the broker downgrades to the granted one.
+    fn from_u8(v: u8) -> Result<QoS, Error> {
+        match v {
+            0 => Ok(QoS::AtMostOnce),
+            1 => Ok(QoS::AtLeastOnce),
+            _ => Err(Error::Protocol("MQTT QoS 2")),
+        }
+    }
+}
+
+// 3.1 CONNECT, a clean session drops the subscriptions and the queued messages.
+#[derive(Debug, Format, Clone, PartialEq)]
+pub struct Options<'a> {
+    pub client_id: &'a str,
+    pub keep_alive_secs: u16, // 0 turns it off
+    pub clean_session: bool,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+}
+
+impl<'a> Options<'a> {
+    pub fn new(client_id: &'a str, keep_alive_secs: u16) -> Self {
+        Options {
+            client_id,
+            keep_alive_secs,
+            clean_session: true,
+            username: None,
+            password: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub topic: AString,
+    pub payload: AVec<u8>,
+    pub qos: QoS,
+    pub retain: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    ConnAck { session_present: bool, code: u8 },
+    Publish { id: Option<u16>, message: Message },
+    PubAck(u16),
+    SubAck { id: u16, code: u8 }, // granted QoS, 0x80 failure
+    PingResp,
+}
+
+pub fn encode_connect(options: &Options) -> Result<AVec<u8>, Error> {
+    let mut flags = 0u8;
+    if options.clean_session {
+        flags |= 0x02;
+    }
+    if options.username.is_some() {
+        flags |= 0x80;
+    }
+    if options.password.is_some() {
+        flags |= 0x40;
+    }
+    let mut body = AVec::new();
+    put_str(&mut body, "MQTT")?;
+    body.push(4); // protocol level 3.1.1
+    body.push(flags);
+    body.extend_from_slice(&options.keep_alive_secs.to_be_bytes());
+    put_str(&mut body, options.client_id)?;
+    for field in [options.username, options.password].into_iter().flatten() {
+        put_str(&mut body, field)?;
+    }
+    packet(CONNECT, &body)
+}
+
+pub fn encode_publish(
+    topic: &str,
+    payload: &[u8],
+    qos: QoS,
+    retain: bool,
+    id: u16,
+) -> Result<AVec<u8>, Error> {
+    let mut body = AVec::with_capacity(2 + topic.len() + 2 + payload.len());
+    put_str(&mut body, topic)?;
+    if qos == QoS::AtLeastOnce {
+        body.extend_from_slice(&id.to_be_bytes());
+    }
+    body.extend_from_slice(payload);
+    packet(PUBLISH | (qos as u8) << 1 | retain as u8, &body)
+}
+
+pub fn encode_puback(id: u16) -> AVec<u8> {
+    let mut ret = AVec::from([PUBACK, 2]);
+    ret.extend_from_slice(&id.to_be_bytes());
+    ret
+}
+
+pub fn encode_subscribe(id: u16, filter: &str, qos: QoS) -> Result<AVec<u8>, Error> {
+    let mut body = AVec::new();
+    body.extend_from_slice(&id.to_be_bytes());
+    put_str(&mut body, filter)?;
+    body.push(qos as u8);
+    packet(SUBSCRIBE, &body)
+}
+
+pub fn encode_pingreq() -> AVec<u8> {
+    AVec::from([PINGREQ, 0])
+}
+
+pub fn encode_disconnect() -> AVec<u8> {
+    AVec::from([DISCONNECT, 0])
+}
+
+// The first packet of the buffer and its length, None while it is incomplete.
+pub fn decode(buf: &[u8]) -> Result<Option<(Packet, usize)>, Error> {
+    let Some((length, header)) = remaining_length(buf)? else {
+        return Ok(None);
+    };
+    if header + length > MAX_PACKET {
+        return Err(Error::Capacity("MQTT packet"));
+    }
+    if buf.len() < header + length {
+        return Ok(None);
+    }
+    let body = &buf[header..header + length];
+    let id = |body: &[u8]| -> Result<u16, Error> {
+        match body {
+            [hi, lo, ..] => Ok(u16::from_be_bytes([*hi, *lo])),
+            _ => Err(Error::Parse("MQTT packet")),
+        }
+    };
+    let packet = match (buf[0] & 0xF0, body) {
+        (CONNACK, [flags, code]) => Packet::ConnAck {
+            session_present: flags & 0x01 != 0,
+            code: *code,
+        },
+        (PUBLISH, _) => {
+            let qos = QoS::from_u8((buf[0] >> 1) & 0x03)?;
+            let topic_length = id(body)? as usize;
+            let mut rest = body
+                .get(2 + topic_length..)
+                .ok_or(Error::Parse("MQTT packet"))?;
+            let topic = core::str::from_utf8(&body[2..2 + topic_length])?;
+            let id = match qos {
+                QoS::AtMostOnce => None,
+                QoS::AtLeastOnce => {
+                    let packet_id = id(rest)?;
+                    rest = &rest[2..];
+                    Some(packet_id)
+                }
+            };
+            Packet::Publish {
+                id,
+                message: Message {
+                    topic: AString::from(topic),
+                    payload: rest.to_vec(),
+                    qos,
+                    retain: buf[0] & 0x01 != 0,
+                },
+            }
+        }
+        (PUBACK, [_, _]) => Packet::PubAck(id(body)?),
+        (SUBACK, [_, _, code]) => Packet::SubAck {
+            id: id(body)?,
+            code: *code,
+        },
+        (PINGRESP, []) => Packet::PingResp,
+        _ => return Err(Error::Parse("MQTT packet")),
+    };
+    Ok(Some((packet, header + length)))
+}
+
+fn put_str(buf: &mut AVec<u8>, s: &str) -> Result<(), Error> {
+    let length = u16::try_from(s.len()).map_err(|_| Error::Capacity("MQTT string"))?;
+    buf.extend_from_slice(&length.to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn packet(header: u8, body: &[u8]) -> Result<AVec<u8>, Error> {
+    // 2.2.3 Remaining Length, 7 bits a byte, the top one continues
+    if body.len() > 0x0FFF_FFFF {
+        return Err(Error::Capacity("MQTT packet"));
+    }
+    let mut ret = AVec::with_capacity(body.len() + 5);
+    ret.push(header);
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length == 0 {
+            ret.push(byte);
+            break;
+        }
+        ret.push(byte | 0x80);
+    }
+    ret.extend_from_slice(body);
+    Ok(ret)
+}
+
+// The remaining length and the size of the fixed header, None while it is incomplete.
+fn remaining_length(buf: &[u8]) -> Result<Option<(usize, usize)>, Error> {
+    let mut length = 0;
+    for i in 1..5 {
+        let Some(byte) = buf.get(i) else {
+            return Ok(None);
+        };
+        length |= ((byte & 0x7F) as usize) << (7 * (i - 1));
+        if byte & 0x80 == 0 {
+            return Ok(Some((length, i + 1)));
+        }
+    }
+    Err(Error::Parse("MQTT remaining length"))
+}
+
+// The stream gives up a read after the given time with Error::Timeout.
+pub trait ReadTimeout {
+    fn set_read_timeout(&mut self, millis: u64);
+}
+
+impl<T: atat::asynch::AtatClient, U: crate::at::PicoHW> ReadTimeout
+    for ip::Connection<'_, '_, T, U>
+{
+    fn set_read_timeout(&mut self, millis: u64) {
+        self.set_timeout(millis);
+    }
+}
+
+// A session with the broker, the messages of the subscriptions are queued until taken
+// with Client::next_message.
+pub struct Client<C> {
+    connection: C,
+    next_id: u16,
+    input: AVec<u8>,
+    incoming: VecDeque<Message>,
+}
+
+impl<C: Read + Write + ErrorType<Error = Error> + ReadTimeout> Client<C> {
+    // Sends CONNECT, returns once the broker accepted it.
+    pub async fn connect(mut connection: C, options: &Options<'_>) -> Result<Self, Error> {
+        connection.set_read_timeout(READ_TIMEOUT_MILLIS);
+        let mut client = Client {
+            connection,
+            next_id: 0,
+            // a partial packet and a read, see wait
+            input: AVec::with_capacity(MAX_PACKET + READ_CHUNK),
+            incoming: VecDeque::with_capacity(MAX_INCOMING),
+        };
+        client.send(&encode_connect(options)?).await?;
+        match client.wait(|p| matches!(p, Packet::ConnAck { .. })).await? {
+            Packet::ConnAck { code: 0, .. } => Ok(client),
+            Packet::ConnAck { code, .. } => {
+                debug!("MQTT connection refused: {}", code);
+                Err(Error::Rejected("MQTT connection refused"))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Returns once the broker has it on QoS 1.
+    pub async fn publish(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<(), Error> {
+        let id = self.packet_id();
+        self.send(&encode_publish(topic, payload, qos, retain, id)?)
+            .await?;
+        if qos == QoS::AtLeastOnce {
+            self.wait(|p| *p == Packet::PubAck(id)).await?;
+        }
+        Ok(())
+    }
+
+    // Returns the QoS granted by the broker.
+    pub async fn subscribe(&mut self, filter: &str, qos: QoS) -> Result<QoS, Error> {
+        let id = self.packet_id();
+        self.send(&encode_subscribe(id, filter, qos)?).await?;
+        match self
+            .wait(|p| matches!(p, Packet::SubAck { id: i, .. } if *i == id))
+            .await?
+        {
+            Packet::SubAck { code: 0x80, .. } => Err(Error::Rejected("MQTT subscription refused")),
+            Packet::SubAck { code, .. } => QoS::from_u8(code),
+            _ => unreachable!(),
+        }
+    }
+
+    // Keeps the session alive, to be called within the keep alive of the options.
+    pub async fn ping(&mut self) -> Result<(), Error> {
+        self.send(&encode_pingreq()).await?;
+        self.wait(|p| *p == Packet::PingResp).await?;
+        Ok(())
+    }
+
+    pub fn next_message(&mut self) -> Option<Message> {
+        self.incoming.pop_front()
+    }
+
+    // Ends the session, the connection is handed back to be closed.
+    pub async fn disconnect(mut self) -> Result<C, Error> {
+        self.send(&encode_disconnect()).await?;
+        Ok(self.connection)
+    }
+
+    fn packet_id(&mut self) -> u16 {
+        // 0 is not a valid packet identifier
+        self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+        self.next_id
+    }
+
+    async fn send(&mut self, packet: &[u8]) -> Result<(), Error> {
+        self.connection.write_all(packet).await?;
+        self.connection.flush().await
+    }
+
+    // Reads until the expected packet, the messages in between are acknowledged and queued.
+    async fn wait(&mut self, expected: impl Fn(&Packet) -> bool) -> Result<Packet, Error> {
+        loop {
+            while let Some((packet, length)) = decode(&self.input)? {
+                self.input.drain(..length);
+                match packet {
+                    Packet::Publish { id, message } => {
+                        if self.incoming.len() >= MAX_INCOMING {
+                            debug!("MQTT message dropped, the queue is full");
+                            continue;
+                        }
+                        if let Some(id) = id {
+                            self.send(&encode_puback(id)).await?;
+                        }
+                        self.incoming.push_back(message);
+                    }
+                    p if expected(&p) => return Ok(p),
+                    _ => debug!("MQTT packet ignored"),
+                }
+            }
+            let mut buf = [0u8; READ_CHUNK];
+            let n = self.connection.read(&mut buf).await?;
+            if n == 0 {
+                return Err(Error::Protocol("MQTT connection closed"));
+            }
+            self.input.extend_from_slice(&buf[..n]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use embedded_nal_async::TcpConnect;
+
+    // The broker side of a connection, scripted.
+    #[derive(Default)]
+    struct Pipe {
+        input: VecDeque<u8>,
+        output: AVec<u8>,
+        timeout_millis: u64,
+    }
+
+    impl ErrorType for Pipe {
+        type Error = Error;
+    }
+
+    impl ReadTimeout for Pipe {
+        fn set_read_timeout(&mut self, millis: u64) {
+            self.timeout_millis = millis;
+        }
+    }
+
+    impl Read for Pipe {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            let n = buf.len().min(self.input.len()).min(3); // split packets
+            for b in buf[..n].iter_mut() {
+                *b = self.input.pop_front().unwrap();
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for Pipe {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn pipe(input: &[&[u8]]) -> Pipe {
+        Pipe {
+            input: input.concat().into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let options = Options {
+            username: Some("u"),
+            password: Some("p"),
+            ..Options::new("dev", 60)
+        };
+        assert_eq!(
+            b"\x10\x15\x00\x04MQTT\x04\xc2\x00\x3c\x00\x03dev\x00\x01u\x00\x01p".to_vec(),
+            encode_connect(&options).unwrap()
+        );
+        assert_eq!(
+            b"\x30\x05\x00\x01ahi".to_vec(),
+            encode_publish("a", b"hi", QoS::AtMostOnce, false, 7).unwrap()
+        );
+        assert_eq!(
+            b"\x33\x07\x00\x01a\x00\x07hi".to_vec(),
+            encode_publish("a", b"hi", QoS::AtLeastOnce, true, 7).unwrap()
+        );
+        assert_eq!(
+            b"\x82\x08\x00\x02\x00\x03a/#\x01".to_vec(),
+            encode_subscribe(2, "a/#", QoS::AtLeastOnce).unwrap()
+        );
+        assert_eq!(b"\x40\x02\x01\x02".to_vec(), encode_puback(0x0102));
+
+        // two bytes of remaining length from 128
+        let packet = encode_publish("a", &[0x55; 200], QoS::AtMostOnce, false, 0).unwrap();
+        assert_eq!([0x30, 0xCB, 0x01], packet[..3]);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            Ok(Some((
+                Packet::ConnAck {
+                    session_present: true,
+                    code: 0
+                },
+                4
+            ))),
+            decode(b"\x20\x02\x01\x00\xd0")
+        );
+        assert_eq!(Ok(Some((Packet::PingResp, 2))), decode(b"\xd0\x00"));
+        assert_eq!(
+            Ok(Some((Packet::PubAck(7), 4))),
+            decode(b"\x40\x02\x00\x07")
+        );
+        assert_eq!(
+            Ok(Some((Packet::SubAck { id: 2, code: 0x80 }, 5))),
+            decode(b"\x90\x03\x00\x02\x80")
+        );
+        assert_eq!(
+            Ok(Some((
+                Packet::Publish {
+                    id: Some(7),
+                    message: Message {
+                        topic: AString::from("a"),
+                        payload: b"hi".to_vec(),
+                        qos: QoS::AtLeastOnce,
+                        retain: true,
+                    }
+                },
+                9
+            ))),
+            decode(b"\x33\x07\x00\x01a\x00\x07hi")
+        );
+        let packet = encode_publish("a", &[0x55; 200], QoS::AtMostOnce, false, 0).unwrap();
+        match decode(&packet) {
+            Ok(Some((Packet::Publish { id: None, message }, 206))) => {
+                assert_eq!(200, message.payload.len())
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // incomplete
+        assert_eq!(Ok(None), decode(b""));
+        assert_eq!(Ok(None), decode(b"\x30"));
+        assert_eq!(Ok(None), decode(&packet[..100]));
+
+        assert_eq!(
+            Err(Error::Protocol("MQTT QoS 2")),
+            decode(b"\x34\x03\x00\x01a")
+        );
+        assert_eq!(Err(Error::Parse("MQTT packet")), decode(b"\x20\x01\x00"));
+        assert_eq!(
+            Err(Error::Parse("MQTT packet")),
+            decode(b"\x30\x02\x00\x05")
+        );
+        assert_eq!(Err(Error::Capacity("MQTT packet")), decode(b"\x30\x80\x10"));
+        assert_eq!(
+            Err(Error::Parse("MQTT remaining length")),
+            decode(b"\x30\xff\xff\xff\xff\x01")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client() {
+        let connection = pipe(&[
+            b"\x20\x02\x00\x00",              // CONNACK
+            b"\x90\x03\x00\x01\x01",          // SUBACK
+            b"\x32\x09\x00\x03cmd\x00\x09ab", // PUBLISH before the PUBACK
+            b"\x40\x02\x00\x02",              // PUBACK
+            b"\x30\x06\x00\x03cmdx",          // PUBLISH QoS 0 before the PINGRESP
+            b"\xd0\x00",                      // PINGRESP
+        ]);
+        let mut client = Client::connect(connection, &Options::new("dev", 60))
+            .await
+            .unwrap();
+        assert_eq!(
+            Ok(QoS::AtLeastOnce),
+            client.subscribe("cmd", QoS::AtLeastOnce).await
+        );
+        client
+            .publish("up", b"1", QoS::AtLeastOnce, false)
+            .await
+            .unwrap();
+        client
+            .publish("up", b"2", QoS::AtMostOnce, false)
+            .await
+            .unwrap();
+        client.ping().await.unwrap();
+        let first = client.next_message().unwrap();
+        assert_eq!(
+            ("cmd", b"ab".to_vec()),
+            (first.topic.as_str(), first.payload)
+        );
+        assert_eq!(b"x".to_vec(), client.next_message().unwrap().payload);
+        assert_eq!(None, client.next_message());
+
+        let connection = client.disconnect().await.unwrap();
+        assert_eq!(READ_TIMEOUT_MILLIS, connection.timeout_millis);
+        assert_eq!(
+            [
+                encode_connect(&Options::new("dev", 60)).unwrap(),
+                encode_subscribe(1, "cmd", QoS::AtLeastOnce).unwrap(),
+                encode_publish("up", b"1", QoS::AtLeastOnce, false, 2).unwrap(),
+                encode_puback(9),
+                encode_publish("up", b"2", QoS::AtMostOnce, false, 3).unwrap(),
+                encode_pingreq(),
+                encode_disconnect(),
+            ]
+            .concat(),
+            connection.output
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_refused() {
+        let connection = pipe(&[b"\x20\x02\x00\x05"]);
+        assert_eq!(
+            Some(Error::Rejected("MQTT connection refused")),
+            Client::connect(connection, &Options::new("dev", 60))
+                .await
+                .err()
+        );
+
+        let connection = pipe(&[b"\x20\x02\x00\x00\x90\x03\x00\x01\x80"]);
+        let mut client = Client::connect(connection, &Options::new("dev", 60))
+            .await
+            .unwrap();
+        assert_eq!(
+            Err(Error::Rejected("MQTT subscription refused")),
+            client.subscribe("cmd", QoS::AtLeastOnce).await
+        );
+        // closed by the broker
+        assert_eq!(
+            Err(Error::Protocol("MQTT connection closed")),
+            client.ping().await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_silent_broker() {
+        use crate::at::PicoHW;
+        let mut harness = crate::at::tests::Harness::new(sim868_emu::Sim868::default());
+        harness.modem.with(|sim| {
+            sim.servers.insert(
+                "10.0.0.2:1883".to_string(),
+                alloc::boxed::Box::new(sim868_emu::sim868::Silent),
+            );
+        });
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let elapsed = harness
+            .run(async |client| {
+                ip::configure(client).await.unwrap();
+                crate::gsm::Bearer::default()
+                    .acquire(client, &mut pico, "online")
+                    .await
+                    .unwrap();
+                let start = pico.uptime_millis();
+                let stack = ip::Stack::new(client, &mut pico);
+                let connection = stack
+                    .connect("10.0.0.2:1883".parse().unwrap())
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    Some(Error::Timeout),
+                    Client::connect(connection, &Options::new("dev", 300))
+                        .await
+                        .err()
+                );
+                drop(stack);
+                pico.uptime_millis() - start
+            })
+            .await;
+        // not the keep alive, the read timeout and the setup of the connection
+        assert!((5000..6000).contains(&elapsed));
+    }
+
+    #[tokio::test]
+    async fn test_client_queue_full() {
+        let connection = pipe(&[
+            b"\x20\x02\x00\x00",           // CONNACK
+            b"\x32\x06\x00\x01a\x00\x01a", // PUBLISH
+            b"\x32\x06\x00\x01a\x00\x02b", // PUBLISH
+            b"\x32\x06\x00\x01a\x00\x03c", // PUBLISH over the queue, not acknowledged
+            b"\xd0\x00",                   // PINGRESP
+        ]);
+        let mut client = Client::connect(connection, &Options::new("dev", 60))
+            .await
+            .unwrap();
+        client.ping().await.unwrap();
+        assert_eq!(b"a".to_vec(), client.next_message().unwrap().payload);
+        assert_eq!(b"b".to_vec(), client.next_message().unwrap().payload);
+        assert_eq!(None, client.next_message());
+
+        let connection = client.disconnect().await.unwrap();
+        assert_eq!(
+            [
+                encode_connect(&Options::new("dev", 60)).unwrap(),
+                encode_pingreq(),
+                encode_puback(1),
+                encode_puback(2),
+                encode_disconnect(),
+            ]
+            .concat(),
+            connection.output
+        );
+    }
+}
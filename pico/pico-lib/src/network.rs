@@ -6,8 +6,12 @@ use atat::atat_derive::AtatCmd;
 use atat::atat_derive::AtatEnum;
 use atat::atat_derive::AtatResp;
 use atat::heapless::String;
+use atat::nom::IResult;
+use atat::nom::error::ErrorKind;
+use atat::nom::error::ParseError;
 
 use crate::at::NoResponse;
+use crate::error::Error;
 use crate::utils::send_command_logged;
 
 #[derive(Clone, Debug, Format, AtatCmd)]
@@ -48,6 +52,52 @@ pub struct NetworkRegistrationReadResponse {
     pub ci: Option<String<4>>,
 }
 
+impl NetworkRegistrationStatus {
+    pub fn is_registered(&self) -> bool {
+        matches!(
+            self,
+            NetworkRegistrationStatus::Registered | NetworkRegistrationStatus::RegisteredRoaming
+        )
+    }
+}
+
+// AT+CGREG=<n>
+// 0 disable network registration unsolicited result code
+// 1 enable network registration unsolicited result code +CGREG: <stat>
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CGREG", NoResponse)]
+pub struct AtNetworkRegistrationWrite {
+    #[at_arg(position = 0)]
+    pub n: u8,
+}
+
+// +CGREG: <stat>
+#[derive(Debug, Format, Clone, AtatResp, PartialEq)]
+pub struct NetworkRegistrationUrc {
+    #[at_arg(position = 0)]
+    pub stat: NetworkRegistrationStatus,
+}
+
+// The URC and the length it takes from the buffer, see atat::digest::parser::urc_helper.
+type UrcResult<'a, E> = IResult<&'a [u8], (&'a [u8], usize), E>;
+
+// The digester tries the URCs before the responses, only the single parameter of the URC is
+// taken, the answer of AT+CGREG? (+CGREG: <n>,<stat>) is left for the command.
+pub fn parse_registration_urc<'a, E: ParseError<&'a [u8]>>(
+    token: &'a [u8],
+) -> impl Fn(&'a [u8]) -> UrcResult<'a, E> {
+    move |buf| {
+        let (rest, (urc, len)) = atat::digest::parser::urc_helper(token)(buf)?;
+        if urc.contains(&b',') {
+            return Err(atat::nom::Err::Error(E::from_error_kind(
+                buf,
+                ErrorKind::Verify,
+            )));
+        }
+        Ok((rest, (urc, len)))
+    }
+}
+
 // 3.2.28 AT+CPIN Enter Pin
 #[derive(Clone, Debug, Format, AtatCmd)]
 #[at_cmd("+CPIN?", EnterPinReadResponse, timeout_ms = 5000)]
@@ -142,13 +192,31 @@ pub enum SlowClockMode {
     EnableSlowClockAuto = 2,
 }
 
+// AT+GSN Request TA Serial Number Identification (IMEI), 2.2 V.25TER Commands
+// The bare number, e.g. 861234567890123
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+GSN", ImeiResponse)]
+pub struct AtImeiExecute;
+
+#[derive(Debug, Format, Clone, AtatResp, PartialEq)]
+pub struct ImeiResponse {
+    #[at_arg(position = 0)]
+    pub imei: String<15>,
+}
+
 #[cfg(test)]
 extern crate std;
 
+// The identity of the device, e.g. in the MQTT topics (see telemetry).
+pub async fn imei<T: atat::asynch::AtatClient>(client: &mut T) -> Result<String<15>, Error> {
+    let resp = send_command_logged(client, &AtImeiExecute, "AtImeiExecute".to_string()).await?;
+    Ok(resp.imei)
+}
+
 pub async fn init_network<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
     client: &mut T,
     pico: &mut U,
-) {
+) -> Result<(), Error> {
     let mut registered = false;
     while !registered {
         loop {
@@ -228,6 +296,8 @@ pub async fn init_network<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
         }
     }
 
+    // +CPIN is a URC as well, the digester may take the answer for one, so only a code other
+    // than READY is an error.
     match send_command_logged(client, &AtEnterPinRead, "AtEnterPinRead".to_string()).await {
         Ok(v) => {
             info!("  {:?}", v);
@@ -235,6 +305,7 @@ pub async fn init_network<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
                 pico.set_led_high();
                 info!("  !!!DISABLE PIN ON SIM CARD!!!");
                 pico.sleep(60 * 1000).await;
+                return Err(Error::Rejected("SIM PIN required"));
             }
         }
         Err(_) => (),
@@ -261,6 +332,8 @@ pub async fn init_network<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
         Ok(v) => info!("  {:?}", v),
         Err(_) => (),
     }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -283,6 +356,10 @@ mod tests {
             AtNetworkRegistrationRead,
             "AT+CGREG?\r",
         ),
+        test_network_registration_write: (
+            AtNetworkRegistrationWrite { n: 1 },
+            "AT+CGREG=1\r",
+        ),
         test_enter_pin: (
             AtEnterPinRead,
             "AT+CPIN?\r",
@@ -303,6 +380,10 @@ mod tests {
             AtConfigureSlowClockRead,
             "AT+CSCLK?\r",
         ),
+        test_imei_execute: (
+            AtImeiExecute,
+            "AT+GSN\r",
+        ),
     }
 
     #[test]
@@ -387,6 +468,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_imei_execute_responses() {
+        let cmd = AtImeiExecute;
+        assert_eq!(
+            ImeiResponse {
+                imei: String::try_from("861234567890123").unwrap()
+            },
+            cmd.parse(Ok(b"861234567890123")).unwrap()
+        );
+    }
+
     #[test]
     fn test_operator_selection_read_responses() {
         let cmd = AtOperatorSelectionRead;
@@ -446,6 +538,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_registration_urc_digest() {
+        use atat::DigestResult;
+        use atat::Digester;
+        let mut digester = atat::DefaultDigester::<crate::urc::Urc>::default();
+        assert_eq!(
+            (DigestResult::Urc(&b"+CGREG: 2"[..]), 13),
+            digester.digest(b"\r\n+CGREG: 2\r\n")
+        );
+        // the answer of AT+CGREG?
+        assert_eq!(
+            (DigestResult::Response(Ok(&b"+CGREG: 1,2"[..])), 21),
+            digester.digest(b"\r\n+CGREG: 1,2\r\n\r\nOK\r\n")
+        );
+    }
+
     #[tokio::test]
     async fn test_init_network() {
         let mut client = crate::at::tests::ClientMock::default();
@@ -464,7 +572,7 @@ mod tests {
             .push_back(Ok("0,0,\"PANNON GSM\"".as_bytes())); // AT+COPS
 
         let mut pico = crate::at::tests::PicoMock::default();
-        init_network(&mut client, &mut pico).await;
+        assert_eq!(Ok(()), init_network(&mut client, &mut pico).await);
         assert_eq!(11, client.sent_commands.len());
         assert_eq!("ATE0\r", client.sent_commands.get(0).unwrap());
         assert_eq!("AT\r", client.sent_commands.get(1).unwrap());
@@ -484,4 +592,97 @@ mod tests {
         assert_eq!(0, pico.set_led_low_calls);
         assert_eq!(1, pico.restart_module_calls);
     }
+
+    #[tokio::test]
+    async fn test_init_network_sim_pin() {
+        let mut client = crate::at::tests::ClientMock::default();
+        client.results.push_back(Ok("".as_bytes())); // ATE
+        client.results.push_back(Ok("".as_bytes())); // AT
+        client.results.push_back(Ok("1".as_bytes())); // AT+CFUN full
+        client.results.push_back(Ok("0".as_bytes())); // AT+CSCLK slow clock is disabled
+        client.results.push_back(Ok("0,1".as_bytes())); // AT+CGREG Ready
+        client.results.push_back(Ok("SIM PIN".as_bytes())); // AT+CPIN
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        assert_eq!(
+            Err(Error::Rejected("SIM PIN required")),
+            init_network(&mut client, &mut pico).await
+        );
+        assert_eq!(6, client.sent_commands.len());
+        assert_eq!(1, pico.set_led_high_calls);
+    }
+
+    #[tokio::test]
+    async fn test_init_network_emulated() {
+        let mut sim = sim868_emu::Sim868::default();
+        sim.functionality = 0; // minimum, fixed by a restart
+        sim.registration = sim868_emu::Registration::Searching;
+        sim.on(
+            "AT+CGREG?",
+            sim868_emu::Action::Hook(alloc::boxed::Box::new(|s| {
+                s.set_registration(sim868_emu::Registration::Registered)
+            })),
+        );
+        let mut harness = crate::at::tests::Harness::new(sim);
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+
+        let result = harness
+            .run(async |client| init_network(client, &mut pico).await)
+            .await;
+        assert_eq!(Ok(()), result);
+
+        assert_eq!(1, pico.restart_module_calls);
+        assert!(pico.sleep_calls.is_empty());
+        assert!(!pico.led_high);
+        assert_eq!(
+            alloc::vec![
+                "ATE0",
+                "AT",
+                "AT+CFUN?",
+                "ATE0",
+                "AT",
+                "AT+CFUN?",
+                "AT+CSCLK?",
+                "AT+CGREG?",
+                "AT+CPIN?",
+                "AT+CSQ",
+                "AT+COPS?"
+            ],
+            harness.modem.with(|sim| sim.commands.clone())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_init_network_emulated_no_network() {
+        let mut sim = sim868_emu::Sim868::default();
+        sim.registration = sim868_emu::Registration::Searching;
+        // the network shows up after the module restart, i.e. at the second ATE0
+        sim.on(
+            "ATE0",
+            sim868_emu::Action::Delay(core::time::Duration::ZERO),
+        )
+        .on(
+            "ATE0",
+            sim868_emu::Action::Hook(alloc::boxed::Box::new(|s| {
+                s.set_registration(sim868_emu::Registration::Registered)
+            })),
+        );
+        let mut harness = crate::at::tests::Harness::new(sim);
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+
+        let result = harness
+            .run(async |client| init_network(client, &mut pico).await)
+            .await;
+        assert_eq!(Ok(()), result);
+
+        assert_eq!(1, pico.restart_module_calls);
+        assert_eq!(30, pico.sleep_calls.len());
+        let cgreg = harness.modem.with(|sim| {
+            sim.commands
+                .iter()
+                .filter(|c| c.as_str() == "AT+CGREG?")
+                .count()
+        });
+        assert_eq!(31, cgreg);
+    }
 }
@@ -0,0 +1,882 @@
+use core::str::FromStr;
+
+use atat::heapless::Vec;
+use defmt::Format;
+use defmt::debug;
+use embedded_io_async::ErrorType;
+use embedded_io_async::Read;
+use fasttime::DateTime;
+
+use crate::error::Error;
+use crate::gps;
+use crate::location;
+use crate::utils;
+
+// NMEA 0183 sentences of the GNSS receiver, AT+CGNSTST=1 streams them to the AT port (see
+// gps::start_stream), e.g.
+//
+//     $GPGGA,120221.000,4645.7491,N,01837.8275,E,1,8,0.90,329.2,M,38.2,M,,*51
+//
+// Only the sentences needed for the navigation state are parsed: GGA, RMC, GSA, GSV and VTG.
+
+pub const MAX_SATELLITES: usize = 32; // in view, GPS and GLONASS together
+
+// The system of the sentence, the first two letters of the address.
+#[derive(Clone, Copy, Debug, Format, PartialEq)]
+pub enum Talker {
+    Gps,      // GP
+    Glonass,  // GL
+    Galileo,  // GA
+    Beidou,   // BD, GB
+    Combined, // GN, more systems in one sentence
+}
+
+#[derive(Clone, Copy, Debug, Format, PartialEq)]
+pub struct Time {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub millis: u16,
+}
+
+#[derive(Clone, Copy, Debug, Format, PartialEq)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+#[derive(Clone, Copy, Debug, Format, PartialEq, Default)]
+pub enum Fix {
+    #[default]
+    None,
+    TwoD,
+    ThreeD,
+}
+
+// $GPGGA,<time>,<lat>,<N/S>,<lon>,<E/W>,<quality>,<used>,<HDOP>,<altitude>,M,<geoid>,M,<age>,<station>*hh
+#[derive(Clone, Debug, Format, PartialEq)]
+pub struct Gga {
+    pub time: Option<Time>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub quality: u8, // 0 no fix, 1 GPS, 2 DGPS, 6 estimated
+    pub satellites_used: Option<u8>,
+    pub hdop: Option<f64>,
+    pub altitude: Option<f64>, // above the mean sea level, meters
+}
+
+// $GPRMC,<time>,<A/V>,<lat>,<N/S>,<lon>,<E/W>,<speed>,<course>,<date>,<variation>,<E/W>[,<mode>]*hh
+#[derive(Clone, Debug, Format, PartialEq)]
+pub struct Rmc {
+    pub time: Option<Time>,
+    pub valid: bool, // A valid, V void (no fix)
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub speed_knots: Option<f64>,
+    pub course: Option<f64>, // true, degrees
+    pub date: Option<Date>,
+}
+
+// $GPGSA,<A/M>,<mode>,<PRN>*12,<PDOP>,<HDOP>,<VDOP>*hh
+#[derive(Clone, Debug, Format, PartialEq)]
+pub struct Gsa {
+    pub fix: Fix,
+    pub satellites: Vec<u8, 12>, // PRNs used for the fix
+    pub pdop: Option<f64>,
+    pub hdop: Option<f64>,
+    pub vdop: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, Format, PartialEq)]
+pub struct Satellite {
+    pub talker: Talker,
+    pub prn: u8,
+    pub elevation: Option<u8>, // degrees
+    pub azimuth: Option<u16>,  // degrees
+    pub snr: Option<u8>,       // dBHz, none when not tracked
+}
+
+// $GPGSV,<total>,<number>,<in view>,(<PRN>,<elevation>,<azimuth>,<SNR>)*4*hh, the
+// satellites in view are listed in <total> sentences, 4 per sentence.
+#[derive(Clone, Debug, Format, PartialEq)]
+pub struct Gsv {
+    pub talker: Talker,
+    pub total: u8,
+    pub number: u8,
+    pub in_view: u8,
+    pub satellites: Vec<Satellite, 4>,
+}
+
+// $GPVTG,<course>,T,<course>,M,<speed>,N,<speed>,K[,<mode>]*hh
+#[derive(Clone, Debug, Format, PartialEq)]
+pub struct Vtg {
+    pub course: Option<f64>, // true, degrees
+    pub speed_kmh: Option<f64>,
+}
+
+#[derive(Clone, Debug, Format, PartialEq)]
+pub enum Sentence {
+    Gga(Gga),
+    Rmc(Rmc),
+    Gsa(Gsa),
+    Gsv(Gsv),
+    Vtg(Vtg),
+}
+
+// XOR of the characters between '$' and '*'.
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |acc, b| acc ^ b)
+}
+
+fn number<F: FromStr>(field: Option<&str>) -> Result<Option<F>, Error> {
+    match field {
+        None | Some("") => Ok(None),
+        Some(text) => text
+            .parse()
+            .map(Some)
+            .map_err(|_| Error::Parse("NMEA number")),
+    }
+}
+
+// ddmm.mmmm (latitude) or dddmm.mmmm (longitude) and the hemisphere to degrees.
+fn coordinate(value: Option<&str>, hemisphere: Option<&str>) -> Result<Option<f64>, Error> {
+    let Some(value) = number::<f64>(value)? else {
+        return Ok(None);
+    };
+    let degrees = (value / 100.0) as u32 as f64;
+    let degrees = degrees + (value - degrees * 100.0) / 60.0;
+    match hemisphere {
+        Some("N") | Some("E") => Ok(Some(degrees)),
+        Some("S") | Some("W") => Ok(Some(-degrees)),
+        _ => Err(Error::Parse("NMEA hemisphere")),
+    }
+}
+
+// hhmmss.sss
+fn time(field: Option<&str>) -> Result<Option<Time>, Error> {
+    let text = match field {
+        None | Some("") => return Ok(None),
+        Some(text) => text,
+    };
+    if text.len() < 6 || !text.is_ascii() {
+        return Err(Error::Parse("NMEA time"));
+    }
+    let (hms, fraction) = text.split_at(6);
+    let millis = match fraction {
+        "" => 0,
+        _ => (number::<f64>(Some(fraction))?.unwrap_or_default() * 1000.0 + 0.5) as u16,
+    };
+    Ok(Some(Time {
+        hour: hms[0..2].parse()?,
+        minute: hms[2..4].parse()?,
+        second: hms[4..6].parse()?,
+        millis,
+    }))
+}
+
+// ddmmyy
+fn date(field: Option<&str>) -> Result<Option<Date>, Error> {
+    let text = match field {
+        None | Some("") => return Ok(None),
+        Some(text) => text,
+    };
+    if text.len() != 6 || !text.is_ascii() {
+        return Err(Error::Parse("NMEA date"));
+    }
+    let year = text[4..6].parse::<u16>()?;
+    Ok(Some(Date {
+        year: if year < 80 { 2000 + year } else { 1900 + year }, // GPS time starts in 1980
+        month: text[2..4].parse()?,
+        day: text[0..2].parse()?,
+    }))
+}
+
+// A sentence without the line ending, the checksum is mandatory.
+pub fn parse(line: &str) -> Result<Sentence, Error> {
+    let line = line.trim_end();
+    let body = line.strip_prefix('$').ok_or(Error::Parse("not NMEA"))?;
+    let (data, sum) = body.rsplit_once('*').ok_or(Error::Parse("NMEA checksum"))?;
+    if u8::from_str_radix(sum, 16).ok() != Some(checksum(data)) {
+        return Err(Error::Parse("NMEA checksum"));
+    }
+
+    let mut fields = data.split(',');
+    let address = fields.next().unwrap_or_default();
+    if address.len() != 5 || !address.is_ascii() {
+        return Err(Error::Parse("NMEA address"));
+    }
+    let (talker, kind) = address.split_at(2);
+    let talker = match talker {
+        "GP" => Talker::Gps,
+        "GL" => Talker::Glonass,
+        "GA" => Talker::Galileo,
+        "BD" | "GB" => Talker::Beidou,
+        "GN" => Talker::Combined,
+        _ => return Err(Error::Protocol("unsupported NMEA talker")),
+    };
+
+    match kind {
+        "GGA" => Ok(Sentence::Gga(Gga {
+            time: time(fields.next())?,
+            latitude: coordinate(fields.next(), fields.next())?,
+            longitude: coordinate(fields.next(), fields.next())?,
+            quality: number(fields.next())?.unwrap_or_default(),
+            satellites_used: number(fields.next())?,
+            hdop: number(fields.next())?,
+            altitude: number(fields.next())?,
+        })),
+        "RMC" => Ok(Sentence::Rmc(Rmc {
+            time: time(fields.next())?,
+            valid: fields.next() == Some("A"),
+            latitude: coordinate(fields.next(), fields.next())?,
+            longitude: coordinate(fields.next(), fields.next())?,
+            speed_knots: number(fields.next())?,
+            course: number(fields.next())?,
+            date: date(fields.next())?,
+        })),
+        "GSA" => {
+            fields.next(); // A automatic, M manual 2D/3D selection
+            let fix = match fields.next() {
+                Some("2") => Fix::TwoD,
+                Some("3") => Fix::ThreeD,
+                _ => Fix::None,
+            };
+            let mut satellites = Vec::new();
+            for _ in 0..12 {
+                if let Some(prn) = number(fields.next())? {
+                    let _ = satellites.push(prn);
+                }
+            }
+            Ok(Sentence::Gsa(Gsa {
+                fix,
+                satellites,
+                pdop: number(fields.next())?,
+                hdop: number(fields.next())?,
+                vdop: number(fields.next())?,
+            }))
+        }
+        "GSV" => {
+            let total = number(fields.next())?.unwrap_or_default();
+            let number_ = number(fields.next())?.unwrap_or_default();
+            let in_view = number(fields.next())?.unwrap_or_default();
+            let mut satellites = Vec::new();
+            // NMEA 4.1 appends a signal id, a group of 4 is a satellite
+            let rest: Vec<&str, 17> = fields.take(17).collect();
+            for chunk in rest.as_chunks::<4>().0 {
+                let Some(prn) = number(Some(chunk[0]))? else {
+                    continue;
+                };
+                let _ = satellites.push(Satellite {
+                    talker,
+                    prn,
+                    elevation: number(Some(chunk[1]))?,
+                    azimuth: number(Some(chunk[2]))?,
+                    snr: number(Some(chunk[3]))?,
+                });
+            }
+            Ok(Sentence::Gsv(Gsv {
+                talker,
+                total,
+                number: number_,
+                in_view,
+                satellites,
+            }))
+        }
+        "VTG" => {
+            let course = number(fields.next())?;
+            fields.next(); // T
+            fields.next(); // magnetic course
+            fields.next(); // M
+            fields.next(); // speed in knots
+            fields.next(); // N
+            Ok(Sentence::Vtg(Vtg {
+                course,
+                speed_kmh: number(fields.next())?,
+            }))
+        }
+        _ => Err(Error::Protocol("unsupported NMEA sentence")),
+    }
+}
+
+// The live state built from the stream, every sentence updates its part.
+#[derive(Clone, Debug, Format, PartialEq)]
+pub struct Navigation {
+    pub fix: Fix,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub altitude: Option<f64>,
+    pub time: Option<Time>,
+    pub date: Option<Date>,
+    pub speed_kmh: Option<f64>,
+    pub course: Option<f64>,
+    pub pdop: Option<f64>,
+    pub hdop: Option<f64>,
+    pub vdop: Option<f64>,
+    pub satellites_used: Option<u8>,
+    pub satellites: Vec<Satellite, MAX_SATELLITES>, // in view
+    pub updated_millis: Option<u64>,                // uptime of the last RMC or GGA
+}
+
+impl Default for Navigation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Navigation {
+    // const for a static shared with the ingress.
+    pub const fn new() -> Self {
+        Navigation {
+            fix: Fix::None,
+            latitude: None,
+            longitude: None,
+            altitude: None,
+            time: None,
+            date: None,
+            speed_kmh: None,
+            course: None,
+            pdop: None,
+            hdop: None,
+            vdop: None,
+            satellites_used: None,
+            satellites: Vec::new(),
+            updated_millis: None,
+        }
+    }
+
+    pub fn update(&mut self, sentence: &Sentence, uptime_millis: u64) {
+        match sentence {
+            Sentence::Gga(gga) => {
+                self.updated_millis = Some(uptime_millis);
+                self.time = gga.time.or(self.time);
+                self.satellites_used = gga.satellites_used;
+                self.hdop = gga.hdop.or(self.hdop);
+                if gga.quality == 0 {
+                    self.fix = Fix::None;
+                    return;
+                }
+                // a position at least, the dimension comes with GSA
+                if self.fix == Fix::None {
+                    self.fix = Fix::TwoD;
+                }
+                self.latitude = gga.latitude;
+                self.longitude = gga.longitude;
+                self.altitude = gga.altitude;
+            }
+            Sentence::Rmc(rmc) => {
+                self.updated_millis = Some(uptime_millis);
+                self.time = rmc.time.or(self.time);
+                self.date = rmc.date.or(self.date);
+                if !rmc.valid {
+                    self.fix = Fix::None;
+                    return;
+                }
+                self.latitude = rmc.latitude;
+                self.longitude = rmc.longitude;
+                self.speed_kmh = rmc.speed_knots.map(|knots| knots * 1.852);
+                self.course = rmc.course;
+            }
+            Sentence::Gsa(gsa) => {
+                self.fix = gsa.fix;
+                self.pdop = gsa.pdop;
+                self.hdop = gsa.hdop;
+                self.vdop = gsa.vdop;
+            }
+            Sentence::Gsv(gsv) => {
+                // the first sentence of a cycle replaces the satellites of the system
+                if gsv.number <= 1 {
+                    self.satellites.retain(|s| s.talker != gsv.talker);
+                }
+                for satellite in gsv.satellites.iter() {
+                    let _ = self.satellites.push(*satellite);
+                }
+            }
+            Sentence::Vtg(vtg) => {
+                self.course = vtg.course.or(self.course);
+                self.speed_kmh = vtg.speed_kmh.or(self.speed_kmh);
+            }
+        }
+    }
+
+    // A +UGNSINF report (see gps::StreamOutput), it stands for a whole cycle of sentences but
+    // the satellites in view, these are left as they were.
+    pub fn update_information(
+        &mut self,
+        information: &gps::GnssNavigationInformationResponse,
+        uptime_millis: u64,
+    ) {
+        self.updated_millis = Some(uptime_millis);
+        // yyyyMMddhhmmss.sss
+        if let Some(utc) = information.utc_date_time.as_ref()
+            && let Ok(text) = core::str::from_utf8(utc)
+            && text.len() > 8
+            && text.is_ascii()
+            && let Ok(Some(time)) = time(Some(&text[8..]))
+            && let (Ok(year), Ok(month), Ok(day)) =
+                (text[0..4].parse(), text[4..6].parse(), text[6..8].parse())
+        {
+            self.time = Some(time);
+            self.date = Some(Date { year, month, day });
+        }
+        self.satellites_used = information.gnss_satellites_used;
+        if information.fix_status != Some(gps::FixStatus::FixedPosition) {
+            self.fix = Fix::None;
+            return;
+        }
+        self.fix = match information.msl_altitude {
+            Some(_) => Fix::ThreeD,
+            None => Fix::TwoD,
+        };
+        self.latitude = information.latitude;
+        self.longitude = information.longitude;
+        self.altitude = information.msl_altitude;
+        self.speed_kmh = information.speed_over_ground;
+        self.course = information.course_over_ground;
+        self.pdop = information.pdop;
+        self.hdop = information.hdop;
+        self.vdop = information.vdop;
+    }
+
+    pub fn unix_timestamp_millis(&self) -> Option<i64> {
+        let (date, time) = (self.date?, self.time?);
+        let datetime = DateTime {
+            date: fasttime::Date {
+                year: date.year as i32,
+                month: date.month,
+                day: date.day,
+            },
+            time: fasttime::Time {
+                hour: time.hour,
+                minute: time.minute,
+                second: time.second,
+                nanosecond: time.millis as u32 * 1_000_000u32,
+            },
+        };
+        Some((datetime.unix_timestamp_nanos() / 1_000_000) as i64)
+    }
+
+    // The strongest signal in view, 0 when none is tracked.
+    pub fn snr_max(&self) -> u8 {
+        self.satellites
+            .iter()
+            .filter_map(|s| s.snr)
+            .max()
+            .unwrap_or_default()
+    }
+
+    // None without a fix or before the first RMC (the date).
+    pub fn location(&self) -> Option<location::Location> {
+        if self.fix == Fix::None {
+            return None;
+        }
+        Some(location::Location {
+            latitude: self.latitude?,
+            longitude: self.longitude?,
+            accuracy: utils::estimate_gps_accuracy(self.pdop.unwrap_or(10.0)),
+            unix_timestamp_millis: self.unix_timestamp_millis()?,
+            source: location::Source::Gnss,
+            speed: self.speed_kmh,
+            course: self.course,
+        })
+    }
+}
+
+// The UART reader of the ingress with the NMEA lines taken out. They arrive at any time, in
+// the middle of a multi-line response too (e.g. between the +CMGR header and the PDU), a
+// digester can not cut them out of the ingress buffer. A line is held back while it looks
+// like a sentence ('$', the address, ',' and printable characters), the sentences are passed
+// to `on_sentence`, e.g. to update a Navigation shared with the main loop. Invalid ones are
+// dropped, anything else is forwarded, e.g. a '$' at the start of an HTTP body.
+pub struct NmeaReader<R: Read> {
+    inner: R,
+    on_sentence: fn(Sentence),
+    line: Vec<u8, { MAX_LINE + 1 }>, // held back, or being forwarded from `released`
+    released: Option<usize>,
+    line_start: bool,
+    input: [u8; READ_CHUNK],
+    pos: usize,
+    len: usize,
+}
+
+const MAX_LINE: usize = 96; // 82 characters by the standard
+const READ_CHUNK: usize = 128;
+
+enum Step {
+    Forward(u8),
+    Held,
+    Release, // not a sentence, the held line is forwarded
+}
+
+impl<R: Read> NmeaReader<R> {
+    pub fn new(inner: R, on_sentence: fn(Sentence)) -> Self {
+        NmeaReader {
+            inner,
+            on_sentence,
+            line: Vec::new(),
+            released: None,
+            line_start: true,
+            input: [0; READ_CHUNK],
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    fn step(&mut self, b: u8) -> Step {
+        if self.line.is_empty() {
+            let start = self.line_start;
+            self.line_start = b == b'\n';
+            if start && b == b'$' {
+                let _ = self.line.push(b);
+                return Step::Held;
+            }
+            return Step::Forward(b);
+        }
+        let _ = self.line.push(b);
+        let sentence = match self.line.len() - 1 {
+            1..=5 => b.is_ascii_uppercase() || b.is_ascii_digit(),
+            6 => b == b',',
+            _ => b == b'\r' || b == b'\n' || (b' '..=b'~').contains(&b),
+        };
+        if !sentence || self.line.len() > MAX_LINE {
+            self.line_start = b == b'\n';
+            return Step::Release;
+        }
+        if b == b'\n' {
+            let line = self.line.trim_ascii_end();
+            match core::str::from_utf8(line)
+                .map_err(Error::from)
+                .and_then(parse)
+            {
+                Ok(sentence) => (self.on_sentence)(sentence),
+                Err(e) => debug!("NMEA dropped {:?}: {}", line, e),
+            }
+            self.line.clear();
+            self.line_start = true;
+        }
+        Step::Held
+    }
+}
+
+impl<R: Read> ErrorType for NmeaReader<R> {
+    type Error = R::Error;
+}
+
+impl<R: Read> Read for NmeaReader<R> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, R::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let mut n = 0;
+            while n < buf.len() {
+                if let Some(i) = self.released {
+                    buf[n] = self.line[i];
+                    n += 1;
+                    self.released = Some(i + 1).filter(|i| *i < self.line.len());
+                    if self.released.is_none() {
+                        self.line.clear();
+                    }
+                    continue;
+                }
+                if self.pos == self.len {
+                    break;
+                }
+                let b = self.input[self.pos];
+                self.pos += 1;
+                match self.step(b) {
+                    Step::Forward(b) => {
+                        buf[n] = b;
+                        n += 1;
+                    }
+                    Step::Held => {}
+                    Step::Release => self.released = Some(0),
+                }
+            }
+            if n > 0 {
+                return Ok(n);
+            }
+            // everything read so far is held back
+            self.len = self.inner.read(&mut self.input).await?;
+            self.pos = 0;
+            if self.len == 0 {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+extern crate std;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GGA: &str = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+    const RMC: &str = "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A";
+    const GSA: &str = "$GPGSA,A,3,04,05,,09,12,,,24,,,,,2.5,1.3,2.1*39";
+    const GSV1: &str = "$GPGSV,2,1,08,01,40,083,46,02,17,308,41,12,07,344,39,14,22,228,45*75";
+    const GSV2: &str = "$GPGSV,2,2,08,15,,,,17,62,110,,22,05,044,32,25,11,223,28*4D";
+    const VTG: &str = "$GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48";
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Ok(Sentence::Gga(Gga {
+                time: Some(Time {
+                    hour: 12,
+                    minute: 35,
+                    second: 19,
+                    millis: 0
+                }),
+                latitude: Some(48.11729999999999),
+                longitude: Some(11.516666666666667),
+                quality: 1,
+                satellites_used: Some(8),
+                hdop: Some(0.9),
+                altitude: Some(545.4),
+            })),
+            parse(GGA)
+        );
+        let Ok(Sentence::Rmc(rmc)) = parse(RMC) else {
+            panic!("not RMC");
+        };
+        assert!(rmc.valid);
+        assert_eq!(Some(22.4), rmc.speed_knots);
+        assert_eq!(
+            Some(Date {
+                year: 1994,
+                month: 3,
+                day: 23
+            }),
+            rmc.date
+        );
+        let Ok(Sentence::Gsa(gsa)) = parse(GSA) else {
+            panic!("not GSA");
+        };
+        assert_eq!(Fix::ThreeD, gsa.fix);
+        assert_eq!([4, 5, 9, 12, 24], gsa.satellites.as_slice());
+        assert_eq!(
+            (Some(2.5), Some(1.3), Some(2.1)),
+            (gsa.pdop, gsa.hdop, gsa.vdop)
+        );
+        let Ok(Sentence::Gsv(gsv)) = parse(GSV2) else {
+            panic!("not GSV");
+        };
+        assert_eq!((2, 2, 8), (gsv.total, gsv.number, gsv.in_view));
+        assert_eq!(
+            Satellite {
+                talker: Talker::Gps,
+                prn: 15,
+                elevation: None,
+                azimuth: None,
+                snr: None
+            },
+            gsv.satellites[0]
+        );
+        assert_eq!(Some(28), gsv.satellites[3].snr);
+        assert_eq!(
+            Ok(Sentence::Vtg(Vtg {
+                course: Some(54.7),
+                speed_kmh: Some(10.2)
+            })),
+            parse(VTG)
+        );
+
+        // the SIM868 sends GLONASS satellites as GL, the milliseconds and the mode field
+        let Ok(Sentence::Rmc(rmc)) =
+            parse("$GNRMC,120221.123,A,4645.7491,N,01837.8275,E,0.00,0.0,121222,,,A*4F")
+        else {
+            panic!("not RMC");
+        };
+        assert_eq!(123, rmc.time.unwrap().millis);
+        assert!((rmc.latitude.unwrap() - 46.7624859).abs() < 0.000001);
+        assert!((rmc.longitude.unwrap() - 18.6304591).abs() < 0.000001);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(
+            Err(Error::Parse("NMEA checksum")),
+            parse("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*48")
+        );
+        assert_eq!(
+            Err(Error::Parse("NMEA checksum")),
+            parse("$GPGGA,123519,4807.038,N")
+        );
+        assert_eq!(Err(Error::Parse("not NMEA")), parse("+CGNSINF: 0"));
+        assert_eq!(
+            Err(Error::Protocol("unsupported NMEA sentence")),
+            parse("$GPGLL,4916.45,N,12311.12,W,225444,A,*1D")
+        );
+        assert_eq!(
+            Err(Error::Parse("NMEA hemisphere")),
+            parse("$GPGGA,123519,4807.038,X,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*51")
+        );
+        assert_eq!(Err(Error::Parse("NMEA time")), time(Some("1é2519")));
+        assert_eq!(
+            Err(Error::Parse("NMEA time")),
+            parse("$GPGGA,1é2519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*1E")
+        );
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut navigation = Navigation::default();
+        assert_eq!(None, navigation.location());
+
+        for (i, line) in [GGA, GSA, GSV1, GSV2, RMC, VTG].into_iter().enumerate() {
+            navigation.update(&parse(line).unwrap(), i as u64);
+        }
+        assert_eq!(Fix::ThreeD, navigation.fix);
+        assert_eq!(Some(4), navigation.updated_millis);
+        assert_eq!(Some(545.4), navigation.altitude);
+        assert_eq!(Some(10.2), navigation.speed_kmh);
+        assert_eq!(Some(54.7), navigation.course);
+        assert_eq!(8, navigation.satellites.len());
+        assert_eq!(46, navigation.snr_max());
+        assert_eq!(
+            Some(location::Location {
+                latitude: 48.11729999999999,
+                longitude: 11.516666666666667,
+                accuracy: 6.25,
+                unix_timestamp_millis: 764426119000,
+                source: location::Source::Gnss,
+                speed: Some(10.2),
+                course: Some(54.7),
+            }),
+            navigation.location()
+        );
+
+        // the next cycle replaces the satellites in view
+        navigation.update(&parse("$GPGSV,1,1,01,01,40,083,46*44").unwrap(), 1000);
+        assert_eq!(Some(4), navigation.updated_millis);
+        assert_eq!(1, navigation.satellites.len());
+
+        // the fix is lost
+        navigation.update(
+            &parse("$GPRMC,123520,V,,,,,,,230394,003.1,W*42").unwrap(),
+            1000,
+        );
+        assert_eq!(Fix::None, navigation.fix);
+        assert_eq!(None, navigation.location());
+    }
+
+    #[test]
+    fn test_navigation_emulated() {
+        let mut sim = sim868_emu::Sim868::default();
+        sim.gnss_power = true;
+        sim.nmea_output = true;
+        sim.emit_nmea();
+        let mut navigation = Navigation::default();
+        for (_, bytes) in sim.take_output() {
+            let line = core::str::from_utf8(&bytes).unwrap();
+            navigation.update(&parse(line).unwrap(), 0);
+        }
+        let location = navigation.location().unwrap();
+        assert!((location.latitude - 46.7624859).abs() < 0.00001);
+        assert!((location.longitude - 18.6304591).abs() < 0.00001);
+        assert_eq!(utils::estimate_gps_accuracy(2.3), location.accuracy);
+        assert_eq!(1670846541123, location.unix_timestamp_millis);
+        assert_eq!(Some(8), navigation.satellites_used);
+        assert_eq!(46, navigation.snr_max());
+    }
+
+    static SENTENCES: std::sync::Mutex<std::vec::Vec<Sentence>> =
+        std::sync::Mutex::new(std::vec::Vec::new());
+
+    // The UART, in chunks.
+    struct Chunks(std::collections::VecDeque<&'static [u8]>);
+
+    impl ErrorType for Chunks {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for Chunks {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let Some(chunk) = self.0.pop_front() else {
+                return Ok(0);
+            };
+            let n = buf.len().min(chunk.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            if n < chunk.len() {
+                self.0.push_front(&chunk[n..]);
+            }
+            Ok(n)
+        }
+    }
+
+    async fn read_all<R: Read>(reader: &mut R) -> std::vec::Vec<u8> {
+        let mut ret = std::vec::Vec::new();
+        let mut buf = [0u8; 16];
+        loop {
+            match reader.read(&mut buf).await {
+                Ok(0) | Err(_) => return ret,
+                Ok(n) => ret.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reader() {
+        const PDU: &str = "00040B916303214365F7000862100171522340020061";
+        let response = std::format!("\r\n+CMGR: 0,,21\r\n{}\r\n\r\nOK\r\n", PDU);
+        let uart = std::format!(
+            "\r\n+CMGR: 0,,21\r\n{}\r\n{}\r\n\r\nOK\r\n{}\r\n",
+            RMC,
+            PDU,
+            VTG
+        );
+        let uart: &'static str = std::boxed::Box::leak(uart.into_boxed_str());
+        // the sentence is split over the reads
+        let mut reader = NmeaReader::new(
+            Chunks([&uart.as_bytes()[..20], &uart.as_bytes()[20..]].into()),
+            |sentence| SENTENCES.lock().unwrap().push(sentence),
+        );
+        assert_eq!(response.as_bytes(), read_all(&mut reader).await.as_slice());
+        assert_eq!(
+            [parse(RMC).unwrap(), parse(VTG).unwrap()],
+            SENTENCES.lock().unwrap().as_slice()
+        );
+
+        // the response is whole for the digester and the parser
+        let (result, _) = atat::Digester::digest(
+            &mut atat::DefaultDigester::<crate::urc::Urc>::default(),
+            response.as_bytes(),
+        );
+        let atat::DigestResult::Response(Ok(cmgr)) = result else {
+            panic!("{:?}", result);
+        };
+        let cmd = crate::sms::AtReadSMSMessagesPduWrite {
+            index: 1,
+            mode: None,
+        };
+        assert!(atat::AtatCmd::parse(&cmd, Ok(cmgr)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_reader_not_nmea() {
+        // invalid sentences are dropped, the rest is forwarded
+        for (uart, expected) in [
+            (&b"$GPVTG,*00\r\nOK\r\n"[..], &b"OK\r\n"[..]),
+            (
+                b"\r\n$tATA/location/12345\r\n",
+                b"\r\n$tATA/location/12345\r\n",
+            ),
+            (
+                b"+HTTPREAD: 4\r\n$\x00\x01\n\r\nOK",
+                b"+HTTPREAD: 4\r\n$\x00\x01\n\r\nOK",
+            ),
+            (b"a$GPVTG,\r\n", b"a$GPVTG,\r\n"),
+        ] {
+            let mut reader = NmeaReader::new(Chunks([uart].into()), |_| {});
+            assert_eq!(expected, read_all(&mut reader).await.as_slice());
+        }
+        // too long for a sentence
+        let long = std::format!("$GPVTG,{}\r\n", "1".repeat(MAX_LINE));
+        let long: &'static [u8] = std::boxed::Box::leak(long.into_bytes().into_boxed_slice());
+        let mut reader = NmeaReader::new(Chunks([long].into()), |_| {});
+        assert_eq!(long, read_all(&mut reader).await.as_slice());
+    }
+}
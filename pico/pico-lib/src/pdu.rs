@@ -0,0 +1,853 @@
+use atat::heapless::String;
+use atat::heapless::Vec;
+use defmt::Format;
+
+use crate::error::Error;
+use crate::hexstr;
+
+// SMS PDU mode (AT+CMGF=0) encoding and decoding, GSM 03.40 / 3GPP TS 23.040.
+// http://rfc.nop.hu/sms/default.htm
+// https://en.wikipedia.org/wiki/GSM_03.40
+//
+// Every PDU given to or read from the module starts with the SMSC address, 00 means the
+// one stored on the SIM. E.g. the SMS-SUBMIT of "hi" to +36301234567 in 7-bit:
+//
+//   00 01 00 0B 91 6303214365F7 00 00 02 E834
+//   |  |  |  |  |  |            |  |  |  user data, packed septets
+//   |  |  |  |  |  |            |  |  TP-UDL, septets for 7-bit, octets otherwise
+//   |  |  |  |  |  |            |  TP-DCS
+//   |  |  |  |  |  |            TP-PID
+//   |  |  |  |  |  TP-DA, swapped semi-octets, F padded
+//   |  |  |  |  type of address, 91 international, 81 unknown
+//   |  |  |  TP-DA length in digits
+//   |  |  TP-MR, 00 is replaced by the module
+//   |  SMS-SUBMIT without validity period, +20 status report request, +40 UDH
+//   SMSC
+
+pub const MAX_PDU: usize = 176; // SMSC 12, header 16, user data 140 + rounding
+pub const MAX_USER_DATA: usize = 140;
+pub const MAX_SEPTETS: usize = 160;
+pub const MAX_TEXT: usize = 480; // 160 characters, up to 3 bytes each in UTF-8
+
+const MTI_DELIVER: u8 = 0x00;
+const MTI_SUBMIT: u8 = 0x01;
+const MTI_STATUS_REPORT: u8 = 0x02;
+const SRR: u8 = 0x20; // status report request (SUBMIT), qualifier (STATUS-REPORT)
+const UDHI: u8 = 0x40;
+
+const TYPE_INTERNATIONAL: u8 = 0x91;
+const TYPE_UNKNOWN: u8 = 0x81;
+
+// TP-DCS, only the alphabet matters here, message classes and indications are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub enum Dcs {
+    Gsm7,
+    Data8,
+    Ucs2,
+}
+
+impl Dcs {
+    pub fn from_u8(dcs: u8) -> Result<Dcs, Error> {
+        match dcs >> 4 {
+            // general data coding, bit 6 marks the automatic deletion group (0x40..=0x7F)
+            0x0..=0x7 => {
+                if dcs & 0x20 != 0 {
+                    return Err(Error::Protocol("compressed user data"));
+                }
+                match (dcs >> 2) & 0x03 {
+                    0 => Ok(Dcs::Gsm7),
+                    1 => Ok(Dcs::Data8),
+                    2 => Ok(Dcs::Ucs2),
+                    _ => Err(Error::Protocol("reserved data coding")),
+                }
+            }
+            // message waiting indication, discard / store message
+            0xC | 0xD => Ok(Dcs::Gsm7),
+            0xE => Ok(Dcs::Ucs2),
+            // data coding / message class
+            0xF => Ok(if dcs & 0x04 == 0 {
+                Dcs::Gsm7
+            } else {
+                Dcs::Data8
+            }),
+            _ => Err(Error::Protocol("unsupported data coding")),
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            Dcs::Gsm7 => 0x00,
+            Dcs::Data8 => 0x04,
+            Dcs::Ucs2 => 0x08,
+        }
+    }
+}
+
+// TP-SCTS / TP-DT, local time of the SMSC with its offset from UTC.
+#[derive(Debug, Clone, PartialEq, Default, Format)]
+pub struct Timestamp {
+    pub year: i32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub utc_offset_quarters: i8, // quarters of an hour, Hungary is +4 in winter
+}
+
+impl Timestamp {
+    pub fn unix_timestamp_millis(&self) -> i64 {
+        let local = fasttime::DateTime {
+            date: fasttime::Date {
+                year: self.year,
+                month: self.month,
+                day: self.day,
+            },
+            time: fasttime::Time {
+                hour: self.hour,
+                minute: self.minute,
+                second: self.second,
+                nanosecond: 0,
+            },
+        };
+        (local.unix_timestamp_nanos() / 1_000_000) as i64
+            - self.utc_offset_quarters as i64 * 15 * 60 * 1000
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::large_enum_variant)] // heapless, kept on the stack
+pub enum UserData {
+    Text(String<MAX_TEXT>),
+    Data(Vec<u8, MAX_USER_DATA>),
+}
+
+// SMS-SUBMIT, user_data is UTF-8 text for Gsm7 and Ucs2, raw bytes for Data8.
+// header is the user data header without its length, e.g. Concat::header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Submit<'a> {
+    pub destination: &'a str,
+    pub dcs: Dcs,
+    pub header: &'a [u8],
+    pub user_data: &'a [u8],
+    pub status_report: bool,
+}
+
+impl Submit<'_> {
+    pub fn encode(&self) -> Result<Vec<u8, MAX_PDU>, Error> {
+        let mut pdu = Vec::new();
+        push(&mut pdu, &[0x00])?; // SMSC from the SIM
+        let mut first = MTI_SUBMIT;
+        if self.status_report {
+            first |= SRR;
+        }
+        let mut udh = Vec::<u8, MAX_USER_DATA>::new();
+        if !self.header.is_empty() {
+            first |= UDHI;
+            push(&mut udh, &[self.header.len() as u8])?;
+            push(&mut udh, self.header)?;
+        }
+        push(&mut pdu, &[first, 0x00])?;
+        encode_address(&mut pdu, self.destination)?;
+        push(&mut pdu, &[0x00, self.dcs.as_u8()])?; // PID, DCS
+
+        match self.dcs {
+            Dcs::Gsm7 => {
+                let text = core::str::from_utf8(self.user_data)
+                    .map_err(|_| Error::Parse("invalid UTF-8"))?;
+                // the text starts at the next septet boundary after the header
+                let header_septets = (udh.len() * 8).div_ceil(7);
+                let fill_bits = header_septets * 7 - udh.len() * 8;
+                let septets = hexstr::encode_gsm7::<MAX_SEPTETS>(text)?;
+                if header_septets + septets.len() > MAX_SEPTETS {
+                    return Err(Error::Capacity("message too long"));
+                }
+                push(&mut pdu, &[(header_septets + septets.len()) as u8])?;
+                push(&mut pdu, &udh)?;
+                push(
+                    &mut pdu,
+                    &hexstr::pack_septets::<MAX_USER_DATA>(&septets, fill_bits)?,
+                )?;
+            }
+            Dcs::Ucs2 => {
+                let text = core::str::from_utf8(self.user_data)
+                    .map_err(|_| Error::Parse("invalid UTF-8"))?;
+                let mut data = udh;
+                for unit in text.encode_utf16() {
+                    push(&mut data, &unit.to_be_bytes())
+                        .map_err(|_| Error::Capacity("message too long"))?;
+                }
+                push(&mut pdu, &[data.len() as u8])?;
+                push(&mut pdu, &data)?;
+            }
+            Dcs::Data8 => {
+                let mut data = udh;
+                push(&mut data, self.user_data).map_err(|_| Error::Capacity("message too long"))?;
+                push(&mut pdu, &[data.len() as u8])?;
+                push(&mut pdu, &data)?;
+            }
+        }
+        Ok(pdu)
+    }
+}
+
+// Concatenated short message information element, part `index` of `count` (1 based).
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub struct Concat {
+    pub reference: u16,
+    pub count: u8,
+    pub index: u8,
+}
+
+const IEI_CONCAT_8: u8 = 0x00;
+const IEI_CONCAT_16: u8 = 0x08;
+
+impl Concat {
+    // The information element with 8-bit reference number.
+    pub fn header(&self) -> [u8; 5] {
+        [
+            IEI_CONCAT_8,
+            3,
+            self.reference as u8,
+            self.count,
+            self.index,
+        ]
+    }
+
+    pub fn from_header(header: &[u8]) -> Option<Concat> {
+        let mut rest = header;
+        while let [iei, len, tail @ ..] = rest {
+            let (data, next) = tail.split_at_checked(*len as usize)?;
+            let concat = match (*iei, data) {
+                (IEI_CONCAT_8, [reference, count, index]) => Some(Concat {
+                    reference: *reference as u16,
+                    count: *count,
+                    index: *index,
+                }),
+                (IEI_CONCAT_16, [high, low, count, index]) => Some(Concat {
+                    reference: u16::from_be_bytes([*high, *low]),
+                    count: *count,
+                    index: *index,
+                }),
+                _ => None,
+            };
+            // invalid parts are treated as single messages
+            if let Some(c) = concat.filter(|c| c.index >= 1 && c.index <= c.count) {
+                return Some(c);
+            }
+            rest = next;
+        }
+        None
+    }
+}
+
+// The <length> of AT+CMGS, the SMSC part is not counted.
+pub fn tpdu_length(pdu: &[u8]) -> usize {
+    pdu.len() - 1 - pdu.first().copied().unwrap_or_default() as usize
+}
+
+// SMS-DELIVER, the user data header (if any) is kept raw.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deliver {
+    pub smsc: String<30>,
+    pub originator: String<30>,
+    pub dcs: Dcs,
+    pub timestamp: Timestamp,
+    pub header: Vec<u8, MAX_USER_DATA>,
+    pub user_data: UserData,
+}
+
+impl Deliver {
+    pub fn concat(&self) -> Option<Concat> {
+        Concat::from_header(&self.header)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusReport {
+    pub smsc: String<30>,
+    pub message_reference: u8,
+    pub recipient: String<30>,
+    pub timestamp: Timestamp, // when the SMSC received the SUBMIT
+    pub discharge: Timestamp, // when it was delivered, or the last attempt
+    pub status: u8,
+}
+
+impl StatusReport {
+    pub fn delivered(&self) -> bool {
+        self.status < 0x20
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::large_enum_variant)] // heapless, kept on the stack
+pub enum Pdu {
+    Deliver(Deliver),
+    StatusReport(StatusReport),
+}
+
+pub fn decode(pdu: &[u8]) -> Result<Pdu, Error> {
+    let mut r = Reader { pdu, pos: 0 };
+    let smsc = decode_smsc(&mut r)?;
+    let first = r.u8()?;
+    match first & 0x03 {
+        MTI_DELIVER => {
+            let originator = decode_address(&mut r)?;
+            let _pid = r.u8()?;
+            let dcs = Dcs::from_u8(r.u8()?)?;
+            let timestamp = decode_timestamp(&mut r)?;
+            let udl = r.u8()? as usize;
+            let (header, user_data) = decode_user_data(&mut r, dcs, udl, first & UDHI != 0)?;
+            Ok(Pdu::Deliver(Deliver {
+                smsc,
+                originator,
+                dcs,
+                timestamp,
+                header,
+                user_data,
+            }))
+        }
+        MTI_STATUS_REPORT => Ok(Pdu::StatusReport(StatusReport {
+            smsc,
+            message_reference: r.u8()?,
+            recipient: decode_address(&mut r)?,
+            timestamp: decode_timestamp(&mut r)?,
+            discharge: decode_timestamp(&mut r)?,
+            status: r.u8()?,
+        })),
+        _ => Err(Error::Protocol("unsupported PDU type")),
+    }
+}
+
+struct Reader<'a> {
+    pdu: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        let ret = self
+            .pdu
+            .get(self.pos..self.pos + n)
+            .ok_or(Error::Parse("PDU too short"))?;
+        self.pos += n;
+        Ok(ret)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+}
+
+fn push<const N: usize>(pdu: &mut Vec<u8, N>, bytes: &[u8]) -> Result<(), Error> {
+    pdu.extend_from_slice(bytes)
+        .map_err(|_| Error::Capacity("PDU too long"))
+}
+
+fn encode_address(pdu: &mut Vec<u8, MAX_PDU>, number: &str) -> Result<(), Error> {
+    let (toa, digits) = match number.strip_prefix('+') {
+        Some(digits) => (TYPE_INTERNATIONAL, digits),
+        None => (TYPE_UNKNOWN, number),
+    };
+    if digits.is_empty() || digits.len() > 20 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::Parse("invalid phone number"));
+    }
+    push(pdu, &[digits.len() as u8, toa])?;
+    for pair in digits.as_bytes().chunks(2) {
+        let low = pair[0] - b'0';
+        let high = pair.get(1).map(|d| d - b'0').unwrap_or(0x0F);
+        push(pdu, &[high << 4 | low])?;
+    }
+    Ok(())
+}
+
+fn decode_semi_octets(toa: u8, bytes: &[u8]) -> Result<String<30>, Error> {
+    let mut ret = String::new();
+    if toa & 0x70 == 0x10 {
+        ret.push('+')
+            .map_err(|_| Error::Capacity("address too long"))?;
+    }
+    for nibble in bytes.iter().flat_map(|b| [b & 0x0F, b >> 4]) {
+        match nibble {
+            0..=9 => ret
+                .push((b'0' + nibble) as char)
+                .map_err(|_| Error::Capacity("address too long"))?,
+            0x0F => break,
+            _ => return Err(Error::Parse("invalid address")),
+        }
+    }
+    Ok(ret)
+}
+
+// TP-OA, TP-DA and TP-RA, the length is in semi-octets.
+fn decode_address(r: &mut Reader) -> Result<String<30>, Error> {
+    let len = r.u8()? as usize;
+    let toa = r.u8()?;
+    let bytes = r.take(len.div_ceil(2))?;
+    if toa & 0x70 == 0x50 {
+        // alphanumeric, e.g. the name of the operator
+        let septets = hexstr::unpack_septets::<MAX_SEPTETS>(bytes, len * 4 / 7, 0)?;
+        return hexstr::decode_gsm7(&septets).map_err(|_| Error::Capacity("address too long"));
+    }
+    decode_semi_octets(toa, bytes)
+}
+
+// The SMSC length is in octets, including the type of address.
+fn decode_smsc(r: &mut Reader) -> Result<String<30>, Error> {
+    let len = r.u8()? as usize;
+    if len == 0 {
+        return Ok(String::new());
+    }
+    let toa = r.u8()?;
+    decode_semi_octets(toa, r.take(len - 1)?)
+}
+
+fn decode_timestamp(r: &mut Reader) -> Result<Timestamp, Error> {
+    let bytes = r.take(7)?;
+    let bcd = |b: u8| -> Result<u8, Error> {
+        let (low, high) = (b & 0x0F, b >> 4);
+        if low > 9 || high > 9 {
+            return Err(Error::Parse("invalid timestamp"));
+        }
+        Ok(low * 10 + high)
+    };
+    // the sign is the 4th bit of the tens of quarters
+    let quarters = (bcd(bytes[6] & 0xF7)?) as i8;
+    Ok(Timestamp {
+        year: 2000 + bcd(bytes[0])? as i32,
+        month: bcd(bytes[1])?,
+        day: bcd(bytes[2])?,
+        hour: bcd(bytes[3])?,
+        minute: bcd(bytes[4])?,
+        second: bcd(bytes[5])?,
+        utc_offset_quarters: if bytes[6] & 0x08 != 0 {
+            -quarters
+        } else {
+            quarters
+        },
+    })
+}
+
+fn decode_user_data(
+    r: &mut Reader,
+    dcs: Dcs,
+    udl: usize,
+    has_header: bool,
+) -> Result<(Vec<u8, MAX_USER_DATA>, UserData), Error> {
+    let octets = match dcs {
+        Dcs::Gsm7 => (udl * 7).div_ceil(8),
+        _ => udl,
+    };
+    if octets > MAX_USER_DATA {
+        return Err(Error::Capacity("user data too long"));
+    }
+    let bytes = r.take(octets)?;
+
+    let mut header = Vec::new();
+    let mut skip = 0; // octets
+    if has_header {
+        let udhl = *bytes.first().ok_or(Error::Parse("PDU too short"))? as usize;
+        header
+            .extend_from_slice(
+                bytes
+                    .get(1..1 + udhl)
+                    .ok_or(Error::Parse("PDU too short"))?,
+            )
+            .map_err(|_| Error::Capacity("user data too long"))?;
+        skip = 1 + udhl;
+    }
+
+    let user_data = match dcs {
+        Dcs::Gsm7 => {
+            // the text starts at the next septet boundary after the header
+            let header_septets = (skip * 8).div_ceil(7);
+            let fill_bits = header_septets * 7 - skip * 8;
+            let count = udl
+                .checked_sub(header_septets)
+                .ok_or(Error::Parse("PDU too short"))?;
+            let septets = hexstr::unpack_septets::<MAX_SEPTETS>(&bytes[skip..], count, fill_bits)?;
+            UserData::Text(
+                hexstr::decode_gsm7(&septets).map_err(|_| Error::Capacity("user data too long"))?,
+            )
+        }
+        Dcs::Ucs2 => {
+            let units = bytes[skip..]
+                .as_chunks::<2>()
+                .0
+                .iter()
+                .map(|c| u16::from_be_bytes(*c));
+            let mut text = String::new();
+            for c in char::decode_utf16(units) {
+                text.push(c.map_err(|_| Error::Parse("invalid UCS2"))?)
+                    .map_err(|_| Error::Capacity("user data too long"))?;
+            }
+            UserData::Text(text)
+        }
+        Dcs::Data8 => UserData::Data(Vec::from_slice(&bytes[skip..]).unwrap()),
+    };
+    Ok((header, user_data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hexstr::decode_hex_u8;
+    use crate::hexstr::encode_utf8_hex_string;
+
+    fn hex(pdu: &[u8]) -> String<512> {
+        encode_utf8_hex_string(pdu).unwrap()
+    }
+
+    fn bytes(hex: &str) -> alloc::vec::Vec<u8> {
+        decode_hex_u8(hex).unwrap()
+    }
+
+    #[test]
+    fn test_dcs() {
+        assert_eq!(Ok(Dcs::Gsm7), Dcs::from_u8(0x00));
+        assert_eq!(Ok(Dcs::Data8), Dcs::from_u8(0x04));
+        assert_eq!(Ok(Dcs::Ucs2), Dcs::from_u8(0x08));
+        assert_eq!(Ok(Dcs::Gsm7), Dcs::from_u8(0x10)); // class 0, flash
+        assert_eq!(Ok(Dcs::Ucs2), Dcs::from_u8(0x19));
+        assert_eq!(Ok(Dcs::Data8), Dcs::from_u8(0xF4));
+        assert_eq!(Ok(Dcs::Gsm7), Dcs::from_u8(0xF0));
+        assert_eq!(Ok(Dcs::Gsm7), Dcs::from_u8(0xC8));
+        assert_eq!(Ok(Dcs::Ucs2), Dcs::from_u8(0xE0));
+        // automatic deletion
+        assert_eq!(Ok(Dcs::Gsm7), Dcs::from_u8(0x40));
+        assert_eq!(Ok(Dcs::Data8), Dcs::from_u8(0x44));
+        assert_eq!(Ok(Dcs::Ucs2), Dcs::from_u8(0x48));
+        assert_eq!(Ok(Dcs::Ucs2), Dcs::from_u8(0x59));
+        assert_eq!(
+            Err(Error::Protocol("compressed user data")),
+            Dcs::from_u8(0x68)
+        );
+        assert_eq!(
+            Err(Error::Protocol("compressed user data")),
+            Dcs::from_u8(0x28)
+        );
+        assert_eq!(
+            Err(Error::Protocol("reserved data coding")),
+            Dcs::from_u8(0x0C)
+        );
+        assert_eq!(
+            Err(Error::Protocol("unsupported data coding")),
+            Dcs::from_u8(0x80)
+        );
+    }
+
+    #[test]
+    fn test_encode_submit() {
+        let submit = Submit {
+            destination: "+36301234567",
+            dcs: Dcs::Gsm7,
+            header: &[],
+            user_data: b"hi",
+            status_report: false,
+        };
+        let pdu = submit.encode().unwrap();
+        assert_eq!("0001000B916303214365F7000002E834", hex(&pdu));
+        assert_eq!(15, tpdu_length(&pdu));
+
+        let submit = Submit {
+            user_data: "€".as_bytes(),
+            ..submit
+        };
+        assert_eq!(
+            "0001000B916303214365F70000029B32",
+            hex(&submit.encode().unwrap())
+        );
+
+        let submit = Submit {
+            destination: "06301234567",
+            dcs: Dcs::Ucs2,
+            header: &[],
+            user_data: "Árvíz".as_bytes(),
+            status_report: true,
+        };
+        assert_eq!(
+            "0021000B816003214365F700080A00C10072007600ED007A",
+            hex(&submit.encode().unwrap())
+        );
+
+        let submit = Submit {
+            destination: "+36301234567",
+            dcs: Dcs::Data8,
+            header: &[],
+            user_data: &[0xCA, 0xFE],
+            status_report: false,
+        };
+        assert_eq!(
+            "0001000B916303214365F7000402CAFE",
+            hex(&submit.encode().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_encode_submit_concat() {
+        let concat = Concat {
+            reference: 0x42,
+            count: 2,
+            index: 1,
+        };
+        let submit = Submit {
+            destination: "+36301234567",
+            dcs: Dcs::Gsm7,
+            header: &concat.header(),
+            user_data: b"hi",
+            status_report: false,
+        };
+        assert_eq!(
+            "0041000B916303214365F7000009050003420201D069",
+            hex(&submit.encode().unwrap())
+        );
+        let submit = Submit {
+            dcs: Dcs::Ucs2,
+            ..submit
+        };
+        assert_eq!(
+            "0041000B916303214365F700080A05000342020100680069",
+            hex(&submit.encode().unwrap())
+        );
+
+        // 153 septets or 67 UCS2 characters fit next to the header
+        let gsm7 = |user_data| Submit {
+            dcs: Dcs::Gsm7,
+            user_data,
+            ..submit.clone()
+        };
+        assert!(gsm7(&[b'a'; 153]).encode().is_ok());
+        assert_eq!(
+            Err(Error::Capacity("message too long")),
+            gsm7(&[b'a'; 154]).encode()
+        );
+        let ucs2 = |user_data| Submit {
+            dcs: Dcs::Ucs2,
+            user_data,
+            ..submit.clone()
+        };
+        assert!(ucs2(&[b'a'; 67]).encode().is_ok());
+        assert_eq!(
+            Err(Error::Capacity("message too long")),
+            ucs2(&[b'a'; 68]).encode()
+        );
+    }
+
+    #[test]
+    fn test_concat_from_header() {
+        assert_eq!(
+            Some(Concat {
+                reference: 0x42,
+                count: 2,
+                index: 1
+            }),
+            Concat::from_header(&[0x00, 0x03, 0x42, 0x02, 0x01])
+        );
+        assert_eq!(
+            Some(Concat {
+                reference: 0x1234,
+                count: 3,
+                index: 3
+            }),
+            Concat::from_header(&[0x08, 0x04, 0x12, 0x34, 0x03, 0x03])
+        );
+        // port addressing first
+        assert_eq!(
+            Some(Concat {
+                reference: 0x42,
+                count: 2,
+                index: 2
+            }),
+            Concat::from_header(&[
+                0x05, 0x04, 0x0B, 0x84, 0x23, 0xF0, 0x00, 0x03, 0x42, 0x02, 0x02
+            ])
+        );
+        assert_eq!(None, Concat::from_header(&[]));
+        assert_eq!(None, Concat::from_header(&[0x00, 0x03, 0x42, 0x02, 0x03]));
+        assert_eq!(None, Concat::from_header(&[0x00, 0x03, 0x42, 0x02, 0x00]));
+        assert_eq!(None, Concat::from_header(&[0x00, 0x03, 0x42, 0x02]));
+        assert_eq!(None, Concat::from_header(&[0x05, 0x04, 0x0B, 0x84]));
+    }
+
+    #[test]
+    fn test_encode_submit_errors() {
+        let submit = |destination, dcs, user_data| Submit {
+            destination,
+            dcs,
+            header: &[],
+            user_data,
+            status_report: false,
+        };
+        assert_eq!(
+            Err(Error::Parse("invalid phone number")),
+            submit("+3630abc", Dcs::Gsm7, b"hi").encode()
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid phone number")),
+            submit("", Dcs::Gsm7, b"hi").encode()
+        );
+        assert_eq!(
+            Err(Error::Parse("not a GSM 7-bit character")),
+            submit("+36301234567", Dcs::Gsm7, "ő".as_bytes()).encode()
+        );
+        assert_eq!(
+            Err(Error::Capacity("message too long")),
+            submit("+36301234567", Dcs::Gsm7, &[b'a'; 161]).encode()
+        );
+        assert_eq!(
+            Err(Error::Capacity("message too long")),
+            submit("+36301234567", Dcs::Ucs2, &[b'a'; 71]).encode()
+        );
+        assert_eq!(
+            Err(Error::Capacity("message too long")),
+            submit("+36301234567", Dcs::Data8, &[0; 141]).encode()
+        );
+        assert!(
+            submit("+36301234567", Dcs::Gsm7, &[b'a'; 160])
+                .encode()
+                .is_ok()
+        );
+        assert!(
+            submit("+36301234567", Dcs::Ucs2, &[b'a'; 70])
+                .encode()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_decode_deliver_gsm7() {
+        // SMSC +36309888000, from +36301234567, 2026-01-10 17:25:32 UTC+1
+        let pdu = bytes("07916303898800F0040B916303214365F70000621001715223400AE8329BFD4697D9EC37");
+        let Ok(Pdu::Deliver(deliver)) = decode(&pdu) else {
+            panic!("not a deliver");
+        };
+        assert_eq!("+36309888000", deliver.smsc);
+        assert_eq!("+36301234567", deliver.originator);
+        assert_eq!(Dcs::Gsm7, deliver.dcs);
+        assert_eq!(
+            Timestamp {
+                year: 2026,
+                month: 1,
+                day: 10,
+                hour: 17,
+                minute: 25,
+                second: 32,
+                utc_offset_quarters: 4,
+            },
+            deliver.timestamp
+        );
+        assert_eq!(1768062332000, deliver.timestamp.unix_timestamp_millis());
+        assert!(deliver.header.is_empty());
+        assert_eq!(
+            UserData::Text(String::try_from("hellohello").unwrap()),
+            deliver.user_data
+        );
+
+        // the euro sign is in the extension table, 2 septets
+        let Ok(Pdu::Deliver(deliver)) =
+            decode(&bytes("00040B916303214365F7000062100171522340029B32"))
+        else {
+            panic!("not a deliver");
+        };
+        assert_eq!(
+            UserData::Text(String::try_from("€").unwrap()),
+            deliver.user_data
+        );
+    }
+
+    #[test]
+    fn test_decode_deliver_ucs2() {
+        // alphanumeric sender "Yettel", no SMSC, UTC-3
+        let pdu = bytes("00040CD0D9329D5E66030008328060512000290601500020000D");
+        let Ok(Pdu::Deliver(deliver)) = decode(&pdu) else {
+            panic!("not a deliver");
+        };
+        assert_eq!("", deliver.smsc);
+        assert_eq!("Yettel", deliver.originator);
+        assert_eq!(Dcs::Ucs2, deliver.dcs);
+        assert_eq!(-12, deliver.timestamp.utc_offset_quarters);
+        assert_eq!(2023, deliver.timestamp.year);
+        assert_eq!(
+            UserData::Text(String::try_from("Ő \r").unwrap()),
+            deliver.user_data
+        );
+    }
+
+    #[test]
+    fn test_decode_deliver_header() {
+        // concatenated part 1/2, reference 0x42, 7-bit "hi" after 1 fill bit
+        let pdu = bytes("00440B916303214365F700006210017152234009050003420201D069");
+        let Ok(Pdu::Deliver(deliver)) = decode(&pdu) else {
+            panic!("not a deliver");
+        };
+        assert_eq!(&[0x00, 0x03, 0x42, 0x02, 0x01], deliver.header.as_slice());
+        assert_eq!(
+            Some(Concat {
+                reference: 0x42,
+                count: 2,
+                index: 1
+            }),
+            deliver.concat()
+        );
+        assert_eq!(
+            UserData::Text(String::try_from("hi").unwrap()),
+            deliver.user_data
+        );
+
+        // 8-bit data with header
+        let pdu = bytes("00440B916303214365F700046210017152234007050003420201CA");
+        let Ok(Pdu::Deliver(deliver)) = decode(&pdu) else {
+            panic!("not a deliver");
+        };
+        assert_eq!(&[0x00, 0x03, 0x42, 0x02, 0x01], deliver.header.as_slice());
+        assert_eq!(
+            UserData::Data(Vec::from_slice(&[0xCA]).unwrap()),
+            deliver.user_data
+        );
+    }
+
+    #[test]
+    fn test_decode_status_report() {
+        let pdu = bytes("07916303898800F006420B916303214365F7621001715223406210017152534000");
+        let Ok(Pdu::StatusReport(report)) = decode(&pdu) else {
+            panic!("not a status report");
+        };
+        assert_eq!("+36309888000", report.smsc);
+        assert_eq!(0x42, report.message_reference);
+        assert_eq!("+36301234567", report.recipient);
+        assert_eq!(32, report.timestamp.second);
+        assert_eq!(35, report.discharge.second);
+        assert_eq!(0, report.status);
+        assert!(report.delivered());
+
+        let mut pdu = pdu.clone();
+        *pdu.last_mut().unwrap() = 0x45; // permanent error, rejected by the SME
+        let Ok(Pdu::StatusReport(report)) = decode(&pdu) else {
+            panic!("not a status report");
+        };
+        assert_eq!(0x45, report.status);
+        assert!(!report.delivered());
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(Err(Error::Parse("PDU too short")), decode(&[]));
+        assert_eq!(Err(Error::Parse("PDU too short")), decode(&bytes("0004")));
+        assert_eq!(
+            Err(Error::Protocol("unsupported PDU type")),
+            decode(&bytes("0001"))
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid timestamp")),
+            decode(&bytes("00040B916303214365F70000F2100171522340021111"))
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid address")),
+            decode(&bytes("00040B9163A3214365F70000621001715223400100"))
+        );
+        assert_eq!(
+            Err(Error::Parse("PDU too short")),
+            decode(&bytes("00040B916303214365F7000062100171522340021B"))
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use crate::error::Error;
+use atat::heapless::String;
+use atat::heapless::Vec;
+
+// Phone number comparison, loosely following Android's PhoneNumberUtils.compare, so the
+// numbers typed into the Watcher app match the ones reported by the network:
+//   +36301234567 == 06301234567 == 36301234567 == 301234567
+//
+// The numbers with '+', 00 or the trunk prefix of the home network are international,
+// these have to be equal, the trunk prefix stands for the country code of the home network
+// only. So 06301234567 is not +16301234567, the contacts authorize the SMS commands. A
+// number without any of these (e.g. the network left out the country code) has to match
+// the end of the other one, at least MIN_MATCH digits, and at most MAX_PREFIX digits may
+// remain in front.
+
+// The home network, e.g. Hungary: +36 30 123 4567 is 06 30 123 4567 in the national format
+// (see config::Config). An empty trunk prefix is none.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Home<'a> {
+    pub country_code: &'a str,
+    pub trunk_prefix: &'a str,
+}
+
+const MIN_MATCH: usize = 7;
+const MAX_PREFIX: usize = 3;
+
+// Keeps the leading '+' and the digits, e.g. "+36 (30) 123-4567" -> "+36301234567".
+pub fn normalize(number: &str) -> Result<String<30>, Error> {
+    let mut ret = String::new();
+    for (i, c) in number.trim().chars().enumerate() {
+        match c {
+            '+' if i == 0 => ret
+                .push(c)
+                .map_err(|_| Error::Capacity("phone number too long"))?,
+            '0'..='9' => ret
+                .push(c)
+                .map_err(|_| Error::Capacity("phone number too long"))?,
+            ' ' | '-' | '(' | ')' | '.' => (),
+            _ => return Err(Error::Parse("invalid phone number")),
+        }
+    }
+    if ret.bytes().filter(|b| b.is_ascii_digit()).count() < MIN_MATCH {
+        return Err(Error::Parse("invalid phone number"));
+    }
+    Ok(ret)
+}
+
+pub fn compare(a: &str, b: &str, home: Home) -> bool {
+    let (a, a_international) = canonical(a, home);
+    let (b, b_international) = canonical(b, home);
+    if a.len() < MIN_MATCH || b.len() < MIN_MATCH {
+        return false;
+    }
+    match (a_international, b_international) {
+        (true, true) => a == b,
+        (true, false) => is_suffix(&b, &a),
+        (false, true) => is_suffix(&a, &b),
+        (false, false) if a.len() < b.len() => is_suffix(&a, &b),
+        (false, false) => is_suffix(&b, &a),
+    }
+}
+
+// The digits with the country code, and whether the number is international.
+fn canonical(number: &str, home: Home) -> (Vec<u8, 32>, bool) {
+    let number = number.trim();
+    let digits = digits(number);
+    if number.starts_with('+') {
+        (digits, true)
+    } else if let Some(rest) = digits.strip_prefix(b"00") {
+        (Vec::from_slice(rest).unwrap(), true)
+    } else if !home.trunk_prefix.is_empty()
+        && let Some(rest) = digits.strip_prefix(home.trunk_prefix.as_bytes())
+    {
+        let mut ret: Vec<u8, 32> = Vec::new();
+        for b in home.country_code.bytes().chain(rest.iter().copied()) {
+            if ret.push(b).is_err() {
+                break; // cut like the digits
+            }
+        }
+        (ret, true)
+    } else {
+        (digits, false)
+    }
+}
+
+fn is_suffix(short: &[u8], long: &[u8]) -> bool {
+    long.ends_with(short) && long.len() - short.len() <= MAX_PREFIX
+}
+
+fn digits(number: &str) -> Vec<u8, 32> {
+    let mut ret = Vec::new();
+    for b in number.bytes().filter(|b| b.is_ascii_digit()) {
+        if ret.push(b).is_err() {
+            break;
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOME: Home = Home {
+        country_code: "36",
+        trunk_prefix: "06",
+    };
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            Ok("+36301234567"),
+            normalize(" +36 (30) 123-4567").as_deref()
+        );
+        assert_eq!(Ok("06301234567"), normalize("06301234567").as_deref());
+        assert_eq!(
+            Err(Error::Parse("invalid phone number")),
+            normalize("36+301234567")
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid phone number")),
+            normalize("+3630abc4567")
+        );
+        assert_eq!(Err(Error::Parse("invalid phone number")), normalize("+36"));
+        assert_eq!(
+            Err(Error::Capacity("phone number too long")),
+            normalize("+3630123456789012345678901234567")
+        );
+    }
+
+    #[test]
+    fn test_compare() {
+        assert!(compare("+36301234567", "+36301234567", HOME));
+        assert!(compare("+36301234567", "06301234567", HOME));
+        assert!(compare("+36301234567", "36301234567", HOME));
+        assert!(compare("06301234567", "36301234567", HOME));
+        assert!(compare("+36301234567", "301234567", HOME));
+        assert!(compare("+36 30 123 4567", "06-30-123-4567", HOME));
+
+        assert!(!compare("+36301234567", "+36301234568", HOME));
+        assert!(!compare("+36301234567", "+44301234567", HOME));
+        assert!(!compare("+36301234567", "06201234567", HOME));
+        assert!(!compare("+36301234567", "4567", HOME));
+        assert!(!compare("+36301234567", "+1234301234567", HOME));
+        assert!(!compare("", "", HOME));
+        assert!(compare("0036301234567", "06301234567", HOME));
+        assert!(!compare("+36301234567", "+361234567", HOME));
+        assert!(!compare("1234567", "+36301234567", HOME));
+    }
+
+    #[test]
+    fn test_compare_foreign_country_code() {
+        assert!(!compare("06301234567", "+16301234567", HOME));
+        assert!(!compare("+16301234567", "06301234567", HOME));
+        assert!(!compare("+36301234567", "0301234567", HOME));
+        assert!(!compare("0301234567", "+36301234567", HOME));
+        assert!(!compare("06301234567", "+96301234567", HOME));
+        assert!(!compare("06301234567", "0016301234567", HOME));
+        // the trunk prefix of another country
+        assert!(!compare("030123456", "+4930123456", HOME));
+    }
+
+    #[test]
+    fn test_compare_home() {
+        let germany = Home {
+            country_code: "49",
+            trunk_prefix: "0",
+        };
+        assert!(compare("030123456", "+4930123456", germany));
+        assert!(compare("0030123456", "+30123456", germany));
+        assert!(!compare("06301234567", "+36301234567", germany));
+
+        // Italy has none, the leading 0 of a landline is dialled from abroad too
+        let italy = Home {
+            country_code: "39",
+            trunk_prefix: "",
+        };
+        assert!(compare("0612345678", "+390612345678", italy));
+        assert!(compare("3123456789", "+393123456789", italy));
+    }
+}
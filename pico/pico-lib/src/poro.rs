@@ -6,6 +6,7 @@ use alloc::vec::Vec;
 use machine_derive::MachineDumper;
 use machine_derive::MachineParser;
 
+use crate::error::Error;
 use crate::utils;
 use alloc::string::String;
 
@@ -36,6 +37,8 @@ pub struct CarLocation {
     pub accuracy: f32,
     pub battery: f32,
     pub timestamp: i64,
+    #[machine(skip)]
+    pub source: Option<crate::location::Source>,
 }
 
 #[derive(Debug, Format, PartialEq, Default, MachineParser, MachineDumper)]
@@ -44,12 +47,14 @@ pub struct ParkLocation {
     pub accuracy: f32,
 }
 
-#[derive(Debug, Format, Default, PartialEq)]
+#[derive(Debug, Format, Default, PartialEq, Clone)]
 pub enum Status {
     #[default]
     ParkingDetected,
     ParkingUpdated,
     CarTheftDetected,
+    ZoneEntered, // see geofence, not known by the Android application
+    ZoneExited,
 }
 
 #[derive(Debug, Format, PartialEq, Default, MachineParser, MachineDumper)]
@@ -58,6 +63,8 @@ pub struct Protector {
     pub park_location: Option<ParkLocation>,
     pub status: Option<Status>,
     pub service: Option<Service>,
+    #[machine(skip)]
+    pub zone: Option<atat::heapless::String<{ crate::geofence::MAX_NAME }>>,
 }
 
 // Watcher
@@ -173,11 +180,11 @@ trait MachineDumper {
 }
 
 trait MachineParser {
-    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), &'static str>;
+    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), Error>;
 }
 
 impl<T: MachineParser + Default> MachineParser for Option<T> {
-    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), &'static str> {
+    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), Error> {
         match tokens.front() {
             Some(top) => {
                 if top == NULLS {
@@ -192,7 +199,7 @@ impl<T: MachineParser + Default> MachineParser for Option<T> {
                     return res;
                 }
             }
-            None => return Err("no token for Option<T>"),
+            None => return Err(Error::Parse("no token for Option<T>")),
         }
 
         return Ok(());
@@ -200,53 +207,53 @@ impl<T: MachineParser + Default> MachineParser for Option<T> {
 }
 
 impl MachineParser for f64 {
-    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), &'static str> {
+    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), Error> {
         match tokens.pop_front() {
             Some(token) => match i64::from_str_radix(token.as_str(), 36) {
                 Ok(num) => *self = num as f64 / F64_PRECISION,
-                Err(_) => return Err("could not parse radix f64"),
+                Err(_) => return Err(Error::Parse("could not parse radix f64")),
             },
-            None => return Err("no token for f64"),
+            None => return Err(Error::Parse("no token for f64")),
         }
         return Ok(());
     }
 }
 
 impl MachineParser for f32 {
-    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), &'static str> {
+    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), Error> {
         match tokens.pop_front() {
             Some(token) => match i64::from_str_radix(token.as_str(), 36) {
                 Ok(num) => *self = num as f32 / F32_PRECISION,
-                Err(_) => return Err("could not parse radix f32"),
+                Err(_) => return Err(Error::Parse("could not parse radix f32")),
             },
-            None => return Err("no token for f32"),
+            None => return Err(Error::Parse("no token for f32")),
         }
         return Ok(());
     }
 }
 
 impl MachineParser for i64 {
-    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), &'static str> {
+    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), Error> {
         match tokens.pop_front() {
             Some(token) => match i64::from_str_radix(token.as_str(), 36) {
                 Ok(num) => *self = num,
-                Err(_) => return Err("could not parse radix i64"),
+                Err(_) => return Err(Error::Parse("could not parse radix i64")),
             },
-            None => return Err("no token for i64"),
+            None => return Err(Error::Parse("no token for i64")),
         }
         return Ok(());
     }
 }
 
 impl MachineParser for bool {
-    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), &'static str> {
+    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), Error> {
         match tokens.pop_front() {
             Some(token) => match token.as_str() {
                 TRUE => *self = true,
                 FALSE => *self = false,
-                _ => return Err("could not parse bool"),
+                _ => return Err(Error::Parse("could not parse bool")),
             },
-            None => return Err("no token for bool"),
+            None => return Err(Error::Parse("no token for bool")),
         }
         return Ok(());
     }
@@ -258,12 +265,14 @@ impl Status {
             Status::ParkingDetected => 0,
             Status::ParkingUpdated => 1,
             Status::CarTheftDetected => 2,
+            Status::ZoneEntered => 3,
+            Status::ZoneExited => 4,
         }
     }
 }
 
 impl MachineParser for Status {
-    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), &'static str> {
+    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), Error> {
         let mut x = 0i64;
         x.x_parse(tokens)?;
 
@@ -271,7 +280,9 @@ impl MachineParser for Status {
             0 => *self = Status::ParkingDetected,
             1 => *self = Status::ParkingUpdated,
             2 => *self = Status::CarTheftDetected,
-            _ => return Err("invalid Status"),
+            3 => *self = Status::ZoneEntered,
+            4 => *self = Status::ZoneExited,
+            _ => return Err(Error::Parse("invalid Status")),
         }
 
         Ok(())
@@ -290,7 +301,7 @@ impl Source {
 }
 
 impl MachineParser for Source {
-    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), &'static str> {
+    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), Error> {
         let mut x = 0i64;
         x.x_parse(tokens)?;
 
@@ -299,7 +310,7 @@ impl MachineParser for Source {
             1 => *self = Source::SmsHuman,
             2 => *self = Source::SmsMachine,
             3 => *self = Source::Service,
-            _ => return Err("invalid Source"),
+            _ => return Err(Error::Parse("invalid Source")),
         }
 
         Ok(())
@@ -307,13 +318,13 @@ impl MachineParser for Source {
 }
 
 impl MachineParser for String {
-    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), &'static str> {
+    fn x_parse(&mut self, tokens: &mut VecDeque<String>) -> Result<(), Error> {
         match tokens.pop_front() {
             Some(token) => {
                 *self = unescape(token);
                 Ok(())
             }
-            None => Err("no token for String"),
+            None => Err(Error::Parse("no token for String")),
         }
     }
 }
@@ -378,7 +389,7 @@ impl ProtectorMachine {
         o.x_dump()
     }
 
-    pub fn parse(&self, d: String) -> Result<Protector, &'static str> {
+    pub fn parse(&self, d: String) -> Result<Protector, Error> {
         let mut tokens = utils::as_tokens(d, DELIMITER);
         let mut p = Protector::default();
         p.x_parse(&mut tokens)?;
@@ -386,6 +397,15 @@ impl ProtectorMachine {
     }
 }
 
+fn format_unix_timestamp_ms(timestamp: i64) -> String {
+    let secs = timestamp / 1000i64;
+    let nanos = (timestamp - secs * 1000) as i32 * 1_000_000i32;
+    match DateTime::from_unix_timestamp(secs, nanos) {
+        Ok(dt) => format!("{}", dt),
+        Err(_) => format!("{}millis", timestamp),
+    }
+}
+
 pub struct ProtectorHuman {}
 
 impl ProtectorHuman {
@@ -396,23 +416,26 @@ impl ProtectorHuman {
                 loc.latitude, loc.longitude
             );
         };
-        let format_unix_timestamp_ms = |timestamp: i64| -> String {
-            let secs = timestamp / 1000i64;
-            let nanos = (timestamp - secs * 1000) as i32 * 1_000_000i32;
-            match DateTime::from_unix_timestamp(secs, nanos) {
-                Ok(dt) => format!("{}", dt),
-                Err(_) => format!("{}millis", timestamp),
-            }
-        };
-
         let mut ret = String::new();
 
+        match (o.status.as_ref(), o.zone.as_ref()) {
+            (Some(Status::ZoneEntered), Some(z)) => {
+                ret.push_str(format!("Entered {}\n\n", z).as_str())
+            }
+            (Some(Status::ZoneExited), Some(z)) => ret.push_str(format!("Left {}\n\n", z).as_str()),
+            _ => (),
+        }
+
         match o.car_location.as_ref() {
             Some(c) => ret.push_str(
                 format!(
-                    "{maps_link}\n\n{accuracy:.2} meters, {battery:.2} %, {timestamp}\n\n",
+                    "{maps_link}\n\n{accuracy:.2} meters{source}, {battery:.2} %, {timestamp}\n\n",
                     maps_link = create_maps_link(&c.position),
                     accuracy = c.accuracy,
+                    source = match c.source {
+                        Some(s) => format!(" ({})", s.as_str()),
+                        None => String::new(),
+                    },
                     battery = c.battery * 100.0f32,
                     timestamp = format_unix_timestamp_ms(c.timestamp),
                 )
@@ -421,8 +444,10 @@ impl ProtectorHuman {
             None => (),
         };
 
-        if o.service.is_some() {
-            ret.push_str("Service on\n\n");
+        match o.service.as_ref() {
+            Some(s) if s.value => ret.push_str("Service on\n\n"),
+            Some(_) => ret.push_str("Service off\n\n"),
+            None => (),
         }
 
         match o.park_location.as_ref() {
@@ -458,7 +483,7 @@ impl ProtectorHuman {
 pub struct WatcherHuman {}
 
 impl WatcherHuman {
-    pub fn parse(&self, d: String) -> Result<Watcher, &'static str> {
+    pub fn parse(&self, d: String) -> Result<Watcher, Error> {
         match d.as_str() {
             "location" => Ok(Watcher {
                 call: None,
@@ -502,7 +527,7 @@ impl WatcherHuman {
                 receiver: None,
                 service: Some(Service { value: false }),
             }),
-            _ => Err("invalid watcher command"),
+            _ => Err(Error::Parse("invalid watcher command")),
         }
     }
 }
@@ -514,7 +539,7 @@ impl WatcherMachine {
         o.x_dump()
     }
 
-    pub fn parse(&self, d: String) -> Result<Watcher, &'static str> {
+    pub fn parse(&self, d: String) -> Result<Watcher, Error> {
         let mut tokens = utils::as_tokens(d, DELIMITER);
         let mut w = Watcher::default();
         w.x_parse(&mut tokens)?;
@@ -522,6 +547,230 @@ impl WatcherMachine {
     }
 }
 
+// Configuration (human only, the Android application does not know about it)
+//   config?                                  query
+//   config apn=internet park_radius=150      change, unlisted keys are kept
+//   config owner=+36301234567,06201234567 viewer=  phone numbers are comma separated,
+//                                                   empty value removes every number
+//   config zone.home=46.762486,18.630459,200       circle, lat,lon,radius in meters
+//   config zone.town=46.75,18.62;46.77,18.62;46.76,18.64  polygon, lat,lon;lat,lon;...
+//   config zone.home=                              removes the zone
+//   config mqtt=203.0.113.5:1883                   broker of the telemetry, empty turns it off
+//   config country=36 trunk=06                     home network of the national numbers,
+//                                                   empty trunk for none (see phone::Home)
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigChange {
+    pub owners: Option<Vec<String>>,
+    pub viewers: Option<Vec<String>>,
+    pub emergency: Option<Vec<String>>,
+    pub password: Option<String>,
+    pub apn: Option<String>,
+    pub clbs_server: Option<String>,
+    pub mqtt_server: Option<String>, // empty turns it off
+    pub country_code: Option<String>,
+    pub trunk_prefix: Option<String>, // empty for none
+    pub park_radius_meters: Option<u32>,
+    pub zones: Vec<(String, String)>, // name, shape (see geofence::Shape::parse), empty removes
+}
+
+#[derive(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)] // short-lived, one per command
+pub enum ConfigCommand {
+    Query,
+    Change(ConfigChange),
+}
+
+pub struct ConfigHuman {}
+
+impl ConfigHuman {
+    pub fn dump(&self, o: &crate::config::Config) -> String {
+        use crate::config::Role;
+
+        // the password is never sent back
+        let mut ret = String::new();
+        for (key, role) in [
+            ("owner", Role::Owner),
+            ("viewer", Role::Viewer),
+            ("emergency", Role::Emergency),
+        ] {
+            let numbers: Vec<&str> = o.numbers(role).map(|n| n.as_str()).collect();
+            if !numbers.is_empty() {
+                ret.push_str(format!("{}={} ", key, numbers.join(",")).as_str());
+            }
+        }
+        ret.push_str(
+            format!(
+                "apn={} park_radius={} clbs={} country={} trunk={}",
+                o.apn, o.park_radius_meters, o.clbs_server, o.country_code, o.trunk_prefix
+            )
+            .as_str(),
+        );
+        if !o.mqtt_server.is_empty() {
+            ret.push_str(format!(" mqtt={}", o.mqtt_server).as_str());
+        }
+        for z in o.zones.iter() {
+            ret.push_str(format!(" zone.{}={}", z.name, z.shape.dump()).as_str());
+        }
+        ret
+    }
+
+    pub fn parse(&self, d: String) -> Result<ConfigCommand, Error> {
+        if d == "config?" {
+            return Ok(ConfigCommand::Query);
+        }
+        let settings = d
+            .strip_prefix("config ")
+            .ok_or(Error::Parse("invalid config command"))?;
+
+        let numbers = |v: &str| -> Vec<String> {
+            v.split(',')
+                .filter(|n| !n.is_empty())
+                .map(String::from)
+                .collect()
+        };
+
+        let mut change = ConfigChange::default();
+        for setting in settings.split_whitespace() {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or(Error::Parse("invalid config setting"))?;
+            let duplicate = match key {
+                "owner" => change.owners.replace(numbers(value)).is_some(),
+                "viewer" => change.viewers.replace(numbers(value)).is_some(),
+                "emergency" => change.emergency.replace(numbers(value)).is_some(),
+                "password" => change.password.replace(String::from(value)).is_some(),
+                "apn" => change.apn.replace(String::from(value)).is_some(),
+                "clbs" => change.clbs_server.replace(String::from(value)).is_some(),
+                "mqtt" => change.mqtt_server.replace(String::from(value)).is_some(),
+                "country" => change.country_code.replace(String::from(value)).is_some(),
+                "trunk" => change.trunk_prefix.replace(String::from(value)).is_some(),
+                "park_radius" => change
+                    .park_radius_meters
+                    .replace(
+                        value
+                            .parse()
+                            .map_err(|_| Error::Parse("could not parse park_radius"))?,
+                    )
+                    .is_some(),
+                _ if key.starts_with("zone.") => {
+                    let name = String::from(&key["zone.".len()..]);
+                    let duplicate = change.zones.iter().any(|(n, _)| *n == name);
+                    change.zones.push((name, String::from(value)));
+                    duplicate
+                }
+                _ => return Err(Error::Parse("unknown config key")),
+            };
+            if duplicate {
+                return Err(Error::Parse("duplicate config key"));
+            }
+        }
+
+        if change == ConfigChange::default() {
+            return Err(Error::Parse("empty config command"));
+        }
+        Ok(ConfigCommand::Change(change))
+    }
+}
+
+//   track        the latest TRACK_POINTS fixes
+//   track 3      the latest 3 fixes
+//   trip         the summary of the latest trip
+
+pub const TRACK_POINTS: usize = 5;
+pub const MAX_TRACK_POINTS: usize = 7; // fits in concat::MAX_UNITS
+
+#[derive(Debug, PartialEq)]
+pub enum TrackCommand {
+    Last(usize),
+    Trip,
+}
+
+pub struct TrackHuman {}
+
+impl TrackHuman {
+    // 12-03T14:25, the year and the seconds are left out to fit more in the reply
+    fn format_short(timestamp: i64) -> String {
+        let full = format_unix_timestamp_ms(timestamp / 1000 * 1000);
+        match full.get(5..16) {
+            Some(short) if full.len() > 16 => String::from(short),
+            _ => full,
+        }
+    }
+
+    pub fn dump_points(&self, points: &[crate::tracklog::Point]) -> String {
+        if points.is_empty() {
+            return String::from("No track");
+        }
+        let mut ret = String::new();
+        for p in points.iter() {
+            ret.push_str(
+                format!(
+                    "{} {:.5},{:.5}",
+                    Self::format_short(p.unix_timestamp_millis),
+                    p.latitude,
+                    p.longitude
+                )
+                .as_str(),
+            );
+            if let Some(speed) = p.speed {
+                ret.push_str(format!(" {:.0}km/h", speed).as_str());
+            }
+            ret.push('\n');
+        }
+        ret
+    }
+
+    pub fn dump_trip(&self, o: Option<&crate::tracklog::Summary>) -> String {
+        let Some(o) = o else {
+            return String::from("No trip");
+        };
+        let mut ret = format!(
+            "Trip{} {:.2} km, ",
+            if o.ongoing { " ongoing" } else { "" },
+            o.distance_meters / 1000.0
+        );
+        if let Some(speed) = o.max_speed {
+            ret.push_str(format!("max {:.0} km/h, ", speed).as_str());
+        }
+        ret.push_str(format!("{} points\n\n", o.points).as_str());
+        for (label, p) in [
+            ("from", &o.start),
+            (if o.ongoing { "at" } else { "to" }, &o.end),
+        ] {
+            ret.push_str(
+                format!(
+                    "{} {} https://maps.google.com/?q={},{}\n\n",
+                    label,
+                    Self::format_short(p.unix_timestamp_millis),
+                    p.latitude,
+                    p.longitude
+                )
+                .as_str(),
+            );
+        }
+        ret
+    }
+
+    pub fn parse(&self, d: String) -> Result<TrackCommand, Error> {
+        if d == "trip" {
+            return Ok(TrackCommand::Trip);
+        }
+        if d == "track" {
+            return Ok(TrackCommand::Last(TRACK_POINTS));
+        }
+        let n: usize = d
+            .strip_prefix("track ")
+            .ok_or(Error::Parse("invalid track command"))?
+            .parse()
+            .map_err(|_| Error::Parse("could not parse track points"))?;
+        if !(1..=MAX_TRACK_POINTS).contains(&n) {
+            return Err(Error::Parse("track points out of range"));
+        }
+        Ok(TrackCommand::Last(n))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -539,6 +788,7 @@ mod tests {
         accuracy: 250.25f32,
         battery: 0.8912f32,
         timestamp: 1670077542109i64,
+        source: None,
     };
     const PARK_LOC: ParkLocation = ParkLocation {
         position: POS2,
@@ -549,7 +799,7 @@ mod tests {
         ($($name:ident: $value:expr,)*) => {
         $(
             #[test]
-            fn $name() -> Result<(), &'static str> {
+            fn $name() -> Result<(), Error> {
                 let (expected_human, expected_machine, protector) = $value;
 
                 let ph = ProtectorHuman{};
@@ -571,45 +821,102 @@ mod tests {
         test_protector_0: (
             String::from(""),
             String::from("* * * *"),
-            Protector{car_location: None, park_location: None, status: None, service: None},
+            Protector{car_location: None, park_location: None, status: None, service: None, zone: None},
         ),
         test_protector_1: (
             String::from("https://maps.google.com/?q=46.7624859,18.6304591\n\n250.25 meters, 89.12 %, 2022-12-03T14:25:42.109Z\n\n"),
             String::from("5ytnrmgo 2dl4xyfs 44zq4w j3nk lb811qsd * * *"),
-            Protector{car_location: Some(CAR_LOC), park_location: None, status: None, service: None},
+            Protector{car_location: Some(CAR_LOC), park_location: None, status: None, service: None, zone: None},
         ),
         test_protector_2: (
             String::from("Last park location\n\nhttps://maps.google.com/?q=47.1258945,17.8372091\n\n500.50 meters\n\n"),
             String::from("* 60hrep60 29xy4y9k 89zg9s * *"),
-            Protector{car_location: None, park_location: Some(PARK_LOC), status: None, service: None},
+            Protector{car_location: None, park_location: Some(PARK_LOC), status: None, service: None, zone: None},
         ),
         test_protector_3: (
             String::from(""),
             String::from("* * 2 *"),
-            Protector{car_location: None, park_location: None, status: Some(Status::CarTheftDetected), service: None},
+            Protector{car_location: None, park_location: None, status: Some(Status::CarTheftDetected), service: None, zone: None},
         ),
         test_protector_4: (
             String::from("Service on\n\n"),
             String::from("* * * t"),
-            Protector{car_location: None, park_location: None, status: None, service: Some(Service{value: true})},
+            Protector{car_location: None, park_location: None, status: None, service: Some(Service{value: true}), zone: None},
         ),
         test_protector_5: (
             String::from("Last park location\n\nhttps://maps.google.com/?q=47.1258945,17.8372091\n\n500.50 meters\n\n"),
             String::from("* 60hrep60 29xy4y9k 89zg9s 1 *"),
-            Protector{car_location: None, park_location: Some(PARK_LOC), status: Some(Status::ParkingUpdated), service: None},
+            Protector{car_location: None, park_location: Some(PARK_LOC), status: Some(Status::ParkingUpdated), service: None, zone: None},
         ),
         test_protector_6: (
             String::from("https://maps.google.com/?q=46.7624859,18.6304591\n\n250.25 meters, 89.12 %, 2022-12-03T14:25:42.109Z\n\nService on\n\nPark distance 72519.74 meters\n\n"),
             String::from("5ytnrmgo 2dl4xyfs 44zq4w j3nk lb811qsd 60hrep60 29xy4y9k 89zg9s 0 t"),
-            Protector{car_location: Some(CAR_LOC), park_location: Some(PARK_LOC), status: Some(Status::ParkingDetected), service: Some(Service{value: true})},
+            Protector{car_location: Some(CAR_LOC), park_location: Some(PARK_LOC), status: Some(Status::ParkingDetected), service: Some(Service{value: true}), zone: None},
         ),
     }
 
+    #[test]
+    fn test_protector_human_source() {
+        let car_location = |source| CarLocation {
+            source: Some(source),
+            ..CAR_LOC
+        };
+        let protector = Protector {
+            car_location: Some(car_location(crate::location::Source::Gnss)),
+            ..Default::default()
+        };
+        assert_eq!(
+            "https://maps.google.com/?q=46.7624859,18.6304591\n\n250.25 meters (GPS), 89.12 %, 2022-12-03T14:25:42.109Z\n\n",
+            (ProtectorHuman {}).dump(&protector)
+        );
+        let protector = Protector {
+            car_location: Some(car_location(crate::location::Source::Cell)),
+            ..Default::default()
+        };
+        assert!(
+            (ProtectorHuman {})
+                .dump(&protector)
+                .contains("250.25 meters (cell tower), ")
+        );
+        // the Android application does not know about it
+        assert_eq!(
+            "5ytnrmgo 2dl4xyfs 44zq4w j3nk lb811qsd * * *",
+            (ProtectorMachine {}).dump(&protector)
+        );
+    }
+
+    #[test]
+    fn test_protector_human_zone() {
+        let protector = Protector {
+            car_location: Some(CAR_LOC),
+            status: Some(Status::ZoneExited),
+            zone: Some(atat::heapless::String::try_from("home").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "Left home\n\nhttps://maps.google.com/?q=46.7624859,18.6304591\n\n250.25 meters, 89.12 %, 2022-12-03T14:25:42.109Z\n\n",
+            (ProtectorHuman {}).dump(&protector)
+        );
+        let protector = Protector {
+            status: Some(Status::ZoneEntered),
+            ..protector
+        };
+        assert!(
+            (ProtectorHuman {})
+                .dump(&protector)
+                .starts_with("Entered home\n\n")
+        );
+        assert_eq!(
+            "5ytnrmgo 2dl4xyfs 44zq4w j3nk lb811qsd * 3 *",
+            (ProtectorMachine {}).dump(&protector)
+        );
+    }
+
     macro_rules! watcher_human_tests {
         ($($name:ident: $value:expr,)*) => {
         $(
             #[test]
-            fn $name() -> Result<(), &'static str> {
+            fn $name() -> Result<(), Error> {
                 let (expected_watcher, command) = $value;
 
                 let wh = WatcherHuman{};
@@ -688,7 +995,7 @@ mod tests {
         ($($name:ident: $value:expr,)*) => {
         $(
             #[test]
-            fn $name() -> Result<(), &'static str> {
+            fn $name() -> Result<(), Error> {
                 let (expected_machine, watcher) = $value;
 
                 let wm = WatcherMachine{};
@@ -790,7 +1097,7 @@ mod tests {
         ($($name:ident: $value:expr,)*) => {
         $(
             #[test]
-            fn $name() -> Result<(), &'static str> {
+            fn $name() -> Result<(), Error> {
                 let (expected_machine, text) = $value;
 
                 let watcher = Watcher {
@@ -880,4 +1187,193 @@ mod tests {
             "****",
         ),
     }
+    #[test]
+    fn test_config_human_parse() {
+        let ch = ConfigHuman {};
+        assert_eq!(Ok(ConfigCommand::Query), ch.parse(String::from("config?")));
+        assert_eq!(
+            Ok(ConfigCommand::Change(ConfigChange {
+                owners: Some(alloc::vec![
+                    String::from("+36301112222"),
+                    String::from("06201112222")
+                ]),
+                viewers: Some(alloc::vec![]),
+                emergency: None,
+                password: None,
+                apn: Some(String::from("internet")),
+                clbs_server: None,
+                mqtt_server: None,
+                country_code: None,
+                trunk_prefix: None,
+                park_radius_meters: Some(150),
+                zones: alloc::vec![],
+            })),
+            ch.parse(String::from(
+                "config apn=internet owner=+36301112222,06201112222 viewer=  park_radius=150"
+            ))
+        );
+        assert_eq!(
+            Ok(ConfigCommand::Change(ConfigChange {
+                password: Some(String::from("secret")),
+                clbs_server: Some(String::from("lbs-simcom.com:3001")),
+                mqtt_server: Some(String::from("203.0.113.5:1883")),
+                ..Default::default()
+            })),
+            ch.parse(String::from(
+                "config password=secret clbs=lbs-simcom.com:3001 mqtt=203.0.113.5:1883"
+            ))
+        );
+        assert_eq!(
+            Ok(ConfigCommand::Change(ConfigChange {
+                zones: alloc::vec![
+                    (
+                        String::from("home"),
+                        String::from("46.762486,18.630459,200")
+                    ),
+                    (String::from("office"), String::new()),
+                ],
+                ..Default::default()
+            })),
+            ch.parse(String::from(
+                "config zone.home=46.762486,18.630459,200 zone.office="
+            ))
+        );
+    }
+
+    #[test]
+    fn test_config_human_parse_errors() {
+        let ch = ConfigHuman {};
+        assert_eq!(
+            Err(Error::Parse("invalid config command")),
+            ch.parse(String::from("configure"))
+        );
+        assert_eq!(
+            Err(Error::Parse("empty config command")),
+            ch.parse(String::from("config "))
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid config setting")),
+            ch.parse(String::from("config apn"))
+        );
+        assert_eq!(
+            Err(Error::Parse("unknown config key")),
+            ch.parse(String::from("config pin=1234"))
+        );
+        assert_eq!(
+            Err(Error::Parse("duplicate config key")),
+            ch.parse(String::from("config apn=a apn=b"))
+        );
+        assert_eq!(
+            Err(Error::Parse("duplicate config key")),
+            ch.parse(String::from("config zone.home= zone.home=1,2,100"))
+        );
+        assert_eq!(
+            Err(Error::Parse("could not parse park_radius")),
+            ch.parse(String::from("config park_radius=-5"))
+        );
+    }
+
+    #[test]
+    fn test_config_human_dump() {
+        let mut config = crate::config::Config::default();
+        assert_eq!(
+            "owner=+36301234567 apn=online park_radius=100 clbs=lbs-simcom.com:3002 country=36 trunk=06",
+            (ConfigHuman {}).dump(&config)
+        );
+
+        config.contacts[0].role = crate::config::Role::Emergency;
+        assert_eq!(
+            "emergency=+36301234567 apn=online park_radius=100 clbs=lbs-simcom.com:3002 country=36 trunk=06",
+            (ConfigHuman {}).dump(&config)
+        );
+
+        let shape = crate::geofence::Shape::parse("46.762486,18.630459,200").unwrap();
+        let _ = config
+            .zones
+            .push(crate::geofence::Zone::new("home", shape).unwrap());
+        assert!((ConfigHuman {}).dump(&config).ends_with(
+            " clbs=lbs-simcom.com:3002 country=36 trunk=06 zone.home=46.762486,18.630459,200"
+        ));
+
+        config.mqtt_server = atat::heapless::String::try_from("203.0.113.5:1883").unwrap();
+        assert!((ConfigHuman {}).dump(&config).ends_with(
+            " clbs=lbs-simcom.com:3002 country=36 trunk=06 mqtt=203.0.113.5:1883 zone.home=46.762486,18.630459,200"
+        ));
+    }
+
+    #[test]
+    fn test_track_human_parse() {
+        let th = TrackHuman {};
+        assert_eq!(Ok(TrackCommand::Trip), th.parse(String::from("trip")));
+        assert_eq!(
+            Ok(TrackCommand::Last(TRACK_POINTS)),
+            th.parse(String::from("track"))
+        );
+        assert_eq!(Ok(TrackCommand::Last(3)), th.parse(String::from("track 3")));
+        assert_eq!(
+            Err(Error::Parse("track points out of range")),
+            th.parse(String::from("track 0"))
+        );
+        assert_eq!(
+            Err(Error::Parse("track points out of range")),
+            th.parse(String::from("track 8"))
+        );
+        assert_eq!(
+            Err(Error::Parse("could not parse track points")),
+            th.parse(String::from("track x"))
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid track command")),
+            th.parse(String::from("location"))
+        );
+    }
+
+    #[test]
+    fn test_track_human_dump() {
+        let point = crate::tracklog::Point {
+            unix_timestamp_millis: 1670077542109,
+            latitude: 46.7624859,
+            longitude: 18.6304591,
+            speed: Some(35.4),
+            course: Some(285.8),
+            accuracy: 5.8,
+            source: crate::location::Source::Gnss,
+            flags: crate::tracklog::TRIP_START | crate::tracklog::MOVING,
+        };
+        let end = crate::tracklog::Point {
+            unix_timestamp_millis: 1670078922000,
+            latitude: 47.1258945,
+            longitude: 17.8372091,
+            speed: None,
+            flags: crate::tracklog::TRIP_STOP,
+            ..point.clone()
+        };
+        let th = TrackHuman {};
+        assert_eq!("No track", th.dump_points(&[]));
+        assert_eq!(
+            "12-03T14:48 47.12589,17.83721\n12-03T14:25 46.76249,18.63046 35km/h\n",
+            th.dump_points(&[end.clone(), point.clone()])
+        );
+
+        assert_eq!("No trip", th.dump_trip(None));
+        let mut trip = crate::tracklog::Summary {
+            start: point,
+            end,
+            ongoing: false,
+            distance_meters: 75432.1,
+            max_speed: Some(87.5),
+            points: 24,
+        };
+        assert_eq!(
+            "Trip 75.43 km, max 88 km/h, 24 points\n\nfrom 12-03T14:25 https://maps.google.com/?q=46.7624859,18.6304591\n\nto 12-03T14:48 https://maps.google.com/?q=47.1258945,17.8372091\n\n",
+            th.dump_trip(Some(&trip))
+        );
+        trip.ongoing = true;
+        trip.max_speed = None;
+        assert!(
+            th.dump_trip(Some(&trip))
+                .starts_with("Trip ongoing 75.43 km, 24 points\n\nfrom ")
+        );
+        assert!(th.dump_trip(Some(&trip)).contains("\n\nat 12-03T14:48 "));
+    }
 }
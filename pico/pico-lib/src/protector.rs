@@ -0,0 +1,489 @@
+use alloc::vec::Vec;
+use defmt::Format;
+use defmt::info;
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::geofence;
+use crate::geofence::Geofence;
+use crate::location;
+use crate::location::Location;
+use crate::poro;
+use crate::utils;
+
+// tATA Protector
+//
+// Service on: the protector is running, location samples are evaluated.
+// Park on: the owner left the car, once the car stands still for `parking_time_millis`
+//          the park location is recorded (ParkingDetected). More accurate fixes at the
+//          same place refine it (ParkingUpdated). Leaving the park location (taking both
+//          fix accuracies into account) raises the alarm (CarTheftDetected).
+// Zones:   entering or leaving a zone of the geofence (ZoneEntered, ZoneExited) while the
+//          service is on, park on or off.
+
+#[derive(Debug, Format, Clone, PartialEq)]
+pub struct ProtectorConfig {
+    pub park_radius_meters: f64,
+    pub parking_time_millis: i64,
+    pub max_accuracy_meters: f64, // fixes worse than this are dropped (e.g. CLBS from a far tower)
+}
+
+impl Default for ProtectorConfig {
+    fn default() -> Self {
+        ProtectorConfig {
+            park_radius_meters: 100.0,
+            parking_time_millis: 3 * 60 * 1000,
+            max_accuracy_meters: 1000.0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Transition {
+    pub status: poro::Status,
+    pub message: poro::Protector, // to be sent to the watchers
+}
+
+#[derive(Debug, Default)]
+pub struct Guard {
+    pub config: ProtectorConfig,
+    pub geofence: Geofence,
+    park: bool,
+    service: bool,
+    battery: f32,
+    candidate: Option<Location>, // first fix of the current stationary period
+    best: Option<Location>,      // most accurate fix of the current stationary period
+    park_location: Option<Location>,
+    status: Option<poro::Status>,
+    last_location: Option<Location>,
+}
+
+impl Guard {
+    pub fn new(config: ProtectorConfig) -> Self {
+        Guard {
+            config,
+            service: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn park(&self) -> bool {
+        self.park
+    }
+
+    pub fn service(&self) -> bool {
+        self.service
+    }
+
+    pub fn status(&self) -> Option<&poro::Status> {
+        self.status.as_ref()
+    }
+
+    pub fn park_location(&self) -> Option<&Location> {
+        self.park_location.as_ref()
+    }
+
+    pub fn last_location(&self) -> Option<&Location> {
+        self.last_location.as_ref()
+    }
+
+    // A repeated park on keeps the anchor, it must not move to where the car is now.
+    pub fn set_park(&mut self, on: bool) {
+        if self.park == on {
+            return;
+        }
+        self.park = on;
+        self.reset();
+    }
+
+    pub fn set_service(&mut self, on: bool) {
+        self.service = on;
+        if !on {
+            self.reset();
+        }
+    }
+
+    // Charge level in [0, 1], reported in CarLocation.battery.
+    pub fn set_battery(&mut self, battery: f32) {
+        self.battery = battery;
+    }
+
+    pub fn update(&mut self, location: &Location) -> Option<Transition> {
+        if location.accuracy > self.config.max_accuracy_meters {
+            info!("Protector: fix dropped, accuracy {}", location.accuracy);
+            return None;
+        }
+        self.last_location = Some(location.clone());
+
+        if !self.service || !self.park {
+            return None;
+        }
+
+        match self.park_location.clone() {
+            Some(park) => self.update_parked(&park, location),
+            None => self.update_moving(location),
+        }
+    }
+
+    // Transitions of the zones, after update (the same accuracy limit applies).
+    pub fn update_zones(&mut self, location: &Location) -> Vec<Transition> {
+        if !self.service || location.accuracy > self.config.max_accuracy_meters {
+            return Vec::new();
+        }
+        self.geofence
+            .update(location)
+            .into_iter()
+            .map(|e| {
+                let status = match e.crossing {
+                    geofence::Crossing::Enter => poro::Status::ZoneEntered,
+                    geofence::Crossing::Exit => poro::Status::ZoneExited,
+                };
+                let mut message = self.protector(Some(location));
+                message.status = Some(status.clone());
+                message.zone = Some(e.zone);
+                Transition { status, message }
+            })
+            .collect()
+    }
+
+    // Message describing the current state, e.g. a reply for a location request.
+    pub fn protector(&self, location: Option<&Location>) -> poro::Protector {
+        poro::Protector {
+            car_location: location.map(|l| car_location(l, self.battery)),
+            park_location: self.park_location.as_ref().map(park_location),
+            status: None,
+            service: None,
+            zone: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.candidate = None;
+        self.best = None;
+        self.park_location = None;
+        self.status = None;
+    }
+
+    fn update_moving(&mut self, location: &Location) -> Option<Transition> {
+        let candidate = match self.candidate.as_ref() {
+            Some(c) if same_place(c, location, 0.0) => c.clone(),
+            _ => {
+                self.candidate = Some(location.clone());
+                self.best = Some(location.clone());
+                return None;
+            }
+        };
+
+        if self
+            .best
+            .as_ref()
+            .is_none_or(|b| location.accuracy < b.accuracy)
+        {
+            self.best = Some(location.clone());
+        }
+
+        if location.unix_timestamp_millis - candidate.unix_timestamp_millis
+            < self.config.parking_time_millis
+        {
+            return None;
+        }
+
+        self.park_location = self.best.take();
+        self.candidate = None;
+        Some(self.transition(poro::Status::ParkingDetected, location))
+    }
+
+    fn update_parked(&mut self, park: &Location, location: &Location) -> Option<Transition> {
+        if same_place(park, location, self.config.park_radius_meters) {
+            if self.status == Some(poro::Status::CarTheftDetected) {
+                return None; // stays in alarm until park is turned off
+            }
+            if location.accuracy < park.accuracy {
+                self.park_location = Some(location.clone());
+                return Some(self.transition(poro::Status::ParkingUpdated, location));
+            }
+            return None;
+        }
+
+        if self.status == Some(poro::Status::CarTheftDetected) {
+            return None;
+        }
+        Some(self.transition(poro::Status::CarTheftDetected, location))
+    }
+
+    fn transition(&mut self, status: poro::Status, location: &Location) -> Transition {
+        info!("Protector: {:?}", status);
+        self.status = Some(status.clone());
+        let mut message = self.protector(Some(location));
+        message.status = Some(status.clone());
+        Transition { status, message }
+    }
+}
+
+// Two fixes are considered to be at the same place when their distance can be explained
+// by their accuracies (plus the allowed radius).
+fn same_place(a: &Location, b: &Location, radius: f64) -> bool {
+    let distance = utils::get_distance_in_meters(a.latitude, a.longitude, b.latitude, b.longitude);
+    distance <= radius + a.accuracy + b.accuracy
+}
+
+fn car_location(location: &Location, battery: f32) -> poro::CarLocation {
+    poro::CarLocation {
+        position: poro::Position {
+            latitude: location.latitude,
+            longitude: location.longitude,
+        },
+        accuracy: location.accuracy as f32,
+        battery,
+        timestamp: location.unix_timestamp_millis,
+        source: Some(location.source),
+    }
+}
+
+fn park_location(location: &Location) -> poro::ParkLocation {
+    poro::ParkLocation {
+        position: poro::Position {
+            latitude: location.latitude,
+            longitude: location.longitude,
+        },
+        accuracy: location.accuracy as f32,
+    }
+}
+
+pub async fn check<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    pico: &mut U,
+    guard: &mut Guard,
+    strategy: &mut location::Strategy,
+    config: &Config,
+) -> Result<Vec<Transition>, Error> {
+    let loc = strategy
+        .locate(client, pico, config, &location::Request::fresh())
+        .await?;
+    let mut transitions: Vec<Transition> = guard.update(&loc).into_iter().collect();
+    transitions.extend(guard.update_zones(&loc));
+    Ok(transitions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: i64 = 60 * 1000;
+
+    fn loc(latitude: f64, longitude: f64, accuracy: f64, minute: i64) -> Location {
+        Location {
+            latitude,
+            longitude,
+            accuracy,
+            unix_timestamp_millis: 1670846541000 + minute * MINUTE,
+            source: location::Source::Gnss,
+            speed: None,
+            course: None,
+        }
+    }
+
+    fn parked_guard() -> Guard {
+        let mut guard = Guard::new(ProtectorConfig::default());
+        guard.set_park(true);
+        assert_eq!(None, guard.update(&loc(46.7624859, 18.6304591, 10.0, 0)));
+        assert_eq!(None, guard.update(&loc(46.7624860, 18.6304590, 5.0, 1)));
+        let t = guard.update(&loc(46.7624861, 18.6304592, 8.0, 3)).unwrap();
+        assert_eq!(poro::Status::ParkingDetected, t.status);
+        guard
+    }
+
+    #[test]
+    fn test_park_off_is_silent() {
+        let mut guard = Guard::new(ProtectorConfig::default());
+        assert!(guard.service());
+        assert!(!guard.park());
+        assert_eq!(None, guard.update(&loc(46.7624859, 18.6304591, 10.0, 0)));
+        assert_eq!(None, guard.update(&loc(47.1258945, 17.8372091, 10.0, 10)));
+        assert_eq!(None, guard.status());
+        assert_eq!(
+            Some(&loc(47.1258945, 17.8372091, 10.0, 10)),
+            guard.last_location()
+        );
+    }
+
+    #[test]
+    fn test_parking_detected() {
+        let mut guard = Guard::new(ProtectorConfig::default());
+        guard.set_battery(0.5);
+        guard.set_park(true);
+        assert_eq!(None, guard.update(&loc(46.7624859, 18.6304591, 10.0, 0)));
+        assert_eq!(None, guard.update(&loc(46.7624860, 18.6304590, 5.0, 1)));
+        let t = guard.update(&loc(46.7624861, 18.6304592, 8.0, 3)).unwrap();
+        assert_eq!(
+            Transition {
+                status: poro::Status::ParkingDetected,
+                message: poro::Protector {
+                    car_location: Some(poro::CarLocation {
+                        position: poro::Position {
+                            latitude: 46.7624861,
+                            longitude: 18.6304592,
+                        },
+                        accuracy: 8.0,
+                        battery: 0.5,
+                        timestamp: 1670846721000,
+                        source: Some(location::Source::Gnss),
+                    }),
+                    park_location: Some(poro::ParkLocation {
+                        position: poro::Position {
+                            latitude: 46.7624860,
+                            longitude: 18.6304590,
+                        },
+                        accuracy: 5.0,
+                    }),
+                    status: Some(poro::Status::ParkingDetected),
+                    service: None,
+                    zone: None,
+                },
+            },
+            t
+        );
+        assert_eq!(Some(&poro::Status::ParkingDetected), guard.status());
+    }
+
+    #[test]
+    fn test_moving_car_is_not_parked() {
+        let mut guard = Guard::new(ProtectorConfig::default());
+        guard.set_park(true);
+        for i in 0..10 {
+            let latitude = 46.7624859 + 0.01 * i as f64;
+            assert_eq!(None, guard.update(&loc(latitude, 18.6304591, 10.0, i)));
+        }
+        assert_eq!(None, guard.park_location());
+    }
+
+    #[test]
+    fn test_parking_updated() {
+        let mut guard = parked_guard();
+        assert_eq!(None, guard.update(&loc(46.7624859, 18.6304591, 20.0, 4)));
+        let t = guard.update(&loc(46.7624859, 18.6304591, 2.5, 5)).unwrap();
+        assert_eq!(poro::Status::ParkingUpdated, t.status);
+        assert_eq!(2.5, guard.park_location().unwrap().accuracy);
+    }
+
+    #[test]
+    fn test_car_theft_detected() {
+        let mut guard = parked_guard();
+        // ~110 meters, explained by the CLBS accuracy
+        assert_eq!(None, guard.update(&loc(46.7634859, 18.6304591, 550.0, 4)));
+        let t = guard.update(&loc(46.7724859, 18.6304591, 10.0, 5)).unwrap();
+        assert_eq!(poro::Status::CarTheftDetected, t.status);
+        assert_eq!(
+            Some(poro::Status::CarTheftDetected),
+            t.message.status.clone()
+        );
+        assert!(t.message.park_location.is_some());
+
+        // reported only once
+        assert_eq!(None, guard.update(&loc(46.7824859, 18.6304591, 10.0, 6)));
+
+        guard.set_park(false);
+        assert_eq!(None, guard.status());
+        assert_eq!(None, guard.park_location());
+    }
+
+    #[test]
+    fn test_park_on_again_keeps_anchor() {
+        let mut guard = parked_guard();
+        let anchor = guard.park_location().cloned();
+        guard.set_park(true);
+        assert_eq!(anchor.as_ref(), guard.park_location());
+        assert_eq!(Some(&poro::Status::ParkingDetected), guard.status());
+        let t = guard.update(&loc(46.7724859, 18.6304591, 10.0, 5)).unwrap();
+        assert_eq!(poro::Status::CarTheftDetected, t.status);
+    }
+
+    #[test]
+    fn test_inaccurate_fix_dropped() {
+        let mut guard = parked_guard();
+        assert_eq!(None, guard.update(&loc(47.1258945, 17.8372091, 5000.0, 4)));
+        assert_eq!(Some(&poro::Status::ParkingDetected), guard.status());
+    }
+
+    #[test]
+    fn test_service_off() {
+        let mut guard = parked_guard();
+        guard.set_service(false);
+        assert_eq!(None, guard.park_location());
+        assert_eq!(None, guard.update(&loc(47.1258945, 17.8372091, 10.0, 4)));
+        assert_eq!(None, guard.status());
+    }
+
+    #[test]
+    fn test_zones() {
+        let mut guard = Guard::new(ProtectorConfig::default());
+        let shape = geofence::Shape::parse("46.7624859,18.6304591,200").unwrap();
+        guard
+            .geofence
+            .set_zones(&[geofence::Zone::new("home", shape).unwrap()]);
+        assert!(
+            guard
+                .update_zones(&loc(46.7624859, 18.6304591, 10.0, 0))
+                .is_empty()
+        );
+        // the CLBS fix is dropped
+        assert!(
+            guard
+                .update_zones(&loc(46.7724859, 18.6304591, 5000.0, 1))
+                .is_empty()
+        );
+
+        let t = guard.update_zones(&loc(46.7724859, 18.6304591, 10.0, 2));
+        assert_eq!(1, t.len());
+        assert_eq!(poro::Status::ZoneExited, t[0].status);
+        assert_eq!(Some(poro::Status::ZoneExited), t[0].message.status);
+        assert_eq!("home", t[0].message.zone.as_ref().unwrap().as_str());
+        assert!(t[0].message.car_location.is_some());
+        // not a protector state
+        assert_eq!(None, guard.status());
+
+        guard.set_service(false);
+        assert!(
+            guard
+                .update_zones(&loc(46.7624859, 18.6304591, 10.0, 3))
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let mut client = crate::at::tests::ClientMock::default();
+        client.results.push_back(Ok("".as_bytes())); // Turn On
+        client.results.push_back(Ok("+CGNSINF: 1,1,20221212120221.123,46.7624859,18.6304591,329.218,2.20,285.8,1,,2.1,2.3,0.9,,7,6,,,51,,".as_bytes())); // location
+        client.results.push_back(Ok("".as_bytes())); // Turn off
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        let mut guard = Guard::new(ProtectorConfig::default());
+        guard.set_park(true);
+        assert_eq!(
+            Ok(alloc::vec![]),
+            check(
+                &mut client,
+                &mut pico,
+                &mut guard,
+                &mut location::Strategy::default(),
+                &Config::default()
+            )
+            .await
+        );
+        assert_eq!(3, client.sent_commands.len());
+        assert_eq!(
+            Some(&Location {
+                latitude: 46.7624859,
+                longitude: 18.6304591,
+                accuracy: 5.75,
+                unix_timestamp_millis: 1670846541123,
+                source: location::Source::Gnss,
+                speed: Some(2.2),
+                course: Some(285.8),
+            }),
+            guard.last_location()
+        );
+        assert_eq!(1, pico.sleep_calls.len());
+    }
+}
@@ -6,13 +6,42 @@ use atat::atat_derive::AtatCmd;
 use atat::atat_derive::AtatEnum;
 use atat::atat_derive::AtatResp;
 use atat::heapless::String;
+use atat::heapless::Vec;
 use defmt::info;
 
 use crate::at::NoResponse;
+use crate::concat;
+use crate::error::Error;
+use crate::hexstr::Charset;
 use crate::hexstr::UCS2HexString;
+use crate::hexstr::decode_hex_u8;
+use crate::hexstr::encode_utf8_hex_string;
 use crate::hexstr::encode_utf16_hex_string;
+use crate::pdu;
+use crate::utils::as_tokens;
 use crate::utils::send_command_logged;
 
+// 4.2.1 AT+CMGD Delete SMS Message
+// AT+CMGD=<index>[,<delflag>]
+// Deleting many messages takes a while, 25 seconds at most according to the manual.
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CMGD", NoResponse, timeout_ms = 25000)]
+pub struct AtDeleteSMSMessageWrite {
+    pub index: u32,
+    pub delflag: Option<DeleteFlag>,
+}
+
+// <index> is ignored for everything but Index
+#[derive(Debug, Default, Format, Clone, PartialEq, AtatEnum)]
+pub enum DeleteFlag {
+    #[default]
+    Index = 0,
+    Read = 1,
+    ReadAndSent = 2,
+    ReadSentAndUnsent = 3,
+    All = 4,
+}
+
 // 4.2.2 AT+CMGF Select SMS Message Format
 // AT+CMGF=[<mode>]
 #[derive(Clone, Debug, Format, AtatCmd)]
@@ -60,17 +89,23 @@ impl<'a> AtatCmd for AtSMSData {
         &self,
         resp: Result<&[u8], atat::InternalError>,
     ) -> Result<Self::Response, atat::Error> {
-        match resp {
-            Ok(v) => {
-                let s = core::str::from_utf8(&v["+CMGS: ".len()..])
-                    .map_err(|_o| -> atat::Error { atat::Error::Parse })?;
-                let mr: i32 = s
-                    .parse()
-                    .map_err(|_o| -> atat::Error { atat::Error::Parse })?;
-                return Ok(SMSDataResponse { mr: mr });
-            }
-            Err(_) => Err(atat::Error::Parse),
+        parse_sms_data_response(resp)
+    }
+}
+
+fn parse_sms_data_response(
+    resp: Result<&[u8], atat::InternalError>,
+) -> Result<SMSDataResponse, atat::Error> {
+    match resp {
+        Ok(v) => {
+            let s = core::str::from_utf8(v.get("+CMGS: ".len()..).ok_or(atat::Error::Parse)?)
+                .map_err(|_o| -> atat::Error { atat::Error::Parse })?;
+            let mr: i32 = s
+                .parse()
+                .map_err(|_o| -> atat::Error { atat::Error::Parse })?;
+            Ok(SMSDataResponse { mr })
         }
+        Err(e) => Err(e.into()),
     }
 }
 
@@ -80,9 +115,76 @@ pub struct SMSDataResponse {
     mr: i32, // GSM 03.40 TP-Message-Reference in integer format
 }
 
+// PDU mode
+// AT+CMGS=<length><CR>PDU is given[ctrl-Z/ESC]
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CMGS", NoResponse, timeout_ms = 5000)] // NoResponse == waiting for prompt ">"
+pub struct AtSMSSendPdu {
+    pub length: usize, // TPDU length in octets, the SMSC part is not counted
+}
+
+#[derive(Clone, Debug)]
+pub struct AtSMSPduData {
+    pub pdu: Vec<u8, { pdu::MAX_PDU }>,
+}
+
+impl AtatCmd for AtSMSPduData {
+    type Response = SMSDataResponse;
+
+    const MAX_LEN: usize = 2 * pdu::MAX_PDU + 1;
+    const MAX_TIMEOUT_MS: u32 = 60000;
+
+    fn write(&self, buf: &mut [u8]) -> usize {
+        let v: String<{ 2 * pdu::MAX_PDU }> = encode_utf8_hex_string(&self.pdu).unwrap();
+        let bytes = v.as_bytes();
+        let len = bytes.len();
+        let ctrl_z = b"\x1a";
+        buf[..len].copy_from_slice(bytes);
+        buf[len..len + ctrl_z.len()].copy_from_slice(ctrl_z);
+        len + ctrl_z.len()
+    }
+
+    fn parse(
+        &self,
+        resp: Result<&[u8], atat::InternalError>,
+    ) -> Result<Self::Response, atat::Error> {
+        parse_sms_data_response(resp)
+    }
+}
+
 // 4.2.3 AT+CMGL List SMS Messages from Preferred Store
 // AT+CMGL=<stat>[,<mode>]
-// Not Implemented, Read SMS one by one will be enough for this project
+// PDU mode, <stat> is SmsStat::as_pdu_stat. Only the indexes are kept, the messages are read
+// one by one with AT+CMGR. A listing longer than the ingress buffer is cut (the ingress drops
+// what it buffered), it fails or comes up short, see dispatcher::process_stored_messages.
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CMGL", SMSListResponse, timeout_ms = 20000, parse = parse_sms_list)]
+pub struct AtListSMSMessagesPduWrite {
+    pub stat: u8,
+    pub mode: Option<ReadSMSMode>,
+}
+
+pub const MAX_LIST: usize = 50; // the SIM868 stores 50 messages at most
+
+// +CMGL: <index>,<stat>,[<alpha>],<length><CR><LF><pdu>[<CR><LF>+CMGL: ...]
+#[derive(Debug, Clone, AtatResp, PartialEq, Default)]
+pub struct SMSListResponse {
+    pub indexes: Vec<u32, MAX_LIST>,
+}
+
+fn parse_sms_list(response: &[u8]) -> Result<SMSListResponse, Error> {
+    let text = core::str::from_utf8(response)?;
+    let mut indexes = Vec::new();
+    for line in text.lines() {
+        if let Some(header) = line.strip_prefix("+CMGL: ") {
+            let (index, _) = header.split_once(',').ok_or(())?;
+            indexes
+                .push(index.parse()?)
+                .map_err(|_| Error::Capacity("SMS list"))?;
+        }
+    }
+    Ok(SMSListResponse { indexes })
+}
 
 // 4.2.4 AT+CMGR Read SMS Message
 // AT+CMGR=<index>[,<mode>]
@@ -112,12 +214,48 @@ pub struct SMSMessageResponse {
     message: UCS2HexString<1024>,
 }
 
+// PDU mode
+// +CMGR: <stat>,[<alpha>],<length><CR><LF><pdu>
+// An empty slot is a bare OK (the response has no pdu) or +CMS ERROR: 321, see read_pdu.
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CMGR", SMSMessagePduResponse, timeout_ms = 5000, parse = parse_sms_message_pdu)]
+pub struct AtReadSMSMessagesPduWrite {
+    pub index: u32,
+    pub mode: Option<ReadSMSMode>,
+}
+
+#[derive(Debug, Clone, AtatResp, PartialEq, Default)]
+pub struct SMSMessagePduResponse {
+    stat: u8,
+    length: usize,
+    pdu: String<{ 2 * pdu::MAX_PDU }>,
+}
+
+fn parse_sms_message_pdu(response: &[u8]) -> Result<SMSMessagePduResponse, Error> {
+    if response.is_empty() {
+        return Ok(SMSMessagePduResponse::default());
+    }
+    let text = core::str::from_utf8(response)?;
+    let text = text.strip_prefix("+CMGR: ").ok_or(())?;
+    let (header, pdu) = text.split_once("\r\n").ok_or(())?;
+    let mut tokens = as_tokens(header.to_string(), ",");
+    if tokens.len() != 3 {
+        return Err(atat::Error::Parse.into());
+    }
+    Ok(SMSMessagePduResponse {
+        stat: tokens.pop_front().unwrap().parse()?,
+        length: tokens.pop_back().unwrap().parse()?,
+        pdu: String::try_from(pdu.trim_end())?,
+    })
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Sms {
     pub stat: SmsStat,
     pub phone_number: String<64>,
     pub unix_timestamp_millis: i64,
     pub message: String<1024>,
+    pub concat: Option<pdu::Concat>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -130,25 +268,35 @@ pub enum SmsStat {
 }
 
 impl SmsStat {
-    #[allow(dead_code)]
-    fn as_str(&self) -> &'static str {
+    fn as_pdu_stat(&self) -> u8 {
         match self {
-            SmsStat::ReceivedUnread => "REC UNREAD",
-            SmsStat::ReceivedRead => "REC READ",
-            SmsStat::StoredUnsent => "STO UNSENT",
-            SmsStat::StoredSent => "STO SENT",
-            SmsStat::All => "ALL",
+            SmsStat::ReceivedUnread => 0,
+            SmsStat::ReceivedRead => 1,
+            SmsStat::StoredUnsent => 2,
+            SmsStat::StoredSent => 3,
+            SmsStat::All => 4,
         }
     }
 
-    fn from_str(input: &str) -> Result<SmsStat, &'static str> {
+    fn from_pdu_stat(input: u8) -> Result<SmsStat, Error> {
+        match input {
+            0 => Ok(SmsStat::ReceivedUnread),
+            1 => Ok(SmsStat::ReceivedRead),
+            2 => Ok(SmsStat::StoredUnsent),
+            3 => Ok(SmsStat::StoredSent),
+            4 => Ok(SmsStat::All),
+            _ => Err(Error::Parse("invalid SMS stat")),
+        }
+    }
+
+    fn from_str(input: &str) -> Result<SmsStat, Error> {
         match input {
             "REC UNREAD" => Ok(SmsStat::ReceivedUnread),
             "REC READ" => Ok(SmsStat::ReceivedRead),
             "STO UNSENT" => Ok(SmsStat::StoredUnsent),
             "STO SENT" => Ok(SmsStat::StoredSent),
             "ALL" => Ok(SmsStat::All),
-            _ => Err("invalid SMS stat"),
+            _ => Err(Error::Parse("invalid SMS stat")),
         }
     }
 }
@@ -204,6 +352,45 @@ pub struct NewMessageIndicationUrc {
     pub index: i32,
 }
 
+// 4.2.9 AT+CPMS Preferred SMS Message Storage
+// AT+CPMS=<mem1>[,<mem2>[,<mem3>]]
+// mem1 is read and deleted, mem2 written and sent, new messages are stored in mem3.
+#[derive(Clone, Debug, Format, AtatCmd)]
+#[at_cmd("+CPMS", PreferredSMSStorageResponse)]
+pub struct AtPreferredSMSStorageWrite {
+    pub mem1: String<2>,
+    pub mem2: Option<String<2>>,
+    pub mem3: Option<String<2>>,
+}
+
+// +CPMS: <used1>,<total1>,<used2>,<total2>,<used3>,<total3>
+#[derive(Debug, Clone, AtatResp, PartialEq, Default)]
+pub struct PreferredSMSStorageResponse {
+    pub used1: u32,
+    pub total1: u32,
+    pub used2: u32,
+    pub total2: u32,
+    pub used3: u32,
+    pub total3: u32,
+}
+
+// +CMS ERROR: 321, reading or deleting an empty slot of the storage.
+pub const INVALID_INDEX: u16 = 321;
+
+// Usage of the storage the new messages go to.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Format)]
+pub struct SmsStorage {
+    pub used: u32,
+    pub total: u32,
+}
+
+impl SmsStorage {
+    // New messages are not stored (the SMSC keeps them) until some are deleted.
+    pub fn is_full(&self) -> bool {
+        self.used >= self.total
+    }
+}
+
 // 3.2.12 AT+CSCS Select TE Character Set
 // AT+CSCS=<chset>
 // The character set affects transmission and reception of SMS and SMS Cell Broadcast messages,
@@ -214,13 +401,11 @@ pub struct AtSelectTECharsetWrite {
     pub chset: String<30>, // "GSM" 7-bit, "UCS2", "IRA", "HEX", "PCCP", "PCDN", "8859-1"
 }
 
-pub async fn init<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+// UCS2 + Text mode, see init for PDU mode.
+pub async fn init_text<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
     client: &mut T,
     _pico: &mut U,
-) {
-    // PDU mode might make more sense, currently UCS2 + Text mod is assumed.
-    // http://rfc.nop.hu/sms/default.htm
-    // https://en.wikipedia.org/wiki/GSM_03.40
+) -> Result<(), Error> {
     send_command_logged(
         client,
         &AtSelectSMSMessageFormatWrite {
@@ -228,8 +413,7 @@ pub async fn init<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
         },
         "AtSelectSMSMessageFormatWrite".to_string(),
     )
-    .await
-    .ok();
+    .await?;
 
     send_command_logged(
         client,
@@ -238,9 +422,30 @@ pub async fn init<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
         },
         "AtSelectTECharsetWrite".to_string(),
     )
-    .await
-    .ok();
+    .await?;
+
+    init_indications(client).await
+}
+
+// PDU mode does not depend on the TE character set (+CSCS), see the pdu module.
+pub async fn init<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    pico: &mut U,
+) -> Result<(), Error> {
+    send_command_logged(
+        client,
+        &AtSelectSMSMessageFormatWrite {
+            mode: MessageMode::PDU,
+        },
+        "AtSelectSMSMessageFormatWrite".to_string(),
+    )
+    .await?;
+    select_storage(client, pico).await?;
 
+    init_indications(client).await
+}
+
+async fn init_indications<T: atat::asynch::AtatClient>(client: &mut T) -> Result<(), Error> {
     send_command_logged(
         client,
         &AtNewSMSMessageIndicationsWrite {
@@ -252,16 +457,17 @@ pub async fn init<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
         },
         "AtNewSMSMessageIndicationsWrite".to_string(),
     )
-    .await
-    .ok();
+    .await?;
+    Ok(())
 }
 
-pub async fn send_sms<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+// Text mode, UCS2 only, a single SMS (70 characters).
+pub async fn send_sms_text<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
     client: &mut T,
     _pico: &mut U,
     number: &String<30>,
     message: &String<160>,
-) {
+) -> Result<(), Error> {
     send_command_logged(
         client,
         &AtSMSSend {
@@ -272,8 +478,7 @@ pub async fn send_sms<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
         },
         "AtSMSSend".to_string(),
     )
-    .await
-    .ok();
+    .await?;
     send_command_logged(
         client,
         &AtSMSData {
@@ -284,141 +489,330 @@ pub async fn send_sms<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
         },
         "AtSMSData".to_string(),
     )
-    .await
-    .ok();
+    .await?;
+    Ok(())
 }
 
-// TODO, this is just a temporary helper function.
-pub async fn receive_sms<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+// PDU mode, long messages are sent as concatenated SMS with the given reference, see
+// concat::Reassembler::next_reference. 7-bit is used when every character is in the GSM
+// alphabet, UCS2 otherwise.
+pub async fn send_sms<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
     client: &mut T,
     pico: &mut U,
-) {
-    for i in 1..100 {
-        pico.sleep(100).await;
-        match send_command_logged(
-            client,
-            &AtReadSMSMessagesWrite {
-                index: i,
-                mode: None,
-            },
-            "AtReadSMSMessagesWrite".to_string(),
-        )
-        .await
-        {
-            Ok(v) => {
-                info!(
-                    "SMS RESP state={} date={} sender={} message={}",
-                    v.stat, v.date_time, v.sn, v.message
-                );
-            }
-            Err(_) => break,
+    number: &str,
+    message: &str,
+    reference: u8,
+) -> Result<(), Error> {
+    let charset = Charset::select(message);
+    let dcs = match charset {
+        Charset::Gsm7 => pdu::Dcs::Gsm7,
+        Charset::Ucs2 => pdu::Dcs::Ucs2,
+    };
+    let parts = concat::split(message, charset)?;
+    for (i, part) in parts.iter().enumerate() {
+        let header = pdu::Concat {
+            reference: reference as u16,
+            count: parts.len() as u8,
+            index: i as u8 + 1,
         }
+        .header();
+        let submit = pdu::Submit {
+            destination: number,
+            dcs,
+            header: if parts.len() > 1 { &header } else { &[] },
+            user_data: part.as_bytes(),
+            status_report: false,
+        };
+        send_sms_pdu(client, pico, &submit).await?;
     }
+    Ok(())
 }
 
-pub async fn read_sms<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+// Returns the TP-Message-Reference, the status report (if requested) refers to it.
+pub async fn send_sms_pdu<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    _pico: &mut U,
+    submit: &pdu::Submit<'_>,
+) -> Result<u8, Error> {
+    let pdu = submit.encode()?;
+    send_command_logged(
+        client,
+        &AtSMSSendPdu {
+            length: pdu::tpdu_length(&pdu),
+        },
+        "AtSMSSendPdu".to_string(),
+    )
+    .await?;
+    let v = send_command_logged(client, &AtSMSPduData { pdu }, "AtSMSPduData".to_string()).await?;
+    Ok(v.mr as u8)
+}
+
+// Selects the SIM for everything (the module memory is not used) and returns its usage.
+pub async fn select_storage<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    _pico: &mut U,
+) -> Result<SmsStorage, Error> {
+    let sim = || Some(String::try_from("SM").unwrap());
+    let v = send_command_logged(
+        client,
+        &AtPreferredSMSStorageWrite {
+            mem1: sim().unwrap(),
+            mem2: sim(),
+            mem3: sim(),
+        },
+        "AtPreferredSMSStorageWrite".to_string(),
+    )
+    .await?;
+    info!("SMS storage {}/{}", v.used3, v.total3);
+    Ok(SmsStorage {
+        used: v.used3,
+        total: v.total3,
+    })
+}
+
+// The indexes of the messages with the given stat, unread ones are kept unread.
+pub async fn list_sms<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    _pico: &mut U,
+    stat: SmsStat,
+) -> Result<Vec<u32, MAX_LIST>, Error> {
+    let v = send_command_logged(
+        client,
+        &AtListSMSMessagesPduWrite {
+            stat: stat.as_pdu_stat(),
+            mode: Some(ReadSMSMode::NotChangeStatusOfSMSRecord),
+        },
+        "AtListSMSMessagesPduWrite".to_string(),
+    )
+    .await?;
+    Ok(v.indexes)
+}
+
+pub async fn delete_sms<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
     client: &mut T,
     _pico: &mut U,
     index: u32,
-) -> Result<Sms, &'static str> {
-    match send_command_logged(
+) -> Result<(), Error> {
+    send_command_logged(
         client,
-        &AtReadSMSMessagesWrite {
-            index: index,
-            mode: None,
+        &AtDeleteSMSMessageWrite {
+            index,
+            delflag: None,
+        },
+        "AtDeleteSMSMessageWrite".to_string(),
+    )
+    .await?;
+    Ok(())
+}
+
+// Deletes every message matching the flag, e.g. DeleteFlag::Read.
+pub async fn delete_all_sms<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    _pico: &mut U,
+    flag: DeleteFlag,
+) -> Result<(), Error> {
+    send_command_logged(
+        client,
+        &AtDeleteSMSMessageWrite {
+            index: 1,
+            delflag: Some(flag),
         },
+        "AtDeleteSMSMessageWrite".to_string(),
+    )
+    .await?;
+    Ok(())
+}
+
+// Text mode, the time zone of the timestamp is ignored, see read_sms.
+pub async fn read_sms_text<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    _pico: &mut U,
+    index: u32,
+) -> Result<Sms, Error> {
+    let v = send_command_logged(
+        client,
+        &AtReadSMSMessagesWrite { index, mode: None },
         "AtReadSMSMessagesWrite".to_string(),
     )
-    .await
-    {
-        Ok(v) => {
-            info!(
-                "SMS RESP state={} date={} sender={} message={}",
-                v.stat, v.date_time, v.sn, v.message
-            );
-            // Time zone is given in quarters of an hour.
-            // Hungary is UTC+1 in Winter == 04
-            // Hungary is UTC+2 in Summer == 08
-            // 23/08/06,15:42:16+08
-            // 26/01/10,17:25:32+04
-            let datetime = v.date_time.as_str();
-            let offset_date_time = if datetime.len() == "26/01/10,17:25:32+04".len() {
-                let (year, rest) = datetime.split_at(2);
-                let (_, rest) = rest.split_at(1); // /
-                let (month, rest) = rest.split_at(2);
-                let (_, rest) = rest.split_at(1); // /
-                let (day, rest) = rest.split_at(2);
-                let (_, rest) = rest.split_at(1); // ,
-                let (hour, rest) = rest.split_at(2);
-                let (_, rest) = rest.split_at(1); // :
-                let (minute, rest) = rest.split_at(2);
-                let (_, rest) = rest.split_at(1); // :
-                let (second, rest) = rest.split_at(2);
-                let (sign, time_zone) = rest.split_at(1); // +/-
-
-                let mut utc_offset_seconds: i32 = time_zone.parse().unwrap_or_default();
-                utc_offset_seconds *= 15; // quarter of an hour
-                utc_offset_seconds *= 60; // to second
-                if sign == "-" {
-                    utc_offset_seconds *= -1;
-                }
-
-                let utc_offset = fasttime::UtcOffset::from_seconds(utc_offset_seconds)
-                    .unwrap_or(fasttime::UtcOffset::from_seconds(0).unwrap());
-
-                fasttime::OffsetDateTime {
-                    utc: fasttime::DateTime {
-                        date: fasttime::Date {
-                            year: 2000 + year.parse::<i32>().unwrap_or_default(),
-                            month: month.parse().unwrap_or_default(),
-                            day: day.parse().unwrap_or_default(),
-                        },
-                        time: fasttime::Time {
-                            hour: hour.parse().unwrap_or_default(),
-                            minute: minute.parse().unwrap_or_default(),
-                            second: second.parse().unwrap_or_default(),
-                            nanosecond: 0,
-                        },
-                    },
-                    offset: utc_offset,
-                }
-            } else {
-                fasttime::OffsetDateTime {
-                    utc: fasttime::DateTime {
-                        date: fasttime::Date {
-                            year: 2000,
-                            month: 1,
-                            day: 1,
-                        },
-                        time: fasttime::Time {
-                            hour: 0,
-                            minute: 0,
-                            second: 0,
-                            nanosecond: 0,
-                        },
-                    },
-                    offset: fasttime::UtcOffset::from_seconds(0).unwrap(),
-                }
-            };
+    .await?;
+    info!(
+        "SMS RESP state={} date={} sender={} message={}",
+        v.stat, v.date_time, v.sn, v.message
+    );
+    // Time zone is given in quarters of an hour.
+    // Hungary is UTC+1 in Winter == 04
+    // Hungary is UTC+2 in Summer == 08
+    // 23/08/06,15:42:16+08
+    // 26/01/10,17:25:32+04
+    let datetime = v.date_time.as_str();
+    let offset_date_time = if datetime.len() == "26/01/10,17:25:32+04".len() {
+        let (year, rest) = datetime.split_at(2);
+        let (_, rest) = rest.split_at(1); // /
+        let (month, rest) = rest.split_at(2);
+        let (_, rest) = rest.split_at(1); // /
+        let (day, rest) = rest.split_at(2);
+        let (_, rest) = rest.split_at(1); // ,
+        let (hour, rest) = rest.split_at(2);
+        let (_, rest) = rest.split_at(1); // :
+        let (minute, rest) = rest.split_at(2);
+        let (_, rest) = rest.split_at(1); // :
+        let (second, rest) = rest.split_at(2);
+        let (sign, time_zone) = rest.split_at(1); // +/-
+
+        let mut utc_offset_seconds: i32 = time_zone.parse().unwrap_or_default();
+        utc_offset_seconds *= 15; // quarter of an hour
+        utc_offset_seconds *= 60; // to second
+        if sign == "-" {
+            utc_offset_seconds *= -1;
+        }
+
+        let utc_offset = fasttime::UtcOffset::from_seconds(utc_offset_seconds)
+            .unwrap_or(fasttime::UtcOffset::from_seconds(0).unwrap());
+
+        fasttime::OffsetDateTime {
+            utc: fasttime::DateTime {
+                date: fasttime::Date {
+                    year: 2000 + year.parse::<i32>().unwrap_or_default(),
+                    month: month.parse().unwrap_or_default(),
+                    day: day.parse().unwrap_or_default(),
+                },
+                time: fasttime::Time {
+                    hour: hour.parse().unwrap_or_default(),
+                    minute: minute.parse().unwrap_or_default(),
+                    second: second.parse().unwrap_or_default(),
+                    nanosecond: 0,
+                },
+            },
+            offset: utc_offset,
+        }
+    } else {
+        fasttime::OffsetDateTime {
+            utc: fasttime::DateTime {
+                date: fasttime::Date {
+                    year: 2000,
+                    month: 1,
+                    day: 1,
+                },
+                time: fasttime::Time {
+                    hour: 0,
+                    minute: 0,
+                    second: 0,
+                    nanosecond: 0,
+                },
+            },
+            offset: fasttime::UtcOffset::from_seconds(0).unwrap(),
+        }
+    };
+
+    Ok(Sms {
+        stat: SmsStat::from_str(&v.stat)?,
+        phone_number: v.sn.text,
+        unix_timestamp_millis: (offset_date_time.unix_timestamp_nanos() / 1_000_000) as i64,
+        message: v.message.text,
+        concat: None,
+    })
+}
+
+// An empty slot is Error::Cms(INVALID_INDEX) whichever way the module reports it.
+pub async fn read_pdu<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    _pico: &mut U,
+    index: u32,
+) -> Result<(SmsStat, pdu::Pdu), Error> {
+    let v = send_command_logged(
+        client,
+        &AtReadSMSMessagesPduWrite { index, mode: None },
+        "AtReadSMSMessagesPduWrite".to_string(),
+    )
+    .await?;
+    info!("SMS PDU RESP state={} pdu={}", v.stat, v.pdu);
+    if v.pdu.is_empty() {
+        return Err(Error::Cms(INVALID_INDEX));
+    }
+    let bytes = decode_hex_u8(&v.pdu).map_err(|_| Error::Parse("invalid PDU"))?;
+    Ok((SmsStat::from_pdu_stat(v.stat)?, pdu::decode(&bytes)?))
+}
 
+// A part of a concatenated SMS has the concat set, see concat::Reassembler.
+pub async fn read_sms<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    pico: &mut U,
+    index: u32,
+) -> Result<Sms, Error> {
+    match read_pdu(client, pico, index).await? {
+        (stat, pdu::Pdu::Deliver(deliver)) => {
+            let pdu::UserData::Text(text) = &deliver.user_data else {
+                return Err(Error::Protocol("not a text message"));
+            };
             Ok(Sms {
-                stat: SmsStat::from_str(&v.stat)?,
-                phone_number: v.sn.text,
-                unix_timestamp_millis: (offset_date_time.unix_timestamp_nanos() / 1_000_000) as i64,
-                message: v.message.text,
+                stat,
+                phone_number: String::try_from(deliver.originator.as_str()).unwrap(),
+                unix_timestamp_millis: deliver.timestamp.unix_timestamp_millis(),
+                message: String::try_from(text.as_str())
+                    .map_err(|_| Error::Capacity("SMS too long"))?,
+                concat: deliver.concat(),
             })
         }
-        Err(_) => Err("could not read SMS"),
+        _ => Err(Error::Protocol("not a text message")),
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
+    use crate::at::PicoHW;
     use crate::cmd_serialization_tests;
 
     use super::*;
+    use alloc::string::String as AString;
     use atat::AtatCmd;
 
+    // +CMGR of a UCS2 SMS-DELIVER from +36301234567 at 26/01/10,17:25:32+04.
+    pub fn cmgr(header: &[u8], text: &str) -> AString {
+        let mut ud = alloc::vec::Vec::new();
+        if !header.is_empty() {
+            ud.push(header.len() as u8);
+            ud.extend_from_slice(header);
+        }
+        for unit in text.encode_utf16() {
+            ud.extend_from_slice(&unit.to_be_bytes());
+        }
+        let first = if header.is_empty() { "04" } else { "44" };
+        let ud: String<512> = encode_utf8_hex_string(&ud).unwrap();
+        let pdu = alloc::format!(
+            "00{}0B916303214365F7000862100171522340{:02X}{}",
+            first,
+            ud.len() / 2,
+            ud.as_str()
+        );
+        alloc::format!("+CMGR: 0,,{}\r\n{}", pdu.len() / 2 - 1, pdu)
+    }
+
+    // The AT+CMGS command and the PDU of a UCS2 SMS-SUBMIT.
+    pub fn cmgs(number: &str, header: &[u8], text: &str) -> (AString, AString) {
+        let dcs = match Charset::select(text) {
+            Charset::Gsm7 => pdu::Dcs::Gsm7,
+            Charset::Ucs2 => pdu::Dcs::Ucs2,
+        };
+        cmgs_dcs(number, header, dcs, text)
+    }
+
+    // The parts of a message are sent in the charset of the whole message.
+    pub fn cmgs_dcs(number: &str, header: &[u8], dcs: pdu::Dcs, text: &str) -> (AString, AString) {
+        let submit = pdu::Submit {
+            destination: number,
+            dcs,
+            header,
+            user_data: text.as_bytes(),
+            status_report: false,
+        };
+        let pdu = submit.encode().unwrap();
+        let hex: String<512> = encode_utf8_hex_string(&pdu).unwrap();
+        (
+            alloc::format!("AT+CMGS={}\r", pdu::tpdu_length(&pdu)),
+            alloc::format!("{}\x1a", hex.as_str()),
+        )
+    }
+
     cmd_serialization_tests! {
         test_at_select_sms_message_format_write: (
             AtSelectSMSMessageFormatWrite {
@@ -455,24 +849,202 @@ mod tests {
             },
             "AT+CMGR=42\r",
         ),
+        test_at_send_sms_pdu_1: (
+            AtSMSSendPdu { length: 15 },
+            "AT+CMGS=15\r",
+        ),
+        test_at_send_sms_pdu_2: (
+            AtSMSPduData {
+                pdu: Vec::from_slice(&[0x00, 0x01, 0x00, 0x0B, 0x91, 0x63, 0x03, 0x21, 0x43, 0x65, 0xF7, 0x00, 0x00, 0x02, 0xE8, 0x34]).unwrap(),
+            },
+            "0001000B916303214365F7000002E834\x1a",
+        ),
+        test_at_read_sms_messages_pdu_write: (
+            AtReadSMSMessagesPduWrite {
+                index: 42,
+                mode: Some(ReadSMSMode::NotChangeStatusOfSMSRecord),
+            },
+            "AT+CMGR=42,1\r",
+        ),
+        test_at_list_sms_messages_pdu_write: (
+            AtListSMSMessagesPduWrite {
+                stat: SmsStat::ReceivedUnread.as_pdu_stat(),
+                mode: None,
+            },
+            "AT+CMGL=0\r",
+        ),
+        test_at_delete_sms_message_write_1: (
+            AtDeleteSMSMessageWrite {
+                index: 3,
+                delflag: None,
+            },
+            "AT+CMGD=3\r",
+        ),
+        test_at_delete_sms_message_write_2: (
+            AtDeleteSMSMessageWrite {
+                index: 1,
+                delflag: Some(DeleteFlag::ReadSentAndUnsent),
+            },
+            "AT+CMGD=1,3\r",
+        ),
+        test_at_preferred_sms_storage_write: (
+            AtPreferredSMSStorageWrite {
+                mem1: String::try_from("SM").unwrap(),
+                mem2: None,
+                mem3: None,
+            },
+            "AT+CPMS=\"SM\"\r",
+        ),
     }
 
     #[test]
-    fn test_clip_response() {
-        #[derive(Clone, Debug, Format, AtatCmd)]
-        #[at_cmd("+CMTI", NewMessageIndicationUrc, timeout_ms = 15000)]
-        struct AtUrcHack;
+    fn test_sms_list_response() {
+        let cmd = AtListSMSMessagesPduWrite {
+            stat: 4,
+            mode: None,
+        };
+        assert_eq!(
+            SMSListResponse {
+                indexes: Vec::from_slice(&[1, 3]).unwrap(),
+            },
+            cmd.parse(Ok(b"+CMGL: 1,0,,21\r\n00040B916303214365F7000862100171522340020061\r\n+CMGL: 3,1,\"Yettel\",21\r\n00040B916303214365F7000862100171522340020063"))
+                .unwrap()
+        );
+        assert_eq!(SMSListResponse::default(), cmd.parse(Ok(b"")).unwrap());
+        assert!(cmd.parse(Ok(b"+CMGL: x,0,,21\r\n00")).is_err());
+        assert!(cmd.parse(Ok(b"+CMGL: 1\r\n00")).is_err());
+        // more than a SIM holds
+        let long = (1..=MAX_LIST + 1)
+            .map(|i| alloc::format!("+CMGL: {},0,,1\r\n00", i))
+            .collect::<alloc::vec::Vec<_>>()
+            .join("\r\n");
+        assert!(cmd.parse(Ok(long.as_bytes())).is_err());
+    }
 
-        let cmd = AtUrcHack;
+    #[tokio::test]
+    async fn test_sms_storage() {
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .expect(
+                "AT+CPMS=\"SM\",\"SM\",\"SM\"\r",
+                Ok(b"+CPMS: 30,30,30,30,30,30"),
+            )
+            // empty slots, depending on the firmware
+            .expect("AT+CMGR=1\r", Ok(b""))
+            .expect(
+                "AT+CMGR=2\r",
+                Err(atat::InternalError::CmsError(atat::CmsError::InvalidIndex)),
+            )
+            .expect(
+                "AT+CMGL=0,1\r",
+                Ok(b"+CMGL: 2,0,,21\r\n00040B916303214365F7000862100171522340020061"),
+            )
+            .expect("AT+CMGD=2\r", Ok(b""))
+            .expect("AT+CMGD=1,1\r", Ok(b""))
+            .expect("AT+CMGD=7\r", Err(atat::InternalError::Error));
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        let storage = select_storage(&mut client, &mut pico).await.unwrap();
         assert_eq!(
-            NewMessageIndicationUrc {
-                mem: String::try_from("SM").unwrap(),
-                index: 1
+            SmsStorage {
+                used: 30,
+                total: 30
             },
-            cmd.parse(Ok(b"+CMTI: \"SM\",1\r\n")).unwrap()
+            storage
+        );
+        assert!(storage.is_full());
+        assert_eq!(
+            Err(Error::Cms(INVALID_INDEX)),
+            read_pdu(&mut client, &mut pico, 1).await.map(|_| ())
+        );
+        assert_eq!(
+            Err(Error::Cms(INVALID_INDEX)),
+            read_pdu(&mut client, &mut pico, 2).await.map(|_| ())
+        );
+        assert_eq!(
+            &[2],
+            list_sms(&mut client, &mut pico, SmsStat::ReceivedUnread)
+                .await
+                .unwrap()
+                .as_slice()
+        );
+        assert_eq!(Ok(()), delete_sms(&mut client, &mut pico, 2).await);
+        assert_eq!(
+            Ok(()),
+            delete_all_sms(&mut client, &mut pico, DeleteFlag::Read).await
+        );
+        assert_eq!(
+            Err(Error::Modem),
+            delete_sms(&mut client, &mut pico, 7).await
         );
     }
 
+    #[test]
+    fn test_sms_pdu_response() {
+        let cmd = AtReadSMSMessagesPduWrite {
+            index: 0,
+            mode: None,
+        };
+
+        assert_eq!(
+            SMSMessagePduResponse {
+                stat: 1,
+                length: 26,
+                pdu: String::try_from("07916303898800F0040B916303214365F70000621001715223400AE8329BFD4697D9EC37").unwrap(),
+            },
+            cmd.parse(Ok(b"+CMGR: 1,,26\r\n07916303898800F0040B916303214365F70000621001715223400AE8329BFD4697D9EC37\r\n"))
+                .unwrap(),
+        );
+        assert_eq!(
+            26,
+            cmd.parse(Ok(b"+CMGR: 0,\"Yettel\",26\r\n00"))
+                .unwrap()
+                .length
+        );
+        assert!(cmd.parse(Ok(b"+CMGR: 1,26\r\n00")).is_err());
+        assert!(cmd.parse(Ok(b"+CMGR: \"REC READ\",,26\r\n00")).is_err());
+        assert!(cmd.parse(Ok(b"+CMGR: 1,,26")).is_err());
+        // empty slot
+        assert_eq!(
+            SMSMessagePduResponse::default(),
+            cmd.parse(Ok(b"")).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_new_message_indication_urc() {
+        // the SMS arrives while the module is being configured
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .expect("AT+CMGF=0\r", Ok(b""))
+            .urc(b"+CMTI: \"SM\",1")
+            .expect(
+                "AT+CPMS=\"SM\",\"SM\",\"SM\"\r",
+                Ok(b"+CPMS: 1,30,1,30,1,30"),
+            )
+            .expect("AT+CNMI=2,1,0,0,0\r", Ok(b""))
+            .expect("AT+CMGR=1\r", Ok(cmgr(&[], "OK").leak().as_bytes()));
+        let mut urcs = client.subscribe();
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        init(&mut client, &mut pico).await.unwrap();
+        let index = match urcs.try_next_message_pure() {
+            Some(crate::urc::Urc::NewMessageIndicationUrc(v)) => {
+                assert_eq!(
+                    NewMessageIndicationUrc {
+                        mem: String::try_from("SM").unwrap(),
+                        index: 1
+                    },
+                    v
+                );
+                v.index as u32
+            }
+            _ => panic!("missing URC"),
+        };
+        assert!(urcs.try_next_message_pure().is_none());
+
+        let sms = read_sms(&mut client, &mut pico, index).await.unwrap();
+        assert_eq!("OK", sms.message);
+    }
+
     #[test]
     fn test_send_sms_response() {
         let cmd = AtSMSData {
@@ -540,7 +1112,7 @@ mod tests {
         client.results.push_back(Ok("".as_bytes()));
 
         let mut pico = crate::at::tests::PicoMock::default();
-        init(&mut client, &mut pico).await;
+        init_text(&mut client, &mut pico).await.unwrap();
         assert_eq!(3, client.sent_commands.len());
         assert_eq!("AT+CMGF=1\r", client.sent_commands.get(0).unwrap());
         assert_eq!("AT+CSCS=\"UCS2\"\r", client.sent_commands.get(1).unwrap());
@@ -554,13 +1126,14 @@ mod tests {
         client.results.push_back(Ok("+CMGS: 1".as_bytes()));
 
         let mut pico = crate::at::tests::PicoMock::default();
-        send_sms(
+        send_sms_text(
             &mut client,
             &mut pico,
             &String::try_from("+36301234567").unwrap(),
             &String::try_from("this is the text message").unwrap(),
         )
-        .await;
+        .await
+        .unwrap();
         assert_eq!(2, client.sent_commands.len());
         assert_eq!(
             "AT+CMGS=\"002B00330036003300300031003200330034003500360037\"\r",
@@ -573,16 +1146,142 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_receive_sms() {
-        let mut client = crate::at::tests::ClientMock::default();
-        client.results.push_back(Ok("+CMGR: \"REC READ\",\"002B00330036003300300031003200330034003500360037\",\"\",\"26/01/10,17:25:32+04\"\r\n00240074004100540041002F006C006F0063006100740069006F006E002F00310032003300340035".as_bytes()));
-        client.results.push_back(Err(atat::InternalError::Timeout));
+    async fn test_sms_pdu() {
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .expect("AT+CMGF=0\r", Ok(b""))
+            .expect("AT+CPMS=\"SM\",\"SM\",\"SM\"\r", Ok(b"+CPMS: 0,30,0,30,0,30"))
+            .expect("AT+CNMI=2,1,0,0,0\r", Ok(b""))
+            .expect("AT+CMGS=15\r", Ok(b">"))
+            .expect("0021000B916303214365F7000002E834\x1a", Ok(b"+CMGS: 66"))
+            .expect("AT+CMGS=23\r", Ok(b">"))
+            .expect("0001000B916303214365F700080A00C10072007600ED007A\x1a", Err(atat::InternalError::Timeout))
+            // winter time UTC+1 (CET)
+            .expect("AT+CMGR=1\r", Ok(b"+CMGR: 0,,35\r\n07916303898800F0040B916303214365F70000621001715223400AE8329BFD4697D9EC37"))
+            .expect("AT+CMGR=2\r", Ok(b"+CMGR: 1,,34\r\n07916303898800F006420B916303214365F7621001715223406210017152534000"))
+            .expect("AT+CMGR=3\r", Ok(b"+CMGR: 1,,10\r\n0001"))
+            .expect("AT+CMGR=4\r", Ok(b"+CMGR: 1,,10\r\n07916303898800F0040"))
+            .expect("AT+CMGR=5\r", Ok("+CMGR: 1,,10\r\n0é0".as_bytes()));
 
         let mut pico = crate::at::tests::PicoMock::default();
-        receive_sms(&mut client, &mut pico).await;
-        assert_eq!(2, client.sent_commands.len());
-        assert_eq!("AT+CMGR=1\r", client.sent_commands.get(0).unwrap());
-        assert_eq!("AT+CMGR=2\r", client.sent_commands.get(1).unwrap());
+        init(&mut client, &mut pico).await.unwrap();
+
+        let submit = pdu::Submit {
+            destination: "+36301234567",
+            dcs: pdu::Dcs::Gsm7,
+            header: &[],
+            user_data: b"hi",
+            status_report: true,
+        };
+        assert_eq!(Ok(66), send_sms_pdu(&mut client, &mut pico, &submit).await);
+        let submit = pdu::Submit {
+            dcs: pdu::Dcs::Ucs2,
+            user_data: "Árvíz".as_bytes(),
+            status_report: false,
+            ..submit
+        };
+        assert_eq!(
+            Err(Error::Timeout),
+            send_sms_pdu(&mut client, &mut pico, &submit).await
+        );
+        let submit = pdu::Submit {
+            destination: "+3630abc",
+            ..submit
+        };
+        assert_eq!(
+            Err(Error::Parse("invalid phone number")),
+            send_sms_pdu(&mut client, &mut pico, &submit).await
+        );
+
+        assert_eq!(
+            Ok(Sms {
+                stat: SmsStat::ReceivedUnread,
+                phone_number: String::try_from("+36301234567").unwrap(),
+                unix_timestamp_millis: 1768062332000, // 1 hour before test_read_sms
+                message: String::try_from("hellohello").unwrap(),
+                concat: None,
+            }),
+            read_sms(&mut client, &mut pico, 1).await
+        );
+        assert_eq!(
+            Err(Error::Protocol("not a text message")),
+            read_sms(&mut client, &mut pico, 2).await
+        );
+        assert_eq!(
+            Err(Error::Protocol("unsupported PDU type")),
+            read_sms(&mut client, &mut pico, 3).await
+        );
+        // an odd length or a multi-byte character is not hex
+        assert_eq!(
+            Err(Error::Parse("invalid PDU")),
+            read_sms(&mut client, &mut pico, 4).await
+        );
+        assert_eq!(
+            Err(Error::Parse("invalid PDU")),
+            read_sms(&mut client, &mut pico, 5).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_sms_concatenated() {
+        let text = "https://maps.google.com/?q=46.7624859,18.6304591\n\n5.75 meters, 89.12 %, 2026-01-10 17:25:32";
+        let gsm7 = alloc::format!("{}{}", text, text);
+        let ucs2 = alloc::format!("Dömők: {}", text);
+        let header = |reference, count, index| {
+            pdu::Concat {
+                reference,
+                count,
+                index,
+            }
+            .header()
+        };
+        // 153 septets or 67 UTF-16 units per part
+        let (gsm7_1, gsm7_2) = gsm7.split_at(153);
+        let (ucs2_1, ucs2_2) = ucs2.split_at(ucs2.char_indices().nth(67).unwrap().0);
+        let (cmgs1, pdu1) = cmgs("+36301234567", &header(1, 2, 1), gsm7_1);
+        let (cmgs2, pdu2) = cmgs("+36301234567", &header(1, 2, 2), gsm7_2);
+        let (cmgs3, pdu3) = cmgs("+36301234567", &header(2, 2, 1), ucs2_1);
+        let (cmgs4, pdu4) = cmgs_dcs("+36301234567", &header(2, 2, 2), pdu::Dcs::Ucs2, ucs2_2);
+        let (cmgs5, pdu5) = cmgs("+36301234567", &[], "short");
+        assert!(pdu1.starts_with("0041000B916303214365F70000A0")); // 7-bit, 160 septets
+        assert!(pdu3.starts_with("0041000B916303214365F700088C")); // UCS2, 140 octets
+        let mut client = crate::at::tests::TranscriptMock::default()
+            .expect(cmgs1.leak(), Ok(b">"))
+            .expect(pdu1.leak(), Ok(b"+CMGS: 1"))
+            .expect(cmgs2.leak(), Ok(b">"))
+            .expect(pdu2.leak(), Ok(b"+CMGS: 2"))
+            .expect(cmgs3.leak(), Ok(b">"))
+            .expect(pdu3.leak(), Ok(b"+CMGS: 3"))
+            .expect(cmgs4.leak(), Ok(b">"))
+            .expect(pdu4.leak(), Ok(b"+CMGS: 4"))
+            .expect(cmgs5.leak(), Ok(b">"))
+            .expect(pdu5.leak(), Ok(b"+CMGS: 5"));
+
+        let mut pico = crate::at::tests::PicoMock::default();
+        send_sms(&mut client, &mut pico, "+36301234567", &gsm7, 1)
+            .await
+            .unwrap();
+        send_sms(&mut client, &mut pico, "+36301234567", &ucs2, 2)
+            .await
+            .unwrap();
+        send_sms(&mut client, &mut pico, "+36301234567", "short", 3)
+            .await
+            .unwrap();
+        // 5 parts would be needed
+        assert_eq!(
+            Err(Error::Capacity("message too long")),
+            send_sms(
+                &mut client,
+                &mut pico,
+                "+36301234567",
+                &"a".repeat(4 * 153 + 1),
+                4,
+            )
+            .await
+        );
+        assert_eq!(
+            Err(Error::Capacity("message too long")),
+            send_sms(&mut client, &mut pico, "+36301234567", &"ő".repeat(300), 5).await
+        );
     }
 
     #[tokio::test]
@@ -605,13 +1304,14 @@ mod tests {
         client.results.push_back(Ok("+CMGR: \"STO UNSENT\",\"002B00330036003300300031003200330034003500360037\",\"\",\"23/08/06,xx:42:16+08\"\r\n00240074004100540041002F006C006F0063006100740069006F006E002F00310032003300340035".as_bytes()));
 
         let mut pico = crate::at::tests::PicoMock::default();
-        let sms = read_sms(&mut client, &mut pico, 5).await.unwrap();
+        let sms = read_sms_text(&mut client, &mut pico, 5).await.unwrap();
         assert_eq!(
             Sms {
                 stat: SmsStat::ReceivedRead,
                 phone_number: String::try_from("+36301234567").unwrap(),
                 unix_timestamp_millis: 1768065932000, // todo off with 1 hour?
                 message: String::try_from("$tATA/location/12345").unwrap(),
+                concat: None,
             },
             sms
         );
@@ -619,13 +1319,14 @@ mod tests {
         assert_eq!(1, client.sent_commands.len());
         assert_eq!("AT+CMGR=5\r", client.sent_commands.get(0).unwrap());
 
-        let sms = read_sms(&mut client, &mut pico, 12).await.unwrap();
+        let sms = read_sms_text(&mut client, &mut pico, 12).await.unwrap();
         assert_eq!(
             Sms {
                 stat: SmsStat::ReceivedUnread,
                 phone_number: String::try_from("+36301234567").unwrap(),
                 unix_timestamp_millis: 1691336536000, // todo off with 2 hour?
                 message: String::try_from("$tATA/location/12345").unwrap(),
+                concat: None,
             },
             sms
         );
@@ -633,17 +1334,18 @@ mod tests {
         assert_eq!(2, client.sent_commands.len());
         assert_eq!("AT+CMGR=12\r", client.sent_commands.get(1).unwrap());
 
-        assert_eq!(true, read_sms(&mut client, &mut pico, 20).await.is_err());
+        assert!(read_sms_text(&mut client, &mut pico, 20).await.is_err());
         assert_eq!(3, client.sent_commands.len());
         assert_eq!("AT+CMGR=20\r", client.sent_commands.get(2).unwrap());
 
-        let sms = read_sms(&mut client, &mut pico, 3).await.unwrap();
+        let sms = read_sms_text(&mut client, &mut pico, 3).await.unwrap();
         assert_eq!(
             Sms {
                 stat: SmsStat::StoredUnsent,
                 phone_number: String::try_from("+36301234567").unwrap(),
                 unix_timestamp_millis: 946684800000, // defaults to 2000.01.01 00:00+00
                 message: String::try_from("$tATA/location/12345").unwrap(),
+                concat: None,
             },
             sms
         );
@@ -651,13 +1353,14 @@ mod tests {
         assert_eq!(4, client.sent_commands.len());
         assert_eq!("AT+CMGR=3\r", client.sent_commands.get(3).unwrap());
 
-        let sms = read_sms(&mut client, &mut pico, 9).await.unwrap();
+        let sms = read_sms_text(&mut client, &mut pico, 9).await.unwrap();
         assert_eq!(
             Sms {
                 stat: SmsStat::StoredUnsent,
                 phone_number: String::try_from("+36301234567").unwrap(),
                 unix_timestamp_millis: 1691282536000, // fallbacks to August 6, 2023 12:42:16 AM
                 message: String::try_from("$tATA/location/12345").unwrap(),
+                concat: None,
             },
             sms
         );
@@ -665,4 +1368,115 @@ mod tests {
         assert_eq!(5, client.sent_commands.len());
         assert_eq!("AT+CMGR=9\r", client.sent_commands.get(4).unwrap());
     }
+
+    #[tokio::test]
+    async fn test_sms_emulated() {
+        let mut harness = crate::at::tests::Harness::new(sim868_emu::Sim868::default());
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut sub = harness.urc_channel.subscribe().unwrap();
+
+        harness
+            .run(async |client| init_text(client, &mut pico).await)
+            .await
+            .unwrap();
+        harness
+            .modem
+            .with(|sim| sim.receive_sms("+36301234567", "Hello", "26/01/10,17:25:32+04"));
+        let index = match harness.run(async |_| sub.next_message_pure().await).await {
+            crate::urc::Urc::NewMessageIndicationUrc(v) => v.index as u32,
+            _ => panic!("unexpected URC"),
+        };
+        let sms = harness
+            .run(async |client| read_sms_text(client, &mut pico, index).await)
+            .await
+            .unwrap();
+        assert_eq!(SmsStat::ReceivedUnread, sms.stat);
+        assert_eq!("+36301234567", sms.phone_number);
+        assert_eq!("Hello", sms.message);
+        assert_eq!(1768065932000, sms.unix_timestamp_millis); // same as test_read_sms
+
+        let sms = harness
+            .run(async |client| read_sms_text(client, &mut pico, index).await)
+            .await;
+        assert_eq!(SmsStat::ReceivedRead, sms.unwrap().stat);
+        let sms = harness
+            .run(async |client| read_sms_text(client, &mut pico, index + 1).await)
+            .await;
+        assert_eq!(Err(Error::Cms(321)), sms);
+
+        harness
+            .run(async |client| {
+                send_sms_text(
+                    client,
+                    &mut pico,
+                    &String::try_from("+36301234567").unwrap(),
+                    &String::try_from("Árvíztűrő").unwrap(),
+                )
+                .await
+            })
+            .await
+            .unwrap();
+        let sent = harness.modem.with(|sim| sim.sent_sms.clone());
+        assert_eq!(1, sent.len());
+        assert_eq!("+36301234567", sent[0].number);
+        assert_eq!("Árvíztűrő", sent[0].text);
+    }
+
+    #[tokio::test]
+    async fn test_sms_concatenated_emulated() {
+        let mut harness = crate::at::tests::Harness::new(sim868_emu::Sim868::default());
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+
+        harness
+            .run(async |client| init(client, &mut pico).await)
+            .await
+            .unwrap();
+        let text = "https://maps.google.com/?q=46.7624859,18.6304591\n\n5.75 meters, 89.12 %, 2026-01-10T16:25:32.000Z\n\n";
+        let indexes = harness
+            .modem
+            .with(|sim| sim.receive_long_sms("+36301234567", text, "26/01/10,17:25:32+04", 0x42));
+        assert_eq!(2, indexes.len());
+
+        let mut reassembler = concat::Reassembler::default();
+        let mut received = None;
+        for index in indexes {
+            let sms = harness
+                .run(async |client| read_sms(client, &mut pico, index).await)
+                .await
+                .unwrap();
+            assert_eq!(1768062332000, sms.unix_timestamp_millis);
+            let c = sms.concat.unwrap();
+            assert_eq!((0x42, 2), (c.reference, c.count));
+            received = reassembler
+                .add(&sms.phone_number, &c, &sms.message, pico.uptime_millis())
+                .unwrap();
+        }
+        assert_eq!(Some(text), received.as_deref());
+
+        // 7-bit, 153 septets per part, the same text again gets a new reference
+        let text = alloc::format!("{}{}", text, text);
+        for _ in 0..2 {
+            let reference = reassembler.next_reference();
+            harness
+                .run(async |client| {
+                    send_sms(client, &mut pico, "+36301234567", &text, reference).await
+                })
+                .await
+                .unwrap();
+        }
+        let sent = harness.modem.with(|sim| sim.sent_sms.clone());
+        assert_eq!(4, sent.len());
+        assert_eq!(
+            text,
+            sent[..2]
+                .iter()
+                .map(|s| s.text.as_str())
+                .collect::<AString>()
+        );
+        assert_eq!(alloc::vec![0x00, 0x03, 1, 2, 1], sent[0].header);
+        assert_eq!(alloc::vec![0x00, 0x03, 1, 2, 2], sent[1].header);
+        assert_eq!(alloc::vec![0x00, 0x03, 2, 2, 1], sent[2].header);
+        assert_eq!(alloc::vec![0x00, 0x03, 2, 2, 2], sent[3].header);
+        assert!(sent.iter().all(|s| s.number == "+36301234567"));
+    }
 }
@@ -0,0 +1,102 @@
+// Persistent storage abstraction, implemented by the RP2040 flash in the app.
+//
+// NOR flash semantics are assumed: erase sets every byte of a sector to 0xFF,
+// write can only clear bits.
+
+use crate::error::Error;
+
+pub const ERASED: u8 = 0xFF;
+
+pub trait Storage {
+    fn sector_size(&self) -> usize;
+    fn capacity(&self) -> usize;
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<(), Error>;
+    fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Error>;
+    fn erase_sector(&mut self, offset: usize) -> Result<(), Error>;
+}
+
+#[cfg(test)]
+pub mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    pub struct MemoryStorage {
+        pub data: Vec<u8>,
+        pub sector_size: usize,
+        pub erase_calls: Vec<usize>,
+        pub write_calls: u32,
+    }
+
+    impl MemoryStorage {
+        pub fn new(sector_size: usize, sectors: usize) -> Self {
+            MemoryStorage {
+                data: vec![ERASED; sector_size * sectors],
+                sector_size,
+                erase_calls: Vec::new(),
+                write_calls: 0,
+            }
+        }
+    }
+
+    impl Storage for MemoryStorage {
+        fn sector_size(&self) -> usize {
+            self.sector_size
+        }
+
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+
+        fn read(&mut self, offset: usize, bytes: &mut [u8]) -> Result<(), Error> {
+            if offset + bytes.len() > self.data.len() {
+                return Err(Error::Storage("read out of bounds"));
+            }
+            bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Error> {
+            if offset + bytes.len() > self.data.len() {
+                return Err(Error::Storage("write out of bounds"));
+            }
+            self.write_calls += 1;
+            for (i, b) in bytes.iter().enumerate() {
+                self.data[offset + i] &= b;
+            }
+            Ok(())
+        }
+
+        fn erase_sector(&mut self, offset: usize) -> Result<(), Error> {
+            if !offset.is_multiple_of(self.sector_size) || offset >= self.data.len() {
+                return Err(Error::Storage("invalid sector"));
+            }
+            self.erase_calls.push(offset);
+            self.data[offset..offset + self.sector_size].fill(ERASED);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        let mut storage = MemoryStorage::new(16, 2);
+        assert_eq!(32, storage.capacity());
+        storage.write(3, &[0x0F, 0xF0]).unwrap();
+        storage.write(3, &[0xFC, 0xFF]).unwrap();
+        let mut bytes = [0u8; 3];
+        storage.read(2, &mut bytes).unwrap();
+        assert_eq!([0xFF, 0x0C, 0xF0], bytes);
+        storage.erase_sector(0).unwrap();
+        storage.read(2, &mut bytes).unwrap();
+        assert_eq!([0xFF, 0xFF, 0xFF], bytes);
+        assert_eq!(
+            Err(Error::Storage("invalid sector")),
+            storage.erase_sector(3)
+        );
+        assert_eq!(
+            Err(Error::Storage("read out of bounds")),
+            storage.read(31, &mut bytes)
+        );
+    }
+}
@@ -0,0 +1,500 @@
+use core::net::SocketAddr;
+
+use alloc::string::String as AString;
+use alloc::vec::Vec as AVec;
+use atat::heapless::String;
+use defmt::info;
+use embedded_nal_async::TcpConnect;
+
+use crate::concat::MAX_MESSAGE;
+use crate::config::Config;
+use crate::config::ConfigStore;
+use crate::config::Role;
+use crate::dispatcher;
+use crate::error::Error;
+use crate::ip;
+use crate::location;
+use crate::mqtt;
+use crate::mqtt::MAX_TOPIC;
+use crate::mqtt::QoS;
+use crate::poro;
+use crate::protector::Guard;
+use crate::protector::Transition;
+use crate::storage::Storage;
+use crate::tracklog::Tracklog;
+
+// Location and status updates for the fleet dashboard over MQTT, to the broker of
+// Config::mqtt_server. The topics of the device, by its IMEI (see network::imei):
+//
+//   poro/<imei>/location  the latest position, retained
+//   poro/<imei>/status    park location, protector status and service mode, retained
+//   poro/<imei>/command   subscribed, the text of an SMS command, e.g. $tATA/location/12345
+//   poro/<imei>/reply     the replies of the commands
+//
+// The payloads are the machine format of the SMS replies (see dispatcher::reply).
+//
+// A report is a short session on the bearer: connect, subscribe, publish, then a PINGREQ
+// collects the commands the broker kept for the persistent session. The commands run
+// after the bearer is released, the replies go out with the next session.
+//
+// An Update is over 1 KB, the updates are published one by one instead of being collected.
+// The heap holds the client (mqtt::MAX_HEAP), the replies and a PUBLISH being encoded, at
+// most MAX_HEAP.
+
+pub const TOPIC_ROOT: &str = "poro";
+pub const KEEP_ALIVE_SECS: u16 = 300;
+const MAX_ROUNDS: usize = 3; // sessions of a report, commands of the last one get no reply
+const SENDER: &str = "mqtt";
+// The password of the command is checked as for an SMS, the broker decides who may
+// publish to the command topic.
+const ROLE: Role = Role::Owner;
+// The body and the packet of the largest PUBLISH, see mqtt::encode_publish.
+const MAX_PUBLISH: usize = 2 * (2 + MAX_TOPIC + 2 + MAX_MESSAGE + 5);
+// A reply for each command of a session.
+pub const MAX_HEAP: usize = mqtt::MAX_HEAP + mqtt::MAX_INCOMING * MAX_MESSAGE + MAX_PUBLISH;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    pub topic: String<MAX_TOPIC>,
+    pub payload: String<MAX_MESSAGE>,
+    pub retain: bool,
+}
+
+pub fn topic(device: &str, leaf: &str) -> Result<String<MAX_TOPIC>, Error> {
+    let mut ret = String::new();
+    for part in [TOPIC_ROOT, "/", device, "/", leaf] {
+        ret.push_str(part)
+            .map_err(|_| Error::Capacity("MQTT topic"))?;
+    }
+    Ok(ret)
+}
+
+// None until there is a location.
+pub fn location_update(guard: &Guard, device: &str) -> Result<Option<Update>, Error> {
+    let Some(location) = guard.last_location() else {
+        return Ok(None);
+    };
+    Ok(Some(Update {
+        topic: topic(device, "location")?,
+        payload: dispatcher::reply(&guard.protector(Some(location)), &poro::Source::SmsMachine),
+        retain: true,
+    }))
+}
+
+pub fn status_update(guard: &Guard, device: &str) -> Result<Update, Error> {
+    let mut status = guard.protector(None);
+    status.status = guard.status().cloned();
+    status.service = Some(poro::Service {
+        value: guard.service(),
+    });
+    Ok(Update {
+        topic: topic(device, "status")?,
+        payload: dispatcher::reply(&status, &poro::Source::SmsMachine),
+        retain: true,
+    })
+}
+
+// What a report did, the transitions are for the alert numbers (see dispatcher::send_alerts).
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub commands: usize,
+    pub transitions: AVec<Transition>,
+}
+
+// Publishes the updates, then runs the commands of the dashboard. Fails only if the first
+// session does, a later one only cuts the report short.
+#[allow(clippy::too_many_arguments)] // the state of the main loop, see the app
+pub async fn report<T: atat::asynch::AtatClient, U: crate::at::PicoHW, S: Storage>(
+    client: &mut T,
+    pico: &mut U,
+    guard: &mut Guard,
+    strategy: &mut location::Strategy,
+    store: &mut ConfigStore<S>,
+    tracklog: &mut Tracklog<S>,
+    config: &mut Config,
+    device: &str,
+) -> Result<Report, Error> {
+    let broker: SocketAddr = config
+        .mqtt_server
+        .parse()
+        .map_err(|_| Error::Rejected("no MQTT broker"))?;
+    let mut replies: AVec<AString> = AVec::new();
+    let mut report = Report::default();
+    for round in 1..=MAX_ROUNDS {
+        let commands = match strategy
+            .clbs
+            .bearer
+            .scoped(client, pico, &config.apn, async |client, pico| {
+                session(client, pico, broker, device, guard, &replies).await
+            })
+            .await
+        {
+            Ok(commands) => commands,
+            // the commands already ran, their transitions still go to the alert numbers
+            Err(e) if round > 1 => {
+                info!(
+                    "MQTT report cut short, replies dropped: {}, {}",
+                    replies.len(),
+                    e
+                );
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        if commands.is_empty() {
+            break;
+        }
+        replies.clear();
+        for message in commands {
+            let Ok(text) = core::str::from_utf8(&message.payload) else {
+                info!("Ignoring MQTT command: not utf-8");
+                continue;
+            };
+            report.commands += 1;
+            let outcome = match dispatcher::handle_command(
+                client, pico, guard, strategy, store, tracklog, config, SENDER, ROLE, text,
+            )
+            .await
+            {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    info!("MQTT command failed: {}", e);
+                    continue;
+                }
+            };
+            if outcome.call {
+                info!("No call for an MQTT command");
+            }
+            report.transitions.extend(outcome.transitions);
+            // the size of the text, not of the String
+            if let Some(reply) = outcome.reply {
+                replies.push(AString::from(reply.as_str()));
+            }
+        }
+        if round == MAX_ROUNDS {
+            info!("MQTT replies dropped: {}", replies.len());
+            break;
+        }
+    }
+    Ok(report)
+}
+
+// One session on the bearer, publishes the replies and the updates (the commands may have
+// changed the state), returns the commands the broker had for the device. A retained
+// command is dropped, anyone who may publish to the topic could leave one behind.
+async fn session<T: atat::asynch::AtatClient, U: crate::at::PicoHW>(
+    client: &mut T,
+    pico: &mut U,
+    broker: SocketAddr,
+    device: &str,
+    guard: &Guard,
+    replies: &[AString],
+) -> Result<AVec<mqtt::Message>, Error> {
+    let stack = ip::Stack::new(client, pico);
+    let connection = stack.connect(broker).await?;
+    let options = mqtt::Options {
+        clean_session: false, // the commands are kept between the sessions
+        ..mqtt::Options::new(device, KEEP_ALIVE_SECS)
+    };
+    let mut session = mqtt::Client::connect(connection, &options).await?;
+    session
+        .subscribe(&topic(device, "command")?, QoS::AtLeastOnce)
+        .await?;
+    let reply_topic = topic(device, "reply")?;
+    for reply in replies {
+        session
+            .publish(&reply_topic, reply.as_bytes(), QoS::AtLeastOnce, false)
+            .await?;
+    }
+    if let Some(update) = location_update(guard, device)? {
+        publish(&mut session, &update).await?;
+    }
+    publish(&mut session, &status_update(guard, device)?).await?;
+    session.ping().await?;
+    let mut commands = AVec::with_capacity(mqtt::MAX_INCOMING);
+    while let Some(message) = session.next_message() {
+        // The broker sends the retained ones again on every SUBSCRIBE, the command would
+        // run on every report.
+        if message.retain {
+            info!("Ignoring retained MQTT command");
+            continue;
+        }
+        commands.push(message);
+    }
+    session.disconnect().await?.close().await?;
+    Ok(commands)
+}
+
+async fn publish<C>(session: &mut mqtt::Client<C>, update: &Update) -> Result<(), Error>
+where
+    C: embedded_io_async::Read
+        + embedded_io_async::Write
+        + embedded_io_async::ErrorType<Error = Error>
+        + mqtt::ReadTimeout,
+{
+    session
+        .publish(
+            &update.topic,
+            update.payload.as_bytes(),
+            QoS::AtLeastOnce,
+            update.retain,
+        )
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protector::ProtectorConfig;
+    use crate::storage::tests::MemoryStorage;
+    use alloc::string::ToString;
+    use sim868_emu::mqtt::Broker;
+
+    const DEVICE: &str = "861234567890123";
+
+    #[test]
+    fn test_topic() {
+        assert_eq!(
+            Ok("poro/861234567890123/command"),
+            topic(DEVICE, "command").as_ref().map(|t| t.as_str())
+        );
+        assert_eq!(
+            Err(Error::Capacity("MQTT topic")),
+            topic(&"x".repeat(MAX_TOPIC), "status")
+        );
+    }
+
+    #[test]
+    fn test_updates() {
+        let mut guard = Guard::new(ProtectorConfig::default());
+        guard.set_service(true);
+        assert_eq!(Ok(None), location_update(&guard, DEVICE));
+        let status = status_update(&guard, DEVICE).unwrap();
+        assert_eq!("poro/861234567890123/status", status.topic.as_str());
+        assert_eq!("$tATA/* * * t", status.payload.as_str());
+        assert!(status.retain);
+
+        guard.update(&location::Location {
+            latitude: 46.7624859,
+            longitude: 18.6304591,
+            accuracy: 5.75,
+            unix_timestamp_millis: 1670077542109,
+            source: location::Source::Gnss,
+            speed: None,
+            course: None,
+        });
+        let location = location_update(&guard, DEVICE).unwrap().unwrap();
+        assert_eq!("poro/861234567890123/location", location.topic.as_str());
+        assert_eq!(
+            "$tATA/5ytnrmgo 2dl4xyfs 3f8q8 0 lb811qsd * * *",
+            location.payload.as_str()
+        );
+        assert!(location.retain);
+    }
+
+    #[tokio::test]
+    async fn test_report_emulated() {
+        let broker = Broker::default();
+        let command_topic = "poro/861234567890123/command";
+        broker.queue(command_topic, b"$tATA/config park_radius=150/12345");
+        broker.queue(command_topic, b"$tATA/trip/12345");
+        broker.queue(command_topic, b"$tATA/trip/54321");
+        let mut sim = sim868_emu::Sim868::default();
+        sim.servers.insert(
+            "10.0.0.2:1883".to_string(),
+            alloc::boxed::Box::new(broker.clone()),
+        );
+        let mut harness = crate::at::tests::Harness::new(sim);
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut guard = Guard::new(ProtectorConfig::default());
+        let mut strategy = location::Strategy::default();
+        let mut store = ConfigStore::new(MemoryStorage::new(1024, 2));
+        let mut tracklog = Tracklog::new(MemoryStorage::new(4096, 2));
+        let mut config = Config {
+            mqtt_server: String::try_from("10.0.0.2:1883").unwrap(),
+            ..Default::default()
+        };
+
+        let ret = harness
+            .run(async |client| {
+                ip::configure(client).await?;
+                let device = crate::network::imei(client).await?;
+                report(
+                    client,
+                    &mut pico,
+                    &mut guard,
+                    &mut strategy,
+                    &mut store,
+                    &mut tracklog,
+                    &mut config,
+                    &device,
+                )
+                .await
+            })
+            .await;
+        assert_eq!(Ok(3), ret.map(|r| r.commands));
+        assert_eq!(150, config.park_radius_meters);
+        assert_eq!(config, store.load());
+        assert!(!harness.modem.with(|sim| sim.bearer_open));
+
+        broker.with(|state| {
+            assert_eq!(Some(DEVICE.to_string()), state.client_id);
+            assert_eq!(KEEP_ALIVE_SECS, state.keep_alive_secs);
+            assert!(!state.clean_session);
+            assert_eq!(
+                alloc::vec![(command_topic.to_string(), 1)],
+                state.subscriptions
+            );
+            // the third one is over mqtt::MAX_INCOMING, delivered again in the next session
+            assert_eq!(alloc::vec![1, 2, 4], state.acked);
+            assert_eq!(3, state.pings);
+            assert!(state.disconnected);
+            let published: alloc::vec::Vec<(&str, &str)> = state
+                .published
+                .iter()
+                .map(|m| (m.topic.as_str(), core::str::from_utf8(&m.payload).unwrap()))
+                .collect();
+            assert_eq!(
+                alloc::vec![
+                    ("poro/861234567890123/status", "$tATA/* * * t"),
+                    (
+                        "poro/861234567890123/reply",
+                        "owner=+36301234567 apn=online park_radius=150 clbs=lbs-simcom.com:3002 country=36 trunk=06 mqtt=10.0.0.2:1883"
+                    ),
+                    ("poro/861234567890123/reply", "No trip"),
+                    ("poro/861234567890123/status", "$tATA/* * * t"),
+                    ("poro/861234567890123/status", "$tATA/* * * t"),
+                ],
+                published
+            );
+        });
+    }
+
+    // Refuses the connection after the first one.
+    struct Once(Broker, usize);
+
+    impl sim868_emu::sim868::Peer for Once {
+        fn receive(&mut self, data: &[u8]) -> alloc::vec::Vec<u8> {
+            if data.first() == Some(&0x10) {
+                self.1 += 1;
+                if self.1 > 1 {
+                    self.0.with(|state| state.connect_code = 5);
+                }
+            }
+            self.0.receive(data)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_report_cut_short_emulated() {
+        let broker = Broker::default();
+        broker.queue("poro/861234567890123/command", b"$tATA/location/12345");
+        let mut sim = sim868_emu::Sim868::default();
+        sim.servers.insert(
+            "10.0.0.2:1883".to_string(),
+            alloc::boxed::Box::new(Once(broker.clone(), 0)),
+        );
+        let mut harness = crate::at::tests::Harness::new(sim);
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut guard = Guard::new(ProtectorConfig::default());
+        let shape = crate::geofence::Shape::parse("46.7724859,18.6304591,200").unwrap();
+        guard
+            .geofence
+            .set_zones(&[crate::geofence::Zone::new("home", shape).unwrap()]);
+        guard.update_zones(&location::Location {
+            latitude: 46.7724859,
+            longitude: 18.6304591,
+            accuracy: 10.0,
+            unix_timestamp_millis: 1670077542109,
+            source: location::Source::Gnss,
+            speed: None,
+            course: None,
+        });
+        let mut strategy = location::Strategy::default();
+        let mut store = ConfigStore::new(MemoryStorage::new(1024, 2));
+        let mut tracklog = Tracklog::new(MemoryStorage::new(4096, 2));
+        let mut config = Config {
+            mqtt_server: String::try_from("10.0.0.2:1883").unwrap(),
+            ..Default::default()
+        };
+
+        let ret = harness
+            .run(async |client| {
+                ip::configure(client).await?;
+                let device = crate::network::imei(client).await?;
+                report(
+                    client,
+                    &mut pico,
+                    &mut guard,
+                    &mut strategy,
+                    &mut store,
+                    &mut tracklog,
+                    &mut config,
+                    &device,
+                )
+                .await
+            })
+            .await
+            .unwrap();
+        // the fix of the emulator is out of the zone, the reply is lost with the second session
+        assert_eq!(1, ret.commands);
+        assert_eq!(1, ret.transitions.len());
+        assert_eq!(poro::Status::ZoneExited, ret.transitions[0].status);
+        assert!(!harness.modem.with(|sim| sim.bearer_open));
+        broker.with(|state| {
+            assert!(state.published.iter().all(|m| !m.topic.ends_with("/reply")));
+        });
+    }
+
+    #[tokio::test]
+    async fn test_report_retained_command_emulated() {
+        let broker = Broker::default();
+        broker.retain(
+            "poro/861234567890123/command",
+            b"$tATA/config park_radius=150/12345",
+        );
+        let mut sim = sim868_emu::Sim868::default();
+        sim.servers.insert(
+            "10.0.0.2:1883".to_string(),
+            alloc::boxed::Box::new(broker.clone()),
+        );
+        let mut harness = crate::at::tests::Harness::new(sim);
+        let mut pico = crate::at::tests::EmuPico::new(harness.modem.clone());
+        let mut guard = Guard::new(ProtectorConfig::default());
+        let mut strategy = location::Strategy::default();
+        let mut store = ConfigStore::new(MemoryStorage::new(1024, 2));
+        let mut tracklog = Tracklog::new(MemoryStorage::new(4096, 2));
+        let mut config = Config {
+            mqtt_server: String::try_from("10.0.0.2:1883").unwrap(),
+            ..Default::default()
+        };
+        let park_radius_meters = config.park_radius_meters;
+
+        for _ in 0..2 {
+            let ret = harness
+                .run(async |client| {
+                    ip::configure(client).await?;
+                    let device = crate::network::imei(client).await?;
+                    report(
+                        client,
+                        &mut pico,
+                        &mut guard,
+                        &mut strategy,
+                        &mut store,
+                        &mut tracklog,
+                        &mut config,
+                        &device,
+                    )
+                    .await
+                })
+                .await;
+            assert_eq!(Ok(0), ret.map(|r| r.commands));
+        }
+        assert_eq!(park_radius_meters, config.park_radius_meters);
+        broker.with(|state| {
+            // delivered on both subscriptions
+            assert_eq!(alloc::vec![1, 2], state.acked);
+            assert!(state.published.iter().all(|m| !m.topic.ends_with("/reply")));
+        });
+    }
+}
@@ -0,0 +1,511 @@
+use alloc::vec::Vec;
+use defmt::Format;
+use defmt::info;
+
+use crate::error::Error;
+use crate::location::Location;
+use crate::location::Source;
+use crate::storage::ERASED;
+use crate::storage::Storage;
+use crate::utils;
+use crate::utils::crc32;
+
+// Breadcrumbs, the fixes of the device in a ring buffer in flash.
+//
+// Fixed size records, every append goes to the slot after the latest one (the highest
+// sequence number), the sector ahead is erased when the writer reaches it, so the oldest
+// sector worth of fixes is dropped when the log is full.
+//
+// Record: | seq u32 | unix_timestamp_millis i64 | lat i32 | lon i32 | speed u16 | course u16 |
+//         | accuracy u16 | source u8 | flags u8 | crc32 u32 (seq..flags) |, little endian,
+//         coordinates in 1e-7 degrees, speed in 0.1 km/h, course in 0.01 degrees, accuracy
+//         in 0.1 meters, 0xFFFF speed or course is unknown.
+//
+// Trips: a fix faster than MOVING_KMH or farther from the previous one than their accuracies
+// (plus MOVE_METERS) starts a trip, the first fix after STOP_MILLIS without moving stops it.
+// While parked a fix is logged every PARKED_INTERVAL_MILLIS only.
+
+pub const RECORD_SIZE: usize = 32;
+pub const TRIP_START: u8 = 0x01;
+pub const TRIP_STOP: u8 = 0x02;
+pub const MOVING: u8 = 0x04; // in a trip
+const MOVING_KMH: f64 = 5.0;
+const MOVE_METERS: f64 = 100.0;
+const STOP_MILLIS: i64 = 5 * 60 * 1000;
+const PARKED_INTERVAL_MILLIS: i64 = 15 * 60 * 1000;
+const UNKNOWN: u16 = 0xFFFF;
+
+#[derive(Debug, Format, Clone, PartialEq)]
+pub struct Point {
+    pub unix_timestamp_millis: i64,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub speed: Option<f64>,
+    pub course: Option<f64>,
+    pub accuracy: f64,
+    pub source: Source,
+    pub flags: u8,
+}
+
+impl Point {
+    fn new(location: &Location, flags: u8) -> Point {
+        Point {
+            unix_timestamp_millis: location.unix_timestamp_millis,
+            latitude: location.latitude,
+            longitude: location.longitude,
+            speed: location.speed,
+            course: location.course,
+            accuracy: location.accuracy,
+            source: location.source,
+            flags,
+        }
+    }
+
+    pub fn distance(&self, other: &Point) -> f64 {
+        utils::get_distance_in_meters(
+            self.latitude,
+            self.longitude,
+            other.latitude,
+            other.longitude,
+        )
+    }
+
+    fn encode(&self, seq: u32) -> [u8; RECORD_SIZE] {
+        let scaled = |v: Option<f64>, scale: f64| {
+            v.map_or(UNKNOWN, |v| {
+                libm::round(v * scale).clamp(0.0, (UNKNOWN - 1) as f64) as u16
+            })
+        };
+        let mut record = [0u8; RECORD_SIZE];
+        record[0..4].copy_from_slice(&seq.to_le_bytes());
+        record[4..12].copy_from_slice(&self.unix_timestamp_millis.to_le_bytes());
+        record[12..16].copy_from_slice(&(libm::round(self.latitude * 1e7) as i32).to_le_bytes());
+        record[16..20].copy_from_slice(&(libm::round(self.longitude * 1e7) as i32).to_le_bytes());
+        record[20..22].copy_from_slice(&scaled(self.speed, 10.0).to_le_bytes());
+        record[22..24].copy_from_slice(&scaled(self.course, 100.0).to_le_bytes());
+        record[24..26].copy_from_slice(&scaled(Some(self.accuracy), 10.0).to_le_bytes());
+        record[26] = match self.source {
+            Source::Gnss => 0,
+            Source::Cell => 1,
+        };
+        record[27] = self.flags;
+        let crc = crc32(&record[..28]);
+        record[28..32].copy_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    fn decode(record: &[u8; RECORD_SIZE]) -> Result<(u32, Point), Error> {
+        if record.iter().all(|b| *b == ERASED) {
+            return Err(Error::Storage("no track record"));
+        }
+        if u32::from_le_bytes(record[28..32].try_into().unwrap()) != crc32(&record[..28]) {
+            return Err(Error::Storage("track crc mismatch"));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes(record[i..i + 2].try_into().unwrap());
+        let i32_at = |i: usize| i32::from_le_bytes(record[i..i + 4].try_into().unwrap());
+        let scaled = |v: u16, scale: f64| (v != UNKNOWN).then_some(v as f64 / scale);
+        Ok((
+            u32::from_le_bytes(record[0..4].try_into().unwrap()),
+            Point {
+                unix_timestamp_millis: i64::from_le_bytes(record[4..12].try_into().unwrap()),
+                latitude: i32_at(12) as f64 / 1e7,
+                longitude: i32_at(16) as f64 / 1e7,
+                speed: scaled(u16_at(20), 10.0),
+                course: scaled(u16_at(22), 100.0),
+                accuracy: u16_at(24) as f64 / 10.0,
+                source: match record[26] {
+                    0 => Source::Gnss,
+                    1 => Source::Cell,
+                    _ => return Err(Error::Storage("invalid track source")),
+                },
+                flags: record[27],
+            },
+        ))
+    }
+}
+
+// The latest trip, ongoing when it has not stopped yet.
+#[derive(Debug, Format, Clone, PartialEq)]
+pub struct Summary {
+    pub start: Point,
+    pub end: Point,
+    pub ongoing: bool,
+    pub distance_meters: f64,
+    pub max_speed: Option<f64>,
+    pub points: usize,
+}
+
+pub struct Tracklog<S: Storage> {
+    storage: S,
+    latest: Option<(usize, u32, Point)>, // slot, sequence number
+    last_moving_millis: i64,
+}
+
+impl<S: Storage> Tracklog<S> {
+    pub fn new(storage: S) -> Self {
+        assert!(storage.capacity() >= 2 * storage.sector_size());
+        assert!(storage.sector_size().is_multiple_of(RECORD_SIZE));
+        Tracklog {
+            storage,
+            latest: None,
+            last_moving_millis: 0,
+        }
+    }
+
+    pub fn storage(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    fn slots(&self) -> usize {
+        self.storage.capacity() / RECORD_SIZE
+    }
+
+    fn slots_per_sector(&self) -> usize {
+        self.storage.sector_size() / RECORD_SIZE
+    }
+
+    // Finds the latest record, the trip state continues from there.
+    pub fn load(&mut self) {
+        self.latest = None;
+        for slot in 0..self.slots() {
+            if let Ok((seq, point)) = self.read_slot(slot)
+                && self.latest.as_ref().is_none_or(|(_, s, _)| seq > *s)
+            {
+                self.latest = Some((slot, seq, point));
+            }
+        }
+        self.last_moving_millis = self
+            .latest
+            .as_ref()
+            .map_or(0, |(_, _, p)| p.unix_timestamp_millis);
+        info!(
+            "Tracklog: latest {:?}",
+            self.latest.as_ref().map(|(slot, seq, _)| (*slot, *seq))
+        );
+    }
+
+    // Returns whether the fix was logged, an older one (e.g. cached) or one while parked
+    // within PARKED_INTERVAL_MILLIS is not.
+    pub fn append(&mut self, location: &Location) -> Result<bool, Error> {
+        let point = Point::new(location, 0);
+        let (in_trip, moving) = match self.latest.as_ref().map(|(_, _, p)| p) {
+            None => (false, false),
+            Some(last) if point.unix_timestamp_millis <= last.unix_timestamp_millis => {
+                return Ok(false);
+            }
+            Some(last) => (
+                last.flags & MOVING != 0,
+                point.speed.is_some_and(|s| s >= MOVING_KMH)
+                    || last.distance(&point) > MOVE_METERS + last.accuracy + point.accuracy,
+            ),
+        };
+        let since_moving = point.unix_timestamp_millis - self.last_moving_millis;
+        let flags = match (in_trip, moving) {
+            (false, true) => TRIP_START | MOVING,
+            (true, true) => MOVING,
+            (true, false) if since_moving >= STOP_MILLIS => TRIP_STOP,
+            (true, false) => MOVING,
+            (false, false) => {
+                let since_last = self.latest.as_ref().map_or(i64::MAX, |(_, _, p)| {
+                    point.unix_timestamp_millis - p.unix_timestamp_millis
+                });
+                if since_last < PARKED_INTERVAL_MILLIS {
+                    return Ok(false);
+                }
+                0
+            }
+        };
+        self.write(&Point { flags, ..point })?;
+        if moving {
+            self.last_moving_millis = location.unix_timestamp_millis;
+        }
+        if flags & TRIP_START != 0 {
+            info!("Tracklog: trip started");
+        }
+        if flags & TRIP_STOP != 0 {
+            info!("Tracklog: trip stopped");
+        }
+        Ok(true)
+    }
+
+    fn write(&mut self, point: &Point) -> Result<(), Error> {
+        let (mut slot, seq) = match self.latest.as_ref() {
+            Some((slot, seq, _)) => ((slot + 1) % self.slots(), seq.wrapping_add(1)),
+            None => (0, 1),
+        };
+
+        if slot % self.slots_per_sector() != 0 && !self.is_blank(slot)? {
+            // e.g. interrupted write, continue in the next sector
+            slot = (slot / self.slots_per_sector() + 1) * self.slots_per_sector() % self.slots();
+        }
+        if slot % self.slots_per_sector() == 0 {
+            self.storage.erase_sector(slot * RECORD_SIZE)?;
+        }
+        self.storage.write(slot * RECORD_SIZE, &point.encode(seq))?;
+        self.latest = Some((slot, seq, point.clone()));
+        Ok(())
+    }
+
+    fn is_blank(&mut self, slot: usize) -> Result<bool, Error> {
+        let mut record = [0u8; RECORD_SIZE];
+        self.storage.read(slot * RECORD_SIZE, &mut record)?;
+        Ok(record.iter().all(|b| *b == ERASED))
+    }
+
+    fn read_slot(&mut self, slot: usize) -> Result<(u32, Point), Error> {
+        let mut record = [0u8; RECORD_SIZE];
+        self.storage.read(slot * RECORD_SIZE, &mut record)?;
+        Point::decode(&record)
+    }
+
+    // Newest first, the records in sequence going back from the latest one.
+    fn walk(&mut self) -> impl Iterator<Item = Point> + '_ {
+        let slots = self.slots();
+        let mut next = self.latest.as_ref().map(|(slot, seq, _)| (*slot, *seq));
+        let mut seen = 0;
+        core::iter::from_fn(move || {
+            let (slot, seq) = next?;
+            seen += 1;
+            if seen > slots {
+                return None;
+            }
+            let point = match self.read_slot(slot) {
+                Ok((s, p)) if s == seq => p,
+                _ => return None,
+            };
+            // a skipped slot (see write) is not in sequence, it is looked past
+            let mut prev = (slot + slots - 1) % slots;
+            next = None;
+            for _ in 0..self.slots_per_sector() {
+                if let Ok((s, _)) = self.read_slot(prev)
+                    && s == seq.wrapping_sub(1)
+                {
+                    next = Some((prev, s));
+                    break;
+                }
+                prev = (prev + slots - 1) % slots;
+            }
+            Some(point)
+        })
+    }
+
+    // Whether the latest fix is of an ongoing trip, without reading the log.
+    pub fn in_trip(&self) -> bool {
+        self.latest
+            .as_ref()
+            .is_some_and(|(_, _, p)| p.flags & MOVING != 0)
+    }
+
+    // The commands ask for poro::MAX_TRACK_POINTS at most, see dispatcher::track.
+    pub fn last(&mut self, n: usize) -> Vec<Point> {
+        let mut ret = Vec::with_capacity(n);
+        ret.extend(self.walk().take(n));
+        ret
+    }
+
+    // None without a trip start in the log. A trip can be thousands of fixes, these are
+    // summed up as they are read instead of being collected.
+    pub fn trip(&mut self) -> Option<Summary> {
+        let mut end: Option<Point> = None;
+        let mut prev: Option<Point> = None;
+        let mut distance_meters = 0.0;
+        let mut max_speed: Option<f64> = None;
+        let mut points = 0;
+        for point in self.walk() {
+            if end.is_none() && point.flags & (MOVING | TRIP_STOP) == 0 {
+                continue; // parked since the last trip
+            }
+            if let Some(p) = prev.as_ref() {
+                distance_meters += p.distance(&point);
+            }
+            if let Some(speed) = point.speed {
+                max_speed = Some(max_speed.map_or(speed, |m| m.max(speed)));
+            }
+            points += 1;
+            if end.is_none() {
+                end = Some(point.clone());
+            }
+            if point.flags & TRIP_START != 0 {
+                let end = end?;
+                return Some(Summary {
+                    start: point,
+                    ongoing: end.flags & TRIP_STOP == 0,
+                    end,
+                    distance_meters,
+                    max_speed,
+                    points,
+                });
+            }
+            prev = Some(point);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::MemoryStorage;
+
+    const MINUTE: i64 = 60 * 1000;
+
+    fn loc(latitude: f64, speed: Option<f64>, minute: i64) -> Location {
+        Location {
+            latitude,
+            longitude: 18.6304591,
+            accuracy: 5.75,
+            unix_timestamp_millis: 1670846541000 + minute * MINUTE,
+            source: Source::Gnss,
+            speed,
+            course: speed.map(|_| 285.8),
+        }
+    }
+
+    // 4 sectors of 4 records
+    fn tracklog() -> Tracklog<MemoryStorage> {
+        Tracklog::new(MemoryStorage::new(4 * RECORD_SIZE, 4))
+    }
+
+    #[test]
+    fn test_record() {
+        let point = Point::new(&loc(46.7624859, Some(12.34), 0), TRIP_START | MOVING);
+        let record = point.encode(7);
+        assert_eq!(
+            Ok((
+                7,
+                Point {
+                    speed: Some(12.3),
+                    accuracy: 5.8,
+                    ..point.clone()
+                }
+            )),
+            Point::decode(&record)
+        );
+
+        let point = Point {
+            latitude: -33.8688197,
+            longitude: -151.2092955,
+            speed: None,
+            course: None,
+            accuracy: 10000.0,
+            source: Source::Cell,
+            ..point
+        };
+        let (_, decoded) = Point::decode(&point.encode(8)).unwrap();
+        assert_eq!(-33.8688197, decoded.latitude);
+        assert_eq!(-151.2092955, decoded.longitude);
+        assert_eq!(None, decoded.speed);
+        assert_eq!(6553.4, decoded.accuracy);
+        assert_eq!(Source::Cell, decoded.source);
+
+        let mut record = point.encode(8);
+        record[13] ^= 0x01;
+        assert_eq!(
+            Err(Error::Storage("track crc mismatch")),
+            Point::decode(&record)
+        );
+        assert_eq!(
+            Err(Error::Storage("no track record")),
+            Point::decode(&[ERASED; RECORD_SIZE])
+        );
+    }
+
+    #[test]
+    fn test_trip() {
+        let mut log = tracklog();
+        assert_eq!(None, log.trip());
+        assert!(log.append(&loc(46.7624859, Some(0.0), 0)).unwrap());
+        // parked, not logged
+        assert!(!log.append(&loc(46.7624860, Some(0.0), 5)).unwrap());
+        // the same fix again
+        assert!(!log.append(&loc(46.7624860, Some(0.0), 0)).unwrap());
+
+        // started by the speed
+        assert!(log.append(&loc(46.7634859, Some(30.0), 16)).unwrap());
+        assert!(log.append(&loc(46.7734859, Some(87.5), 17)).unwrap());
+        // standing at a traffic light
+        assert!(log.append(&loc(46.7834859, Some(0.0), 18)).unwrap());
+        assert!(log.append(&loc(46.7834859, Some(0.0), 20)).unwrap());
+        let trip = log.trip().unwrap();
+        assert!(trip.ongoing);
+        assert!(log.in_trip());
+        assert_eq!(4, trip.points);
+        assert_eq!(Some(87.5), trip.max_speed);
+        assert_eq!(TRIP_START | MOVING, trip.start.flags);
+
+        // stopped 5 minutes after the last move
+        assert!(log.append(&loc(46.7834859, Some(0.0), 22)).unwrap());
+        assert!(log.append(&loc(46.7834859, Some(0.0), 23)).unwrap());
+        let trip = log.trip().unwrap();
+        assert!(!trip.ongoing);
+        assert!(!log.in_trip());
+        assert_eq!(TRIP_STOP, trip.end.flags);
+        assert_eq!(1670846541000 + 23 * MINUTE, trip.end.unix_timestamp_millis);
+        assert_eq!(6, trip.points);
+        assert!((trip.distance_meters - 2224.0).abs() < 1.0);
+
+        // parked again, the summary stays
+        assert!(!log.append(&loc(46.7834859, Some(0.0), 30)).unwrap());
+        assert!(log.append(&loc(46.7834859, Some(0.0), 40)).unwrap());
+        assert_eq!(Some(trip), log.trip());
+
+        // started by the distance (e.g. CLBS, no speed)
+        assert!(log.append(&loc(46.7934859, None, 41)).unwrap());
+        let trip = log.trip().unwrap();
+        assert!(trip.ongoing);
+        assert_eq!(1, trip.points);
+        assert_eq!(None, trip.max_speed);
+    }
+
+    #[test]
+    fn test_ring() {
+        let mut log = tracklog();
+        for i in 0..20 {
+            log.append(&loc(46.7624859 + 0.01 * i as f64, Some(50.0), i))
+                .unwrap();
+        }
+        // the first sector was erased for the 17th record
+        let last = log.last(20);
+        assert_eq!(16, last.len());
+        assert_eq!(1670846541000 + 19 * MINUTE, last[0].unix_timestamp_millis);
+        assert_eq!(1670846541000 + 4 * MINUTE, last[15].unix_timestamp_millis);
+        assert_eq!(alloc::vec![0, 128, 256, 384, 0], log.storage().erase_calls);
+        // the trip start is gone, the summary is not reliable
+        assert_eq!(None, log.trip());
+
+        // reloaded, e.g. after a reset
+        let storage = log.storage;
+        let mut log = Tracklog::new(storage);
+        log.load();
+        assert_eq!(3, log.last(3).len());
+        assert_eq!(
+            1670846541000 + 19 * MINUTE,
+            log.last(1)[0].unix_timestamp_millis
+        );
+        log.append(&loc(46.9624859, Some(0.0), 20)).unwrap();
+        assert_eq!(MOVING, log.last(1)[0].flags);
+    }
+
+    #[test]
+    fn test_corrupted_record() {
+        let mut log = tracklog();
+        for i in 0..6 {
+            log.append(&loc(46.7624859 + 0.01 * i as f64, Some(50.0), i))
+                .unwrap();
+        }
+        // the 5th record is damaged, the older ones can not be told in sequence
+        log.storage().data[4 * RECORD_SIZE + 13] ^= 0x01;
+        assert_eq!(1, log.last(10).len());
+
+        // an interrupted write in the middle of a sector
+        log.storage().data[6 * RECORD_SIZE] = 0;
+        log.append(&loc(46.9, Some(50.0), 6)).unwrap();
+        assert_eq!(
+            1670846541000 + 6 * MINUTE,
+            log.last(1)[0].unix_timestamp_millis
+        );
+        assert_eq!(alloc::vec![0, 128, 256], log.storage().erase_calls);
+        // looked past the skipped slots
+        assert_eq!(2, log.last(10).len());
+    }
+}
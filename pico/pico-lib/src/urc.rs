@@ -3,7 +3,11 @@ use atat::atat_derive::AtatUrc;
 use atat::heapless_bytes::Bytes;
 
 use crate::call::ClipUrc;
+use crate::gps::GnssNavigationInformationUrc;
+use crate::http::HttpActionUrc;
 use crate::network::EnterPinReadResponse;
+use crate::network::NetworkRegistrationUrc;
+use crate::network::parse_registration_urc;
 use crate::sms::NewMessageIndicationUrc;
 
 // 18.1 CME ERROR
@@ -56,4 +60,10 @@ pub enum Urc {
     ClipUrc(ClipUrc),
     #[at_urc("+CMTI")]
     NewMessageIndicationUrc(NewMessageIndicationUrc),
+    #[at_urc("+HTTPACTION")]
+    HttpAction(HttpActionUrc),
+    #[at_urc("+CGREG", parse = parse_registration_urc)]
+    NetworkRegistration(NetworkRegistrationUrc),
+    #[at_urc("+UGNSINF")]
+    GnssNavigationInformation(GnssNavigationInformationUrc),
 }
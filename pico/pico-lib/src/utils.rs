@@ -1,13 +1,11 @@
 use defmt::info;
 
-use core::num::ParseFloatError;
-use core::num::ParseIntError;
-use core::str::Utf8Error;
-
 use alloc::collections::vec_deque::VecDeque;
 use alloc::string::String;
 use libm::{asin, cos, pow, sin, sqrt};
 
+use crate::error::Error;
+
 // https://stackoverflow.com/questions/27928/calculate-distance-between-two-latitude-longitude-points-haversine-formula
 pub fn get_distance_in_meters(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
     let earth_radius_in_meters = 6371000f64;
@@ -26,6 +24,19 @@ pub fn estimate_gps_accuracy(pdop: f64) -> f64 {
     return 2.5 * pdop;
 }
 
+// CRC-32 (IEEE 802.3), bitwise, no table to keep the flash footprint small.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for b in bytes {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
+
 pub fn as_tokens(input: String, delimiter: &'static str) -> VecDeque<String> {
     let parts = input.split(delimiter);
     let mut tokens = VecDeque::new();
@@ -51,52 +62,13 @@ pub fn astring_to_string<'a, const N: usize>(string: &'a str) -> atat::heapless:
 
 extern crate atat;
 
-#[allow(dead_code)] // field `0` is never read, TODO: research
-pub struct AtatError(atat::Error);
-
-impl From<ParseFloatError> for AtatError {
-    fn from(_: ParseFloatError) -> Self {
-        AtatError(atat::Error::Parse)
-    }
-}
-
-impl From<ParseIntError> for AtatError {
-    fn from(_: ParseIntError) -> Self {
-        AtatError(atat::Error::Parse)
-    }
-}
-
-impl From<()> for AtatError {
-    fn from(_: ()) -> Self {
-        AtatError(atat::Error::Parse)
-    }
-}
-
-impl From<Utf8Error> for AtatError {
-    fn from(_: Utf8Error) -> Self {
-        AtatError(atat::Error::Parse)
-    }
-}
-
-impl From<atat::Error> for AtatError {
-    fn from(value: atat::Error) -> Self {
-        AtatError(value)
-    }
-}
-
-impl From<atat::serde_at::de::Error> for AtatError {
-    fn from(_: atat::serde_at::de::Error) -> Self {
-        AtatError(atat::Error::Parse)
-    }
-}
-
 pub async fn send_command_logged<T: atat::asynch::AtatClient, U: atat::AtatCmd>(
     client: &mut T,
     command: &U,
     context: String,
-) -> Result<<U as atat::AtatCmd>::Response, atat::Error> {
+) -> Result<<U as atat::AtatCmd>::Response, Error> {
     info!("SENDING COMMAND: {}", context.as_str());
-    let r = client.send(command).await;
+    let r = client.send(command).await.map_err(Error::from);
     match r.as_ref() {
         Ok(_) => info!("  OK"), // TODO: {:?}, v ?
         Err(e) => info!("  ERROR: {:?}", e),
@@ -119,4 +91,14 @@ mod tests {
             get_distance_in_meters(46.7624859f64, 18.6304591f64, 46.7624859f64, 18.6304591f64)
         );
     }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0, crc32(b""));
+        assert_eq!(0xCBF43926, crc32(b"123456789"));
+        assert_eq!(
+            0x414FA339,
+            crc32(b"The quick brown fox jumps over the lazy dog")
+        );
+    }
 }
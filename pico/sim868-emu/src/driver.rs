@@ -0,0 +1,71 @@
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::task::Waker;
+use std::time::Duration;
+use std::time::Instant;
+
+use embassy_time_driver::Driver;
+use embassy_time_driver::TICK_HZ;
+
+// embassy-time driver on the host for the atat client timeouts.
+//
+// embassy-time's own "std" driver pulls in a timer queue which conflicts with the one
+// used by embassy-rp in the app, so a minimal one is provided here: a thread waking the
+// wakers when their time has come.
+
+struct StdDriver {
+    start: OnceLock<Instant>,
+    queue: Mutex<Vec<(u64, Waker)>>,
+    signal: Condvar,
+    timer: OnceLock<()>,
+}
+
+impl StdDriver {
+    fn start(&self) -> Instant {
+        *self.start.get_or_init(Instant::now)
+    }
+
+    fn run(&'static self) {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            let now = self.now();
+            queue.retain(|(at, waker)| {
+                if *at <= now {
+                    waker.wake_by_ref();
+                }
+                *at > now
+            });
+            let timeout = match queue.iter().map(|(at, _)| *at).min() {
+                Some(at) => Duration::from_micros((at - now) * 1_000_000 / TICK_HZ + 1),
+                None => Duration::from_secs(3600),
+            };
+            queue = self.signal.wait_timeout(queue, timeout).unwrap().0;
+        }
+    }
+}
+
+impl Driver for StdDriver {
+    fn now(&self) -> u64 {
+        self.start().elapsed().as_micros() as u64 * TICK_HZ / 1_000_000
+    }
+
+    fn schedule_wake(&self, at: u64, waker: &Waker) {
+        self.timer.get_or_init(|| {
+            std::thread::spawn(|| DRIVER.run());
+        });
+        let mut queue = self.queue.lock().unwrap();
+        match queue.iter_mut().find(|(_, w)| w.will_wake(waker)) {
+            Some(entry) => entry.0 = entry.0.min(at),
+            None => queue.push((at, waker.clone())),
+        }
+        self.signal.notify_one();
+    }
+}
+
+embassy_time_driver::time_driver_impl!(static DRIVER: StdDriver = StdDriver {
+    start: OnceLock::new(),
+    queue: Mutex::new(Vec::new()),
+    signal: Condvar::new(),
+    timer: OnceLock::new(),
+});
@@ -0,0 +1,253 @@
+// SIM868 emulator for host-side end-to-end tests.
+//
+// The emulator sits behind the same atat Client/Ingress pair as the real module on the
+// UART, so pico-lib flows run unchanged under tokio:
+//
+//     let mut harness = Harness::<Urc>::new(Sim868::default());
+//     harness.modem.with(|sim| sim.set_registration(Registration::Searching));
+//     harness.run(async |client| init_network(client, &mut pico).await).await;
+//
+// The modem state (registration, SMS storage, calls, GNSS fix, bearer, ...) can be
+// inspected and changed at any time through `Modem::with`, URCs are injected the same
+// way, and `Sim868::on` scripts replies, errors, timeouts or state changes for the next
+// matching command.
+
+mod driver;
+mod logger;
+pub mod mqtt;
+pub mod pdu;
+pub mod sim868;
+pub mod ucs2;
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use atat::AtatIngress;
+use atat::AtatUrc;
+use atat::DefaultDigester;
+use atat::Digester;
+use atat::Ingress;
+use atat::Parser;
+use atat::ResponseSlot;
+use atat::UrcChannel;
+use atat::asynch::Client;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+pub use sim868::Action;
+pub use sim868::Registration;
+pub use sim868::Sim868;
+
+pub const INGRESS_BUF_SIZE: usize = 1024;
+pub const URC_CAPACITY: usize = 128;
+pub const URC_SUBSCRIBERS: usize = 3;
+
+struct Inner {
+    sim: Sim868,
+    tx: mpsc::UnboundedSender<(Instant, Vec<u8>)>,
+    last: Instant,
+}
+
+impl Inner {
+    fn flush(&mut self) {
+        for (delay, bytes) in self.sim.take_output() {
+            self.last = self.last.max(Instant::now()) + delay;
+            // the reader is gone when the test is over
+            let _ = self.tx.send((self.last, bytes));
+        }
+    }
+}
+
+// Shared handle of the emulated module.
+#[derive(Clone)]
+pub struct Modem {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Modem {
+    // The modem with its UART, host to module and module to host.
+    pub fn new(sim: Sim868) -> (Modem, Writer, Reader) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let modem = Modem {
+            inner: Arc::new(Mutex::new(Inner {
+                sim,
+                tx,
+                last: Instant::now(),
+            })),
+        };
+        let writer = Writer {
+            modem: modem.clone(),
+        };
+        let reader = Reader {
+            rx,
+            pending: Vec::new(),
+        };
+        (modem, writer, reader)
+    }
+
+    // Access to the module state, the output (e.g. URCs) is sent afterwards.
+    pub fn with<R>(&self, f: impl FnOnce(&mut Sim868) -> R) -> R {
+        let mut inner = self.inner.lock().unwrap();
+        let ret = f(&mut inner.sim);
+        inner.flush();
+        ret
+    }
+}
+
+pub struct Writer {
+    modem: Modem,
+}
+
+impl embedded_io_async::ErrorType for Writer {
+    type Error = Infallible;
+}
+
+impl embedded_io_async::Write for Writer {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.modem.with(|sim| sim.write(buf));
+        Ok(buf.len())
+    }
+}
+
+pub struct Reader {
+    rx: mpsc::UnboundedReceiver<(Instant, Vec<u8>)>,
+    pending: Vec<u8>,
+}
+
+impl embedded_io_async::ErrorType for Reader {
+    type Error = Infallible;
+}
+
+impl embedded_io_async::Read for Reader {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.pending.is_empty() {
+            let Some((deliver, bytes)) = self.rx.recv().await else {
+                return core::future::pending().await;
+            };
+            tokio::time::sleep_until(deliver).await;
+            self.pending = bytes;
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+pub type EmuClient = Client<'static, Writer, INGRESS_BUF_SIZE>;
+
+// The atat client and ingress wired to an emulated module, like in app/src/main.rs. The
+// digester is the default one unless given, e.g. the chain of the app.
+pub struct Harness<U: AtatUrc + Parser + 'static, D: Digester = DefaultDigester<U>> {
+    pub modem: Modem,
+    pub client: EmuClient,
+    pub urc_channel: &'static UrcChannel<U, URC_CAPACITY, URC_SUBSCRIBERS>,
+    ingress: Ingress<'static, D, U, INGRESS_BUF_SIZE, URC_CAPACITY, URC_SUBSCRIBERS>,
+    reader: Reader,
+}
+
+impl<U: AtatUrc + Parser + 'static, D: Digester + Default> Harness<U, D> {
+    pub fn new(sim: Sim868) -> Self {
+        Self::with_digester(sim, D::default())
+    }
+}
+
+impl<U: AtatUrc + Parser + 'static, D: Digester> Harness<U, D> {
+    pub fn with_digester(sim: Sim868, digester: D) -> Self {
+        // leaked, one set per test
+        let res_slot: &'static ResponseSlot<INGRESS_BUF_SIZE> =
+            Box::leak(Box::new(ResponseSlot::new()));
+        let urc_channel: &'static UrcChannel<U, URC_CAPACITY, URC_SUBSCRIBERS> =
+            Box::leak(Box::new(UrcChannel::new()));
+        let ingress_buf: &'static mut [u8] = Box::leak(Box::new([0; INGRESS_BUF_SIZE]));
+        let client_buf: &'static mut [u8] = Box::leak(Box::new([0; 2048]));
+
+        let (modem, writer, reader) = Modem::new(sim);
+        Harness {
+            modem,
+            client: Client::new(writer, res_slot, client_buf, atat::Config::default()),
+            urc_channel,
+            ingress: Ingress::new(digester, ingress_buf, res_slot, urc_channel),
+            reader,
+        }
+    }
+
+    // Runs `f` while the ingress is reading the module output.
+    pub async fn run<R>(&mut self, f: impl AsyncFnOnce(&mut EmuClient) -> R) -> R {
+        tokio::select! {
+            ret = f(&mut self.client) => ret,
+            _ = self.ingress.read_from(&mut self.reader) => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atat::asynch::AtatClient;
+    use atat::atat_derive::AtatCmd;
+    use atat::atat_derive::AtatResp;
+    use atat::atat_derive::AtatUrc;
+    use std::time::Duration;
+
+    #[derive(Clone, AtatResp)]
+    struct NoResponse;
+
+    #[derive(Clone, AtatCmd)]
+    #[at_cmd("", NoResponse, timeout_ms = 200)]
+    struct At;
+
+    #[derive(Clone, AtatResp)]
+    struct Csq {
+        rssi: u8,
+        _ber: u8,
+    }
+
+    #[derive(Clone, AtatCmd)]
+    #[at_cmd("+CSQ", Csq, timeout_ms = 200)]
+    struct GetCsq;
+
+    #[derive(Clone, AtatResp)]
+    struct Cmti {
+        _storage: atat::heapless::String<4>,
+        index: u32,
+    }
+
+    #[derive(Clone, AtatUrc)]
+    enum Urc {
+        #[at_urc("+CMTI")]
+        Cmti(Cmti),
+    }
+
+    #[tokio::test]
+    async fn test_harness() {
+        let mut harness = Harness::<Urc>::new(Sim868::default());
+        let mut sub = harness.urc_channel.subscribe().unwrap();
+        harness.modem.with(|sim| {
+            sim.on("AT+CSQ", Action::Silent)
+                .on("AT+CSQ", Action::Delay(Duration::from_millis(50)));
+        });
+
+        harness
+            .run(async |client| {
+                assert!(client.send(&At).await.is_ok());
+                assert_eq!(
+                    Err(atat::Error::Timeout),
+                    client.send(&GetCsq).await.map(|_| ())
+                );
+                assert_eq!(20, client.send(&GetCsq).await.unwrap().rssi);
+            })
+            .await;
+
+        harness
+            .modem
+            .with(|sim| sim.receive_sms("+36301234567", "hello", "26/01/10,17:25:32+04"));
+        let urc = harness.run(async |_| sub.next_message_pure().await).await;
+        assert!(matches!(urc, Urc::Cmti(Cmti { index: 1, .. })));
+        assert_eq!(
+            vec!["AT", "AT+CSQ", "AT+CSQ"],
+            harness.modem.with(|sim| sim.commands.clone())
+        );
+    }
+}
@@ -0,0 +1,25 @@
+// defmt logger for the host test binaries.
+//
+// atat logs the digester errors with defmt in pico-lib, which needs a global logger to
+// link. The frames are binary encoded and there is no probe to decode them, so they are
+// dropped.
+
+#[defmt::global_logger]
+struct NullLogger;
+
+unsafe impl defmt::Logger for NullLogger {
+    fn acquire() {}
+
+    unsafe fn flush() {}
+
+    unsafe fn release() {}
+
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+defmt::timestamp!("{=u64:us}", embassy_time_driver::now());
+
+#[defmt::panic_handler]
+fn panic() -> ! {
+    panic!("defmt panic")
+}
@@ -0,0 +1,379 @@
+// MQTT 3.1.1 broker stand-in, a Peer of Sim868::servers for one client at a time.
+//
+// The messages published to the broker before the client subscribes are kept, they are
+// delivered after the SUBACK of a matching subscription (like the queue of a persistent
+// session). A queued message the client does not acknowledge is queued again when it
+// connects without a clean session (MQTT 3.1.1 4.4). The retained ones are delivered again on every matching SUBSCRIBE, with the
+// RETAIN flag set (MQTT 3.1.1 3.3.1.3). The state is shared by the clones, keep one to
+// inspect it:
+//
+//     let broker = Broker::default();
+//     broker.queue("poro/861234567890123/command", b"...");
+//     sim.servers.insert("10.0.0.2:1883".to_string(), Box::new(broker.clone()));
+
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::sim868::Peer;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: u8,
+    pub retain: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct State {
+    pub client_id: Option<String>, // of the last CONNECT
+    pub keep_alive_secs: u16,
+    pub clean_session: bool,
+    pub connect_code: u8,                 // of the CONNACK, 0 accepts
+    pub subscriptions: Vec<(String, u8)>, // filter, QoS
+    pub published: Vec<Message>,          // by the client
+    pub queued: Vec<Message>,             // for the client
+    pub retained: Vec<Message>,           // the last retained message of each topic
+    pub acked: Vec<u16>,                  // PUBACKs of the client
+    inflight: Vec<(u16, Message)>,        // delivered from the queue, not acknowledged yet
+    pub pings: usize,
+    pub disconnected: bool,
+    input: Vec<u8>,
+    next_id: u16,
+}
+
+#[derive(Clone, Default)]
+pub struct Broker(Arc<Mutex<State>>);
+
+impl Broker {
+    pub fn with<R>(&self, f: impl FnOnce(&mut State) -> R) -> R {
+        f(&mut self.0.lock().unwrap())
+    }
+
+    // Delivered on the next matching subscription.
+    pub fn queue(&self, topic: &str, payload: &[u8]) {
+        self.with(|state| {
+            state.queued.push(Message {
+                topic: topic.to_string(),
+                payload: payload.to_vec(),
+                qos: 1,
+                retain: false,
+            })
+        });
+    }
+
+    // As if another client published it with the RETAIN flag.
+    pub fn retain(&self, topic: &str, payload: &[u8]) {
+        self.with(|state| {
+            state.store_retained(Message {
+                topic: topic.to_string(),
+                payload: payload.to_vec(),
+                qos: 1,
+                retain: true,
+            })
+        });
+    }
+}
+
+impl Peer for Broker {
+    fn receive(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut state = self.0.lock().unwrap();
+        state.input.extend_from_slice(data);
+        let mut reply = Vec::new();
+        while let Some((header, body, length)) = split_packet(&state.input) {
+            state.handle(header, &body, &mut reply);
+            state.input.drain(..length);
+        }
+        reply
+    }
+}
+
+impl State {
+    fn handle(&mut self, header: u8, body: &[u8], reply: &mut Vec<u8>) {
+        match header & 0xF0 {
+            0x10 => {
+                // CONNECT: protocol name, level, flags, keep alive, client id
+                let flags = body[7];
+                self.keep_alive_secs = u16::from_be_bytes([body[8], body[9]]);
+                self.clean_session = flags & 0x02 != 0;
+                self.client_id = Some(get_str(&body[10..]).0);
+                self.disconnected = false;
+                let inflight = std::mem::take(&mut self.inflight);
+                if self.clean_session {
+                    self.subscriptions.clear();
+                } else {
+                    let queued = std::mem::take(&mut self.queued);
+                    self.queued = inflight.into_iter().map(|(_, m)| m).chain(queued).collect();
+                }
+                reply.extend([0x20, 2, 0, self.connect_code]);
+            }
+            0x30 => {
+                let qos = (header >> 1) & 0x03;
+                let (topic, mut rest) = get_str(body);
+                if qos > 0 {
+                    reply.extend([0x40, 2, rest[0], rest[1]]);
+                    rest = &rest[2..];
+                }
+                let message = Message {
+                    topic,
+                    payload: rest.to_vec(),
+                    qos,
+                    retain: header & 0x01 != 0,
+                };
+                if message.retain {
+                    self.store_retained(message.clone());
+                }
+                self.published.push(message);
+            }
+            0x40 => {
+                let id = u16::from_be_bytes([body[0], body[1]]);
+                self.inflight.retain(|(i, _)| *i != id);
+                self.acked.push(id);
+            }
+            0x80 => {
+                // SUBSCRIBE: packet id, then filter and QoS pairs
+                let mut codes = Vec::new();
+                let mut rest = &body[2..];
+                while !rest.is_empty() {
+                    let (filter, tail) = get_str(rest);
+                    let qos = tail[0].min(1);
+                    self.subscriptions.retain(|(f, _)| *f != filter);
+                    self.subscriptions.push((filter, qos));
+                    codes.push(qos);
+                    rest = &tail[1..];
+                }
+                reply.extend(packet(0x90, &[&body[..2], &codes].concat()));
+
+                let (matching, rest): (Vec<Message>, Vec<Message>) =
+                    std::mem::take(&mut self.queued)
+                        .into_iter()
+                        .partition(|m| self.granted(&m.topic).is_some());
+                self.queued = rest;
+                let retained: Vec<Message> = self
+                    .retained
+                    .iter()
+                    .filter(|m| self.granted(&m.topic).is_some())
+                    .cloned()
+                    .collect();
+                for m in matching.into_iter().chain(retained) {
+                    let qos = m.qos.min(self.granted(&m.topic).unwrap());
+                    self.next_id += 1;
+                    reply.extend(publish(&m.topic, &m.payload, qos, m.retain, self.next_id));
+                    if qos > 0 && !m.retain {
+                        self.inflight.push((self.next_id, m));
+                    }
+                }
+            }
+            0xC0 => {
+                self.pings += 1;
+                reply.extend([0xD0, 0]);
+            }
+            0xE0 => self.disconnected = true,
+            _ => panic!("unexpected MQTT packet {:02x}", header),
+        }
+    }
+
+    // An empty payload clears the retained message of the topic.
+    fn store_retained(&mut self, message: Message) {
+        self.retained.retain(|m| m.topic != message.topic);
+        if !message.payload.is_empty() {
+            self.retained.push(message);
+        }
+    }
+
+    fn granted(&self, topic: &str) -> Option<u8> {
+        self.subscriptions
+            .iter()
+            .filter(|(filter, _)| matches(filter, topic))
+            .map(|(_, qos)| *qos)
+            .max()
+    }
+}
+
+// PUBLISH of the broker, e.g. for Sim868::push_data while the client is connected.
+pub fn publish(topic: &str, payload: &[u8], qos: u8, retain: bool, id: u16) -> Vec<u8> {
+    let mut body = (topic.len() as u16).to_be_bytes().to_vec();
+    body.extend(topic.as_bytes());
+    if qos > 0 {
+        body.extend(id.to_be_bytes());
+    }
+    body.extend(payload);
+    packet(0x30 | qos << 1 | retain as u8, &body)
+}
+
+// + matches a level, # the rest.
+pub fn matches(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for f in filter.split('/') {
+        match (f, levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (f, Some(level)) if f == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut ret = vec![header];
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length == 0 {
+            ret.push(byte);
+            break;
+        }
+        ret.push(byte | 0x80);
+    }
+    ret.extend(body);
+    ret
+}
+
+// The fixed header, the body and the length of the first complete packet.
+fn split_packet(buf: &[u8]) -> Option<(u8, Vec<u8>, usize)> {
+    let mut length = 0;
+    for i in 1..5 {
+        let byte = *buf.get(i)?;
+        length |= ((byte & 0x7F) as usize) << (7 * (i - 1));
+        if byte & 0x80 == 0 {
+            let body = buf.get(i + 1..i + 1 + length)?;
+            return Some((buf[0], body.to_vec(), i + 1 + length));
+        }
+    }
+    panic!("invalid MQTT remaining length");
+}
+
+fn get_str(buf: &[u8]) -> (String, &[u8]) {
+    let length = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    (
+        String::from_utf8(buf[2..2 + length].to_vec()).unwrap(),
+        &buf[2 + length..],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("poro/1/command", "poro/1/command"));
+        assert!(matches("poro/+/command", "poro/1/command"));
+        assert!(matches("poro/#", "poro/1/command"));
+        assert!(!matches("poro/+", "poro/1/command"));
+        assert!(!matches("poro/1/command", "poro/1"));
+    }
+
+    #[test]
+    fn test_broker() {
+        let broker = Broker::default();
+        broker.queue("poro/1/command", b"hi");
+        let mut peer = broker.clone();
+
+        let connect = packet(
+            0x10,
+            &[&[0, 4], &b"MQTT"[..], &[4, 0x02, 0, 60, 0, 1], b"1"].concat(),
+        );
+        assert_eq!(vec![0x20, 2, 0, 0], peer.receive(&connect));
+        // split over two sends
+        let subscribe = packet(0x82, &[&[0, 1, 0, 8], &b"poro/1/#"[..], &[1]].concat());
+        assert_eq!(Vec::<u8>::new(), peer.receive(&subscribe[..5]));
+        assert_eq!(
+            [
+                vec![0x90, 3, 0, 1, 1],
+                publish("poro/1/command", b"hi", 1, false, 1)
+            ]
+            .concat(),
+            peer.receive(&subscribe[5..])
+        );
+        assert_eq!(
+            vec![0x40, 2, 0, 7],
+            peer.receive(&publish("poro/1/reply", b"ok", 1, false, 7))
+        );
+        assert_eq!(vec![0xD0, 0], peer.receive(&[0xC0, 0]));
+        assert_eq!(Vec::<u8>::new(), peer.receive(&[0x40, 2, 0, 1, 0xE0, 0]));
+
+        broker.with(|state| {
+            assert_eq!(Some("1".to_string()), state.client_id);
+            assert_eq!(60, state.keep_alive_secs);
+            assert_eq!(vec![("poro/1/#".to_string(), 1)], state.subscriptions);
+            assert_eq!("poro/1/reply", state.published[0].topic);
+            assert_eq!(b"ok".to_vec(), state.published[0].payload);
+            assert_eq!(vec![1], state.acked);
+            assert!(state.queued.is_empty());
+            assert_eq!(1, state.pings);
+            assert!(state.disconnected);
+        });
+    }
+
+    #[test]
+    fn test_broker_retained() {
+        let broker = Broker::default();
+        broker.retain("poro/1/command", b"hi");
+        let mut peer = broker.clone();
+
+        let subscribe = packet(
+            0x82,
+            &[&[0, 1, 0, 14], &b"poro/1/command"[..], &[1]].concat(),
+        );
+        let delivered = [
+            vec![0x90, 3, 0, 1, 1],
+            publish("poro/1/command", b"hi", 1, true, 1),
+        ]
+        .concat();
+        assert_eq!(delivered, peer.receive(&subscribe));
+        // again on the next subscription
+        assert_eq!(
+            [
+                vec![0x90, 3, 0, 1, 1],
+                publish("poro/1/command", b"hi", 1, true, 2),
+            ]
+            .concat(),
+            peer.receive(&subscribe)
+        );
+
+        // a retained publish of the client replaces it, an empty one clears it
+        peer.receive(&publish("poro/1/status", b"a", 0, true, 0));
+        peer.receive(&publish("poro/1/status", b"b", 0, true, 0));
+        peer.receive(&publish("poro/1/command", b"", 0, true, 0));
+        broker.with(|state| {
+            assert_eq!(1, state.retained.len());
+            assert_eq!("poro/1/status", state.retained[0].topic);
+            assert_eq!(b"b".to_vec(), state.retained[0].payload);
+        });
+        assert_eq!(vec![0x90, 3, 0, 1, 1], peer.receive(&subscribe));
+    }
+
+    #[test]
+    fn test_broker_redelivery() {
+        let broker = Broker::default();
+        broker.queue("poro/1/command", b"a");
+        broker.queue("poro/1/command", b"b");
+        let mut peer = broker.clone();
+
+        let connect = packet(
+            0x10,
+            &[&[0, 4], &b"MQTT"[..], &[4, 0, 0, 60, 0, 1], b"1"].concat(),
+        );
+        let subscribe = packet(
+            0x82,
+            &[&[0, 1, 0, 14], &b"poro/1/command"[..], &[1]].concat(),
+        );
+        peer.receive(&connect);
+        peer.receive(&subscribe);
+        // only the first one is acknowledged
+        peer.receive(&[0x40, 2, 0, 1, 0xE0, 0]);
+
+        peer.receive(&connect);
+        assert_eq!(
+            [
+                vec![0x90, 3, 0, 1, 1],
+                publish("poro/1/command", b"b", 1, false, 3),
+            ]
+            .concat(),
+            peer.receive(&subscribe)
+        );
+    }
+}
@@ -0,0 +1,261 @@
+// SMS PDU mode (AT+CMGF=0), just enough of GSM 03.40 for the module side: SMS-DELIVER
+// out (UCS2) and SMS-SUBMIT in (7-bit, 8-bit and UCS2).
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Submit {
+    pub destination: String,
+    pub header: Vec<u8>, // user data header without its length
+    pub text: String,    // 8-bit data is kept as hex
+}
+
+// SMS-DELIVER with the SMSC of the SIM, timestamp is yy/MM/dd,hh:mm:ss+zz.
+pub fn encode_deliver(sender: &str, timestamp: &str, header: &[u8], text: &str) -> Option<String> {
+    let mut pdu = vec![0x00]; // SMSC
+    pdu.push(if header.is_empty() { 0x04 } else { 0x44 });
+    let (toa, digits) = match sender.strip_prefix('+') {
+        Some(digits) => (0x91, digits),
+        None => (0x81, sender),
+    };
+    pdu.push(digits.len() as u8);
+    pdu.push(toa);
+    pdu.extend(semi_octets(digits)?);
+    pdu.extend([0x00, 0x08]); // PID, DCS UCS2
+
+    // 26/01/10,17:25:32+04
+    let bytes = timestamp.as_bytes();
+    if bytes.len() != 20 {
+        return None;
+    }
+    let mut scts = semi_octets(&timestamp[..17].replace(['/', ',', ':'], ""))?;
+    let mut tz = semi_octets(&timestamp[18..])?[0];
+    if bytes[17] == b'-' {
+        tz |= 0x08;
+    }
+    scts.push(tz);
+    pdu.extend(scts);
+
+    let mut ud = Vec::new();
+    if !header.is_empty() {
+        ud.push(header.len() as u8);
+        ud.extend_from_slice(header);
+    }
+    ud.extend(text.encode_utf16().flat_map(|u| u.to_be_bytes()));
+    pdu.push(ud.len() as u8);
+    pdu.extend(ud);
+    Some(hex(&pdu))
+}
+
+pub fn decode_submit(hex: &str) -> Option<Submit> {
+    let pdu = unhex(hex)?;
+    let mut pos = 1 + *pdu.first()? as usize; // SMSC
+    let mut take = |n: usize| {
+        let ret = pdu.get(pos..pos + n);
+        pos += n;
+        ret
+    };
+    let first = take(1)?[0];
+    if first & 0x03 != 0x01 {
+        return None;
+    }
+    take(1)?; // MR
+    let digits = take(1)?[0] as usize;
+    let toa = take(1)?[0];
+    let mut destination = if toa == 0x91 {
+        "+".to_string()
+    } else {
+        String::new()
+    };
+    for b in take(digits.div_ceil(2))? {
+        for nibble in [b & 0x0F, b >> 4] {
+            if nibble < 10 {
+                destination.push((b'0' + nibble) as char);
+            }
+        }
+    }
+    take(1)?; // PID
+    let dcs = take(1)?[0];
+    match (first >> 3) & 0x03 {
+        0 => (),
+        2 => {
+            take(1)?; // relative validity period
+        }
+        _ => {
+            take(7)?;
+        }
+    }
+    let udl = take(1)?[0] as usize;
+    let ud = match dcs & 0x0C {
+        0x00 => take((udl * 7).div_ceil(8))?,
+        _ => take(udl)?,
+    };
+
+    let mut header = Vec::new();
+    let mut skip = 0;
+    if first & 0x40 != 0 {
+        let udhl = *ud.first()? as usize;
+        header = ud.get(1..1 + udhl)?.to_vec();
+        skip = 1 + udhl;
+    }
+    let text = match dcs & 0x0C {
+        0x00 => {
+            let header_septets = (skip * 8).div_ceil(7);
+            let fill_bits = header_septets * 7 - skip * 8;
+            let mut text = String::new();
+            let mut escaped = false;
+            for i in 0..udl.checked_sub(header_septets)? {
+                let bit = skip * 8 + fill_bits + i * 7;
+                let word = ud[bit / 8] as u16 | (*ud.get(bit / 8 + 1).unwrap_or(&0) as u16) << 8;
+                let septet = ((word >> (bit % 8)) & 0x7F) as u8;
+                if escaped {
+                    escaped = false;
+                    text.push(match GSM7_EXTENSION.iter().find(|(s, _)| *s == septet) {
+                        Some((_, c)) => *c,
+                        None => GSM7_BASIC[septet as usize],
+                    });
+                } else if septet == 0x1B {
+                    escaped = true;
+                } else {
+                    text.push(GSM7_BASIC[septet as usize]);
+                }
+            }
+            text
+        }
+        0x08 => {
+            let units: Vec<u16> = ud[skip..]
+                .chunks(2)
+                .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]))
+                .collect();
+            String::from_utf16(&units).ok()?
+        }
+        _ => self::hex(&ud[skip..]),
+    };
+    Some(Submit {
+        destination,
+        header,
+        text,
+    })
+}
+
+// The <length> of +CMGR and AT+CMGS, the SMSC part is not counted.
+pub fn tpdu_length(hex: &str) -> usize {
+    let smsc = u8::from_str_radix(hex.get(..2).unwrap_or("00"), 16).unwrap_or(0) as usize;
+    (hex.len() / 2).saturating_sub(1 + smsc)
+}
+
+fn semi_octets(digits: &str) -> Option<Vec<u8>> {
+    digits
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let low = (pair[0] as char).to_digit(10)? as u8;
+            let high = match pair.get(1) {
+                Some(d) => (*d as char).to_digit(10)? as u8,
+                None => 0x0F,
+            };
+            Some(high << 4 | low)
+        })
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// GSM 03.38 default alphabet, 0x1B escapes to the extension table.
+#[rustfmt::skip]
+const GSM7_BASIC: [char; 128] = [
+    '@', '£', '$', '¥', 'è', 'é', 'ù', 'ì', 'ò', 'Ç', '\n', 'Ø', 'ø', '\r', 'Å', 'å',
+    'Δ', '_', 'Φ', 'Γ', 'Λ', 'Ω', 'Π', 'Ψ', 'Σ', 'Θ', 'Ξ', ' ', 'Æ', 'æ', 'ß', 'É',
+    ' ', '!', '"', '#', '¤', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',
+    '¡', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', 'Ä', 'Ö', 'Ñ', 'Ü', '§',
+    '¿', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', 'ä', 'ö', 'ñ', 'ü', 'à',
+];
+
+const GSM7_EXTENSION: [(u8, char); 10] = [
+    (0x0A, '\x0c'),
+    (0x14, '^'),
+    (0x28, '{'),
+    (0x29, '}'),
+    (0x2F, '\\'),
+    (0x3C, '['),
+    (0x3D, '~'),
+    (0x3E, ']'),
+    (0x40, '|'),
+    (0x65, '€'),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_deliver() {
+        assert_eq!(
+            Some("00040B916303214365F700086210017152234004004F004B".to_string()),
+            encode_deliver("+36301234567", "26/01/10,17:25:32+04", &[], "OK")
+        );
+        assert_eq!(
+            Some("00440B816003214365F7000862100171522348080500034202010041".to_string()),
+            encode_deliver(
+                "06301234567",
+                "26/01/10,17:25:32-04",
+                &[0x00, 0x03, 0x42, 0x02, 0x01],
+                "A"
+            )
+        );
+        assert_eq!(
+            None,
+            encode_deliver("+3630abc", "26/01/10,17:25:32+04", &[], "OK")
+        );
+        assert_eq!(None, encode_deliver("+36301234567", "26/01/10", &[], "OK"));
+    }
+
+    #[test]
+    fn test_decode_submit() {
+        assert_eq!(
+            Some(Submit {
+                destination: "+36301234567".to_string(),
+                header: Vec::new(),
+                text: "hi".to_string()
+            }),
+            decode_submit("0001000B916303214365F7000002E834")
+        );
+        assert_eq!(
+            Some(Submit {
+                destination: "+36301234567".to_string(),
+                header: vec![0x00, 0x03, 0x42, 0x02, 0x01],
+                text: "hi".to_string()
+            }),
+            decode_submit("0041000B916303214365F7000009050003420201D069")
+        );
+        assert_eq!(
+            Some(Submit {
+                destination: "06301234567".to_string(),
+                header: Vec::new(),
+                text: "Árvíz".to_string()
+            }),
+            decode_submit("0021000B816003214365F700080A00C10072007600ED007A")
+        );
+        assert_eq!(
+            Some("€".to_string()),
+            decode_submit("0001000B916303214365F70000029B32").map(|s| s.text)
+        );
+        assert_eq!(
+            Some("CAFE".to_string()),
+            decode_submit("0001000B916303214365F7000402CAFE").map(|s| s.text)
+        );
+        assert_eq!(None, decode_submit("0001000B91"));
+        assert_eq!(None, decode_submit("00040B916303214365F70000"));
+        assert_eq!(15, tpdu_length("0001000B916303214365F7000002E834"));
+    }
+}
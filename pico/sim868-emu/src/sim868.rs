@@ -0,0 +1,1778 @@
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::pdu;
+use crate::ucs2;
+
+// SIM868 state machine, bytes in, bytes out.
+//
+// Only the commands used by pico-lib are emulated, the rest is answered with ERROR.
+// Replies follow the SIM800 Series AT Command Manual V1.09 and the SIM868 GNSS/LBS
+// application notes, including the quirks seen on the real module (e.g. AT+CIFSR
+// answers the bare IP address without a final OK).
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Registration {
+    NotRegistered = 0,
+    Registered = 1,
+    Searching = 2,
+    Denied = 3,
+    Unknown = 4,
+    RegisteredRoaming = 5,
+}
+
+impl Registration {
+    pub fn is_registered(&self) -> bool {
+        matches!(
+            self,
+            Registration::Registered | Registration::RegisteredRoaming
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredSms {
+    pub sender: String,
+    pub timestamp: String, // yy/MM/dd,hh:mm:ss+zz
+    pub text: String,
+    pub header: Vec<u8>, // user data header, PDU mode only
+    pub read: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentSms {
+    pub number: String,
+    pub text: String,
+    pub header: Vec<u8>, // user data header, PDU mode only
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallState {
+    Idle,
+    Dialing(String),
+    Incoming(String),
+    Active(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GnssFix {
+    pub utc: String, // yyyyMMddhhmmss.sss
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: f64,
+    pub hdop: f64,
+    pub pdop: f64,
+    pub vdop: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub accuracy: u32,
+    pub date: String, // dd/MM/yy
+    pub time: String, // hh:mm:ss
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Battery {
+    pub bcs: u8,
+    pub bcl: u8,
+    pub millivolts: u32,
+}
+
+// A request of AT+HTTPACTION, as the stand-in server (Sim868::http_server) got it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub method: u8, // 0 GET, 1 POST, 2 HEAD
+    pub url: String,
+    pub headers: Vec<(String, String)>, // of USERDATA
+    pub content_type: Option<String>,
+    pub body: Vec<u8>, // of AT+HTTPDATA, POST only
+}
+
+// Stand-in of a server on the Internet, see Sim868::servers.
+pub trait Peer: Send {
+    // The reply to the data sent by the host, empty for none.
+    fn receive(&mut self, data: &[u8]) -> Vec<u8>;
+}
+
+// Sends back what it gets.
+pub struct Echo;
+
+impl Peer for Echo {
+    fn receive(&mut self, data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+}
+
+// Accepts the connection, never answers.
+pub struct Silent;
+
+impl Peer for Silent {
+    fn receive(&mut self, _: &[u8]) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+// A connection of AT+CIPSTART, multi IP connection mode only.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Connection {
+    pub protocol: String, // TCP or UDP
+    pub remote: String,   // ip:port
+    pub connected: bool,
+    pub sent: Vec<u8>,     // by the host
+    pub received: Vec<u8>, // from the peer, not read by the host yet
+}
+
+// Scripted behaviour for a command, matched by prefix (e.g. "AT+CGATT=1").
+pub enum Action {
+    Reply(String),                            // raw bytes instead of the emulated reply
+    Error,                                    // ERROR instead of the emulated reply
+    Silent,                                   // no reply at all, the client times out
+    Delay(Duration),                          // the emulated reply arrives late
+    Hook(Box<dyn FnMut(&mut Sim868) + Send>), // runs before the emulated reply
+}
+
+struct Script {
+    prefix: String,
+    action: Action,
+    once: bool,
+}
+
+enum Mode {
+    Command,
+    SmsText { number: String },
+    SmsPdu,
+    FileWrite { filename: String, remaining: usize }, // AT+FSWRITE data after the prompt
+    IpSend { id: u8, remaining: usize },              // AT+CIPSEND data after the prompt
+    HttpData { remaining: usize },                    // AT+HTTPDATA data after DOWNLOAD
+}
+
+pub struct Sim868 {
+    pub echo: bool,
+    pub functionality: u8,
+    pub registration: Registration,
+    pub registration_urc: bool, // AT+CGREG=1, see set_registration
+    pub pin: String,
+    pub rssi: u8,
+    pub operator: String,
+    pub imei: String,
+    pub sms: BTreeMap<u32, StoredSms>,
+    pub sms_capacity: u32, // of the SIM, new messages are rejected when it is full
+    pub sent_sms: Vec<SentSms>,
+    pub call: CallState,
+    pub calls: Vec<String>, // dialed numbers
+    pub gnss_power: bool,
+    pub gnss_fix: Option<GnssFix>,
+    pub nmea_output: bool, // AT+CGNSTST=1, see emit_nmea
+    pub gnss_urc: u8,      // AT+CGNSURC=<n>, see emit_gnss_urc
+    pub gprs_attached: bool,
+    pub apn: Option<String>,
+    pub ip_up: bool,
+    pub ip_address: String,
+    pub bearer_params: BTreeMap<String, String>,
+    pub bearer_open: bool,
+    pub ip_mux: bool,                             // AT+CIPMUX=1
+    pub ip_rx_manual: bool,                       // AT+CIPRXGET=1
+    pub servers: BTreeMap<String, Box<dyn Peer>>, // stand-ins by ip:port, the rest is unreachable
+    pub connections: BTreeMap<u8, Connection>,
+    pub clbs_server: String,
+    pub clbs_times: u32, // AT+CLBS=3, the location requests
+    pub http_server: BTreeMap<String, (u16, Vec<u8>)>, // stand-in server, URL -> status, body
+    pub http_init: bool,
+    pub http_params: BTreeMap<String, String>,
+    pub http_response: Option<Vec<u8>>, // of the last AT+HTTPACTION, for AT+HTTPREAD
+    pub http_data: Vec<u8>,             // of AT+HTTPDATA, the body of the next POST
+    pub http_requests: Vec<HttpRequest>,
+    pub files: BTreeMap<String, Vec<u8>>, // the file system, e.g. C:\User\MTK3.EPO
+    pub gnss_start: Option<String>,       // HOT, WARM or COLD of the last restart
+    pub epo_injected: bool,
+    pub cell_location: Option<CellLocation>,
+    pub battery: Battery,
+    pub commands: Vec<String>, // every received command line, for assertions
+    pub pdu_mode: bool, // AT+CMGF=0, the real module starts in PDU mode, the tests in text mode
+    mode: Mode,
+    input: Vec<u8>,
+    output: VecDeque<(Duration, Vec<u8>)>,
+    scripts: Vec<Script>,
+    next_message_reference: u32,
+}
+
+impl Default for Sim868 {
+    fn default() -> Self {
+        Sim868 {
+            echo: true,
+            functionality: 1,
+            registration: Registration::Registered,
+            registration_urc: false,
+            pin: "READY".to_string(),
+            rssi: 20,
+            operator: "Telekom HU".to_string(),
+            imei: "861234567890123".to_string(),
+            sms: BTreeMap::new(),
+            sms_capacity: 30,
+            sent_sms: Vec::new(),
+            call: CallState::Idle,
+            calls: Vec::new(),
+            gnss_power: false,
+            gnss_fix: Some(GnssFix {
+                utc: "20221212120221.123".to_string(),
+                latitude: 46.7624859,
+                longitude: 18.6304591,
+                altitude: 329.218,
+                hdop: 2.1,
+                pdop: 2.3,
+                vdop: 0.9,
+            }),
+            nmea_output: false,
+            gnss_urc: 0,
+            gprs_attached: false,
+            apn: None,
+            ip_up: false,
+            ip_address: "100.95.173.97".to_string(),
+            bearer_params: BTreeMap::new(),
+            bearer_open: false,
+            ip_mux: false,
+            ip_rx_manual: false,
+            servers: BTreeMap::new(),
+            connections: BTreeMap::new(),
+            clbs_server: "lbs-simcom.com:3002".to_string(),
+            clbs_times: 0,
+            http_server: BTreeMap::new(),
+            http_init: false,
+            http_params: BTreeMap::new(),
+            http_response: None,
+            http_data: Vec::new(),
+            http_requests: Vec::new(),
+            files: BTreeMap::new(),
+            gnss_start: None,
+            epo_injected: false,
+            cell_location: Some(CellLocation {
+                latitude: 46.7624859,
+                longitude: 18.6304591,
+                accuracy: 550,
+                date: "12/12/22".to_string(),
+                time: "12:02:21".to_string(),
+            }),
+            battery: Battery {
+                bcs: 0,
+                bcl: 80,
+                millivolts: 4000,
+            },
+            commands: Vec::new(),
+            pdu_mode: false,
+            mode: Mode::Command,
+            input: Vec::new(),
+            output: VecDeque::new(),
+            scripts: Vec::new(),
+            next_message_reference: 1,
+        }
+    }
+}
+
+impl Sim868 {
+    // Scripted action for the next command starting with `prefix`.
+    pub fn on(&mut self, prefix: &str, action: Action) -> &mut Self {
+        self.add_script(prefix, action, true)
+    }
+
+    // Scripted action for every command starting with `prefix`.
+    pub fn on_every(&mut self, prefix: &str, action: Action) -> &mut Self {
+        self.add_script(prefix, action, false)
+    }
+
+    fn add_script(&mut self, prefix: &str, action: Action, once: bool) -> &mut Self {
+        self.scripts.push(Script {
+            prefix: prefix.to_ascii_uppercase(),
+            action,
+            once,
+        });
+        self
+    }
+
+    // Bytes to be sent to the host, with the delay relative to the previous chunk.
+    pub fn take_output(&mut self) -> Vec<(Duration, Vec<u8>)> {
+        self.output.drain(..).collect()
+    }
+
+    pub fn emit_urc(&mut self, urc: &str) {
+        self.emit(format!("\r\n{}\r\n", urc));
+    }
+
+    // Power cycle, e.g. PWRKEY, keeps the SIM card (SMS storage) and the environment.
+    pub fn restart(&mut self) {
+        self.echo = true;
+        self.functionality = 1;
+        self.registration_urc = false;
+        self.call = CallState::Idle;
+        self.gnss_power = false;
+        self.nmea_output = false;
+        self.gnss_urc = 0;
+        self.epo_injected = false;
+        self.gprs_attached = false;
+        self.apn = None;
+        self.ip_up = false;
+        self.bearer_params.clear();
+        self.bearer_open = false;
+        self.ip_mux = false;
+        self.ip_rx_manual = false;
+        self.connections.clear();
+        self.http_init = false;
+        self.http_params.clear();
+        self.http_response = None;
+        self.http_data.clear();
+        self.mode = Mode::Command;
+        self.input.clear();
+        self.emit_urc("RDY");
+        self.emit_urc("+CPIN: READY");
+        self.emit_urc("Call Ready");
+        self.emit_urc("SMS Ready");
+    }
+
+    // Losing the network drops the PDP context and the bearer as well.
+    pub fn set_registration(&mut self, registration: Registration) {
+        if self.registration_urc && registration as u8 != self.registration as u8 {
+            self.emit_urc(&format!("+CGREG: {}", registration as u8));
+        }
+        self.registration = registration;
+        if registration.is_registered() {
+            return;
+        }
+        if self.bearer_open {
+            self.bearer_open = false;
+            self.emit_urc("+SAPBR 1: DEACT");
+        }
+        if self.ip_up {
+            self.ip_up = false;
+            self.close_connections();
+            self.emit_urc("+PDP: DEACT");
+        }
+        self.gprs_attached = false;
+    }
+
+    // Data sent by the peer on its own, e.g. a published MQTT message.
+    pub fn push_data(&mut self, id: u8, data: &[u8]) {
+        let Some(connection) = self.connections.get_mut(&id) else {
+            return;
+        };
+        let notify = connection.received.is_empty();
+        connection.received.extend_from_slice(data);
+        if !self.ip_rx_manual {
+            let data = std::mem::take(&mut connection.received);
+            let mut bytes = format!("\r\n+RECEIVE,{},{}:\r\n", id, data.len()).into_bytes();
+            bytes.extend(data);
+            self.emit_bytes(bytes);
+        } else if notify {
+            self.emit_urc(&format!("+CIPRXGET: 1,{}", id));
+        }
+    }
+
+    // The peer closes the connection, the unread data stays readable.
+    pub fn close_remote(&mut self, id: u8) {
+        if let Some(connection) = self.connections.get_mut(&id)
+            && connection.connected
+        {
+            connection.connected = false;
+            self.emit_urc(&format!("{}, CLOSED", id));
+        }
+    }
+
+    fn close_connections(&mut self) {
+        let ids: Vec<u8> = self.connections.keys().cloned().collect();
+        for id in ids {
+            self.close_remote(id);
+        }
+    }
+
+    // Stores the message on the SIM, returns its index, None when the SIM is full.
+    pub fn receive_sms(&mut self, sender: &str, text: &str, timestamp: &str) -> Option<u32> {
+        self.store_sms(sender, text, timestamp, Vec::new())
+    }
+
+    // Stores the concatenated parts (UCS2, 67 characters each) on the SIM, returns the
+    // indexes of the stored ones. The parts are readable in PDU mode only.
+    pub fn receive_long_sms(
+        &mut self,
+        sender: &str,
+        text: &str,
+        timestamp: &str,
+        reference: u8,
+    ) -> Vec<u32> {
+        let units: Vec<u16> = text.encode_utf16().collect();
+        let parts: Vec<String> = units.chunks(67).map(String::from_utf16_lossy).collect();
+        let count = parts.len() as u8;
+        parts
+            .iter()
+            .enumerate()
+            .filter_map(|(i, part)| {
+                let header = vec![0x00, 0x03, reference, count, i as u8 + 1];
+                self.store_sms(sender, part, timestamp, header)
+            })
+            .collect()
+    }
+
+    fn store_sms(
+        &mut self,
+        sender: &str,
+        text: &str,
+        timestamp: &str,
+        header: Vec<u8>,
+    ) -> Option<u32> {
+        let index = (1..=self.sms_capacity).find(|i| !self.sms.contains_key(i))?;
+        self.sms.insert(
+            index,
+            StoredSms {
+                sender: sender.to_string(),
+                timestamp: timestamp.to_string(),
+                text: text.to_string(),
+                header,
+                read: false,
+            },
+        );
+        self.emit_urc(&format!("+CMTI: \"SM\",{}", index));
+        Some(index)
+    }
+
+    // One second of the NMEA stream (AT+CGNSTST=1) of the powered GNSS, the sentences of
+    // the fix or the empty ones while searching.
+    pub fn emit_nmea(&mut self) {
+        if !self.gnss_power || !self.nmea_output {
+            return;
+        }
+        let sentences = match self.gnss_fix.as_ref() {
+            None => vec![
+                "GPGGA,,,,,,0,0,,,M,,M,,".to_string(),
+                "GPGSA,A,1,,,,,,,,,,,,,,,".to_string(),
+                "GPGSV,1,1,00".to_string(),
+                "GPRMC,,V,,,,,,,,,,N".to_string(),
+                "GPVTG,,T,,M,,N,,K,N".to_string(),
+            ],
+            Some(f) => {
+                let (date, time) = f.utc.split_at(8);
+                let time = &time[..time.len().min(10)];
+                let date = format!("{}{}{}", &date[6..8], &date[4..6], &date[2..4]);
+                let (lat, ns) = nmea_coordinate(f.latitude, 2, "N", "S");
+                let (lon, ew) = nmea_coordinate(f.longitude, 3, "E", "W");
+                vec![
+                    format!(
+                        "GPGGA,{},{},{},{},{},1,8,{:.2},{:.1},M,38.2,M,,",
+                        time, lat, ns, lon, ew, f.hdop, f.altitude
+                    ),
+                    format!(
+                        "GPGSA,A,3,01,02,12,14,15,17,22,25,,,,,{:.2},{:.2},{:.2}",
+                        f.pdop, f.hdop, f.vdop
+                    ),
+                    "GPGSV,2,1,08,01,40,083,46,02,17,308,41,12,07,344,39,14,22,228,45".to_string(),
+                    "GPGSV,2,2,08,15,56,120,38,17,62,110,44,22,05,044,32,25,11,223,28".to_string(),
+                    format!(
+                        "GPRMC,{},A,{},{},{},{},0.00,0.0,{},,,A",
+                        time, lat, ns, lon, ew, date
+                    ),
+                    "GPVTG,0.0,T,,M,0.00,N,0.00,K,A".to_string(),
+                ]
+            }
+        };
+        for sentence in sentences {
+            let checksum = sentence.bytes().fold(0, |acc, b| acc ^ b);
+            self.emit(format!("${}*{:02X}\r\n", sentence, checksum));
+        }
+    }
+
+    // One report of AT+CGNSURC, the period is not counted, every call is a reported fix.
+    pub fn emit_gnss_urc(&mut self) {
+        if !self.gnss_power || self.gnss_urc == 0 {
+            return;
+        }
+        let line = format!("+UGNSINF: {}", self.navigation_information());
+        self.emit_urc(&line);
+    }
+
+    // The fields of +CGNSINF and +UGNSINF.
+    fn navigation_information(&self) -> String {
+        match (self.gnss_power, self.gnss_fix.as_ref()) {
+            (false, _) => "0,,,,,,,,,,,,,,,,,,,,".to_string(),
+            (true, None) => "1,0,,,,,,,,,,,,,,,,,,,".to_string(),
+            (true, Some(f)) => format!(
+                "1,1,{},{:.7},{:.7},{:.3},0.00,0.0,1,,{:.1},{:.1},{:.1},,12,8,,,45,,",
+                f.utc, f.latitude, f.longitude, f.altitude, f.hdop, f.pdop, f.vdop
+            ),
+        }
+    }
+
+    pub fn incoming_call(&mut self, number: &str) {
+        self.call = CallState::Incoming(number.to_string());
+        self.emit_urc("RING");
+        self.emit_urc(&format!("+CLIP: \"{}\",145,\"\",0,\"\",0", number));
+    }
+
+    // Bytes written by the host.
+    pub fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            match self.mode {
+                Mode::Command => {
+                    if *b == b'\r' {
+                        let line = String::from_utf8_lossy(&self.input).trim().to_string();
+                        self.input.clear();
+                        if self.echo {
+                            self.emit(format!("{}\r", line));
+                        }
+                        if !line.is_empty() {
+                            self.command(&line);
+                        }
+                    } else if *b != b'\n' {
+                        self.input.push(*b);
+                    }
+                }
+                Mode::FileWrite {
+                    ref filename,
+                    ref mut remaining,
+                } => {
+                    self.input.push(*b);
+                    *remaining -= 1;
+                    if *remaining == 0 {
+                        let filename = filename.clone();
+                        self.mode = Mode::Command;
+                        let data = std::mem::take(&mut self.input);
+                        self.files.entry(filename).or_default().extend(data);
+                        self.ok();
+                    }
+                }
+                Mode::IpSend {
+                    id,
+                    ref mut remaining,
+                } => {
+                    self.input.push(*b);
+                    *remaining -= 1;
+                    if *remaining == 0 {
+                        self.mode = Mode::Command;
+                        let data = std::mem::take(&mut self.input);
+                        self.ip_send(id, data);
+                    }
+                }
+                Mode::HttpData { ref mut remaining } => {
+                    self.input.push(*b);
+                    *remaining -= 1;
+                    if *remaining == 0 {
+                        self.mode = Mode::Command;
+                        self.http_data = std::mem::take(&mut self.input);
+                        self.ok();
+                    }
+                }
+                Mode::SmsText { .. } | Mode::SmsPdu => match *b {
+                    0x1A => self.send_sms(),
+                    0x1B => {
+                        self.mode = Mode::Command;
+                        self.input.clear();
+                        self.ok();
+                    }
+                    _ => self.input.push(*b),
+                },
+            }
+        }
+    }
+
+    fn emit(&mut self, text: String) {
+        self.output.push_back((Duration::ZERO, text.into_bytes()));
+    }
+
+    fn emit_bytes(&mut self, bytes: Vec<u8>) {
+        self.output.push_back((Duration::ZERO, bytes));
+    }
+
+    fn ok(&mut self) {
+        self.emit("\r\nOK\r\n".to_string());
+    }
+
+    fn error(&mut self) {
+        self.emit("\r\nERROR\r\n".to_string());
+    }
+
+    fn cms_error(&mut self, code: u16) {
+        self.emit(format!("\r\n+CMS ERROR: {}\r\n", code));
+    }
+
+    fn info(&mut self, line: &str) {
+        self.emit(format!("\r\n{}\r\n\r\nOK\r\n", line));
+    }
+
+    fn command(&mut self, line: &str) {
+        self.commands.push(line.to_string());
+        let upper = line.to_ascii_uppercase();
+
+        let mut delay = Duration::ZERO;
+        if let Some(i) = self
+            .scripts
+            .iter()
+            .position(|s| upper.starts_with(&s.prefix))
+        {
+            let mut script = self.scripts.remove(i);
+            let mut reply = None;
+            match &mut script.action {
+                Action::Reply(r) => reply = Some(r.clone()),
+                Action::Error => reply = Some("\r\nERROR\r\n".to_string()),
+                Action::Silent => reply = Some(String::new()),
+                Action::Delay(d) => delay = *d,
+                Action::Hook(f) => f(self),
+            }
+            if !script.once {
+                self.scripts.insert(i, script);
+            }
+            if let Some(reply) = reply {
+                return self.emit(reply);
+            }
+        }
+
+        let first = self.output.len();
+        self.emulate(line, &upper);
+        if let Some(o) = self.output.get_mut(first) {
+            o.0 += delay;
+        }
+    }
+
+    fn emulate(&mut self, line: &str, upper: &str) {
+        let Some(rest) = upper.strip_prefix("AT") else {
+            return self.error();
+        };
+        let cmd = rest.trim_end_matches(';');
+        // parameters are taken from the original line, e.g. quoted strings keep their case
+        let params = line[line.len() - rest.len()..]
+            .split_once('=')
+            .map(|(_, p)| p.trim_end_matches(';'))
+            .unwrap_or("");
+        let args = split_args(params);
+        let arg = |i: usize| args.get(i).map(|a| a.as_str()).unwrap_or("");
+
+        match cmd {
+            "" => self.ok(),
+            "E0" => {
+                self.echo = false;
+                self.ok()
+            }
+            "E1" => {
+                self.echo = true;
+                self.ok()
+            }
+            "+CFUN?" => self.info(&format!("+CFUN: {}", self.functionality)),
+            "+CSCLK?" => self.info("+CSCLK: 0"),
+            "+CGREG?" => self.info(&format!(
+                "+CGREG: {},{}",
+                self.registration_urc as u8, self.registration as u8
+            )),
+            "+CGREG=0" | "+CGREG=1" => {
+                self.registration_urc = cmd.ends_with('1');
+                self.ok()
+            }
+            "+CPIN?" => self.info(&format!("+CPIN: {}", self.pin)),
+            "+CSQ" => self.info(&format!("+CSQ: {},0", self.rssi)),
+            "+GSN" => self.info(&self.imei.clone()),
+            "+COPS?" => {
+                if self.registration.is_registered() {
+                    self.info(&format!("+COPS: 0,0,\"{}\"", self.operator))
+                } else {
+                    self.info("+COPS: 0")
+                }
+            }
+            "+CBC" => self.info(&format!(
+                "+CBC: {},{},{}",
+                self.battery.bcs, self.battery.bcl, self.battery.millivolts
+            )),
+            "H" | "+CHUP" => {
+                self.call = CallState::Idle;
+                self.ok()
+            }
+            "A" => match self.call.clone() {
+                CallState::Incoming(number) => {
+                    self.call = CallState::Active(number);
+                    self.ok()
+                }
+                _ => self.emit("\r\nNO CARRIER\r\n".to_string()),
+            },
+            _ if cmd.starts_with('D') => {
+                if !self.registration.is_registered() {
+                    return self.emit("\r\nNO DIALTONE\r\n".to_string());
+                }
+                let number = line[3..]
+                    .trim_end_matches(';')
+                    .trim_end_matches(",i")
+                    .to_string();
+                self.calls.push(number.clone());
+                self.call = CallState::Dialing(number);
+                self.ok()
+            }
+            _ if cmd.starts_with("+CMGF=") => {
+                self.pdu_mode = arg(0) == "0";
+                self.ok()
+            }
+            _ if cmd.starts_with("+CSCS=")
+                || cmd.starts_with("+CNMI=")
+                || cmd.starts_with("+CLIP=")
+                || cmd.starts_with("+CHFA=")
+                || cmd.starts_with("+CMIC=")
+                || cmd.starts_with("+CEXTERNTONE=")
+                || cmd.starts_with("+CMICBIAS=") =>
+            {
+                self.ok()
+            }
+            _ if cmd.starts_with("+CMGS=") => {
+                if !self.registration.is_registered() {
+                    return self.cms_error(331); // no network service
+                }
+                if self.pdu_mode {
+                    self.mode = Mode::SmsPdu;
+                    return self.emit("\r\n> ".to_string());
+                }
+                self.mode = Mode::SmsText {
+                    number: ucs2::decode(arg(0)).unwrap_or_else(|| arg(0).to_string()),
+                };
+                self.emit("\r\n> ".to_string())
+            }
+            _ if cmd.starts_with("+CMGR=") => {
+                let index: u32 = arg(0).parse().unwrap_or(0);
+                match self.sms.get_mut(&index) {
+                    Some(sms) if self.pdu_mode => {
+                        let stat = if sms.read { 1 } else { 0 };
+                        sms.read = true;
+                        match pdu::encode_deliver(
+                            &sms.sender,
+                            &sms.timestamp,
+                            &sms.header,
+                            &sms.text,
+                        ) {
+                            Some(hex) => {
+                                let line = format!(
+                                    "+CMGR: {},,{}\r\n{}",
+                                    stat,
+                                    pdu::tpdu_length(&hex),
+                                    hex
+                                );
+                                self.info(&line)
+                            }
+                            None => self.cms_error(500), // unknown error
+                        }
+                    }
+                    Some(sms) => {
+                        let stat = if sms.read { "REC READ" } else { "REC UNREAD" };
+                        sms.read = true;
+                        let line = format!(
+                            "+CMGR: \"{}\",\"{}\",\"\",\"{}\"\r\n{}",
+                            stat,
+                            ucs2::encode(&sms.sender),
+                            sms.timestamp,
+                            ucs2::encode(&sms.text)
+                        );
+                        self.info(&line)
+                    }
+                    None => self.cms_error(321), // invalid memory index
+                }
+            }
+            _ if cmd.starts_with("+CMGL=") => {
+                // PDU mode <stat> is a number, text mode the string, e.g. "REC UNREAD"
+                let stat = match arg(0) {
+                    "0" | "REC UNREAD" => Some(false),
+                    "1" | "REC READ" => Some(true),
+                    "4" | "ALL" => None,
+                    _ => return self.ok(), // no stored outgoing messages
+                };
+                let keep = arg(1) == "1";
+                let mut lines = Vec::new();
+                for (index, sms) in self.sms.iter_mut() {
+                    if stat.is_some_and(|read| read != sms.read) {
+                        continue;
+                    }
+                    let line = if self.pdu_mode {
+                        let hex = pdu::encode_deliver(
+                            &sms.sender,
+                            &sms.timestamp,
+                            &sms.header,
+                            &sms.text,
+                        )
+                        .unwrap_or_default();
+                        format!(
+                            "+CMGL: {},{},,{}\r\n{}",
+                            index,
+                            if sms.read { 1 } else { 0 },
+                            pdu::tpdu_length(&hex),
+                            hex
+                        )
+                    } else {
+                        format!(
+                            "+CMGL: {},\"{}\",\"{}\",\"\",\"{}\"\r\n{}",
+                            index,
+                            if sms.read { "REC READ" } else { "REC UNREAD" },
+                            ucs2::encode(&sms.sender),
+                            sms.timestamp,
+                            ucs2::encode(&sms.text)
+                        )
+                    };
+                    lines.push(line);
+                    if !keep {
+                        sms.read = true;
+                    }
+                }
+                if lines.is_empty() {
+                    self.ok()
+                } else {
+                    self.info(&lines.join("\r\n"))
+                }
+            }
+            _ if cmd.starts_with("+CMGD=") => {
+                let index: u32 = arg(0).parse().unwrap_or(0);
+                // <delflag> 1 read, 2 read and sent, 3 read, sent and unsent, 4 all
+                match arg(1).parse::<u8>().unwrap_or(0) {
+                    0 => {
+                        if self.sms.remove(&index).is_none() {
+                            return self.cms_error(321); // invalid memory index
+                        }
+                    }
+                    1..=3 => self.sms.retain(|_, sms| !sms.read),
+                    4 => self.sms.clear(),
+                    _ => return self.error(),
+                }
+                self.ok()
+            }
+            "+CPMS?" => {
+                let (used, total) = (self.sms.len(), self.sms_capacity);
+                self.info(&format!(
+                    "+CPMS: \"SM\",{0},{1},\"SM\",{0},{1},\"SM\",{0},{1}",
+                    used, total
+                ))
+            }
+            _ if cmd.starts_with("+CPMS=") => {
+                if args.iter().any(|m| m != "SM") {
+                    return self.cms_error(302); // operation not allowed
+                }
+                let (used, total) = (self.sms.len(), self.sms_capacity);
+                self.info(&format!("+CPMS: {0},{1},{0},{1},{0},{1}", used, total))
+            }
+            "+CGNSPWR=1" => {
+                self.gnss_power = true;
+                self.ok()
+            }
+            "+CGNSPWR=0" => {
+                self.gnss_power = false;
+                self.ok()
+            }
+            "+CGNSHOT" | "+CGNSWARM" | "+CGNSCOLD" => {
+                if !self.gnss_power {
+                    return self.error();
+                }
+                self.gnss_start = Some(cmd["+CGNS".len()..].to_string());
+                self.ok()
+            }
+            _ if cmd.starts_with("+CGNSAID=") => {
+                if !self.gnss_power || arg(0) != "31" || !self.files.contains_key(EPO_FILE) {
+                    return self.error();
+                }
+                self.epo_injected = true;
+                self.ok()
+            }
+            "+CGNSTST=1" => {
+                self.nmea_output = true;
+                self.ok()
+            }
+            "+CGNSTST=0" => {
+                self.nmea_output = false;
+                self.ok()
+            }
+            _ if cmd.starts_with("+CGNSURC=") => match arg(0).parse() {
+                Ok(period) => {
+                    self.gnss_urc = period;
+                    self.ok()
+                }
+                Err(_) => self.error(),
+            },
+            "+CGNSINF" => {
+                let line = format!("+CGNSINF: {}", self.navigation_information());
+                self.info(&line)
+            }
+            "+CGATT=1" => {
+                if self.registration.is_registered() {
+                    self.gprs_attached = true;
+                    self.ok()
+                } else {
+                    self.error()
+                }
+            }
+            "+CGATT=0" => {
+                self.gprs_attached = false;
+                self.ip_up = false;
+                self.close_connections();
+                self.bearer_open = false;
+                self.ok()
+            }
+            _ if cmd.starts_with("+CSTT=") => {
+                self.apn = Some(arg(0).to_string());
+                self.ok()
+            }
+            "+CIICR" => {
+                if self.gprs_attached && self.apn.is_some() && !self.ip_up {
+                    self.ip_up = true;
+                    self.ok()
+                } else {
+                    self.error()
+                }
+            }
+            "+CIFSR" => {
+                if self.ip_up {
+                    let ip = self.ip_address.clone();
+                    self.emit(format!("\r\n{}\r\n", ip)) // no final result code
+                } else {
+                    self.error()
+                }
+            }
+            _ if cmd.starts_with("+CIPMUX=") => {
+                // IP INITIAL state only
+                if self.ip_up {
+                    return self.error();
+                }
+                self.ip_mux = arg(0) == "1";
+                self.ok()
+            }
+            "+CIPRXGET=0" | "+CIPRXGET=1" => {
+                self.ip_rx_manual = arg(0) == "1";
+                self.ok()
+            }
+            _ if cmd.starts_with("+CIPRXGET=") => {
+                let id = arg(1).parse::<u8>().unwrap_or(u8::MAX);
+                let max = match arg(0) {
+                    "2" => 1460,
+                    "3" => 730, // hex
+                    _ => return self.error(),
+                };
+                let length = arg(2).parse::<usize>().unwrap_or(0);
+                let Some(connection) = self.connections.get_mut(&id) else {
+                    return self.error();
+                };
+                if !self.ip_rx_manual || length == 0 || length > max {
+                    return self.error();
+                }
+                let n = length.min(connection.received.len());
+                let data: Vec<u8> = connection.received.drain(..n).collect();
+                let left = connection.received.len();
+                let mut bytes =
+                    format!("\r\n+CIPRXGET: {},{},{},{}", arg(0), id, n, left).into_bytes();
+                if n > 0 {
+                    bytes.extend(b"\r\n");
+                    if arg(0) == "3" {
+                        bytes.extend(data.iter().flat_map(|b| format!("{:02X}", b).into_bytes()));
+                    } else {
+                        bytes.extend(data);
+                    }
+                }
+                bytes.extend(b"\r\n\r\nOK\r\n");
+                self.emit_bytes(bytes)
+            }
+            _ if cmd.starts_with("+CIPSTART=") => {
+                let id = arg(0).parse::<u8>().unwrap_or(u8::MAX);
+                let protocol = arg(1).to_ascii_uppercase();
+                if !self.ip_mux
+                    || !self.ip_up
+                    || id > 5
+                    || !(protocol == "TCP" || protocol == "UDP")
+                {
+                    return self.error();
+                }
+                self.ok();
+                if self.connections.get(&id).is_some_and(|c| c.connected) {
+                    return self.emit_urc(&format!("{}, ALREADY CONNECT", id));
+                }
+                let remote = format!("{}:{}", arg(2), arg(3));
+                let connected = self.servers.contains_key(&remote);
+                self.connections.insert(
+                    id,
+                    Connection {
+                        protocol,
+                        remote,
+                        connected,
+                        sent: Vec::new(),
+                        received: Vec::new(),
+                    },
+                );
+                if connected {
+                    self.emit_urc(&format!("{}, CONNECT OK", id))
+                } else {
+                    self.emit_urc(&format!("{}, CONNECT FAIL", id))
+                }
+            }
+            _ if cmd.starts_with("+CIPSTATUS=") => {
+                let id = arg(0).parse::<u8>().unwrap_or(u8::MAX);
+                if id > 5 {
+                    return self.error();
+                }
+                let line = match self.connections.get(&id) {
+                    Some(c) => {
+                        let (ip, port) = c.remote.split_once(':').unwrap_or_default();
+                        let state = if c.connected { "CONNECTED" } else { "CLOSED" };
+                        format!(
+                            "+CIPSTATUS: {},0,\"{}\",\"{}\",\"{}\",\"{}\"",
+                            id, c.protocol, ip, port, state
+                        )
+                    }
+                    None => format!("+CIPSTATUS: {},,\"\",\"\",\"\",\"INITIAL\"", id),
+                };
+                self.info(&line)
+            }
+            _ if cmd.starts_with("+CIPSEND=") => {
+                let id = arg(0).parse::<u8>().unwrap_or(u8::MAX);
+                let length = arg(1).parse::<usize>().unwrap_or(0);
+                if !self.connections.get(&id).is_some_and(|c| c.connected)
+                    || length == 0
+                    || length > 1460
+                {
+                    return self.error();
+                }
+                self.mode = Mode::IpSend {
+                    id,
+                    remaining: length,
+                };
+                self.emit("\r\n> ".to_string())
+            }
+            _ if cmd.starts_with("+CIPCLOSE=") => {
+                let id = arg(0).parse::<u8>().unwrap_or(u8::MAX);
+                match self.connections.remove(&id) {
+                    Some(c) if c.connected => self.emit(format!("\r\n{}, CLOSE OK\r\n", id)),
+                    _ => self.error(),
+                }
+            }
+            _ if cmd.starts_with("+SAPBR=") => self.bearer(arg(0), arg(1), arg(2), arg(3)),
+            "+HTTPINIT" => {
+                if self.http_init {
+                    return self.error();
+                }
+                self.http_init = true;
+                self.ok()
+            }
+            "+HTTPTERM" => {
+                if !self.http_init {
+                    return self.error();
+                }
+                self.http_init = false;
+                self.http_params.clear();
+                self.http_response = None;
+                self.http_data.clear();
+                self.ok()
+            }
+            _ if cmd.starts_with("+HTTPPARA=") && self.http_init => {
+                self.http_params
+                    .insert(arg(0).to_ascii_uppercase(), arg(1).to_string());
+                self.ok()
+            }
+            _ if cmd.starts_with("+HTTPDATA=") && self.http_init => {
+                let size = arg(0).parse::<usize>().unwrap_or(0);
+                if size == 0 || size > 319488 {
+                    return self.error();
+                }
+                self.mode = Mode::HttpData { remaining: size };
+                self.emit("\r\nDOWNLOAD\r\n".to_string())
+            }
+            _ if cmd.starts_with("+HTTPACTION=") && self.http_init => {
+                let method = arg(0).to_string();
+                self.ok();
+                let url = self.http_params.get("URL").cloned().unwrap_or_default();
+                // the escaped line breaks of USERDATA separate the headers
+                let headers = self
+                    .http_params
+                    .get("USERDATA")
+                    .map(|h| {
+                        h.split("\\r\\n")
+                            .filter_map(|l| l.split_once(": "))
+                            .map(|(n, v)| (n.to_string(), v.to_string()))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                self.http_requests.push(HttpRequest {
+                    method: method.parse().unwrap_or(0),
+                    url: url.clone(),
+                    headers,
+                    content_type: self.http_params.get("CONTENT").cloned(),
+                    body: if method == "1" {
+                        self.http_data.clone()
+                    } else {
+                        Vec::new()
+                    },
+                });
+                let (status, body) = match self.http_server.get(&url) {
+                    _ if !self.bearer_open => (601, Vec::new()), // network error
+                    Some((status, body)) => (*status, body.clone()),
+                    None => (404, Vec::new()),
+                };
+                self.emit_urc(&format!(
+                    "+HTTPACTION: {},{},{}",
+                    method,
+                    status,
+                    body.len()
+                ));
+                self.http_response = Some(body);
+            }
+            _ if cmd.starts_with("+HTTPREAD=") && self.http_response.is_some() => {
+                let body = self.http_response.as_ref().unwrap();
+                let start = arg(0).parse::<usize>().unwrap_or(0).min(body.len());
+                let end = (start + arg(1).parse::<usize>().unwrap_or(0)).min(body.len());
+                let mut bytes = format!("\r\n+HTTPREAD: {}\r\n", end - start).into_bytes();
+                bytes.extend_from_slice(&body[start..end]);
+                bytes.extend_from_slice(b"\r\nOK\r\n");
+                self.emit_bytes(bytes)
+            }
+            _ if cmd.starts_with("+FSCREATE=") => {
+                if self.files.contains_key(arg(0)) {
+                    return self.error();
+                }
+                self.files.insert(arg(0).to_string(), Vec::new());
+                self.ok()
+            }
+            _ if cmd.starts_with("+FSDEL=") => match self.files.remove(arg(0)) {
+                Some(_) => self.ok(),
+                None => self.error(),
+            },
+            _ if cmd.starts_with("+FSWRITE=") => {
+                let size = arg(2).parse::<usize>().unwrap_or(0);
+                let Some(file) = self.files.get_mut(arg(0)) else {
+                    return self.error();
+                };
+                if size == 0 || size > 10240 {
+                    return self.error();
+                }
+                if arg(1) == "0" {
+                    file.clear();
+                }
+                self.mode = Mode::FileWrite {
+                    filename: arg(0).to_string(),
+                    remaining: size,
+                };
+                self.emit("\r\n> ".to_string())
+            }
+            _ if cmd.starts_with("+FSFLSIZE=") => match self.files.get(arg(0)) {
+                Some(file) => {
+                    let len = file.len();
+                    self.info(&format!("+FSFLSIZE: {}", len))
+                }
+                None => self.error(),
+            },
+            _ if cmd.starts_with("+CLBSCFG=") => {
+                if arg(0) == "1" && arg(1) == "3" {
+                    self.clbs_server = arg(2).to_string();
+                }
+                self.ok()
+            }
+            _ if cmd.starts_with("+CLBS=") => {
+                if !self.bearer_open {
+                    return self.info("+CLBS: 3"); // net error
+                }
+                match arg(0) {
+                    "3" => return self.info(&format!("+CLBS: 0,{}", self.clbs_times)),
+                    "9" => return self.info("+CLBS: 80"), // reported
+                    _ => self.clbs_times += 1,
+                }
+                let line = match self.cell_location.as_ref() {
+                    Some(l) if arg(0) == "4" => format!(
+                        "+CLBS: 0,{:.6},{:.6},{},{},{}",
+                        l.longitude, l.latitude, l.accuracy, l.date, l.time
+                    ),
+                    Some(l) => format!(
+                        "+CLBS: 0,{:.6},{:.6},{}",
+                        l.longitude, l.latitude, l.accuracy
+                    ),
+                    None => "+CLBS: 1".to_string(),
+                };
+                self.info(&line)
+            }
+            _ => self.error(),
+        }
+    }
+
+    fn bearer(&mut self, cmd_type: &str, cid: &str, tag: &str, value: &str) {
+        if cid != "1" {
+            return self.error();
+        }
+        match cmd_type {
+            "0" if self.bearer_open => {
+                self.bearer_open = false;
+                self.ok()
+            }
+            "1" if !self.bearer_open && self.gprs_attached => {
+                self.bearer_open = true;
+                self.ok()
+            }
+            "2" => {
+                let (status, ip) = if self.bearer_open {
+                    (1, self.ip_address.clone())
+                } else {
+                    (3, "0.0.0.0".to_string())
+                };
+                self.info(&format!("+SAPBR: 1,{},\"{}\"", status, ip))
+            }
+            "3" => {
+                self.bearer_params
+                    .insert(tag.to_ascii_uppercase(), value.to_string());
+                self.ok()
+            }
+            _ => self.error(),
+        }
+    }
+
+    fn ip_send(&mut self, id: u8, data: Vec<u8>) {
+        let Some(connection) = self.connections.get_mut(&id) else {
+            return self.emit(format!("\r\n{}, SEND FAIL\r\n", id));
+        };
+        connection.sent.extend_from_slice(&data);
+        let reply = match self.servers.get_mut(&connection.remote) {
+            Some(peer) => peer.receive(&data),
+            None => Vec::new(),
+        };
+        self.emit(format!("\r\n{}, SEND OK\r\n", id));
+        if !reply.is_empty() {
+            self.push_data(id, &reply);
+        }
+    }
+
+    fn send_sms(&mut self) {
+        let hex = String::from_utf8_lossy(&self.input).to_string();
+        self.input.clear();
+        let sms = match std::mem::replace(&mut self.mode, Mode::Command) {
+            Mode::SmsText { number } => SentSms {
+                number,
+                text: ucs2::decode(&hex).unwrap_or(hex),
+                header: Vec::new(),
+            },
+            Mode::SmsPdu => match pdu::decode_submit(&hex) {
+                Some(submit) => SentSms {
+                    number: submit.destination,
+                    text: submit.text,
+                    header: submit.header,
+                },
+                None => return self.cms_error(304), // invalid PDU mode parameter
+            },
+            _ => unreachable!(),
+        };
+        self.sent_sms.push(sms);
+        let mr = self.next_message_reference;
+        self.next_message_reference += 1;
+        self.info(&format!("+CMGS: {}", mr));
+    }
+}
+
+// Where pico-lib puts the EPO file, AT+CGNSAID fails without it.
+pub const EPO_FILE: &str = "C:\\User\\MTK3.EPO";
+
+// Splits the parameters of a command, quotes are removed.
+fn split_args(params: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in params.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => args.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    if !params.is_empty() {
+        args.push(current);
+    }
+    args
+}
+
+// Degrees to ddmm.mmmm (dddmm.mmmm) and the hemisphere.
+fn nmea_coordinate(value: f64, width: usize, positive: &str, negative: &str) -> (String, String) {
+    let degrees = value.abs().trunc();
+    let minutes = (value.abs() - degrees) * 60.0;
+    let hemisphere = if value < 0.0 { negative } else { positive };
+    (
+        format!("{:0width$}{:07.4}", degrees as u32, minutes, width = width),
+        hemisphere.to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quiet() -> Sim868 {
+        Sim868 {
+            echo: false,
+            ..Default::default()
+        }
+    }
+
+    fn exchange(sim: &mut Sim868, input: &str) -> String {
+        sim.write(input.as_bytes());
+        sim.take_output()
+            .into_iter()
+            .map(|(_, b)| String::from_utf8(b).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(
+            vec!["3", "1", "APN", "online,x"],
+            split_args("3,1,\"APN\",\"online,x\"")
+        );
+        assert!(split_args("").is_empty());
+    }
+
+    #[test]
+    fn test_echo() {
+        let mut sim = Sim868::default();
+        assert_eq!("AT\r\r\nOK\r\n", exchange(&mut sim, "AT\r"));
+        assert_eq!("ATE0\r\r\nOK\r\n", exchange(&mut sim, "ATE0\r"));
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT\r"));
+        assert_eq!("\r\nERROR\r\n", exchange(&mut sim, "AT+FOO\r"));
+        assert_eq!(vec!["AT", "ATE0", "AT", "AT+FOO"], sim.commands);
+    }
+
+    #[test]
+    fn test_sms() {
+        let mut sim = quiet();
+        let index = sim.receive_sms("+36301234567", "hello", "26/01/10,17:25:32+04");
+        assert_eq!(Some(1), index);
+        assert_eq!(
+            "\r\n+CMTI: \"SM\",1\r\n",
+            String::from_utf8(sim.take_output()[0].1.clone()).unwrap()
+        );
+        assert_eq!(
+            "\r\n+CMGR: \"REC UNREAD\",\"002B00330036003300300031003200330034003500360037\",\"\",\"26/01/10,17:25:32+04\"\r\n00680065006C006C006F\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CMGR=1\r")
+        );
+        assert!(exchange(&mut sim, "AT+CMGR=1\r").starts_with("\r\n+CMGR: \"REC READ\""));
+        assert_eq!("\r\n+CMS ERROR: 321\r\n", exchange(&mut sim, "AT+CMGR=2\r"));
+
+        assert_eq!(
+            "\r\n> ",
+            exchange(
+                &mut sim,
+                "AT+CMGS=\"002B00330036003300300031003200330034003500360037\"\r"
+            )
+        );
+        assert_eq!(
+            "\r\n+CMGS: 1\r\n\r\nOK\r\n",
+            exchange(&mut sim, "004F004B\x1a")
+        );
+        assert_eq!(
+            vec![SentSms {
+                number: "+36301234567".to_string(),
+                text: "OK".to_string(),
+                header: Vec::new(),
+            }],
+            sim.sent_sms
+        );
+    }
+
+    #[test]
+    fn test_sms_pdu() {
+        let mut sim = quiet();
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CMGF=0\r"));
+        let indexes = sim.receive_long_sms(
+            "+36301234567",
+            &"a".repeat(70),
+            "26/01/10,17:25:32+04",
+            0x42,
+        );
+        assert_eq!(vec![1, 2], indexes);
+        sim.take_output();
+        assert_eq!(
+            "\r\n+CMGR: 0,,31\r\n00440B916303214365F70008621001715223400C050003420202006100610061\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CMGR=2\r")
+        );
+        assert!(exchange(&mut sim, "AT+CMGR=2\r").starts_with("\r\n+CMGR: 1,,31\r\n"));
+
+        assert_eq!("\r\n> ", exchange(&mut sim, "AT+CMGS=15\r"));
+        assert_eq!(
+            "\r\n+CMGS: 1\r\n\r\nOK\r\n",
+            exchange(&mut sim, "0041000B916303214365F7000009050003420201D069\x1a")
+        );
+        assert_eq!(
+            vec![SentSms {
+                number: "+36301234567".to_string(),
+                text: "hi".to_string(),
+                header: vec![0x00, 0x03, 0x42, 0x02, 0x01],
+            }],
+            sim.sent_sms
+        );
+        exchange(&mut sim, "AT+CMGS=15\r");
+        assert_eq!("\r\n+CMS ERROR: 304\r\n", exchange(&mut sim, "0001\x1a"));
+    }
+
+    #[test]
+    fn test_sms_storage() {
+        let mut sim = quiet();
+        sim.sms_capacity = 3;
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CMGF=0\r"));
+        assert_eq!(
+            "\r\n+CPMS: 0,3,0,3,0,3\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CPMS=\"SM\",\"SM\",\"SM\"\r")
+        );
+        assert_eq!(
+            "\r\n+CMS ERROR: 302\r\n",
+            exchange(&mut sim, "AT+CPMS=\"ME\"\r")
+        );
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CMGL=4\r"));
+
+        sim.receive_sms("+36301234567", "a", "26/01/10,17:25:32+04");
+        sim.receive_sms("+36301234567", "b", "26/01/10,17:25:32+04");
+        sim.receive_sms("+36301234567", "c", "26/01/10,17:25:32+04");
+        assert_eq!(
+            None,
+            sim.receive_sms("+36301234567", "d", "26/01/10,17:25:32+04")
+        );
+        sim.take_output();
+        assert_eq!(
+            "\r\n+CPMS: \"SM\",3,3,\"SM\",3,3,\"SM\",3,3\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CPMS?\r")
+        );
+
+        exchange(&mut sim, "AT+CMGR=2\r");
+        assert_eq!(
+            "\r\n+CMGL: 1,0,,21\r\n00040B916303214365F7000862100171522340020061\r\n+CMGL: 3,0,,21\r\n00040B916303214365F7000862100171522340020063\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CMGL=0,1\r")
+        );
+        assert!(!sim.sms[&1].read);
+        assert!(exchange(&mut sim, "AT+CMGL=1\r").starts_with("\r\n+CMGL: 2,1,,"));
+
+        // read ones
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CMGD=1,1\r"));
+        assert_eq!(vec![1, 3], sim.sms.keys().cloned().collect::<Vec<_>>());
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CMGD=3\r"));
+        assert_eq!("\r\n+CMS ERROR: 321\r\n", exchange(&mut sim, "AT+CMGD=3\r"));
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CMGD=1,4\r"));
+        assert!(sim.sms.is_empty());
+    }
+
+    #[test]
+    fn test_gprs_and_bearer() {
+        let mut sim = quiet();
+        assert_eq!("\r\nERROR\r\n", exchange(&mut sim, "AT+CIICR\r"));
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CGATT=1\r"));
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CSTT=\"online\"\r"));
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CIICR\r"));
+        assert_eq!("\r\n100.95.173.97\r\n", exchange(&mut sim, "AT+CIFSR\r"));
+        assert_eq!(
+            "\r\n+CLBS: 3\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CLBS=4,1\r")
+        );
+        assert_eq!(
+            "\r\nOK\r\n",
+            exchange(&mut sim, "AT+SAPBR=3,1,\"APN\",\"online\"\r")
+        );
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+SAPBR=1,1\r"));
+        assert_eq!(
+            "\r\n+CLBS: 0,18.630459,46.762486,550,12/12/22,12:02:21\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CLBS=4,1\r")
+        );
+        assert_eq!(
+            "\r\n+CLBS: 0,18.630459,46.762486,550\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CLBS=1,1,,,1\r")
+        );
+        assert_eq!(
+            "\r\n+CLBS: 0,2\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CLBS=3,1\r")
+        );
+        assert_eq!(Some(&"online".to_string()), sim.bearer_params.get("APN"));
+
+        sim.set_registration(Registration::Searching);
+        assert_eq!(
+            "\r\n+SAPBR 1: DEACT\r\n\r\n+PDP: DEACT\r\n",
+            exchange(&mut sim, "")
+        );
+        assert!(!sim.gprs_attached);
+        assert_eq!("\r\nERROR\r\n", exchange(&mut sim, "AT+CGATT=1\r"));
+
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CGREG=1\r"));
+        assert_eq!(
+            "\r\n+CGREG: 1,2\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CGREG?\r")
+        );
+        sim.set_registration(Registration::Registered);
+        assert_eq!("\r\n+CGREG: 1\r\n", exchange(&mut sim, ""));
+    }
+
+    #[test]
+    fn test_ip_connections() {
+        let mut sim = quiet();
+        sim.servers.insert("10.0.0.1:7".to_string(), Box::new(Echo));
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CIPMUX=1\r"));
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CIPRXGET=1\r"));
+        assert_eq!(
+            "\r\nERROR\r\n",
+            exchange(&mut sim, "AT+CIPSTART=0,\"TCP\",\"10.0.0.1\",7\r")
+        );
+        exchange(&mut sim, "AT+CGATT=1\r");
+        exchange(&mut sim, "AT+CSTT=\"online\"\r");
+        exchange(&mut sim, "AT+CIICR\r");
+        assert_eq!("\r\nERROR\r\n", exchange(&mut sim, "AT+CIPMUX=0\r"));
+
+        assert_eq!(
+            "\r\nOK\r\n\r\n0, CONNECT OK\r\n",
+            exchange(&mut sim, "AT+CIPSTART=0,\"TCP\",\"10.0.0.1\",7\r")
+        );
+        assert_eq!(
+            "\r\nOK\r\n\r\n1, CONNECT FAIL\r\n",
+            exchange(&mut sim, "AT+CIPSTART=1,\"TCP\",\"10.0.0.2\",7\r")
+        );
+        assert_eq!(
+            "\r\n+CIPSTATUS: 0,0,\"TCP\",\"10.0.0.1\",\"7\",\"CONNECTED\"\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CIPSTATUS=0\r")
+        );
+        assert_eq!(
+            "\r\n+CIPSTATUS: 2,,\"\",\"\",\"\",\"INITIAL\"\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CIPSTATUS=2\r")
+        );
+
+        assert_eq!("\r\n> ", exchange(&mut sim, "AT+CIPSEND=0,5\r"));
+        assert_eq!(
+            "\r\n0, SEND OK\r\n\r\n+CIPRXGET: 1,0\r\n",
+            exchange(&mut sim, "hello")
+        );
+        assert_eq!(
+            "\r\n+CIPRXGET: 3,0,2,3\r\n6865\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CIPRXGET=3,0,2\r")
+        );
+        assert_eq!(
+            "\r\n+CIPRXGET: 2,0,3,0\r\nllo\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CIPRXGET=2,0,10\r")
+        );
+        assert_eq!(
+            "\r\n+CIPRXGET: 3,0,0,0\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CIPRXGET=3,0,10\r")
+        );
+        assert_eq!(b"hello".to_vec(), sim.connections[&0].sent);
+
+        sim.close_remote(0);
+        assert_eq!("\r\n0, CLOSED\r\n", exchange(&mut sim, ""));
+        assert_eq!("\r\nERROR\r\n", exchange(&mut sim, "AT+CIPSEND=0,5\r"));
+        assert_eq!("\r\nERROR\r\n", exchange(&mut sim, "AT+CIPCLOSE=0,1\r"));
+
+        exchange(&mut sim, "AT+CIPSTART=0,\"UDP\",\"10.0.0.1\",7\r");
+        assert_eq!(
+            "\r\n0, CLOSE OK\r\n",
+            exchange(&mut sim, "AT+CIPCLOSE=0,1\r")
+        );
+        assert!(!sim.connections.contains_key(&0));
+    }
+
+    #[test]
+    fn test_gnss() {
+        let mut sim = quiet();
+        assert_eq!(
+            "\r\n+CGNSINF: 0,,,,,,,,,,,,,,,,,,,,\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CGNSINF\r")
+        );
+        exchange(&mut sim, "AT+CGNSPWR=1\r");
+        assert_eq!(
+            "\r\n+CGNSINF: 1,1,20221212120221.123,46.7624859,18.6304591,329.218,0.00,0.0,1,,2.1,2.3,0.9,,12,8,,,45,,\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CGNSINF\r")
+        );
+        sim.gnss_fix = None;
+        assert_eq!(
+            "\r\n+CGNSINF: 1,0,,,,,,,,,,,,,,,,,,,\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CGNSINF\r")
+        );
+    }
+
+    #[test]
+    fn test_http() {
+        let mut sim = quiet();
+        sim.http_server
+            .insert("http://x/a".to_string(), (200, b"ab\r\ncd".to_vec()));
+        assert_eq!("\r\nERROR\r\n", exchange(&mut sim, "AT+HTTPACTION=0\r"));
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+HTTPINIT\r"));
+        assert_eq!(
+            "\r\nOK\r\n",
+            exchange(&mut sim, "AT+HTTPPARA=\"URL\",\"http://x/a\"\r")
+        );
+        assert_eq!(
+            "\r\nOK\r\n\r\n+HTTPACTION: 0,601,0\r\n",
+            exchange(&mut sim, "AT+HTTPACTION=0\r")
+        );
+        sim.bearer_open = true;
+        assert_eq!(
+            "\r\nOK\r\n\r\n+HTTPACTION: 0,200,6\r\n",
+            exchange(&mut sim, "AT+HTTPACTION=0\r")
+        );
+        assert_eq!(
+            "\r\n+HTTPREAD: 4\r\nab\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+HTTPREAD=0,4\r")
+        );
+        assert_eq!(
+            "\r\n+HTTPREAD: 2\r\ncd\r\nOK\r\n",
+            exchange(&mut sim, "AT+HTTPREAD=4,4\r")
+        );
+        exchange(&mut sim, "AT+HTTPPARA=\"URL\",\"http://x/b\"\r");
+        exchange(
+            &mut sim,
+            "AT+HTTPPARA=\"USERDATA\",\"Authorization: Bearer t\\r\\nX-Id: 7\"\r",
+        );
+        assert_eq!(
+            "\r\nDOWNLOAD\r\n",
+            exchange(&mut sim, "AT+HTTPDATA=4,10000\r")
+        );
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "{}\r\n"));
+        assert_eq!(
+            "\r\nOK\r\n\r\n+HTTPACTION: 1,404,0\r\n",
+            exchange(&mut sim, "AT+HTTPACTION=1\r")
+        );
+        assert_eq!(
+            HttpRequest {
+                method: 1,
+                url: "http://x/b".to_string(),
+                headers: vec![
+                    ("Authorization".to_string(), "Bearer t".to_string()),
+                    ("X-Id".to_string(), "7".to_string())
+                ],
+                content_type: None,
+                body: b"{}\r\n".to_vec(),
+            },
+            sim.http_requests[2]
+        );
+        assert_eq!(
+            "\r\nOK\r\n\r\n+HTTPACTION: 0,404,0\r\n",
+            exchange(&mut sim, "AT+HTTPACTION=0\r")
+        );
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+HTTPTERM\r"));
+        assert_eq!("\r\nERROR\r\n", exchange(&mut sim, "AT+HTTPTERM\r"));
+    }
+
+    #[test]
+    fn test_files_and_epo() {
+        let mut sim = quiet();
+        let epo = format!("AT+FSCREATE=\"{}\"\r", EPO_FILE);
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, &epo));
+        assert_eq!("\r\nERROR\r\n", exchange(&mut sim, &epo));
+        assert_eq!(
+            "\r\n> ",
+            exchange(&mut sim, &format!("AT+FSWRITE=\"{}\",1,3,10\r", EPO_FILE))
+        );
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "a\rb"));
+        exchange(&mut sim, &format!("AT+FSWRITE=\"{}\",1,1,10\r", EPO_FILE));
+        exchange(&mut sim, "\x1a");
+        assert_eq!(
+            "\r\n+FSFLSIZE: 4\r\n\r\nOK\r\n",
+            exchange(&mut sim, &format!("AT+FSFLSIZE=\"{}\"\r", EPO_FILE))
+        );
+        assert_eq!(Some(&b"a\rb\x1a".to_vec()), sim.files.get(EPO_FILE));
+
+        assert_eq!("\r\nERROR\r\n", exchange(&mut sim, "AT+CGNSHOT\r"));
+        assert_eq!("\r\nERROR\r\n", exchange(&mut sim, "AT+CGNSAID=31,1,1\r"));
+        exchange(&mut sim, "AT+CGNSPWR=1\r");
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CGNSCOLD\r"));
+        assert_eq!(Some("COLD".to_string()), sim.gnss_start);
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CGNSAID=31,1,1\r"));
+        assert!(sim.epo_injected);
+
+        exchange(&mut sim, &format!("AT+FSDEL=\"{}\"\r", EPO_FILE));
+        assert!(sim.files.is_empty());
+        assert_eq!("\r\nERROR\r\n", exchange(&mut sim, "AT+CGNSAID=31,1,1\r"));
+    }
+
+    #[test]
+    fn test_nmea() {
+        let mut sim = quiet();
+        exchange(&mut sim, "AT+CGNSPWR=1\r");
+        sim.emit_nmea();
+        assert_eq!("", exchange(&mut sim, ""));
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CGNSTST=1\r"));
+        sim.emit_nmea();
+        let output = exchange(&mut sim, "");
+        assert!(
+            output.starts_with(
+                "$GPGGA,120221.123,4645.7492,N,01837.8275,E,1,8,2.10,329.2,M,38.2,M,,*"
+            )
+        );
+        assert!(
+            output.contains("$GPRMC,120221.123,A,4645.7492,N,01837.8275,E,0.00,0.0,121222,,,A*")
+        );
+        assert_eq!(6, output.lines().count());
+
+        sim.gnss_fix = None;
+        sim.emit_nmea();
+        assert!(exchange(&mut sim, "").starts_with("$GPGGA,,,,,,0,0,,,M,,M,,*"));
+        exchange(&mut sim, "AT+CGNSTST=0\r");
+        sim.emit_nmea();
+        assert_eq!("", exchange(&mut sim, ""));
+    }
+
+    #[test]
+    fn test_gnss_urc() {
+        let mut sim = quiet();
+        exchange(&mut sim, "AT+CGNSPWR=1\r");
+        sim.emit_gnss_urc();
+        assert_eq!("", exchange(&mut sim, ""));
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CGNSURC=1\r"));
+        sim.emit_gnss_urc();
+        assert_eq!(
+            "\r\n+UGNSINF: 1,1,20221212120221.123,46.7624859,18.6304591,329.218,0.00,0.0,1,,2.1,2.3,0.9,,12,8,,,45,,\r\n",
+            exchange(&mut sim, "")
+        );
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CGNSURC=0\r"));
+        sim.emit_gnss_urc();
+        assert_eq!("", exchange(&mut sim, ""));
+        assert_eq!("\r\nERROR\r\n", exchange(&mut sim, "AT+CGNSURC=x\r"));
+    }
+
+    #[test]
+    fn test_calls() {
+        let mut sim = quiet();
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "ATD+36301234567,i;\r"));
+        assert_eq!(CallState::Dialing("+36301234567".to_string()), sim.call);
+        assert_eq!("\r\nOK\r\n", exchange(&mut sim, "AT+CHUP;\r"));
+        assert_eq!(CallState::Idle, sim.call);
+
+        sim.incoming_call("+36301234567");
+        assert_eq!(
+            "\r\nRING\r\n\r\n+CLIP: \"+36301234567\",145,\"\",0,\"\",0\r\n\r\nOK\r\n",
+            exchange(&mut sim, "ATA\r")
+        );
+        assert_eq!(CallState::Active("+36301234567".to_string()), sim.call);
+        assert_eq!(vec!["+36301234567"], sim.calls);
+    }
+
+    #[test]
+    fn test_scripts() {
+        let mut sim = quiet();
+        sim.on("AT+CSQ", Action::Error)
+            .on(
+                "AT+CSQ",
+                Action::Reply("\r\n+CSQ: 99,99\r\n\r\nOK\r\n".to_string()),
+            )
+            .on_every("AT+CBC", Action::Silent)
+            .on(
+                "AT+CGATT=1",
+                Action::Hook(Box::new(|s| s.set_registration(Registration::Searching))),
+            )
+            .on("AT+CPIN?", Action::Delay(Duration::from_millis(100)));
+        assert_eq!("\r\nERROR\r\n", exchange(&mut sim, "AT+CSQ\r"));
+        assert_eq!(
+            "\r\n+CSQ: 99,99\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CSQ\r")
+        );
+        assert_eq!(
+            "\r\n+CSQ: 20,0\r\n\r\nOK\r\n",
+            exchange(&mut sim, "AT+CSQ\r")
+        );
+        assert_eq!("", exchange(&mut sim, "AT+CBC\r"));
+        assert_eq!("", exchange(&mut sim, "AT+CBC\r"));
+        assert_eq!("\r\nERROR\r\n", exchange(&mut sim, "AT+CGATT=1\r"));
+
+        sim.write(b"AT+CPIN?\r");
+        assert_eq!(Duration::from_millis(100), sim.take_output()[0].0);
+    }
+
+    #[test]
+    fn test_restart() {
+        let mut sim = quiet();
+        sim.gnss_power = true;
+        sim.receive_sms("+36301234567", "hello", "26/01/10,17:25:32+04");
+        sim.restart();
+        assert!(sim.echo);
+        assert!(!sim.gnss_power);
+        assert_eq!(1, sim.sms.len());
+        let output: String = sim
+            .take_output()
+            .into_iter()
+            .map(|(_, b)| String::from_utf8(b).unwrap())
+            .collect();
+        assert!(output.ends_with("\r\nCall Ready\r\n\r\nSMS Ready\r\n"));
+    }
+}
@@ -0,0 +1,32 @@
+// UCS2 hex encoding used by the module with AT+CSCS="UCS2", e.g. "OK" <-> "004F004B".
+
+pub fn encode(text: &str) -> String {
+    text.encode_utf16().map(|c| format!("{:04X}", c)).collect()
+}
+
+pub fn decode(hex: &str) -> Option<String> {
+    if hex.is_empty() || !hex.len().is_multiple_of(4) {
+        return None;
+    }
+    let units = (0..hex.len())
+        .step_by(4)
+        .map(|i| u16::from_str_radix(hex.get(i..i + 4)?, 16).ok())
+        .collect::<Option<Vec<u16>>>()?;
+    String::from_utf16(&units).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ucs2() {
+        assert_eq!("004F004B", encode("OK"));
+        assert_eq!("00E1", encode("á"));
+        assert_eq!(Some("OK".to_string()), decode("004F004B"));
+        assert_eq!(Some("á".to_string()), decode("00e1"));
+        assert_eq!(None, decode("004F00"));
+        assert_eq!(None, decode("OK"));
+        assert_eq!(None, decode(""));
+    }
+}